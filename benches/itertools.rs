@@ -0,0 +1,105 @@
+//! This crate's merges against those of `itertools`, for deciding whether to migrate.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use itertools::Itertools;
+
+use kmerge::compat;
+use kmerge::generate::{Distribution, Generator};
+use kmerge::{kway, merge2_uniq};
+
+/// Two-way merges, keeping duplicates and dropping them, across sizes and overlaps.
+fn bench_merge(c: &mut Criterion) {
+    for &len in &[1000, 100000] {
+        for &overlap in &[0.0, 0.5, 1.0] {
+            let (a, b) = Generator::new(len, len)
+                .overlap(overlap)
+                .distribution(Distribution::Uniform)
+                .seed(42)
+                .pair();
+
+            let name = |what| format!("{} {} {}", what, len, overlap);
+            c.bench_function(&name("itertools::merge"), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| itertools::merge(a, b).collect::<Vec<_>>(),
+                    BatchSize::SmallInput,
+                )
+            });
+            c.bench_function(&name("compat::merge"), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| compat::merge(a, b).collect::<Vec<_>>(),
+                    BatchSize::SmallInput,
+                )
+            });
+            c.bench_function(&name("itertools::merge dedup"), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| itertools::merge(a, b).dedup().collect::<Vec<_>>(),
+                    BatchSize::SmallInput,
+                )
+            });
+            c.bench_function(&name("merge_uniq"), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| kmerge::merge_uniq(a, b),
+                    BatchSize::SmallInput,
+                )
+            });
+            c.bench_function(&name("merge2_uniq::raw_ptr"), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| merge2_uniq::raw_ptr(a, b),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+}
+
+/// K-way merges of runs cut from generated pairs, so that neighbouring runs overlap as chosen.
+fn bench_kmerge(c: &mut Criterion) {
+    for &(k, len) in &[(8, 10000), (64, 10000)] {
+        for &overlap in &[0.0, 0.5, 1.0] {
+            let runs: Vec<Vec<u64>> = (0..k / 2)
+                .flat_map(|i| {
+                    let (a, b) = Generator::new(len, len)
+                        .overlap(overlap)
+                        .distribution(Distribution::Uniform)
+                        .seed(i as u64)
+                        .pair();
+                    vec![a, b]
+                })
+                .collect();
+
+            let name = |what| format!("{} {}x{} {}", what, k, len, overlap);
+            c.bench_function(&name("itertools::kmerge"), |bench| {
+                bench.iter_batched(
+                    || runs.clone(),
+                    |runs| itertools::kmerge(runs).collect::<Vec<_>>(),
+                    BatchSize::LargeInput,
+                )
+            });
+            c.bench_function(&name("compat::kmerge"), |bench| {
+                bench.iter_batched(
+                    || runs.clone(),
+                    |runs| compat::kmerge(runs).collect::<Vec<_>>(),
+                    BatchSize::LargeInput,
+                )
+            });
+            c.bench_function(&name("itertools::kmerge dedup"), |bench| {
+                bench.iter_batched(
+                    || runs.clone(),
+                    |runs| itertools::kmerge(runs).dedup().collect::<Vec<_>>(),
+                    BatchSize::LargeInput,
+                )
+            });
+            c.bench_function(&name("kway::merge_uniq"), |bench| {
+                bench.iter_batched(|| runs.clone(), kway::merge_uniq, BatchSize::LargeInput)
+            });
+        }
+    }
+}
+
+criterion_group!(benches, bench_merge, bench_kmerge);
+criterion_main!(benches);
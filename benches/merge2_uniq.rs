@@ -1,13 +1,18 @@
-use criterion::{criterion_group, criterion_main, Criterion, BatchSize};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use rand::prelude::*;
 
-use kmerge::merge2_uniq;
+use kmerge::generate::{Distribution, Generator};
+use kmerge::simd::{self, Backend};
+use kmerge::{kway, merge2_uniq, merge3_uniq};
 
 fn bench_input<T>(len: usize, seed: u64) -> Vec<T>
-where rand::distributions::Standard: Distribution<T>
+where
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
 {
     let rng = SmallRng::seed_from_u64(seed);
-    rng.sample_iter(rand::distributions::Standard).take(len).collect()
+    rng.sample_iter(rand::distributions::Standard)
+        .take(len)
+        .collect()
 }
 
 fn bench_merge(c: &mut Criterion) {
@@ -19,33 +24,451 @@ fn bench_merge(c: &mut Criterion) {
     b.sort_unstable();
     b.dedup();
 
-    c.bench_function("naive 100k", |bench| bench.iter_batched(
-        || (a.clone(), b.clone()),
-        |(a, b)| merge2_uniq::naive(a, b),
-        BatchSize::SmallInput,
-    ));
-    c.bench_function("vec::IntoIter 100k", |bench| bench.iter_batched(
-        || (a.clone(), b.clone()),
-        |(a, b)| merge2_uniq::into_iter(a, b),
-        BatchSize::SmallInput,
-    ));
-    c.bench_function("vec::IntoIter safer 100k", |bench| bench.iter_batched(
-        || (a.clone(), b.clone()),
-        |(a, b)| merge2_uniq::into_iter_safer(a, b),
-        BatchSize::SmallInput,
-    ));
-    c.bench_function("raw ptr 10k", |bench| bench.iter_batched(
-        || (a.clone(), b.clone()),
-        |(a, b)| merge2_uniq::raw_ptr(a, b),
-        BatchSize::SmallInput,
-    ));
-    c.bench_function("safe 10k", |bench| bench.iter_batched(
-        || (a.clone(), b.clone()),
-        |(a, b)| merge2_uniq::old_datafrog(a, b),
-        BatchSize::SmallInput,
-    ));
-}
-
-criterion_group!(benches, bench_merge);
-criterion_main!(benches);
+    c.bench_function("naive 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::naive(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("vec::IntoIter 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::into_iter(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("vec::IntoIter safer 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::into_iter_safer(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    // The inputs are `(u64, u64)`, which is `Copy`, so `raw_ptr_copied` and `raw_ptr` can be
+    // compared on them directly.
+    c.bench_function("raw ptr 10k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::raw_ptr(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("raw ptr copied 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::raw_ptr_copied(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("merge_uniq_copied 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| kmerge::merge_uniq_copied(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("branchless 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::branchless(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("safe 10k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::old_datafrog(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("low memory 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::low_memory(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("exact 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::exact(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("merge_uniq 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| kmerge::merge_uniq(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Three inputs, as when merging two deltas into previous state.
+fn bench_merge3(c: &mut Criterion) {
+    let mut a = bench_input::<u64>(100000, 42);
+    a.sort_unstable();
+    a.dedup();
+
+    let mut b = bench_input::<u64>(100000, 35);
+    b.sort_unstable();
+    b.dedup();
+
+    let mut d = bench_input::<u64>(100000, 17);
+    d.sort_unstable();
+    d.dedup();
+
+    c.bench_function("merge3 raw ptr u64 3x100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone(), d.clone()),
+            |(a, b, d)| merge3_uniq::raw_ptr(a, b, d),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("chained raw ptr u64 3x100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone(), d.clone()),
+            |(a, b, d)| merge2_uniq::raw_ptr(merge2_uniq::raw_ptr(a, b), d),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("loser tree u64 3x100k", |bench| {
+        bench.iter_batched(
+            || vec![a.clone(), b.clone(), d.clone()],
+            |runs| {
+                kway::KMergeUniq::new(runs.into_iter().map(Vec::into_iter).collect())
+                    .collect::<Vec<_>>()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bench_merge_skewed(c: &mut Criterion) {
+    let mut a = bench_input::<(u64, u64)>(100000, 42);
+    a.sort_unstable();
+    a.dedup();
+
+    let mut b = bench_input::<(u64, u64)>(1000, 35);
+    b.sort_unstable();
+    b.dedup();
+
+    c.bench_function("raw ptr 100k/1k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::raw_ptr(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("galloping 100k/1k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::galloping(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// 256-byte records, keyed by their first word.
+fn bench_merge_large(c: &mut Criterion) {
+    let records = |seed| {
+        let mut v: Vec<[u64; 32]> = bench_input::<u64>(100000, seed)
+            .into_iter()
+            .map(|key| {
+                let mut record = [0; 32];
+                record[0] = key;
+                record
+            })
+            .collect();
+        v.sort_unstable();
+        v.dedup();
+        v
+    };
+    let (a, b) = (records(42), records(35));
+
+    c.bench_function("raw ptr 256-byte 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::raw_ptr(a, b),
+            BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("index gather 256-byte 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::index_gather(a, b),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Inputs with a chosen overlap and interleaving, which decide whether galloping pays off.
+fn bench_merge_overlap(c: &mut Criterion) {
+    for &(name, distribution) in &[
+        ("uniform", Distribution::Uniform),
+        ("clustered", Distribution::Clustered { mean_run: 64 }),
+        ("zipf", Distribution::Zipf { exponent: 1.5 }),
+    ] {
+        for &overlap in &[0.0, 0.5, 1.0] {
+            let (a, b) = Generator::new(100000, 100000)
+                .overlap(overlap)
+                .distribution(distribution)
+                .seed(42)
+                .pair();
+
+            c.bench_function(&format!("raw ptr {} {} 100k", name, overlap), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| merge2_uniq::raw_ptr(a, b),
+                    BatchSize::SmallInput,
+                )
+            });
+            c.bench_function(&format!("galloping {} {} 100k", name, overlap), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| merge2_uniq::galloping(a, b),
+                    BatchSize::SmallInput,
+                )
+            });
+            c.bench_function(&format!("adaptive {} {} 100k", name, overlap), |bench| {
+                bench.iter_batched(
+                    || (a.clone(), b.clone()),
+                    |(a, b)| merge2_uniq::adaptive(a, b),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+}
+
+fn bench_merge_int(c: &mut Criterion) {
+    let mut a = bench_input::<u64>(100000, 42);
+    a.sort_unstable();
+    a.dedup();
 
+    let mut b = bench_input::<u64>(100000, 35);
+    b.sort_unstable();
+    b.dedup();
+
+    c.bench_function("raw ptr u64 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::raw_ptr(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("simd u64 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| simd::merge_uniq(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Compares the vector backends against each other, skipping the ones this CPU doesn't support.
+fn bench_simd_backends(c: &mut Criterion) {
+    let mut a64 = bench_input::<u64>(100000, 42);
+    a64.sort_unstable();
+    a64.dedup();
+
+    let mut b64 = bench_input::<u64>(100000, 35);
+    b64.sort_unstable();
+    b64.dedup();
+
+    let mut a32 = bench_input::<u32>(100000, 42);
+    a32.sort_unstable();
+    a32.dedup();
+
+    let mut b32 = bench_input::<u32>(100000, 35);
+    b32.sort_unstable();
+    b32.dedup();
+
+    for &(name, backend) in &[
+        ("avx2", Backend::Avx2),
+        ("avx512", Backend::Avx512),
+        ("neon", Backend::Neon),
+        ("simd128", Backend::Simd128),
+    ] {
+        if !backend.is_supported() {
+            continue;
+        }
+
+        c.bench_function(&format!("{} u32 100k", name), |bench| {
+            bench.iter_batched(
+                || (a32.clone(), b32.clone()),
+                |(a, b)| simd::merge_uniq_with(backend, a, b),
+                BatchSize::SmallInput,
+            )
+        });
+        c.bench_function(&format!("{} u64 100k", name), |bench| {
+            bench.iter_batched(
+                || (a64.clone(), b64.clone()),
+                |(a, b)| simd::merge_uniq_with(backend, a, b),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+/// Byte-string keys, whose contents are scattered across the heap.
+fn bench_merge_bytes(c: &mut Criterion) {
+    let keys = |seed| {
+        let mut keys: Vec<Vec<u8>> = bench_input::<u64>(100000, seed)
+            .into_iter()
+            .map(|x| x.to_be_bytes()[..(x % 8) as usize + 1].to_vec())
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    };
+    let (a, b) = (keys(42), keys(35));
+
+    c.bench_function("merge_uniq bytes 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| kmerge::merge_uniq(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("bytes::merge_uniq 100k", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| kmerge::bytes::merge_uniq(a, b),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Sorting and deduplicating concatenations of sorted batches. Random batches all overlap each
+/// other, and nearly ordered ones, as from a log of events, each overlap only their neighbours.
+fn bench_natural_sort(c: &mut Criterion) {
+    for batches in [16u64, 1000] {
+        let len = 1_000_000 / batches;
+        let batch = |seed: u64, shift: bool| {
+            let mut batch = bench_input::<u64>(len as usize, seed);
+            if shift {
+                for x in &mut batch {
+                    *x = seed * len + *x % (2 * len);
+                }
+            }
+            batch.sort_unstable();
+            batch
+        };
+        let random: Vec<u64> = (0..batches).flat_map(|seed| batch(seed, false)).collect();
+        let ordered: Vec<u64> = (0..batches).flat_map(|seed| batch(seed, true)).collect();
+
+        for (name, v) in [("random", &random), ("ordered", &ordered)] {
+            c.bench_function(
+                &format!("sort + dedup {} {} batches", batches, name),
+                |bench| {
+                    bench.iter_batched(
+                        || v.clone(),
+                        |mut v| {
+                            v.sort();
+                            v.dedup();
+                            v
+                        },
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+            c.bench_function(
+                &format!("sort_unstable + dedup {} {} batches", batches, name),
+                |bench| {
+                    bench.iter_batched(
+                        || v.clone(),
+                        |mut v| {
+                            v.sort_unstable();
+                            v.dedup();
+                            v
+                        },
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+            c.bench_function(
+                &format!("natural_sort_uniq {} {} batches", batches, name),
+                |bench| {
+                    bench.iter_batched(
+                        || v.clone(),
+                        kmerge::sort::natural_sort_uniq,
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
+    }
+}
+
+/// Inputs much larger than the last-level cache, to measure the effect of the `prefetch` feature.
+///
+/// The inputs take about 1.6 GB, so this only runs when the `KMERGE_BENCH_HUGE` environment
+/// variable is set.
+fn bench_merge_huge(c: &mut Criterion) {
+    if std::env::var_os("KMERGE_BENCH_HUGE").is_none() {
+        return;
+    }
+
+    let mut a = bench_input::<u64>(100_000_000, 42);
+    a.sort_unstable();
+    a.dedup();
+
+    let mut b = bench_input::<u64>(100_000_000, 35);
+    b.sort_unstable();
+    b.dedup();
+
+    c.bench_function("huge raw ptr u64 100M", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| merge2_uniq::raw_ptr(a, b),
+            BatchSize::LargeInput,
+        )
+    });
+    #[cfg(feature = "rayon")]
+    c.bench_function("huge parallel u64 100M", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| kmerge::parallel::merge_uniq(a, b),
+            BatchSize::LargeInput,
+        )
+    });
+    #[cfg(any(feature = "rayon", feature = "threads"))]
+    c.bench_function("huge parallel radix u64 100M", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| kmerge::parallel::merge_uniq_radix(a, b),
+            BatchSize::LargeInput,
+        )
+    });
+    #[cfg(feature = "threads")]
+    c.bench_function("huge scoped threads u64 100M", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(a, b)| {
+                kmerge::parallel::merge_uniq_with(&kmerge::parallel::ScopedThreads::default(), a, b)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_merge,
+    bench_merge3,
+    bench_merge_skewed,
+    bench_merge_large,
+    bench_merge_overlap,
+    bench_merge_int,
+    bench_simd_backends,
+    bench_merge_bytes,
+    bench_natural_sort
+);
+criterion_group! {
+    name = huge;
+    config = Criterion::default().sample_size(10);
+    targets = bench_merge_huge
+}
+criterion_main!(benches, huge);
@@ -0,0 +1,224 @@
+//! Merges sorted files from the command line.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::process;
+
+use kmerge::external::{FixedSize, LineKey, LineMerger, Merger};
+
+const USAGE: &str = "\
+usage: kmerge [options] [file...]
+
+Merges sorted files into one sorted output. With no files, or when a file is `-`, reads standard
+input.
+
+options:
+    -u, --unique            keep only the first of each group of equal lines or records
+    -k, --key N             compare lines by their Nth field, counting from 1
+    -t, --separator C       with --key, separate fields by the byte C instead of whitespace
+    -r, --record-size N     merge fixed-size binary records of N bytes instead of lines
+    -o, --output FILE       write to FILE instead of standard output
+    -h, --help              print this message
+";
+
+#[derive(Debug, Default)]
+struct Args {
+    unique: bool,
+    key: Option<usize>,
+    separator: Option<u8>,
+    record_size: Option<usize>,
+    output: Option<String>,
+    inputs: Vec<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} requires a value", name))
+        };
+        match arg.as_str() {
+            "-u" | "--unique" => parsed.unique = true,
+            "-k" | "--key" => {
+                let n = value(&arg)?;
+                match n.parse() {
+                    Ok(n) if n > 0 => parsed.key = Some(n),
+                    _ => return Err(format!("invalid field number: {}", n)),
+                }
+            }
+            "-t" | "--separator" => match value(&arg)?.as_bytes() {
+                &[sep] => parsed.separator = Some(sep),
+                _ => return Err("the separator must be a single byte".to_owned()),
+            },
+            "-r" | "--record-size" => {
+                let n = value(&arg)?;
+                match n.parse() {
+                    Ok(n) if n > 0 => parsed.record_size = Some(n),
+                    _ => return Err(format!("invalid record size: {}", n)),
+                }
+            }
+            "-o" | "--output" => parsed.output = Some(value(&arg)?),
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            "--" => {
+                parsed.inputs.extend(args);
+                break;
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option: {}", arg))
+            }
+            _ => parsed.inputs.push(arg),
+        }
+    }
+
+    if parsed.record_size.is_some() && (parsed.key.is_some() || parsed.separator.is_some()) {
+        return Err("--key and --separator only apply to lines".to_owned());
+    }
+    if parsed.separator.is_some() && parsed.key.is_none() {
+        return Err("--separator requires --key".to_owned());
+    }
+    if parsed.inputs.is_empty() {
+        parsed.inputs.push("-".to_owned());
+    }
+    Ok(parsed)
+}
+
+fn open(path: &str) -> io::Result<Box<dyn Read>> {
+    if path == "-" {
+        return Ok(Box::new(io::stdin()));
+    }
+    let file =
+        File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    Ok(Box::new(file))
+}
+
+/// Fails if `output` names the same file as one of `inputs`, which creating it would truncate
+/// before it is read.
+fn check_output(output: &str, inputs: &[String]) -> io::Result<()> {
+    let output = match fs::canonicalize(output) {
+        Ok(output) => output,
+        // The output doesn't exist yet, so it can't be an input.
+        Err(_) => return Ok(()),
+    };
+    for input in inputs.iter().filter(|&path| path != "-") {
+        if fs::canonicalize(input).ok().as_ref() == Some(&output) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: the output file is also an input", input),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    if let Some(output) = &args.output {
+        check_output(output, &args.inputs)?;
+    }
+    let inputs = args
+        .inputs
+        .iter()
+        .map(|path| open(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    match args.record_size {
+        Some(size) => {
+            let merger = Merger::new(FixedSize::new(size));
+            if args.unique {
+                merger.merge_uniq(inputs, out)?;
+            } else {
+                merger.merge(inputs, out)?;
+            }
+        }
+        None => {
+            let key = match args.key {
+                Some(field) => LineKey::Field {
+                    index: field - 1,
+                    separator: args.separator,
+                },
+                None => LineKey::Line,
+            };
+            let inputs = inputs.into_iter().map(BufReader::new).collect();
+            LineMerger::new()
+                .key(key)
+                .unique(args.unique)
+                .merge(inputs, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("kmerge: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(&args) {
+        eprintln!("kmerge: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|&s| s.to_owned()))
+    }
+
+    #[test]
+    fn parse_options() {
+        let args = parse(&["-u", "--key", "2", "-t", ",", "a", "-o", "out", "--", "-b"]).unwrap();
+        assert!(args.unique);
+        assert_eq!(args.key, Some(2));
+        assert_eq!(args.separator, Some(b','));
+        assert_eq!(args.output.as_deref(), Some("out"));
+        assert_eq!(args.inputs, ["a", "-b"]);
+
+        assert_eq!(parse(&[]).unwrap().inputs, ["-"]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse(&["-k"]).is_err());
+        assert!(parse(&["-k", "0"]).is_err());
+        assert!(parse(&["-t", "ab"]).is_err());
+        assert!(parse(&["-r", "4", "-k", "1"]).is_err());
+        assert!(parse(&["-t", ","]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+
+    #[test]
+    fn output_is_input() {
+        let dir = env::temp_dir().join(format!("kmerge-cli-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a");
+        fs::write(&a, "1\n2\n").unwrap();
+        let a = a.to_str().unwrap().to_owned();
+
+        let args = Args {
+            output: Some(a.clone()),
+            inputs: vec!["-".to_owned(), a.clone()],
+            ..Args::default()
+        };
+        let err = run(&args).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "1\n2\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+//! Merging the contents of `BTreeSet`s and the keys of `BTreeMap`s.
+//!
+//! Their iterators are already sorted and free of duplicates, so they feed a k-way merge directly,
+//! without being collected into a vector each first.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::kway::KMergeUniq;
+
+/// Returns the union of `sets` as a sorted, deduplicated vector.
+///
+/// When an element appears in several sets, the one from the earliest set is kept.
+pub fn union<T: Ord>(sets: Vec<BTreeSet<T>>) -> Vec<T> {
+    let mut out = Vec::with_capacity(sets.iter().map(BTreeSet::len).sum());
+    out.extend(KMergeUniq::new(
+        sets.into_iter().map(BTreeSet::into_iter).collect(),
+    ));
+    out
+}
+
+/// Like `union`, but borrows the sets and clones the elements of the result.
+pub fn union_cloned<'a, T, I>(sets: I) -> Vec<T>
+where
+    T: Ord + Clone + 'a,
+    I: IntoIterator<Item = &'a BTreeSet<T>>,
+{
+    let sources: Vec<_> = sets.into_iter().map(BTreeSet::iter).collect();
+    let mut out = Vec::with_capacity(sources.iter().map(ExactSizeIterator::len).sum());
+    out.extend(KMergeUniq::new(sources).cloned());
+    out
+}
+
+/// Returns the union of the keys of `maps` as a sorted, deduplicated vector.
+pub fn keys_union<'a, K, V, I>(maps: I) -> Vec<K>
+where
+    K: Ord + Clone + 'a,
+    V: 'a,
+    I: IntoIterator<Item = &'a BTreeMap<K, V>>,
+{
+    let sources: Vec<_> = maps.into_iter().map(BTreeMap::keys).collect();
+    let mut out = Vec::with_capacity(sources.iter().map(ExactSizeIterator::len).sum());
+    out.extend(KMergeUniq::new(sources).cloned());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn union_impl(sets: Vec<BTreeSet<u8>>) -> bool {
+        let expected: Vec<_> = sets
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        union_cloned(&sets) == expected && union(sets) == expected
+    }
+
+    #[quickcheck]
+    fn keys_union_impl(maps: Vec<BTreeMap<u8, u8>>) -> bool {
+        let expected: Vec<_> = maps
+            .iter()
+            .flat_map(BTreeMap::keys)
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        keys_union(&maps) == expected
+    }
+}
@@ -0,0 +1,274 @@
+//! Configuring a merge with several options at once.
+
+use std::cmp::{Ordering, Reverse};
+
+use crate::capacity::{self, Capacity};
+use crate::kway::{self, KMergeStable, KMergeUniq};
+use crate::memory::MergePlan;
+
+/// Which of several equal elements a deduplicating merge keeps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Keep {
+    /// The one from the earliest input.
+    #[default]
+    First,
+    /// The one from the latest input, as when later inputs hold newer versions of each element.
+    Last,
+}
+
+/// The order the inputs are sorted in, and the output is produced in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Direction {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Merges sorted vectors with a chosen set of options.
+///
+/// With the default options, this gives the same results as `crate::merge_uniq` and
+/// `kway::merge_uniq`, and merges ascending inputs with the same kernels. The merges in
+/// `parallel` only support the default options, so they aren't offered here.
+#[derive(Clone, Debug)]
+pub struct MergeBuilder {
+    dedup: bool,
+    keep: Keep,
+    direction: Direction,
+    capacity: Capacity,
+    validate: bool,
+}
+
+impl Default for MergeBuilder {
+    fn default() -> Self {
+        MergeBuilder::new()
+    }
+}
+
+impl MergeBuilder {
+    pub fn new() -> Self {
+        MergeBuilder {
+            dedup: true,
+            keep: Keep::First,
+            direction: Direction::Ascending,
+            capacity: Capacity::Sum,
+            validate: false,
+        }
+    }
+
+    /// Drops duplicates, so that each element appears once in the output. Defaults to `true`.
+    ///
+    /// Without deduplication, the inputs only need to be sorted, and equal elements are yielded in
+    /// the order of their inputs.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Sets which of several equal elements is kept when deduplicating. Defaults to `Keep::First`.
+    pub fn keep(mut self, keep: Keep) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Sets the order of the inputs and the output. Defaults to `Direction::Ascending`.
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Sets how much room is allocated for the output. Defaults to `Capacity::Sum`. Merges that
+    /// keep duplicates always allocate exactly as much as they need.
+    pub fn capacity(mut self, capacity: Capacity) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Checks that the inputs are sorted, and deduplicated when deduplicating, before merging.
+    /// Defaults to `false`, in which case inputs that aren't give unspecified (but memory-safe)
+    /// results.
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Merges two sorted vectors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if validation is on and an input is out of order.
+    pub fn merge2<T: Ord>(&self, a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        self.merge_k(vec![a, b])
+    }
+
+    /// Merges any number of sorted vectors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if validation is on and an input is out of order.
+    pub fn merge_k<T: Ord>(&self, mut runs: Vec<Vec<T>>) -> Vec<T> {
+        if self.validate {
+            self.check(&runs);
+        }
+        // Ties go to the earliest input, so reversing the inputs keeps the last of each.
+        if self.dedup && self.keep == Keep::Last {
+            runs.reverse();
+        }
+
+        let len = runs.iter().map(Vec::len).sum();
+        match (self.direction, self.dedup) {
+            (Direction::Ascending, true) => capacity::merge_k_uniq_with(self.capacity, runs),
+            (Direction::Ascending, false) => kway::merge_stable(runs),
+            (Direction::Descending, true) => {
+                let len = match self.capacity {
+                    Capacity::Exact => {
+                        KMergeUniq::new(runs.iter().map(|run| run.iter().map(Reverse)).collect())
+                            .count()
+                    }
+                    Capacity::Sum | Capacity::Shrink => len,
+                };
+                let mut out = Vec::with_capacity(len);
+                out.extend(KMergeUniq::new(reversed(runs)).map(|Reverse(x)| x));
+                if self.capacity == Capacity::Shrink {
+                    out.shrink_to_fit();
+                }
+                out
+            }
+            (Direction::Descending, false) => {
+                let mut out = Vec::with_capacity(len);
+                out.extend(KMergeStable::new(reversed(runs)).map(|Reverse(x)| x));
+                out
+            }
+        }
+    }
+
+    /// Plans a merge of runs of `T` with the given lengths with these options, for
+    /// `memory::estimate_peak_memory`.
+    pub fn plan<T>(&self, run_lens: &[usize]) -> MergePlan {
+        MergePlan::in_memory::<T>(run_lens, self.dedup && self.capacity == Capacity::Shrink)
+    }
+
+    /// Panics if one of `runs` is out of order for these options.
+    fn check<T: Ord>(&self, runs: &[Vec<T>]) {
+        for (input, run) in runs.iter().enumerate() {
+            let out_of_order = run.windows(2).position(|w| {
+                let ord = match self.direction {
+                    Direction::Ascending => w[0].cmp(&w[1]),
+                    Direction::Descending => w[1].cmp(&w[0]),
+                };
+                ord == Ordering::Greater || (self.dedup && ord == Ordering::Equal)
+            });
+            if let Some(i) = out_of_order {
+                panic!("input {} is out of order at index {}", input, i + 1);
+            }
+        }
+    }
+}
+
+/// Wraps the elements of `runs` in `Reverse`, so that descending runs can be merged as ascending.
+fn reversed<T>(runs: Vec<Vec<T>>) -> Vec<impl Iterator<Item = Reverse<T>>> {
+    runs.into_iter()
+        .map(|run| run.into_iter().map(Reverse))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    /// An element ordered by its value alone, remembering which input it came from.
+    #[derive(Clone, Copy, Debug)]
+    struct Tagged {
+        value: u8,
+        input: usize,
+    }
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Self) -> bool {
+            self.value == other.value
+        }
+    }
+
+    impl Eq for Tagged {}
+
+    impl PartialOrd for Tagged {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Tagged {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.value.cmp(&other.value)
+        }
+    }
+
+    #[quickcheck]
+    fn merge_k_impl(
+        runs: Vec<Vec<u8>>,
+        dedup: bool,
+        last: bool,
+        descending: bool,
+        capacity: u8,
+    ) -> bool {
+        let key = |x: &Tagged| if descending { 255 - x.value } else { x.value };
+        let runs: Vec<Vec<Tagged>> = runs
+            .into_iter()
+            .enumerate()
+            .map(|(input, run)| {
+                let mut run: Vec<_> = run
+                    .into_iter()
+                    .map(|value| Tagged { value, input })
+                    .collect();
+                run.sort_by_key(key);
+                if dedup {
+                    run.dedup();
+                }
+                run
+            })
+            .collect();
+
+        // A stable sort of the concatenated runs, keeping the first or last of each group.
+        let mut expected = runs.concat();
+        expected.sort_by_key(key);
+        if dedup && last {
+            expected.reverse();
+            expected.dedup();
+            expected.reverse();
+        } else if dedup {
+            expected.dedup();
+        }
+
+        let builder = MergeBuilder::new()
+            .dedup(dedup)
+            .keep(if last { Keep::Last } else { Keep::First })
+            .direction(if descending {
+                Direction::Descending
+            } else {
+                Direction::Ascending
+            })
+            .capacity([Capacity::Sum, Capacity::Exact, Capacity::Shrink][capacity as usize % 3])
+            .validate(true);
+        let actual = builder.merge_k(runs);
+
+        let tags = |v: &[Tagged]| v.iter().map(|x| (x.value, x.input)).collect::<Vec<_>>();
+        tags(&expected) == tags(&actual)
+    }
+
+    #[test]
+    #[should_panic(expected = "input 1 is out of order at index 2")]
+    fn validate_rejects_unsorted() {
+        MergeBuilder::new()
+            .validate(true)
+            .merge2(vec![1, 2, 3], vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn validate_allows_duplicates_without_dedup() {
+        let out = MergeBuilder::new()
+            .dedup(false)
+            .validate(true)
+            .merge2(vec![1, 1, 2], vec![1, 3]);
+        assert_eq!(out, [1, 1, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,60 @@
+//! Merging into vectors and slices allocated in a `bumpalo::Bump` arena.
+
+use bumpalo::collections::Vec as BumpVec;
+use bumpalo::Bump;
+
+/// Merges two sorted, deduplicated vectors into a new vector allocated in `bump`.
+pub fn merge_uniq_in<'bump, T: Ord>(a: Vec<T>, b: Vec<T>, bump: &'bump Bump) -> BumpVec<'bump, T> {
+    let mut out = BumpVec::with_capacity_in(a.len() + b.len(), bump);
+    merge_into(&mut out, a, b);
+    out
+}
+
+/// Merges two sorted, deduplicated vectors into a slice allocated in `bump`.
+///
+/// Like everything else allocated in a `Bump`, the elements of the returned slice are never
+/// dropped.
+pub fn merge_uniq_to_slice<T: Ord>(a: Vec<T>, b: Vec<T>, bump: &Bump) -> &[T] {
+    merge_uniq_in(a, b, bump).into_bump_slice()
+}
+
+#[cfg(not(feature = "force-safe"))]
+fn merge_into<T: Ord>(out: &mut BumpVec<'_, T>, a: Vec<T>, b: Vec<T>) {
+    use std::mem::MaybeUninit;
+
+    // SAFETY: `out` has room for `a.len() + b.len()` elements past its length, and
+    // `merge_uniq_into_uninit` initializes the first `len` of them.
+    unsafe {
+        let spare = std::slice::from_raw_parts_mut(
+            out.as_mut_ptr().add(out.len()) as *mut MaybeUninit<T>,
+            out.capacity() - out.len(),
+        );
+        let len = crate::merge_uniq_into_uninit(a, b, spare);
+        out.set_len(out.len() + len);
+    }
+}
+
+#[cfg(feature = "force-safe")]
+fn merge_into<T: Ord>(out: &mut BumpVec<'_, T>, a: Vec<T>, b: Vec<T>) {
+    out.extend(crate::merge_uniq(a, b));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn merge_uniq_in_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let bump = Bump::new();
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual = merge_uniq_to_slice(a, b, &bump);
+        expected == actual
+    }
+}
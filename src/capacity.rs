@@ -0,0 +1,86 @@
+//! Controlling how much memory a merge allocates for its output.
+
+use crate::kway::{self, KMergeUniq};
+
+/// How much room a merge allocates for its output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Capacity {
+    /// Room for every input element, as if none were duplicates. This costs nothing extra, but
+    /// wastes up to half of the allocation when the inputs overlap heavily.
+    #[default]
+    Sum,
+    /// Exactly as much room as the output needs, counted by a first pass that compares the inputs
+    /// without moving them. The merge then runs a simple linear loop instead of the tuned kernels;
+    /// see `merge2_uniq::exact`.
+    Exact,
+    /// Room for every input element during the merge, after which the output is shrunk to fit.
+    /// Shrinking may copy the output, and both allocations are alive while it does.
+    Shrink,
+}
+
+/// Like `crate::merge_uniq`, but allocates the output according to `capacity`.
+pub fn merge_uniq_with<T: Ord>(capacity: Capacity, a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    match capacity {
+        Capacity::Sum => crate::merge_uniq(a, b),
+        Capacity::Exact => crate::merge2_uniq::exact(a, b),
+        Capacity::Shrink => {
+            let mut out = crate::merge_uniq(a, b);
+            out.shrink_to_fit();
+            out
+        }
+    }
+}
+
+/// Like `kway::merge_uniq`, but allocates the output according to `capacity`.
+pub fn merge_k_uniq_with<T: Ord>(capacity: Capacity, runs: Vec<Vec<T>>) -> Vec<T> {
+    match capacity {
+        Capacity::Sum => kway::merge_uniq(runs),
+        Capacity::Exact => {
+            let len = KMergeUniq::new(runs.iter().map(|run| run.iter()).collect()).count();
+            let mut out = Vec::with_capacity(len);
+            out.extend(KMergeUniq::new(
+                runs.into_iter().map(Vec::into_iter).collect(),
+            ));
+            out
+        }
+        Capacity::Shrink => {
+            let mut out = kway::merge_uniq(runs);
+            out.shrink_to_fit();
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    const POLICIES: [Capacity; 3] = [Capacity::Sum, Capacity::Exact, Capacity::Shrink];
+
+    #[quickcheck]
+    fn merge_uniq_with_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+        let expected = naive(a.clone(), b.clone());
+
+        POLICIES.iter().all(|&capacity| {
+            let out = merge_uniq_with(capacity, a.clone(), b.clone());
+            let tight = capacity == Capacity::Sum || out.capacity() == out.len();
+            out == expected && tight
+        })
+    }
+
+    #[quickcheck]
+    fn merge_k_uniq_with_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        POLICIES.iter().all(|&capacity| {
+            let out = merge_k_uniq_with(capacity, runs.clone());
+            let tight = capacity == Capacity::Sum || out.capacity() == out.len();
+            out == expected && tight
+        })
+    }
+}
@@ -0,0 +1,220 @@
+//! Merges that check their inputs are sorted and deduplicated first.
+//!
+//! The other merges trust their callers, and produce unspecified (but memory-safe) results for
+//! inputs that break the invariant. With the `debug-validate` feature, they check it too in builds
+//! with debug assertions, and panic on inputs that break it.
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+
+/// The error returned by the checked merges for an input that isn't sorted and deduplicated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnsortedInput {
+    /// The position of the offending input among the inputs.
+    pub input: usize,
+    /// The index of the first element that isn't greater than the one before it.
+    pub index: usize,
+}
+
+impl fmt::Display for UnsortedInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "input {} is not sorted and deduplicated at index {}",
+            self.input, self.index
+        )
+    }
+}
+
+impl Error for UnsortedInput {}
+
+/// Why a run isn't sorted and deduplicated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunError {
+    /// The element at `index` is less than the one before it.
+    OutOfOrder { index: usize },
+    /// The element at `index` is equal to the one before it.
+    Duplicate { index: usize },
+}
+
+impl RunError {
+    /// Returns the index of the offending element.
+    pub fn index(&self) -> usize {
+        match *self {
+            RunError::OutOfOrder { index } | RunError::Duplicate { index } => index,
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::OutOfOrder { index } => write!(f, "element {} is out of order", index),
+            RunError::Duplicate { index } => write!(f, "element {} is a duplicate", index),
+        }
+    }
+}
+
+impl Error for RunError {}
+
+/// Checks that `run` is sorted and deduplicated, reporting the first element that isn't greater
+/// than the one before it.
+pub fn validate_run<T: Ord>(run: &[T]) -> Result<(), RunError> {
+    for (i, w) in run.windows(2).enumerate() {
+        match w[0].cmp(&w[1]) {
+            Ordering::Less => {}
+            Ordering::Equal => return Err(RunError::Duplicate { index: i + 1 }),
+            Ordering::Greater => return Err(RunError::OutOfOrder { index: i + 1 }),
+        }
+    }
+    Ok(())
+}
+
+/// Sorts and deduplicates `run`, keeping the first of several equal elements.
+///
+/// The run is checked first, and returned untouched if it's already sorted and deduplicated. A
+/// run that is sorted but has duplicates is only deduplicated, not sorted.
+pub fn repair_run<T: Ord>(mut run: Vec<T>) -> Vec<T> {
+    match validate_run(&run) {
+        Ok(()) => return run,
+        Err(RunError::Duplicate { index }) if run[index..].windows(2).all(|w| w[0] <= w[1]) => {}
+        Err(_) => run.sort(),
+    }
+    run.dedup();
+    run
+}
+
+/// Returns `true` if every element of `v` is greater than the one before it.
+pub fn is_sorted_unique<T: Ord>(v: &[T]) -> bool {
+    v.windows(2).all(|w| w[0] < w[1])
+}
+
+/// Checks that each of `inputs` is sorted and deduplicated.
+pub fn validate<T: Ord>(inputs: &[&[T]]) -> Result<(), UnsortedInput> {
+    for (input, v) in inputs.iter().enumerate() {
+        if let Some(i) = v.windows(2).position(|w| w[0] >= w[1]) {
+            return Err(UnsortedInput {
+                input,
+                index: i + 1,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Panics if any of `inputs` isn't sorted and deduplicated, when the `debug-validate` feature and
+/// debug assertions are both enabled. Does nothing otherwise.
+#[inline]
+pub(crate) fn debug_validate<T: Ord>(inputs: &[&[T]]) {
+    #[cfg(all(feature = "debug-validate", debug_assertions))]
+    {
+        #[cfg(feature = "instrument")]
+        let checked = crate::instrument::unrecorded(|| validate(inputs));
+        #[cfg(not(feature = "instrument"))]
+        let checked = validate(inputs);
+        if let Err(e) = checked {
+            panic!("{}", e);
+        }
+    }
+
+    #[cfg(not(all(feature = "debug-validate", debug_assertions)))]
+    let _ = inputs;
+}
+
+/// Like `crate::merge_uniq`, but returns an error if either input isn't sorted and deduplicated.
+pub fn checked_merge_uniq<T: Ord>(a: Vec<T>, b: Vec<T>) -> Result<Vec<T>, UnsortedInput> {
+    validate(&[&a, &b])?;
+    Ok(crate::merge_uniq(a, b))
+}
+
+/// Like `kway::merge_uniq`, but returns an error if any input isn't sorted and deduplicated.
+pub fn checked_kmerge_uniq<T: Ord>(runs: Vec<Vec<T>>) -> Result<Vec<T>, UnsortedInput> {
+    let slices: Vec<&[T]> = runs.iter().map(|run| &run[..]).collect();
+    validate(&slices)?;
+    Ok(crate::kway::merge_uniq(runs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    fn first_unsorted(v: &[u8]) -> Option<usize> {
+        (1..v.len()).find(|&i| v[i - 1] >= v[i])
+    }
+
+    #[quickcheck]
+    fn checked_merge_uniq_impl(a: Vec<u8>, b: Vec<u8>, sort: bool) -> bool {
+        let (a, b) = if sort {
+            (sorted_unique(a), sorted_unique(b))
+        } else {
+            (a, b)
+        };
+
+        let expected = match (first_unsorted(&a), first_unsorted(&b)) {
+            (Some(index), _) => Err(UnsortedInput { input: 0, index }),
+            (None, Some(index)) => Err(UnsortedInput { input: 1, index }),
+            (None, None) => Ok(sorted_unique([&a[..], &b].concat())),
+        };
+        checked_merge_uniq(a, b) == expected
+    }
+
+    #[quickcheck]
+    fn checked_kmerge_uniq_impl(runs: Vec<Vec<u8>>) -> bool {
+        let expected = runs
+            .iter()
+            .enumerate()
+            .find_map(|(input, run)| {
+                first_unsorted(run).map(|index| UnsortedInput { input, index })
+            })
+            .map_or_else(|| Ok(sorted_unique(runs.concat())), Err);
+        checked_kmerge_uniq(runs) == expected
+    }
+
+    #[quickcheck]
+    fn validate_run_impl(run: Vec<u8>, sort: bool) -> bool {
+        let run = if sort { sorted_unique(run) } else { run };
+        let expected = match first_unsorted(&run) {
+            Some(index) if run[index - 1] == run[index] => Err(RunError::Duplicate { index }),
+            Some(index) => Err(RunError::OutOfOrder { index }),
+            None => Ok(()),
+        };
+        validate_run(&run) == expected
+    }
+
+    #[quickcheck]
+    fn repair_run_impl(run: Vec<u8>, sort: bool) -> bool {
+        use crate::kway::ByKey;
+
+        // Sorted runs with duplicates are only deduplicated, so they get a test of their own.
+        let run = match sort {
+            true => sorted_unique(run).into_iter().map(|x| x / 4).collect(),
+            false => run,
+        };
+        let mut expected: Vec<_> = run
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(i, x)| (x, i))
+            .collect();
+        expected.sort();
+        expected.dedup_by_key(|&mut (x, _)| x);
+
+        // Elements are tagged with their position, to check that the first of equal ones is kept.
+        let tagged = run
+            .iter()
+            .enumerate()
+            .map(|(item, &key)| ByKey { key, item });
+        let repaired = repair_run(tagged.collect());
+        repaired.iter().map(|x| (x.key, x.item)).eq(expected)
+    }
+
+    #[cfg(all(feature = "debug-validate", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "input 1 is not sorted and deduplicated at index 2")]
+    fn debug_validate_panics() {
+        crate::merge_uniq(vec![1, 2, 3], vec![1, 4, 4]);
+    }
+}
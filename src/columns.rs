@@ -0,0 +1,223 @@
+//! Merging key/value data stored as parallel arrays of keys and values.
+
+use std::ops::Range;
+
+use crate::kway::LoserTree;
+
+/// Merges two sets of parallel arrays, each with sorted, deduplicated keys, into one.
+///
+/// Each value moves along with the key at the same index, and when both inputs hold a key, the
+/// value from `a` is kept and the one from `b` is dropped. This avoids zipping columnar data into
+/// `Vec<(K, V)>` just to merge it.
+///
+/// # Panics
+///
+/// Panics if an input has a different number of keys and values.
+pub fn merge_uniq_columns<K: Ord, V>(
+    (keys_a, vals_a): (Vec<K>, Vec<V>),
+    (keys_b, vals_b): (Vec<K>, Vec<V>),
+) -> (Vec<K>, Vec<V>) {
+    assert_eq!(keys_a.len(), vals_a.len(), "`a` has mismatched columns");
+    assert_eq!(keys_b.len(), vals_b.len(), "`b` has mismatched columns");
+
+    let len = crate::merge2_uniq::merged_len(keys_a.len(), keys_b.len());
+    let mut keys = Vec::with_capacity(len);
+    let mut vals = Vec::with_capacity(len);
+    let mut a = keys_a.into_iter().zip(vals_a).peekable();
+    let mut b = keys_b.into_iter().zip(vals_b).peekable();
+
+    loop {
+        let (k, v) = match (a.peek(), b.peek()) {
+            (Some((x, _)), Some((y, _))) if y < x => b.next().unwrap(),
+            (Some((x, _)), Some((y, _))) if x == y => {
+                b.next();
+                a.next().unwrap()
+            }
+            (Some(_), _) => a.next().unwrap(),
+            (None, Some(_)) => b.next().unwrap(),
+            (None, None) => break,
+        };
+        keys.push(k);
+        vals.push(v);
+    }
+
+    (keys, vals)
+}
+
+/// The layout of the value columns of a `ColumnarRun`: the size in bytes of one element of each
+/// column.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Schema {
+    widths: Vec<usize>,
+}
+
+impl Schema {
+    pub fn new(widths: Vec<usize>) -> Self {
+        Schema { widths }
+    }
+
+    pub fn widths(&self) -> &[usize] {
+        &self.widths
+    }
+}
+
+/// A sorted run of rows, stored as a column of sorted, deduplicated keys and any number of value
+/// columns of fixed-size elements.
+///
+/// Row `i` is made up of `keys[i]` and bytes `i * width..(i + 1) * width` of each value column,
+/// where `width` is that column's element size in the `Schema`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ColumnarRun<K> {
+    pub keys: Vec<K>,
+    pub columns: Vec<Vec<u8>>,
+}
+
+/// Merges any number of columnar runs laid out as `schema` describes into one.
+///
+/// Rows are ordered by key alone, so only the key column is compared. When several runs hold a
+/// key, the row from the earliest run is kept. Consecutive rows of the output that come from the
+/// same run are copied with one move per column.
+///
+/// # Panics
+///
+/// Panics if a run doesn't have one column per width in `schema`, or if a column's length isn't
+/// its width times the number of keys.
+pub fn merge_k_uniq_columns<K: Ord>(schema: &Schema, runs: Vec<ColumnarRun<K>>) -> ColumnarRun<K> {
+    let mut len = 0usize;
+    for (i, run) in runs.iter().enumerate() {
+        assert_eq!(
+            run.columns.len(),
+            schema.widths.len(),
+            "run {} has the wrong number of columns",
+            i
+        );
+        for (column, &width) in run.columns.iter().zip(&schema.widths) {
+            assert!(
+                run.keys.len().checked_mul(width) == Some(column.len()),
+                "run {} has mismatched columns",
+                i
+            );
+        }
+        len = crate::merge2_uniq::merged_len(len, run.keys.len());
+    }
+
+    let mut out = ColumnarRun {
+        keys: Vec::with_capacity(len),
+        columns: schema
+            .widths
+            .iter()
+            .map(|w| Vec::with_capacity(len.checked_mul(*w).expect("capacity overflow")))
+            .collect(),
+    };
+    let (keys, columns): (Vec<_>, Vec<_>) = runs
+        .into_iter()
+        .map(|run| (run.keys.into_iter(), run.columns))
+        .unzip();
+    let mut next_row = vec![0; keys.len()];
+    let mut tree = LoserTree::new(keys);
+
+    // Rows waiting to be copied: a range of rows of one run.
+    let mut pending: Option<(usize, Range<usize>)> = None;
+    let flush = |out: &mut ColumnarRun<K>, pending: Option<(usize, Range<usize>)>| {
+        if let Some((run, rows)) = pending {
+            let parts = out
+                .columns
+                .iter_mut()
+                .zip(&columns[run])
+                .zip(&schema.widths);
+            for ((dst, src), width) in parts {
+                dst.extend_from_slice(&src[rows.start * width..rows.end * width]);
+            }
+        }
+    };
+
+    while let Some((run, key)) = tree.pop() {
+        let row = next_row[run];
+        next_row[run] += 1;
+        if out.keys.last() == Some(&key) {
+            continue;
+        }
+        out.keys.push(key);
+
+        match &mut pending {
+            Some((r, rows)) if *r == run && rows.end == row => rows.end += 1,
+            _ => flush(&mut out, pending.replace((run, row..row + 1))),
+        }
+    }
+    flush(&mut out, pending);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeMap;
+
+    fn columns(map: &BTreeMap<u8, u16>) -> (Vec<u8>, Vec<u16>) {
+        map.iter().map(|(&k, &v)| (k, v)).unzip()
+    }
+
+    #[quickcheck]
+    fn merge_uniq_columns_impl(a: BTreeMap<u8, u16>, b: BTreeMap<u8, u16>) -> bool {
+        let mut expected = b.clone();
+        expected.extend(a.iter().map(|(&k, &v)| (k, v)));
+
+        merge_uniq_columns(columns(&a), columns(&b)) == columns(&expected)
+    }
+
+    #[quickcheck]
+    fn merge_k_uniq_columns_impl(maps: Vec<BTreeMap<u8, u16>>) -> bool {
+        // Two value columns: the value itself, and the index of the run it came from.
+        let schema = Schema::new(vec![2, 1]);
+        let runs = maps
+            .iter()
+            .enumerate()
+            .map(|(i, map)| ColumnarRun {
+                keys: map.keys().copied().collect(),
+                columns: vec![
+                    map.values().flat_map(|v| v.to_le_bytes()).collect(),
+                    vec![i as u8; map.len()],
+                ],
+            })
+            .collect();
+
+        let mut expected = BTreeMap::new();
+        for (i, map) in maps.iter().enumerate().rev() {
+            expected.extend(map.iter().map(|(&k, &v)| (k, (v, i as u8))));
+        }
+        let expected = ColumnarRun {
+            keys: expected.keys().copied().collect(),
+            columns: vec![
+                expected
+                    .values()
+                    .flat_map(|(v, _)| v.to_le_bytes())
+                    .collect(),
+                expected.values().map(|&(_, i)| i).collect(),
+            ],
+        };
+
+        merge_k_uniq_columns(&schema, runs) == expected
+    }
+
+    #[test]
+    #[should_panic(expected = "run 1 has mismatched columns")]
+    fn mismatched_run() {
+        let run = |keys: Vec<u8>, column: Vec<u8>| ColumnarRun {
+            keys,
+            columns: vec![column],
+        };
+        let schema = Schema::new(vec![2]);
+        merge_k_uniq_columns(
+            &schema,
+            vec![run(vec![1], vec![0, 0]), run(vec![1], vec![0])],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "`b` has mismatched columns")]
+    fn mismatched_columns() {
+        merge_uniq_columns((vec![1], vec!['a']), (vec![1, 2], vec!['b']));
+    }
+}
@@ -0,0 +1,97 @@
+//! Compacting sorted runs of versioned entries, as the levels of a log-structured merge tree are.
+
+use std::cmp::Reverse;
+
+use crate::kway::{ByKey, LoserTree};
+
+/// What an entry does to its key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Op<V> {
+    /// Sets the key to a value.
+    Put(V),
+    /// Deletes the key. The tombstone shadows older entries for the key in runs that aren't part of
+    /// the compaction.
+    Delete,
+}
+
+/// Merges runs of `(key, version, op)` entries into one, keeping only the newest version of each
+/// key.
+///
+/// Each run must be sorted by key, and then by version from newest to oldest. If the newest entry
+/// for a key is a tombstone older than `horizon`, it is dropped too, so `horizon` should only pass
+/// a tombstone once no reader needs the versions it hides and no run outside the compaction holds
+/// older entries for its key. Of entries with the same key and version, the one from the earliest
+/// run is kept.
+pub fn compact<K, Ver, V>(runs: Vec<Vec<(K, Ver, Op<V>)>>, horizon: Ver) -> Vec<(K, Ver, Op<V>)>
+where
+    K: Ord,
+    Ver: Ord,
+{
+    let len = runs.iter().map(Vec::len).sum();
+    let sources = runs
+        .into_iter()
+        .map(|run| {
+            run.into_iter().map(|(key, version, op)| ByKey {
+                key: (key, Reverse(version)),
+                item: op,
+            })
+        })
+        .collect();
+    let mut tree = LoserTree::new(sources);
+
+    let mut out = Vec::with_capacity(len);
+    while let Some(ByKey {
+        key: (key, Reverse(version)),
+        item: op,
+    }) = tree.next()
+    {
+        // Older versions of the key come right after the newest one.
+        while tree.peek().is_some_and(|next| next.key.0 == key) {
+            tree.next();
+        }
+        if matches!(op, Op::Delete) && version < horizon {
+            continue;
+        }
+        out.push((key, version, op));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeMap;
+
+    fn op(value: Option<u16>) -> Op<u16> {
+        value.map_or(Op::Delete, Op::Put)
+    }
+
+    #[quickcheck]
+    fn compact_impl(runs: Vec<Vec<(u8, u8, Option<u16>)>>, horizon: u8) -> bool {
+        let runs: Vec<Vec<_>> = runs
+            .into_iter()
+            .map(|run| {
+                let mut run: Vec<_> = run.into_iter().map(|(k, v, x)| (k, v, op(x))).collect();
+                run.sort_by_key(|&(k, v, _)| (k, Reverse(v)));
+                run.dedup_by_key(|&mut (k, v, _)| (k, v));
+                run
+            })
+            .collect();
+
+        // The newest entry for each key, from the earliest run among equal versions.
+        let mut newest: BTreeMap<u8, (u8, Op<u16>)> = BTreeMap::new();
+        for &(k, v, x) in runs.iter().flatten() {
+            if newest.get(&k).is_none_or(|&(newer, _)| v > newer) {
+                newest.insert(k, (v, x));
+            }
+        }
+        let expected: Vec<_> = newest
+            .into_iter()
+            .filter(|&(_, (v, x))| x != Op::Delete || v >= horizon)
+            .map(|(k, (v, x))| (k, v, x))
+            .collect();
+
+        compact(runs, horizon) == expected
+    }
+}
@@ -0,0 +1,310 @@
+//! Merge adapters with the same signatures as their `itertools` counterparts.
+//!
+//! Code that merges with `itertools` can switch to this crate's loser tree by changing an import:
+//! `use itertools::{kmerge, kmerge_by, merge};` becomes `use kmerge::compat::{kmerge, kmerge_by,
+//! merge};`, and the `kmerge`, `kmerge_by`, `merge` and `merge_by` methods of `Itertools` are
+//! provided by `MergeAdapters`. The k-way merges always yield equal items in the order of their
+//! inputs, which `itertools` doesn't promise.
+
+use std::iter::{FusedIterator, Peekable};
+
+use crate::kway::{Less, LoserTree};
+
+/// The order of a k-way merge, as a strict "less than" predicate. Implemented for closures and
+/// for `KMergeByLt`.
+pub trait KMergePredicate<T> {
+    fn kmerge_pred(&mut self, a: &T, b: &T) -> bool;
+}
+
+impl<T, F: FnMut(&T, &T) -> bool> KMergePredicate<T> for F {
+    fn kmerge_pred(&mut self, a: &T, b: &T) -> bool {
+        self(a, b)
+    }
+}
+
+/// Orders the items of `KMerge` by their `PartialOrd` implementation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KMergeByLt;
+
+impl<T: PartialOrd> KMergePredicate<T> for KMergeByLt {
+    fn kmerge_pred(&mut self, a: &T, b: &T) -> bool {
+        a < b
+    }
+}
+
+/// Adapts a `KMergePredicate` to the order of a `LoserTree`.
+#[derive(Clone)]
+struct Pred<F>(F);
+
+impl<T, F: KMergePredicate<T>> Less<T> for Pred<F> {
+    #[inline]
+    fn less(&mut self, a: &T, b: &T) -> bool {
+        self.0.kmerge_pred(a, b)
+    }
+}
+
+/// An iterator over the merged contents of several iterators, each sorted by a predicate, keeping
+/// duplicates. Returned by `kmerge_by`.
+pub struct KMergeBy<I: Iterator, F> {
+    tree: LoserTree<I, Pred<F>>,
+}
+
+/// An iterator over the merged contents of several sorted iterators. Returned by `kmerge`.
+pub type KMerge<I> = KMergeBy<I, KMergeByLt>;
+
+impl<I, F> Iterator for KMergeBy<I, F>
+where
+    I: Iterator,
+    F: KMergePredicate<I::Item>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tree.pop().map(|(_, item)| item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.tree.size_hint()
+    }
+}
+
+impl<I, F> FusedIterator for KMergeBy<I, F>
+where
+    I: Iterator,
+    F: KMergePredicate<I::Item>,
+{
+}
+
+impl<I, F> Clone for KMergeBy<I, F>
+where
+    I: Iterator + Clone,
+    I::Item: Clone,
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        KMergeBy {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+/// Merges the sorted iterators yielded by `iterable`, like `itertools::kmerge`.
+pub fn kmerge<I>(iterable: I) -> KMerge<<I::Item as IntoIterator>::IntoIter>
+where
+    I: IntoIterator,
+    I::Item: IntoIterator,
+    <I::Item as IntoIterator>::Item: PartialOrd,
+{
+    kmerge_by(iterable, KMergeByLt)
+}
+
+/// Merges the iterators yielded by `iterable`, each sorted so that `first(a, b)` holds when `a`
+/// comes before `b`, like `itertools::kmerge_by`.
+pub fn kmerge_by<I, F>(iterable: I, first: F) -> KMergeBy<<I::Item as IntoIterator>::IntoIter, F>
+where
+    I: IntoIterator,
+    I::Item: IntoIterator,
+    F: KMergePredicate<<I::Item as IntoIterator>::Item>,
+{
+    let sources = iterable.into_iter().map(IntoIterator::into_iter).collect();
+    KMergeBy {
+        tree: LoserTree::with_less(sources, Pred(first)),
+    }
+}
+
+/// The order of a two-way merge, as a predicate that holds when its first argument should be
+/// yielded first. Implemented for closures and for `MergeLte`.
+pub trait MergePredicate<T> {
+    fn merge_pred(&mut self, a: &T, b: &T) -> bool;
+}
+
+impl<T, F: FnMut(&T, &T) -> bool> MergePredicate<T> for F {
+    fn merge_pred(&mut self, a: &T, b: &T) -> bool {
+        self(a, b)
+    }
+}
+
+/// Orders the items of `Merge` by their `PartialOrd` implementation, taking from the first input
+/// on ties.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MergeLte;
+
+impl<T: PartialOrd> MergePredicate<T> for MergeLte {
+    fn merge_pred(&mut self, a: &T, b: &T) -> bool {
+        a <= b
+    }
+}
+
+/// An iterator over the merged contents of two iterators sorted by a predicate, keeping
+/// duplicates. Returned by `MergeAdapters::merge_by`.
+pub struct MergeBy<I: Iterator, J: Iterator, F> {
+    a: Peekable<I>,
+    b: Peekable<J>,
+    first: F,
+}
+
+impl<I, J, F> Clone for MergeBy<I, J, F>
+where
+    I: Iterator + Clone,
+    J: Iterator + Clone,
+    I::Item: Clone,
+    J::Item: Clone,
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        MergeBy {
+            a: self.a.clone(),
+            b: self.b.clone(),
+            first: self.first.clone(),
+        }
+    }
+}
+
+/// An iterator over the merged contents of two sorted iterators. Returned by `merge`.
+pub type Merge<I, J> = MergeBy<I, J, MergeLte>;
+
+impl<I, J, F> Iterator for MergeBy<I, J, F>
+where
+    I: Iterator,
+    J: Iterator<Item = I::Item>,
+    F: MergePredicate<I::Item>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.a.peek(), self.b.peek()) {
+            (Some(x), Some(y)) if !self.first.merge_pred(x, y) => self.b.next(),
+            (Some(_), _) => self.a.next(),
+            (None, _) => self.b.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a_lo, a_hi) = self.a.size_hint();
+        let (b_lo, b_hi) = self.b.size_hint();
+        let hi = match (a_hi, b_hi) {
+            (Some(a_hi), Some(b_hi)) => a_hi.checked_add(b_hi),
+            _ => None,
+        };
+        (a_lo.saturating_add(b_lo), hi)
+    }
+}
+
+/// Merges two sorted iterators, taking from `a` on ties, like `itertools::merge`.
+pub fn merge<I, J>(a: I, b: J) -> Merge<I::IntoIter, J::IntoIter>
+where
+    I: IntoIterator,
+    J: IntoIterator<Item = I::Item>,
+    I::Item: PartialOrd,
+{
+    MergeBy {
+        a: a.into_iter().peekable(),
+        b: b.into_iter().peekable(),
+        first: MergeLte,
+    }
+}
+
+/// The merge methods of `itertools::Itertools`, for every iterator.
+pub trait MergeAdapters: Iterator + Sized {
+    /// See `kmerge`.
+    fn kmerge(self) -> KMerge<<Self::Item as IntoIterator>::IntoIter>
+    where
+        Self::Item: IntoIterator,
+        <Self::Item as IntoIterator>::Item: PartialOrd,
+    {
+        kmerge(self)
+    }
+
+    /// See `kmerge_by`.
+    fn kmerge_by<F>(self, first: F) -> KMergeBy<<Self::Item as IntoIterator>::IntoIter, F>
+    where
+        Self::Item: IntoIterator,
+        F: FnMut(&<Self::Item as IntoIterator>::Item, &<Self::Item as IntoIterator>::Item) -> bool,
+    {
+        kmerge_by(self, first)
+    }
+
+    /// See `merge`.
+    fn merge<J>(self, other: J) -> Merge<Self, J::IntoIter>
+    where
+        J: IntoIterator<Item = Self::Item>,
+        Self::Item: PartialOrd,
+    {
+        merge(self, other)
+    }
+
+    /// Merges `self` with `other`, taking the head of `self` whenever `first` holds for the two
+    /// heads, like `Itertools::merge_by`.
+    fn merge_by<J, F>(self, other: J, first: F) -> MergeBy<Self, J::IntoIter, F>
+    where
+        J: IntoIterator<Item = Self::Item>,
+        F: FnMut(&Self::Item, &Self::Item) -> bool,
+    {
+        MergeBy {
+            a: self.peekable(),
+            b: other.into_iter().peekable(),
+            first,
+        }
+    }
+}
+
+impl<I: Iterator> MergeAdapters for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn sorted(runs: Vec<Vec<(u8, u8)>>) -> Vec<Vec<(u8, u8)>> {
+        runs.into_iter()
+            .map(|mut run| {
+                run.sort_by_key(|&(key, _)| key);
+                run
+            })
+            .collect()
+    }
+
+    #[quickcheck]
+    fn kmerge_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs
+            .into_iter()
+            .map(|mut run| {
+                run.sort_unstable();
+                run
+            })
+            .collect();
+        let mut expected = runs.concat();
+        expected.sort_unstable();
+
+        let merged = kmerge(runs.clone());
+        merged.size_hint() == (expected.len(), Some(expected.len()))
+            && merged.eq(expected.iter().copied())
+            && runs.into_iter().kmerge().eq(expected)
+    }
+
+    #[quickcheck]
+    fn kmerge_by_impl(runs: Vec<Vec<(u8, u8)>>) -> bool {
+        // Sorted by key alone, so equal keys must come out in the order of their runs.
+        let runs = sorted(runs);
+        let mut expected = runs.concat();
+        expected.sort_by_key(|&(key, _)| key);
+
+        let first = |a: &(u8, u8), b: &(u8, u8)| a.0 < b.0;
+        kmerge_by(runs.clone(), first).eq(expected.iter().copied())
+            && runs.into_iter().kmerge_by(first).eq(expected)
+    }
+
+    #[quickcheck]
+    fn merge_impl(a: Vec<(u8, u8)>, b: Vec<(u8, u8)>) -> bool {
+        let mut runs = sorted(vec![a, b]).into_iter();
+        let (a, b) = (runs.next().unwrap(), runs.next().unwrap());
+        let mut expected = [&a[..], &b].concat();
+        expected.sort_by_key(|&(key, _)| key);
+
+        let keys = |v: &[(u8, u8)]| v.iter().map(|&(key, _)| key).collect::<Vec<_>>();
+        let expected_keys = keys(&expected);
+
+        let first = |x: &(u8, u8), y: &(u8, u8)| x.0 <= y.0;
+        merge(keys(&a), keys(&b)).eq(expected_keys) && a.into_iter().merge_by(b, first).eq(expected)
+    }
+}
@@ -0,0 +1,164 @@
+//! Random access into the merged, deduplicated view of a set of sorted runs, without merging them.
+
+use std::borrow::Borrow;
+
+use crate::gallop::{gallop, gallop_back};
+
+/// A cursor over the merged, deduplicated contents of several sorted, deduplicated runs.
+///
+/// The cursor sits in a gap between two elements of the merged view, starting before the first.
+/// `next` and `prev` step over the element after or before the gap, and `seek` moves it in front of
+/// any key, which makes range scans from arbitrary keys cheap. Of several equal elements, the one
+/// from the earliest run is returned.
+///
+/// Each step looks at the next element of every run, so it takes `O(k)` comparisons for `k` runs.
+/// A seek gallops from the current position in each run, so short seeks are cheap too.
+#[derive(Clone, Debug)]
+pub struct RunSetCursor<'a, T> {
+    runs: Vec<&'a [T]>,
+    /// The index in each run of its first element after the gap.
+    pos: Vec<usize>,
+}
+
+impl<'a, T: Ord> RunSetCursor<'a, T> {
+    /// Creates a cursor before the first element of the merged view of `runs`.
+    pub fn new(runs: Vec<&'a [T]>) -> Self {
+        crate::checked::debug_validate(&runs);
+        let pos = vec![0; runs.len()];
+        RunSetCursor { runs, pos }
+    }
+
+    /// Moves the cursor to just before the first element that isn't less than `key`.
+    pub fn seek<Q>(&mut self, key: &Q)
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        for (run, pos) in self.runs.iter().zip(&mut self.pos) {
+            if run.get(*pos).is_some_and(|x| x.borrow() < key) {
+                *pos += gallop(&run[*pos..], |x| x.borrow() < key);
+            } else {
+                *pos -= gallop_back(&run[..*pos], |x| x.borrow() >= key);
+            }
+        }
+    }
+
+    /// Returns the element after the cursor without moving it.
+    pub fn peek_next(&self) -> Option<&'a T> {
+        let mut min: Option<&'a T> = None;
+        for (run, &pos) in self.runs.iter().zip(&self.pos) {
+            if let Some(x) = run.get(pos) {
+                if min.is_none_or(|m| x < m) {
+                    min = Some(x);
+                }
+            }
+        }
+        min
+    }
+
+    /// Returns the element before the cursor without moving it.
+    pub fn peek_prev(&self) -> Option<&'a T> {
+        let mut max: Option<&'a T> = None;
+        for (run, &pos) in self.runs.iter().zip(&self.pos) {
+            if let Some(x) = pos.checked_sub(1).map(|i| &run[i]) {
+                if max.is_none_or(|m| x > m) {
+                    max = Some(x);
+                }
+            }
+        }
+        max
+    }
+
+    /// Moves the cursor back over the element before it, and returns that element.
+    pub fn prev(&mut self) -> Option<&'a T> {
+        let max = self.peek_prev()?;
+        for (run, pos) in self.runs.iter().zip(&mut self.pos) {
+            if *pos > 0 && run[*pos - 1] == *max {
+                *pos -= 1;
+            }
+        }
+        Some(max)
+    }
+}
+
+impl<'a, T: Ord> Iterator for RunSetCursor<'a, T> {
+    type Item = &'a T;
+
+    /// Moves the cursor forward over the element after it, and returns that element.
+    fn next(&mut self) -> Option<&'a T> {
+        let min = self.peek_next()?;
+        for (run, pos) in self.runs.iter().zip(&mut self.pos) {
+            if run.get(*pos) == Some(min) {
+                *pos += 1;
+            }
+        }
+        Some(min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kway::ByKey;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn run_set_cursor_impl(runs: Vec<Vec<u8>>, ops: Vec<(u8, u8)>) -> bool {
+        // Each element is tagged with its run, to check that ties go to the earliest one.
+        let runs: Vec<Vec<_>> = runs
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut run)| {
+                run.sort_unstable();
+                run.dedup();
+                run.into_iter().map(|key| ByKey { key, item: i }).collect()
+            })
+            .collect();
+
+        // The merged view, and the index of the element after the cursor in it.
+        let mut merged: Vec<_> = runs.iter().flatten().map(|x| (x.key, x.item)).collect();
+        merged.sort_by_key(|&(key, _)| key);
+        merged.dedup_by_key(|&mut (key, _)| key);
+        let mut gap = 0;
+
+        let mut cursor = RunSetCursor::new(runs.iter().map(Vec::as_slice).collect());
+        let untag = |x: Option<&ByKey<u8, usize>>| x.map(|x| (x.key, x.item));
+        for (op, key) in ops {
+            let (actual, expected) = match op % 3 {
+                0 => {
+                    cursor.seek(&ByKey { key, item: 0 });
+                    gap = merged.partition_point(|&(k, _)| k < key);
+                    (untag(cursor.peek_next()), merged.get(gap).copied())
+                }
+                1 => {
+                    let expected = merged.get(gap).copied();
+                    gap += usize::from(expected.is_some());
+                    (untag(cursor.next()), expected)
+                }
+                _ => {
+                    let expected = gap.checked_sub(1).map(|i| merged[i]);
+                    gap -= usize::from(expected.is_some());
+                    (untag(cursor.prev()), expected)
+                }
+            };
+            let prev = gap.checked_sub(1).map(|i| merged[i]);
+            if actual != expected || untag(cursor.peek_prev()) != prev {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[test]
+    fn range_scan() {
+        let (a, b, c) = ([1, 4, 7, 10], [2, 4, 8], [3, 9, 10, 12]);
+        let mut cursor = RunSetCursor::new(vec![&a[..], &b, &c]);
+        cursor.seek(&4);
+        let scan: Vec<_> = cursor.by_ref().take_while(|&&x| x < 10).collect();
+        assert_eq!(scan, [&4, &7, &8, &9]);
+        assert_eq!(cursor.prev(), Some(&10));
+        cursor.seek(&0);
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.next(), Some(&1));
+    }
+}
@@ -0,0 +1,150 @@
+//! Set operations on dense sets of `u32`s, through bitsets where that is faster.
+//!
+//! A bitset over the range of the inputs costs one bit per value in the range, whether it is in a
+//! set or not, so it only pays off when the sets cover much of their range. Intersecting through a
+//! bitset of `b` beats `set_ops::intersection` down to densities of about 1/256, and taking the
+//! union through a bitset beats the scalar merge down to about 1/64. The vectorized kernels in
+//! `simd` keep up with the bitset at every density, so the union only uses it when the CPU has
+//! none.
+
+use crate::simd::{self, Backend};
+
+/// Returns the number of 64-bit words in a bitset over `lo..=hi`.
+fn words(lo: u32, hi: u32) -> usize {
+    ((hi - lo) as usize >> 6) + 1
+}
+
+/// A set of values in `lo..lo + 64 * words.len()`.
+struct Bitset {
+    lo: u32,
+    words: Vec<u64>,
+}
+
+impl Bitset {
+    /// Creates a bitset over `lo..=hi` holding every element of `sets`, all of which must lie in
+    /// that range.
+    fn new(lo: u32, hi: u32, sets: &[&[u32]]) -> Self {
+        let mut words = vec![0u64; self::words(lo, hi)];
+        for &x in sets.iter().copied().flatten() {
+            let i = (x - lo) as usize;
+            words[i >> 6] |= 1 << (i & 63);
+        }
+        Bitset { lo, words }
+    }
+
+    fn contains(&self, x: u32) -> bool {
+        let i = match x.checked_sub(self.lo) {
+            Some(i) => i as usize,
+            None => return false,
+        };
+        self.words
+            .get(i >> 6)
+            .is_some_and(|word| word >> (i & 63) & 1 != 0)
+    }
+
+    fn into_vec(self) -> Vec<u32> {
+        let len = self.words.iter().map(|w| w.count_ones() as usize).sum();
+        let mut out = Vec::with_capacity(len);
+        for (i, &word) in self.words.iter().enumerate() {
+            let base = self.lo + 64 * i as u32;
+            let mut word = word;
+            while word != 0 {
+                out.push(base + word.trailing_zeros());
+                word &= word - 1;
+            }
+        }
+        out
+    }
+}
+
+/// Merges two sorted, deduplicated vectors of `u32`s.
+///
+/// When the CPU has no vector kernels and the bitset over the range of the inputs would have at
+/// most one word for every four elements, this goes through the bitset. Otherwise, it uses
+/// `simd::merge_uniq`.
+pub fn merge_uniq(a: Vec<u32>, b: Vec<u32>) -> Vec<u32> {
+    crate::checked::debug_validate(&[&a, &b]);
+    let (lo, hi) = match (a.first(), a.last(), b.first(), b.last()) {
+        (Some(&af), Some(&al), Some(&bf), Some(&bl)) => (af.min(bf), al.max(bl)),
+        _ if a.is_empty() => return b,
+        _ => return a,
+    };
+
+    if Backend::detect() == Backend::Scalar && words(lo, hi) <= (a.len() + b.len()) / 4 {
+        return Bitset::new(lo, hi, &[&a, &b]).into_vec();
+    }
+    simd::merge_uniq(a, b)
+}
+
+/// Keeps the elements of `a` that are also in `b`, where both are sorted and deduplicated.
+///
+/// When the bitset over the range of `b` would have at most two words for every element of `b`,
+/// each element of `a` is looked up in it. Otherwise, this uses `set_ops::intersection`.
+pub fn intersection(mut a: Vec<u32>, b: &[u32]) -> Vec<u32> {
+    crate::checked::debug_validate(&[&a, b]);
+    let (lo, hi) = match (b.first(), b.last()) {
+        (Some(&lo), Some(&hi)) => (lo, hi),
+        _ => return Vec::new(),
+    };
+
+    if words(lo, hi) <= 2 * b.len() {
+        let set = Bitset::new(lo, hi, &[b]);
+        a.retain(|&x| set.contains(x));
+        return a;
+    }
+    crate::set_ops::intersection(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    /// Sorts and deduplicates `v`, packing it into `0..range` if `range` is nonzero.
+    fn sorted_unique(v: Vec<u32>, range: u16) -> Vec<u32> {
+        let mut v: Vec<_> = match range {
+            0 => v,
+            range => v.into_iter().map(|x| x % u32::from(range)).collect(),
+        };
+        v.sort_unstable();
+        v.dedup();
+        v
+    }
+
+    #[quickcheck]
+    fn merge_uniq_impl(a: Vec<u32>, b: Vec<u32>, range: u16) -> bool {
+        let (a, b) = (sorted_unique(a, range), sorted_unique(b, range));
+        let expected = naive(a.clone(), b.clone());
+        let bitset = match (a.first(), b.first()) {
+            (Some(&af), Some(&bf)) => {
+                let hi = *a.last().unwrap().max(b.last().unwrap());
+                Bitset::new(af.min(bf), hi, &[&a, &b]).into_vec() == expected
+            }
+            _ => true,
+        };
+        bitset && merge_uniq(a, b) == expected
+    }
+
+    #[quickcheck]
+    fn intersection_impl(a: Vec<u32>, b: Vec<u32>, range: u16) -> bool {
+        let (a, b) = (sorted_unique(a, range), sorted_unique(b, range));
+        let expected: Vec<_> = a.iter().copied().filter(|x| b.contains(x)).collect();
+        intersection(a, &b) == expected
+    }
+
+    #[test]
+    fn extremes() {
+        let a = vec![0, 1, u32::MAX - 1];
+        let b = vec![1, u32::MAX];
+        assert_eq!(
+            merge_uniq(a.clone(), b.clone()),
+            [0, 1, u32::MAX - 1, u32::MAX]
+        );
+        assert_eq!(intersection(a, &b), [1]);
+        assert_eq!(
+            Bitset::new(u32::MAX - 70, u32::MAX, &[&[u32::MAX - 70, u32::MAX]]).into_vec(),
+            [u32::MAX - 70, u32::MAX]
+        );
+    }
+}
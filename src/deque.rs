@@ -0,0 +1,65 @@
+//! Merging `VecDeque`s without making them contiguous first.
+
+use std::collections::VecDeque;
+
+use crate::iter::MergeUniq;
+
+/// Merges two sorted, deduplicated `VecDeque`s.
+///
+/// Elements are moved out with each deque's own iterator, which walks both halves of the ring
+/// buffer in turn, so the inputs never have to be made contiguous first. The output is built in a
+/// `Vec` and converted to a `VecDeque` without reallocating.
+pub fn merge_uniq<T: Ord>(a: VecDeque<T>, b: VecDeque<T>) -> VecDeque<T> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    out.extend(MergeUniq::new(a.into_iter(), b.into_iter()));
+    VecDeque::from(out)
+}
+
+/// Merges two sorted, deduplicated `VecDeque`s, pushing the result onto the back of `out`.
+pub fn merge_uniq_into<T: Ord>(out: &mut VecDeque<T>, a: VecDeque<T>, b: VecDeque<T>) {
+    out.reserve(a.len() + b.len());
+    out.extend(MergeUniq::new(a.into_iter(), b.into_iter()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    /// Builds a deque whose contents wrap around the end of its buffer.
+    fn wrapped(v: &[usize]) -> VecDeque<usize> {
+        let mut d = VecDeque::with_capacity(v.len());
+        for &x in v.iter().rev() {
+            d.push_front(x);
+        }
+        d
+    }
+
+    #[quickcheck]
+    fn merge_uniq_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: VecDeque<_> = naive(a.clone(), b.clone()).into();
+        let actual = merge_uniq(wrapped(&a), wrapped(&b));
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn merge_uniq_into_impl(prefix: Vec<usize>, mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let mut expected = prefix.clone();
+        expected.extend(naive(a.clone(), b.clone()));
+
+        let mut actual = wrapped(&prefix);
+        merge_uniq_into(&mut actual, wrapped(&a), wrapped(&b));
+        actual == expected
+    }
+}
@@ -0,0 +1,224 @@
+//! Merges that report the duplicates they drop.
+
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::kway::KMergeStable;
+
+/// Counts of where the output of a two-way merge came from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MergeStats {
+    /// The number of elements of `b` that were dropped because `a` had an equal one.
+    pub duplicates_dropped: usize,
+    /// The number of output elements taken from `a`. Since ties go to `a`, this is all of it.
+    pub from_left: usize,
+    /// The number of output elements taken from `b`.
+    pub from_right: usize,
+}
+
+/// Like `crate::merge_uniq`, but also returns counts of where the output came from.
+///
+/// Since both inputs are deduplicated, every element missing from the output was a duplicate, so
+/// the counts follow from the lengths alone and cost nothing to compute.
+pub fn merge_uniq_with_stats<T: Ord>(a: Vec<T>, b: Vec<T>) -> (Vec<T>, MergeStats) {
+    let (alen, blen) = (a.len(), b.len());
+    let out = crate::merge_uniq(a, b);
+    let duplicates_dropped = alen + blen - out.len();
+    let stats = MergeStats {
+        duplicates_dropped,
+        from_left: alen,
+        from_right: blen - duplicates_dropped,
+    };
+    (out, stats)
+}
+
+/// Merges two sorted, deduplicated vectors, passing each duplicate dropped from `b` to `on_dup`.
+///
+/// `on_dup` is called in ascending order, with the element from `b` whose equal in `a` was kept.
+/// This lets callers recycle the duplicates' resources instead of dropping them. Unlike
+/// `crate::merge_uniq`, this always runs a simple linear merge.
+pub fn merge2_uniq_take_dups<T, F>(a: Vec<T>, b: Vec<T>, mut on_dup: F) -> Vec<T>
+where
+    T: Ord,
+    F: FnMut(T),
+{
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter();
+    let mut b = b.into_iter();
+    let mut x = a.next();
+    let mut y = b.next();
+
+    while let (Some(xa), Some(yb)) = (&x, &y) {
+        match xa.cmp(yb) {
+            Ordering::Less => out.extend(mem::replace(&mut x, a.next())),
+            Ordering::Greater => out.extend(mem::replace(&mut y, b.next())),
+            Ordering::Equal => {
+                out.extend(mem::replace(&mut x, a.next()));
+                on_dup(mem::replace(&mut y, b.next()).unwrap());
+            }
+        }
+    }
+
+    out.extend(x.into_iter().chain(a));
+    out.extend(y.into_iter().chain(b));
+    out
+}
+
+/// Like `merge2_uniq_take_dups`, but `on_dup` only gets to look at each duplicate before it's
+/// dropped.
+pub fn merge2_uniq_inspect_dups<T, F>(a: Vec<T>, b: Vec<T>, mut on_dup: F) -> Vec<T>
+where
+    T: Ord,
+    F: FnMut(&T),
+{
+    merge2_uniq_take_dups(a, b, |dup| on_dup(&dup))
+}
+
+/// Merges two sorted, deduplicated vectors, returning the union along with the elements that
+/// appeared in both.
+///
+/// The overlap holds the copies from `b`, since the union keeps the ones from `a`. Both vectors
+/// are produced in a single pass, by `merge2_uniq_take_dups`.
+pub fn merge2_uniq_split<T: Ord>(a: Vec<T>, b: Vec<T>) -> (Vec<T>, Vec<T>) {
+    let mut overlap = Vec::new();
+    let union = merge2_uniq_take_dups(a, b, |dup| overlap.push(dup));
+    (union, overlap)
+}
+
+/// Merges two sorted vectors, which may hold duplicates, into their distinct elements, each paired
+/// with the number of times it occurs in both.
+///
+/// Of equal elements, the first one in `a` is kept, or the first one in `b` if `a` has none. The
+/// output isn't allocated up front, since it may be much shorter than the inputs.
+///
+/// # Panics
+///
+/// Panics if an element occurs more than `u32::MAX` times.
+pub fn merge2_rle<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<(T, u32)> {
+    let mut out = Vec::new();
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        let next = if x <= y { a.next() } else { b.next() };
+        push_rle(&mut out, next.unwrap());
+    }
+    for x in a.chain(b) {
+        push_rle(&mut out, x);
+    }
+    out
+}
+
+/// Like `merge2_rle`, but for any number of sorted vectors. Of equal elements, the first one in
+/// the earliest run is kept.
+///
+/// # Panics
+///
+/// Panics if an element occurs more than `u32::MAX` times.
+pub fn merge_k_rle<T: Ord>(runs: Vec<Vec<T>>) -> Vec<(T, u32)> {
+    let mut out = Vec::new();
+    let merged = KMergeStable::new(runs.into_iter().map(Vec::into_iter).collect());
+    for x in merged {
+        push_rle(&mut out, x);
+    }
+    out
+}
+
+/// Appends `x` to `out`, or counts it as another occurrence of the last element if they're equal.
+fn push_rle<T: Ord>(out: &mut Vec<(T, u32)>, x: T) {
+    match out.last_mut() {
+        Some((last, n)) if *last == x => {
+            *n = n.checked_add(1).expect("multiplicity overflowed `u32`");
+        }
+        _ => out.push((x, 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kway::ByKey;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn merge_uniq_with_stats_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let dups = a.iter().filter(|x| b.contains(x)).count();
+        let expected = MergeStats {
+            duplicates_dropped: dups,
+            from_left: a.len(),
+            from_right: b.len() - dups,
+        };
+        let merged = sorted_unique([&a[..], &b].concat());
+        merge_uniq_with_stats(a, b) == (merged, expected)
+    }
+
+    #[quickcheck]
+    fn merge2_uniq_take_dups_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let expected_dups: Vec<_> = a.iter().copied().filter(|x| b.contains(x)).collect();
+        let expected = sorted_unique([&a[..], &b].concat());
+
+        let mut dups = Vec::new();
+        let actual = merge2_uniq_take_dups(a.clone(), b.clone(), |dup| dups.push(dup));
+        let mut seen = Vec::new();
+        let inspected = merge2_uniq_inspect_dups(a, b, |&dup| seen.push(dup));
+        actual == expected && inspected == expected && dups == expected_dups && seen == dups
+    }
+
+    #[quickcheck]
+    fn merge2_uniq_split_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let overlap: Vec<_> = a.iter().copied().filter(|x| b.contains(x)).collect();
+        let union = sorted_unique([&a[..], &b].concat());
+        merge2_uniq_split(a, b) == (union, overlap)
+    }
+
+    /// Pairs each element of `v`, sorted, with its multiplicity, keeping the first of equal ones.
+    fn rle(mut v: Vec<(u8, usize)>) -> Vec<((u8, usize), u32)> {
+        v.sort_by_key(|&(x, _)| x);
+        let mut out: Vec<((u8, usize), u32)> = Vec::new();
+        for x in v {
+            match out.last_mut() {
+                Some((last, n)) if last.0 == x.0 => *n += 1,
+                _ => out.push((x, 1)),
+            }
+        }
+        out
+    }
+
+    /// Sorts each run, tagging its elements with the run's index so ties can be checked.
+    fn tagged(runs: Vec<Vec<u8>>) -> Vec<Vec<ByKey<u8, usize>>> {
+        runs.into_iter()
+            .enumerate()
+            .map(|(i, mut run)| {
+                run.sort_unstable();
+                run.into_iter().map(|key| ByKey { key, item: i }).collect()
+            })
+            .collect()
+    }
+
+    fn untag(v: Vec<(ByKey<u8, usize>, u32)>) -> Vec<((u8, usize), u32)> {
+        v.into_iter().map(|(x, n)| ((x.key, x.item), n)).collect()
+    }
+
+    #[quickcheck]
+    fn merge2_rle_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let mut runs = tagged(vec![a, b]);
+        let b = runs.pop().unwrap();
+        let a = runs.pop().unwrap();
+        let flat = a.iter().chain(&b).map(|x| (x.key, x.item)).collect();
+        untag(merge2_rle(a, b)) == rle(flat)
+    }
+
+    #[quickcheck]
+    fn merge_k_rle_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs = tagged(runs);
+        let flat = runs.iter().flatten().map(|x| (x.key, x.item)).collect();
+        untag(merge_k_rle(runs)) == rle(flat)
+    }
+}
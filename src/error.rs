@@ -0,0 +1,209 @@
+//! One error type for the fallible parts of the crate.
+//!
+//! Each subsystem reports failures with its own type: `checked::UnsortedInput`, the
+//! `external::CorruptRun` and `external::ChecksumMismatch` wrapped in `io::Error`s,
+//! `progress::Cancelled`, `tolerant::DisplacedElement`, and `TryReserveError`. All of them convert into `Error`, so an
+//! application that uses several subsystems can propagate their failures with `?` and match on
+//! what went wrong. An `io::Error` that wraps one of the crate's own errors converts to the
+//! matching variant rather than to `Error::Io`, and converting an `Error` back to an `io::Error`
+//! wraps it the way the external merges do.
+
+use crate::checked::UnsortedInput;
+use crate::external::{ChecksumMismatch, CorruptRun};
+use crate::progress::Cancelled;
+use crate::tolerant::DisplacedElement;
+use std::collections::TryReserveError;
+use std::fmt;
+use std::io;
+
+/// A failure of one of the crate's fallible merges.
+///
+/// `Display` names the kind of failure, and `source` returns the error it came from, if any.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a run failed.
+    Io(io::Error),
+    /// An input wasn't sorted and deduplicated.
+    Unsorted(UnsortedInput),
+    /// A run read during an external merge wasn't sorted and deduplicated.
+    Corrupt(CorruptRun),
+    /// A block of a run in the format of `external::write_run` didn't match its checksum.
+    Checksum(ChecksumMismatch),
+    /// The merge was cancelled through its `progress::CancelToken`.
+    Cancelled,
+    /// The output couldn't be allocated.
+    Alloc(TryReserveError),
+    /// An element of a `tolerant::TolerantMerge` input was further from its place than allowed.
+    Displaced(DisplacedElement),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::Io(_) => "I/O error",
+            Error::Unsorted(_) => "input is not sorted and deduplicated",
+            Error::Corrupt(_) => "corrupt run",
+            Error::Checksum(_) => "run failed its checksum",
+            Error::Cancelled => "the merge was cancelled",
+            Error::Alloc(_) => "allocation failed",
+            Error::Displaced(_) => "input element is displaced too far",
+        })
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Unsorted(e) => Some(e),
+            Error::Corrupt(e) => Some(e),
+            Error::Checksum(e) => Some(e),
+            Error::Cancelled => None,
+            Error::Alloc(e) => Some(e),
+            Error::Displaced(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        let ours = e.get_ref().is_some_and(|inner| {
+            inner.is::<UnsortedInput>()
+                || inner.is::<CorruptRun>()
+                || inner.is::<ChecksumMismatch>()
+                || inner.is::<Cancelled>()
+                || inner.is::<TryReserveError>()
+                || inner.is::<DisplacedElement>()
+        });
+        if !ours {
+            return Error::Io(e);
+        }
+
+        let inner = e.into_inner().unwrap();
+        let inner = match inner.downcast() {
+            Ok(e) => return Error::Unsorted(*e),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast() {
+            Ok(e) => return Error::Corrupt(*e),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast() {
+            Ok(e) => return Error::Checksum(*e),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast() {
+            Ok(e) => return Error::Alloc(*e),
+            Err(inner) => inner,
+        };
+        match inner.downcast() {
+            Ok(e) => Error::Displaced(*e),
+            Err(_) => Error::Cancelled,
+        }
+    }
+}
+
+impl From<UnsortedInput> for Error {
+    fn from(e: UnsortedInput) -> Self {
+        Error::Unsorted(e)
+    }
+}
+
+impl From<CorruptRun> for Error {
+    fn from(e: CorruptRun) -> Self {
+        Error::Corrupt(e)
+    }
+}
+
+impl From<ChecksumMismatch> for Error {
+    fn from(e: ChecksumMismatch) -> Self {
+        Error::Checksum(e)
+    }
+}
+
+impl From<Cancelled> for Error {
+    fn from(_: Cancelled) -> Self {
+        Error::Cancelled
+    }
+}
+
+impl From<DisplacedElement> for Error {
+    fn from(e: DisplacedElement) -> Self {
+        Error::Displaced(e)
+    }
+}
+
+impl From<TryReserveError> for Error {
+    fn from(e: TryReserveError) -> Self {
+        Error::Alloc(e)
+    }
+}
+
+/// Converts back to the `io::Error` the external merges would have returned, for callers that
+/// mix the crate's merges with other I/O.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Unsorted(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Error::Corrupt(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Error::Checksum(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Error::Cancelled => Cancelled.into(),
+            Error::Alloc(e) => io::Error::new(io::ErrorKind::OutOfMemory, e),
+            Error::Displaced(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checked::RunError;
+    use std::error::Error as _;
+
+    #[test]
+    fn from_io_error() {
+        let corrupt = CorruptRun {
+            run: 1,
+            path: None,
+            error: RunError::Duplicate { index: 3 },
+        };
+        let e = Error::from(io::Error::new(io::ErrorKind::InvalidData, corrupt.clone()));
+        assert!(matches!(&e, Error::Corrupt(c) if *c == corrupt));
+        assert_eq!(e.source().unwrap().to_string(), corrupt.to_string());
+
+        let mismatch = ChecksumMismatch {
+            run: 0,
+            path: None,
+            offset: 16,
+        };
+        let e = Error::from(io::Error::new(io::ErrorKind::InvalidData, mismatch.clone()));
+        assert!(matches!(e, Error::Checksum(c) if c == mismatch));
+
+        assert!(matches!(
+            Error::from(io::Error::from(Cancelled)),
+            Error::Cancelled
+        ));
+
+        let e = Error::from(io::Error::new(io::ErrorKind::NotFound, "no such run"));
+        assert!(matches!(&e, Error::Io(io) if io.kind() == io::ErrorKind::NotFound));
+        assert_eq!(e.source().unwrap().to_string(), "no such run");
+    }
+
+    #[test]
+    fn into_io_error() {
+        let e = io::Error::from(Error::from(io::Error::from(Cancelled)));
+        assert!(e.get_ref().is_some_and(|e| e.is::<Cancelled>()));
+
+        let unsorted = crate::checked::checked_merge_uniq(vec![1, 2], vec![3, 3]).unwrap_err();
+        let e = io::Error::from(Error::from(unsorted));
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(e), Error::Unsorted(u) if u == unsorted));
+
+        let displaced = crate::tolerant::merge_tolerant(vec![vec![3, 2, 1]], 1).unwrap_err();
+        let e = io::Error::from(Error::from(displaced));
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(e), Error::Displaced(d) if d == displaced));
+    }
+}
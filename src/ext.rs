@@ -0,0 +1,91 @@
+//! Method-call syntax for the merge and set operations on sorted, deduplicated vectors.
+//!
+//! `kway::KMergeUniqExt` does the same for iterators over runs. Both are re-exported from the
+//! crate root.
+
+use crate::set_ops;
+
+/// Adds merges and set operations to `Vec`s that are sorted and deduplicated.
+///
+/// The results are unspecified (but memory-safe) if either input isn't.
+pub trait MergeExt<T>: Sized {
+    /// Merges `self` and `other` with `crate::merge_uniq`. Of two equal elements, the one from
+    /// `self` is kept.
+    fn merge_uniq(self, other: Vec<T>) -> Vec<T>
+    where
+        T: Ord;
+
+    /// Keeps the elements that are also in `other`, with `set_ops::intersection`.
+    fn intersect_sorted(self, other: &[T]) -> Vec<T>
+    where
+        T: Ord;
+
+    /// Removes the elements that are in `other`, with `set_ops::difference`.
+    fn difference_sorted(self, other: &[T]) -> Vec<T>
+    where
+        T: Ord;
+
+    /// Returns the elements that are in exactly one of `self` and `other`, with
+    /// `set_ops::symmetric_difference`.
+    fn symmetric_difference_sorted(self, other: Vec<T>) -> Vec<T>
+    where
+        T: Ord;
+}
+
+impl<T> MergeExt<T> for Vec<T> {
+    fn merge_uniq(self, other: Vec<T>) -> Vec<T>
+    where
+        T: Ord,
+    {
+        crate::merge_uniq(self, other)
+    }
+
+    fn intersect_sorted(self, other: &[T]) -> Vec<T>
+    where
+        T: Ord,
+    {
+        set_ops::intersection(self, other)
+    }
+
+    fn difference_sorted(self, other: &[T]) -> Vec<T>
+    where
+        T: Ord,
+    {
+        set_ops::difference(self, other)
+    }
+
+    fn symmetric_difference_sorted(self, other: Vec<T>) -> Vec<T>
+    where
+        T: Ord,
+    {
+        set_ops::symmetric_difference(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sorted_unique;
+    use crate::KMergeUniqExt;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeSet;
+
+    #[quickcheck]
+    fn merge_ext_impl(a: Vec<u8>, b: Vec<u8>, c: Vec<u8>) -> bool {
+        let (a, b, c) = (sorted_unique(a), sorted_unique(b), sorted_unique(c));
+        let (sa, sb): (BTreeSet<_>, BTreeSet<_>) =
+            (a.iter().copied().collect(), b.iter().copied().collect());
+
+        let union: Vec<_> = sa.union(&sb).copied().collect();
+        let intersection: Vec<_> = sa.intersection(&sb).copied().collect();
+        let difference: Vec<_> = sa.difference(&sb).copied().collect();
+        let symmetric: Vec<_> = sa.symmetric_difference(&sb).copied().collect();
+        let all = sorted_unique([&a[..], &b, &c].concat());
+
+        a.clone().merge_uniq(b.clone()) == union
+            && a.clone().intersect_sorted(&b) == intersection
+            && a.clone().difference_sorted(&b) == difference
+            && a.clone().symmetric_difference_sorted(b.clone()) == symmetric
+            && vec![a, b, c].into_iter().kmerge_uniq() == all
+    }
+}
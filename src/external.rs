@@ -0,0 +1,2672 @@
+//! Merging sorted runs that live outside of memory.
+//!
+//! A run is any `Read`er yielding a sorted, deduplicated sequence of records. A `Codec` describes
+//! how records are framed in the byte stream. Runs are read through a `BufReader` each, so only
+//! one buffer per run and the records at the head of each run are held in memory at once.
+//!
+//! A `Sorter` produces such runs from an unsorted stream by sorting chunks that fit in a memory
+//! budget and spilling them to temporary files, then merges the runs.
+
+use std::borrow::Borrow;
+use std::convert::{TryFrom, TryInto};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "serde")]
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+use crate::checked::RunError;
+use crate::kway::{self, LoserTree};
+use crate::memory::{ExternalPlan, MergePlan};
+use crate::progress::{CancelToken, Observer, PROGRESS_INTERVAL};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The default capacity of the read buffer of each run, and of the output buffer.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Frames records in a byte stream.
+pub trait Codec {
+    type Record: Ord;
+
+    /// Reads the next record from `r`, or returns `None` if `r` is at its end.
+    ///
+    /// A stream that ends in the middle of a record is an `UnexpectedEof` error.
+    fn read<R: BufRead>(&self, r: &mut R) -> io::Result<Option<Self::Record>>;
+
+    /// Writes `record` to `w`.
+    fn write<W: Write>(&self, w: &mut W, record: &Self::Record) -> io::Result<()>;
+
+    /// Returns roughly how many bytes of memory `record` occupies, including heap allocations.
+    fn record_size(&self, _record: &Self::Record) -> usize {
+        mem::size_of::<Self::Record>()
+    }
+}
+
+/// Byte strings, each preceded by its length as a little-endian `u32`.
+///
+/// Records compare lexicographically.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LengthPrefixed;
+
+impl Codec for LengthPrefixed {
+    type Record = Vec<u8>;
+
+    fn read<R: BufRead>(&self, r: &mut R) -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0; 4];
+        if !read_exact_or_eof(r, &mut len)? {
+            return Ok(None);
+        }
+
+        // The prefix may be corrupt, so grow the record as its bytes arrive instead of allocating
+        // all of it up front.
+        let len = u32::from_le_bytes(len);
+        let mut record = Vec::new();
+        r.by_ref().take(u64::from(len)).read_to_end(&mut record)?;
+        if record.len() < len as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended in the middle of a record",
+            ));
+        }
+        Ok(Some(record))
+    }
+
+    fn write<W: Write>(&self, w: &mut W, record: &Vec<u8>) -> io::Result<()> {
+        let len = u32::try_from(record.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "record longer than u32::MAX")
+        })?;
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(record)
+    }
+
+    fn record_size(&self, record: &Vec<u8>) -> usize {
+        mem::size_of::<Vec<u8>>() + record.capacity()
+    }
+}
+
+/// Byte strings that all have the same length, stored back to back.
+///
+/// Records compare lexicographically, so integer keys should be stored big-endian.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedSize {
+    len: usize,
+}
+
+impl FixedSize {
+    /// # Panics
+    ///
+    /// Panics if `len` is zero.
+    pub fn new(len: usize) -> Self {
+        assert!(len > 0, "records must be at least one byte long");
+        FixedSize { len }
+    }
+}
+
+impl Codec for FixedSize {
+    type Record = Vec<u8>;
+
+    fn read<R: BufRead>(&self, r: &mut R) -> io::Result<Option<Vec<u8>>> {
+        let mut record = vec![0; self.len];
+        Ok(read_exact_or_eof(r, &mut record)?.then_some(record))
+    }
+
+    fn write<W: Write>(&self, w: &mut W, record: &Vec<u8>) -> io::Result<()> {
+        if record.len() != self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "record length differs from the fixed record size",
+            ));
+        }
+        w.write_all(record)
+    }
+
+    fn record_size(&self, record: &Vec<u8>) -> usize {
+        mem::size_of::<Vec<u8>>() + record.capacity()
+    }
+}
+
+/// The type of a key field of a `BinarySchema`, which also sets its width.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    /// Compared by the IEEE 754 total order, like `f32::total_cmp`.
+    F32,
+    /// Compared by the IEEE 754 total order, like `f64::total_cmp`.
+    F64,
+    /// A byte string of the given length, compared lexicographically. Its byte order is ignored.
+    Bytes(usize),
+}
+
+impl FieldType {
+    pub fn width(self) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => 8,
+            FieldType::Bytes(len) => len,
+        }
+    }
+}
+
+/// The byte order of a key field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+/// The direction a key field is sorted in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// A field of a binary record that records are ordered by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyField {
+    offset: usize,
+    ty: FieldType,
+    endian: Endian,
+    direction: Direction,
+}
+
+impl KeyField {
+    /// A big-endian field of type `ty` starting `offset` bytes into the record, sorted in
+    /// ascending order.
+    pub fn new(offset: usize, ty: FieldType) -> Self {
+        KeyField {
+            offset,
+            ty,
+            endian: Endian::Big,
+            direction: Direction::Ascending,
+        }
+    }
+
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Appends the bytes of this field of `record` to `key`, transformed so that comparing keys
+    /// lexicographically orders records by this field.
+    fn encode(&self, record: &[u8], key: &mut Vec<u8>) {
+        let start = key.len();
+        key.extend_from_slice(&record[self.offset..self.offset + self.ty.width()]);
+        let field = &mut key[start..];
+
+        if self.endian == Endian::Little && !matches!(self.ty, FieldType::Bytes(_)) {
+            field.reverse();
+        }
+        match self.ty {
+            FieldType::I8 | FieldType::I16 | FieldType::I32 | FieldType::I64 => field[0] ^= 0x80,
+            // Negative floats sort in reverse order of their bits, and before the positive ones.
+            FieldType::F32 | FieldType::F64 if field[0] & 0x80 != 0 => {
+                field.iter_mut().for_each(|b| *b = !*b)
+            }
+            FieldType::F32 | FieldType::F64 => field[0] ^= 0x80,
+            _ => {}
+        }
+        if self.direction == Direction::Descending {
+            field.iter_mut().for_each(|b| *b = !*b);
+        }
+    }
+}
+
+/// Fixed-size binary records, ordered by a list of key fields read straight from their bytes.
+///
+/// This lets the external merges work on existing binary file formats without deserializing them.
+/// Records are compared by the first key field, then by the second, and so on. Records whose key
+/// fields are all equal are equal, so the merges that deduplicate keep only the first of them.
+#[derive(Clone, Debug)]
+pub struct BinarySchema {
+    record_len: usize,
+    fields: Vec<KeyField>,
+    key_len: usize,
+}
+
+impl BinarySchema {
+    /// # Panics
+    ///
+    /// Panics if `record_len` is zero, or if a field doesn't fit in a record.
+    pub fn new(record_len: usize, fields: Vec<KeyField>) -> Self {
+        assert!(record_len > 0, "records must be at least one byte long");
+        for field in &fields {
+            assert!(
+                field.offset + field.ty.width() <= record_len,
+                "key field at offset {} doesn't fit in a record",
+                field.offset
+            );
+        }
+        let key_len = fields.iter().map(|field| field.ty.width()).sum();
+        BinarySchema {
+            record_len,
+            fields,
+            key_len,
+        }
+    }
+
+    /// Wraps `bytes` in a record ordered by this schema, such as to pass to a `BoundedMerger`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` isn't exactly as long as a record.
+    pub fn record(&self, bytes: &[u8]) -> BinaryRecord {
+        assert_eq!(bytes.len(), self.record_len, "record has the wrong length");
+        let mut buf = Vec::with_capacity(self.key_len + self.record_len);
+        for field in &self.fields {
+            field.encode(bytes, &mut buf);
+        }
+        buf.extend_from_slice(bytes);
+        BinaryRecord {
+            buf,
+            key_len: self.key_len,
+        }
+    }
+}
+
+impl Codec for BinarySchema {
+    type Record = BinaryRecord;
+
+    fn read<R: BufRead>(&self, r: &mut R) -> io::Result<Option<BinaryRecord>> {
+        let mut bytes = vec![0; self.record_len];
+        if !read_exact_or_eof(r, &mut bytes)? {
+            return Ok(None);
+        }
+        Ok(Some(self.record(&bytes)))
+    }
+
+    fn write<W: Write>(&self, w: &mut W, record: &BinaryRecord) -> io::Result<()> {
+        w.write_all(record.bytes())
+    }
+
+    fn record_size(&self, record: &BinaryRecord) -> usize {
+        mem::size_of::<BinaryRecord>() + record.buf.capacity()
+    }
+}
+
+/// A record read by a `BinarySchema`, which compares by its key fields alone.
+///
+/// The key fields are copied in front of the record, in a form that compares as plain bytes.
+#[derive(Clone, Debug)]
+pub struct BinaryRecord {
+    buf: Vec<u8>,
+    key_len: usize,
+}
+
+impl BinaryRecord {
+    /// Returns the bytes of the record, as they were read.
+    pub fn bytes(&self) -> &[u8] {
+        &self.buf[self.key_len..]
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.buf[..self.key_len]
+    }
+}
+
+impl PartialEq for BinaryRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for BinaryRecord {}
+
+impl PartialOrd for BinaryRecord {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BinaryRecord {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(other.key())
+    }
+}
+
+/// Values of any serde type, each encoded with bincode and framed by `LengthPrefixed`.
+///
+/// Records compare with `T`'s `Ord` impl. `record_size` only counts `size_of::<T>()`, so set a
+/// smaller memory budget for types that own heap allocations.
+#[cfg(feature = "serde")]
+pub struct Bincode<T> {
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T> Bincode<T> {
+    pub fn new() -> Self {
+        Bincode {
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Default for Bincode<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Clone for Bincode<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Copy for Bincode<T> {}
+
+#[cfg(feature = "serde")]
+impl<T> std::fmt::Debug for Bincode<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Bincode")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Codec for Bincode<T>
+where
+    T: Serialize + DeserializeOwned + Ord,
+{
+    type Record = T;
+
+    fn read<R: BufRead>(&self, r: &mut R) -> io::Result<Option<T>> {
+        match LengthPrefixed.read(r)? {
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(None),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W, record: &T) -> io::Result<()> {
+        let bytes = bincode::serialize(record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        LengthPrefixed.write(w, &bytes)
+    }
+}
+
+/// Fills `buf` from `r`, returning `false` if `r` was already at its end.
+fn read_exact_or_eof<R: BufRead>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    if r.fill_buf()?.is_empty() {
+        return Ok(false);
+    }
+    r.read_exact(buf)?;
+    Ok(true)
+}
+
+/// The error wrapped in the `io::Error`, of kind `InvalidData`, returned by a `Merger` that
+/// validates its runs when one of them isn't sorted and deduplicated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptRun {
+    /// The position of the run among the runs being merged.
+    pub run: usize,
+    /// The file the run was read from, when merging files by path.
+    pub path: Option<PathBuf>,
+    pub error: RunError,
+}
+
+impl fmt::Display for CorruptRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "run {}", self.run)?;
+        if let Some(path) = &self.path {
+            write!(f, " ({})", path.display())?;
+        }
+        write!(f, " is corrupt: {}", self.error)
+    }
+}
+
+impl Error for CorruptRun {}
+
+/// The error wrapped in the `io::Error`, of kind `InvalidData`, returned when a block of a run in
+/// the format of `write_run` doesn't match its checksum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The position of the run among the runs being merged, or zero for `read_run`.
+    pub run: usize,
+    /// The file the run was read from, when merging files by path.
+    pub path: Option<PathBuf>,
+    /// The position of the block in the run, counting the bytes `write_run` wrote before it.
+    pub offset: u64,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "run {}", self.run)?;
+        if let Some(path) = &self.path {
+            write!(f, " ({})", path.display())?;
+        }
+        write!(f, " has a corrupt block at byte {}", self.offset)
+    }
+}
+
+impl Error for ChecksumMismatch {}
+
+/// The bytes that start a run written by `write_run`, before the version of its format.
+const RUN_MAGIC: [u8; 6] = *b"KMRUN\0";
+
+/// The version of the format written by `write_run`. `read_run` rejects runs of other versions.
+///
+/// A run starts with `RUN_MAGIC` and the version as a little-endian `u16`. The records follow in
+/// blocks, each preceded by its length in bytes and followed by the CRC-32C of its length and
+/// bytes, both as little-endian `u32`s, and ended by an empty block without a checksum. Then comes
+/// the trailer: the number of records and the index stride (zero for no index) as little-endian
+/// `u64`s, the first and last records unless the run is empty, every stride-th record starting
+/// with the first, the CRC-32C of all of that as a little-endian `u32`, and the length of the
+/// trailer, including itself, as a little-endian `u64`.
+///
+/// What the trailer records is only known once the last record is written, so it comes at the end
+/// rather than in a header. That way a run can be written by a merge, or through a compressor,
+/// without seeking back, and `read_run_info` can still find it from the end of a file.
+pub const RUN_FORMAT_VERSION: u16 = 2;
+
+/// The records in a block of a run before it's written, unless one record is longer.
+const RUN_BLOCK_SIZE: usize = 64 * 1024;
+
+/// What the trailer of a run written by `write_run` says about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunInfo<T> {
+    pub count: u64,
+    /// The first record, or `None` if the run is empty.
+    pub min: Option<T>,
+    /// The last record, or `None` if the run is empty.
+    pub max: Option<T>,
+    /// How many records apart the records in `index` are, if the run was written with an index.
+    pub index_stride: Option<usize>,
+    /// Every `index_stride`th record, starting with the first, as for `indexed::IndexedRun`.
+    pub index: Vec<T>,
+}
+
+/// Writes `records`, which must be sorted and deduplicated, to `out` as a run in the format of
+/// `RUN_FORMAT_VERSION`, returning the number of records written.
+///
+/// With an `index_stride`, every `index_stride`th record is also copied into the trailer, where
+/// `read_run_info` can find it without reading the records.
+///
+/// # Panics
+///
+/// Panics if `index_stride` is zero.
+pub fn write_run<C, W, I>(
+    codec: &C,
+    out: W,
+    records: I,
+    index_stride: Option<usize>,
+) -> io::Result<u64>
+where
+    C: Codec,
+    W: Write,
+    I: IntoIterator,
+    I::Item: Borrow<C::Record>,
+{
+    let mut w = RunWriter::new(BufWriter::new(out), RUN_BLOCK_SIZE, index_stride)?;
+    for record in records {
+        w.push(codec, record.borrow())?;
+    }
+    w.finish()
+}
+
+/// Reads a whole run written by `write_run`, checking it against its trailer.
+///
+/// A run that was truncated, has a different count or checksum than its trailer says, or isn't a
+/// run of this version at all is an `InvalidData` error.
+pub fn read_run<C: Codec, R: Read>(codec: &C, input: R) -> io::Result<Vec<C::Record>> {
+    let mut run = RunReader::open(BufReader::new(input))?;
+    let mut records = Vec::new();
+    while let Some(record) = run.read(codec)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Reads the trailer of a run written by `write_run`, seeking past the records to it.
+///
+/// Only the trailer is checked against its checksum, since the records aren't read. On success,
+/// `input` is left at the end of the run.
+pub fn read_run_info<C: Codec, R: Read + Seek>(
+    codec: &C,
+    mut input: R,
+) -> io::Result<RunInfo<C::Record>> {
+    let mut header = [0; 8];
+    input.seek(SeekFrom::Start(0))?;
+    input.read_exact(&mut header)?;
+    check_run_header(header)?;
+
+    let mut len = [0; 8];
+    input.seek(SeekFrom::End(-8))?;
+    input.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    let end = input.seek(SeekFrom::End(0))?;
+    if len < TRAILER_MIN_LEN || len > end - 8 {
+        return Err(corrupt_run("trailer length is out of bounds"));
+    }
+    input.seek(SeekFrom::Start(end - len))?;
+    let mut trailer = Vec::new();
+    input.read_to_end(&mut trailer)?;
+    let (info, _) = parse_trailer(codec, &trailer)?;
+    Ok(info)
+}
+
+/// The length of the trailer of an empty run without an index.
+const TRAILER_MIN_LEN: u64 = 28;
+
+fn corrupt_run(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt run: {}", msg))
+}
+
+fn check_run_header(header: [u8; 8]) -> io::Result<()> {
+    if header[..6] != RUN_MAGIC {
+        return Err(corrupt_run("not a kmerge run"));
+    }
+    let version = u16::from_le_bytes([header[6], header[7]]);
+    if version != RUN_FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported run format version {}", version),
+        ));
+    }
+    Ok(())
+}
+
+/// The bytes of the first and last records of a run, as its trailer holds them.
+type RunEnds<'t> = (&'t [u8], &'t [u8]);
+
+/// Decodes a whole trailer, returning what it says and the bytes of its first and last records.
+fn parse_trailer<'t, C: Codec>(
+    codec: &C,
+    trailer: &'t [u8],
+) -> io::Result<(RunInfo<C::Record>, Option<RunEnds<'t>>)> {
+    if (trailer.len() as u64) < TRAILER_MIN_LEN {
+        return Err(corrupt_run("trailer is too short"));
+    }
+    let u64_at = |i: usize| u64::from_le_bytes(trailer[i..i + 8].try_into().unwrap());
+    let end = trailer.len() - 12;
+    if u64_at(end + 4) != trailer.len() as u64 {
+        return Err(corrupt_run("trailer length does not match"));
+    }
+    if crc32c(0, &trailer[..end]).to_le_bytes() != trailer[end..end + 4] {
+        return Err(corrupt_run("trailer does not match its checksum"));
+    }
+    let (count, stride) = (u64_at(0), u64_at(8));
+
+    let mut rest = &trailer[16..end];
+    let mut next = || -> io::Result<(C::Record, &'t [u8])> {
+        let before = rest;
+        match codec.read(&mut rest) {
+            Ok(Some(record)) => Ok((record, &before[..before.len() - rest.len()])),
+            _ => Err(corrupt_run("trailer ends in the middle of a record")),
+        }
+    };
+    let (mut min, mut max, mut ends) = (None, None, None);
+    if count > 0 {
+        let (first, first_bytes) = next()?;
+        let (last, last_bytes) = next()?;
+        min = Some(first);
+        max = Some(last);
+        ends = Some((first_bytes, last_bytes));
+    }
+    let index_stride = usize::try_from(stride).ok().filter(|&stride| stride > 0);
+    let mut index = Vec::new();
+    if let Some(stride) = index_stride {
+        for _ in 0..count.div_ceil(stride as u64) {
+            index.push(next()?.0);
+        }
+    }
+    if !rest.is_empty() {
+        return Err(corrupt_run("trailer has bytes left over"));
+    }
+
+    let info = RunInfo {
+        count,
+        min,
+        max,
+        index_stride,
+        index,
+    };
+    Ok((info, ends))
+}
+
+/// The lookup table of `crc32c`, for the reflected Castagnoli polynomial.
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = (crc >> 1) ^ (0x82f6_3b78 & (crc & 1).wrapping_neg());
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the CRC-32C `crc` of some bytes with `bytes`. Start from zero.
+fn crc32c(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in bytes {
+        crc = (crc >> 8) ^ CRC32C_TABLE[((crc ^ u32::from(b)) & 0xff) as usize];
+    }
+    !crc
+}
+
+/// Writes a run in the format of `RUN_FORMAT_VERSION`, a block at a time.
+struct RunWriter<W: Write> {
+    out: W,
+    block: Vec<u8>,
+    block_size: usize,
+    /// Where the last record pushed starts in `block`.
+    last_start: usize,
+    /// The bytes of the first and last records, once they've left `block`.
+    first: Vec<u8>,
+    last: Vec<u8>,
+    index: Vec<u8>,
+    index_stride: Option<usize>,
+    count: u64,
+}
+
+impl<W: Write> RunWriter<W> {
+    fn new(mut out: W, block_size: usize, index_stride: Option<usize>) -> io::Result<Self> {
+        assert!(index_stride != Some(0), "cannot index every zeroth record");
+        out.write_all(&RUN_MAGIC)?;
+        out.write_all(&RUN_FORMAT_VERSION.to_le_bytes())?;
+        Ok(RunWriter {
+            out,
+            block: Vec::new(),
+            block_size,
+            last_start: 0,
+            first: Vec::new(),
+            last: Vec::new(),
+            index: Vec::new(),
+            index_stride,
+            count: 0,
+        })
+    }
+
+    fn push<C: Codec>(&mut self, codec: &C, record: &C::Record) -> io::Result<()> {
+        let start = self.block.len();
+        codec.write(&mut self.block, record)?;
+        if self.count == 0 {
+            self.first = self.block[start..].to_vec();
+        }
+        if let Some(stride) = self.index_stride {
+            if self.count.is_multiple_of(stride as u64) {
+                self.index.extend_from_slice(&self.block[start..]);
+            }
+        }
+        self.last_start = start;
+        self.count += 1;
+        if self.block.len() >= self.block_size {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let len = u32::try_from(self.block.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "record longer than u32::MAX")
+        })?;
+        let len = len.to_le_bytes();
+        let crc = crc32c(crc32c(0, &len), &self.block);
+        self.out.write_all(&len)?;
+        self.out.write_all(&self.block)?;
+        self.out.write_all(&crc.to_le_bytes())?;
+        self.last = self.block[self.last_start..].to_vec();
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last block and the trailer, returning the number of records written.
+    fn finish(mut self) -> io::Result<u64> {
+        self.write_block()?;
+        self.out.write_all(&0u32.to_le_bytes())?;
+
+        let mut trailer = Vec::new();
+        trailer.extend_from_slice(&self.count.to_le_bytes());
+        trailer.extend_from_slice(&(self.index_stride.unwrap_or(0) as u64).to_le_bytes());
+        if self.count > 0 {
+            trailer.extend_from_slice(&self.first);
+            trailer.extend_from_slice(&self.last);
+        }
+        trailer.extend_from_slice(&self.index);
+        let crc = crc32c(0, &trailer);
+        trailer.extend_from_slice(&crc.to_le_bytes());
+        trailer.extend_from_slice(&(trailer.len() as u64 + 8).to_le_bytes());
+        self.out.write_all(&trailer)?;
+        self.out.flush()?;
+        Ok(self.count)
+    }
+}
+
+/// Reads a run in the format of `RUN_FORMAT_VERSION`, a block at a time, and checks it against its
+/// trailer once the records run out.
+struct RunReader<R> {
+    input: R,
+    block: Vec<u8>,
+    pos: usize,
+    /// Where the last record read is in `block`, and a copy of it once `block` moves on.
+    last: Range<usize>,
+    last_bytes: Vec<u8>,
+    first: Vec<u8>,
+    count: u64,
+    /// The position in the run of the next block.
+    offset: u64,
+    done: bool,
+}
+
+impl<R: BufRead> RunReader<R> {
+    fn open(mut input: R) -> io::Result<Self> {
+        let mut header = [0; 8];
+        input.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => corrupt_run("not a kmerge run"),
+            _ => e,
+        })?;
+        check_run_header(header)?;
+        Ok(RunReader {
+            input,
+            block: Vec::new(),
+            pos: 0,
+            last: 0..0,
+            last_bytes: Vec::new(),
+            first: Vec::new(),
+            count: 0,
+            offset: header.len() as u64,
+            done: false,
+        })
+    }
+
+    fn read<C: Codec>(&mut self, codec: &C) -> io::Result<Option<C::Record>> {
+        while self.pos == self.block.len() {
+            if self.done || !self.read_block(codec)? {
+                self.done = true;
+                return Ok(None);
+            }
+        }
+
+        let mut rest = &self.block[self.pos..];
+        let record = match codec.read(&mut rest) {
+            Ok(Some(record)) => record,
+            _ => return Err(corrupt_run("block ends in the middle of a record")),
+        };
+        let start = self.pos;
+        self.pos = self.block.len() - rest.len();
+        if self.count == 0 {
+            self.first = self.block[start..self.pos].to_vec();
+        }
+        self.last = start..self.pos;
+        self.count += 1;
+        Ok(Some(record))
+    }
+
+    /// Reads the next block, or checks the trailer and returns `false` if there are no more.
+    fn read_block<C: Codec>(&mut self, codec: &C) -> io::Result<bool> {
+        let truncated = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => corrupt_run("run is truncated"),
+            _ => e,
+        };
+        if !self.block.is_empty() {
+            self.last_bytes = self.block[self.last.clone()].to_vec();
+        }
+
+        let mut len_bytes = [0; 4];
+        self.input.read_exact(&mut len_bytes).map_err(truncated)?;
+        let len = u32::from_le_bytes(len_bytes);
+        if len == 0 {
+            self.check_trailer(codec)?;
+            return Ok(false);
+        }
+
+        // The length may be corrupt, so grow the block as its bytes arrive.
+        self.block.clear();
+        self.pos = 0;
+        (&mut self.input)
+            .take(u64::from(len))
+            .read_to_end(&mut self.block)?;
+        if self.block.len() < len as usize {
+            return Err(corrupt_run("run is truncated"));
+        }
+        let mut crc = [0; 4];
+        self.input.read_exact(&mut crc).map_err(truncated)?;
+        if crc32c(crc32c(0, &len_bytes), &self.block).to_le_bytes() != crc {
+            let mismatch = ChecksumMismatch {
+                run: 0,
+                path: None,
+                offset: self.offset,
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, mismatch));
+        }
+        self.offset += 8 + u64::from(len);
+        Ok(true)
+    }
+
+    /// Checks the trailer against the records read, once they've run out.
+    fn check_trailer<C: Codec>(&mut self, codec: &C) -> io::Result<()> {
+        let mut trailer = Vec::new();
+        self.input.read_to_end(&mut trailer)?;
+        if (trailer.len() as u64) < TRAILER_MIN_LEN {
+            return Err(corrupt_run("run is truncated"));
+        }
+
+        let (info, ends) = parse_trailer(codec, &trailer)?;
+        if info.count != self.count {
+            return Err(corrupt_run("record count does not match"));
+        }
+        if let Some((first, last)) = ends {
+            if first != self.first || last != self.last_bytes {
+                return Err(corrupt_run("first or last record does not match"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges sorted runs of records framed by a `Codec`.
+#[derive(Clone, Debug)]
+pub struct Merger<C> {
+    codec: C,
+    buffer_size: usize,
+    cancel: Option<CancelToken>,
+    validate: bool,
+    run_format: bool,
+}
+
+impl<C: Codec> Merger<C> {
+    pub fn new(codec: C) -> Self {
+        Merger {
+            codec,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            cancel: None,
+            validate: false,
+            run_format: false,
+        }
+    }
+
+    /// Sets the capacity of the read buffer of each run, and of the output buffer.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Makes merges fail with a `progress::Cancelled` error soon after `cancel` is cancelled.
+    /// Whatever was written to the output by then is left there.
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Makes merges check that each run is sorted, and for `merge_uniq` deduplicated, as it is
+    /// read, failing with a `CorruptRun` error at the first record that isn't. This takes one
+    /// more comparison per record.
+    pub fn validate_runs(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Makes merges read runs written by `write_run`, and write their output as one, instead of
+    /// bare records. Each run is checked against its trailer once it has been read, so a run
+    /// that was truncated or changed fails the merge with an `InvalidData` error.
+    pub fn run_format(mut self, run_format: bool) -> Self {
+        self.run_format = run_format;
+        self
+    }
+
+    /// Plans a merge of `runs` runs with `merge_uniq` or `merge`, for
+    /// `memory::estimate_peak_memory`. `record_size` is the largest `Codec::record_size` of any
+    /// record in the runs.
+    pub fn plan(&self, runs: usize, record_size: usize) -> MergePlan {
+        MergePlan::external(self.external_plan(runs, record_size, self.run_format, false))
+    }
+
+    /// Like `plan`, but for `merge_uniq_pipelined`.
+    pub fn plan_pipelined(&self, runs: usize, record_size: usize) -> MergePlan {
+        MergePlan::external(self.external_plan(runs, record_size, self.run_format, true))
+    }
+
+    fn external_plan(
+        &self,
+        runs: usize,
+        record_size: usize,
+        formatted: bool,
+        pipelined: bool,
+    ) -> ExternalPlan {
+        ExternalPlan {
+            runs,
+            record_size,
+            buffer_size: self.buffer_size,
+            block_size: formatted.then_some(RUN_BLOCK_SIZE),
+            pipelined,
+        }
+    }
+
+    fn check_cancelled(&self) -> io::Result<()> {
+        match &self.cancel {
+            Some(cancel) => Ok(cancel.check()?),
+            None => Ok(()),
+        }
+    }
+
+    /// Merges sorted, deduplicated runs into `out`, returning the number of records written.
+    ///
+    /// When a record appears in several runs, the one from the earliest run is kept. The first
+    /// error from any run or from `out` stops the merge and is returned.
+    pub fn merge_uniq<R: Read, W: Write>(&self, runs: Vec<R>, out: W) -> io::Result<u64> {
+        self.merge_with(runs, out, true, &mut ())
+    }
+
+    /// Like `merge_uniq`, but keeps every record. Equal records are written in the order of
+    /// their runs.
+    pub fn merge<R: Read, W: Write>(&self, runs: Vec<R>, out: W) -> io::Result<u64> {
+        self.merge_with(runs, out, false, &mut ())
+    }
+
+    /// Like `merge_uniq`, but reports its progress to `observer`. The number of records left in
+    /// the runs is unknown, so it is reported as `None`. See `progress::Observer`.
+    pub fn merge_uniq_observed<R, W, O>(
+        &self,
+        runs: Vec<R>,
+        out: W,
+        observer: &mut O,
+    ) -> io::Result<u64>
+    where
+        R: Read,
+        W: Write,
+        O: Observer + ?Sized,
+    {
+        self.merge_with(runs, out, true, observer)
+    }
+
+    /// Like `merge_uniq`, but spreads the work over a pipeline of threads, so reading, merging and
+    /// writing overlap instead of taking turns.
+    ///
+    /// Each run is read and decoded by its own thread, which keeps a batch of about `buffer_size`
+    /// bytes of records queued ahead of the merge while it decodes the next. The merge runs on the
+    /// calling thread, and a writer thread writes its output, with one buffer queued between them.
+    /// The queues are bounded, so a slow stage holds the others back instead of letting them
+    /// buffer without limit.
+    ///
+    /// The pipeline only pays off with cores to spare for it. On a single core, the stages take
+    /// turns anyway, and this is slower than `merge_uniq` by the cost of handing batches between
+    /// them.
+    pub fn merge_uniq_pipelined<R, W>(&self, runs: Vec<R>, out: W) -> io::Result<u64>
+    where
+        C: Sync,
+        C::Record: Send,
+        R: Read + Send,
+        W: Write + Send,
+    {
+        thread::scope(|scope| {
+            let sources = runs
+                .into_iter()
+                .map(|run| {
+                    let (tx, rx) = mpsc::sync_channel(1);
+                    scope.spawn(move || self.read_batches(run, tx));
+                    PipedRun {
+                        batch: Vec::new().into_iter(),
+                        rx,
+                        error: None,
+                    }
+                })
+                .collect();
+
+            let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(1);
+            let writer = scope.spawn(move || {
+                let mut out = out;
+                for chunk in rx {
+                    out.write_all(&chunk)?;
+                }
+                out.flush()
+            });
+
+            let out = self.output(ChannelWriter(tx), self.run_format)?;
+            let merged = self.merge_sources(sources, out, true, &mut ());
+            // If the writer failed, the merge only saw that it stopped listening.
+            match writer.join() {
+                Ok(Ok(())) => merged,
+                Ok(Err(e)) => Err(e),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        })
+    }
+
+    /// Reads `run` for `merge_uniq_pipelined`, sending its records in batches until it ends,
+    /// fails, or the merge stops listening.
+    fn read_batches<R: Read>(&self, run: R, tx: SyncSender<io::Result<Vec<C::Record>>>) {
+        let mut reader = match self.open(run, self.run_format) {
+            Ok(reader) => reader,
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                return;
+            }
+        };
+        loop {
+            let mut batch = Vec::new();
+            let mut size = 0;
+            let more = loop {
+                match reader.read(&self.codec) {
+                    Ok(Some(record)) => {
+                        size += self.codec.record_size(&record);
+                        batch.push(record);
+                        if size >= self.buffer_size {
+                            break true;
+                        }
+                    }
+                    Ok(None) => break false,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                }
+            };
+
+            if !batch.is_empty() && tx.send(Ok(batch)).is_err() {
+                return;
+            }
+            if !more {
+                return;
+            }
+        }
+    }
+
+    fn merge_with<R, W, O>(
+        &self,
+        runs: Vec<R>,
+        out: W,
+        unique: bool,
+        observer: &mut O,
+    ) -> io::Result<u64>
+    where
+        R: Read,
+        W: Write,
+        O: Observer + ?Sized,
+    {
+        let runs = runs
+            .into_iter()
+            .map(|r| self.open(r, self.run_format))
+            .collect::<io::Result<_>>()?;
+        let out = self.output(out, self.run_format)?;
+        self.merge_inputs(runs, out, unique, observer)
+    }
+
+    fn merge_inputs<R, W, O>(
+        &self,
+        runs: Vec<RunInput<R>>,
+        out: RunOutput<W>,
+        unique: bool,
+        observer: &mut O,
+    ) -> io::Result<u64>
+    where
+        R: Read,
+        W: Write,
+        O: Observer + ?Sized,
+    {
+        let runs = runs
+            .into_iter()
+            .map(|reader| Run {
+                reader,
+                codec: &self.codec,
+                error: None,
+            })
+            .collect();
+        self.merge_sources(runs, out, unique, observer)
+    }
+
+    /// Opens `run` for reading, as a run written by `write_run` if `formatted`.
+    fn open<R: Read>(&self, run: R, formatted: bool) -> io::Result<RunInput<R>> {
+        let reader = BufReader::with_capacity(self.buffer_size, run);
+        Ok(match formatted {
+            true => RunInput::Formatted(RunReader::open(reader)?),
+            false => RunInput::Records(reader),
+        })
+    }
+
+    /// Buffers `out` for writing, as a run in the format of `write_run` if `formatted`.
+    fn output<W: Write>(&self, out: W, formatted: bool) -> io::Result<RunOutput<W>> {
+        let out = BufWriter::with_capacity(self.buffer_size, out);
+        Ok(match formatted {
+            true => RunOutput::Formatted(RunWriter::new(out, RUN_BLOCK_SIZE, None)?),
+            false => RunOutput::Records(out),
+        })
+    }
+
+    fn merge_sources<S, W, O>(
+        &self,
+        runs: Vec<S>,
+        mut out: RunOutput<W>,
+        unique: bool,
+        observer: &mut O,
+    ) -> io::Result<u64>
+    where
+        S: RunSource<Item = C::Record>,
+        W: Write,
+        O: Observer + ?Sized,
+    {
+        self.check_cancelled()?;
+        let k = runs.len();
+        let mut positions = vec![0; k];
+        let mut tree = LoserTree::new(runs);
+        for i in 0..k {
+            check_run(&mut tree, i)?;
+            if tree.is_exhausted(i) {
+                observer.on_run_exhausted(i);
+            }
+        }
+
+        let mut written = 0u64;
+        while let Some((i, record)) = tree.pop() {
+            check_run(&mut tree, i)?;
+            self.check_order(&tree, i, &record, &mut positions, unique)?;
+            if tree.is_exhausted(i) {
+                observer.on_run_exhausted(i);
+            }
+            if unique {
+                while tree.peek() == Some(&record) {
+                    let (j, _) = tree.pop().unwrap();
+                    check_run(&mut tree, j)?;
+                    self.check_order(&tree, j, &record, &mut positions, unique)?;
+                    if tree.is_exhausted(j) {
+                        observer.on_run_exhausted(j);
+                    }
+                }
+            }
+
+            out.push(&self.codec, &record)?;
+            written += 1;
+            if written.is_multiple_of(PROGRESS_INTERVAL) {
+                observer.on_progress(written, None);
+                self.check_cancelled()?;
+            }
+        }
+
+        out.finish()?;
+        observer.on_progress(written, None);
+        Ok(written)
+    }
+
+    /// If validating, checks that the head of run `i` comes after `record`, which was just taken
+    /// from it. `positions` holds the index in each run of its head.
+    fn check_order<S: RunSource<Item = C::Record>>(
+        &self,
+        tree: &LoserTree<S>,
+        i: usize,
+        record: &C::Record,
+        positions: &mut [usize],
+        unique: bool,
+    ) -> io::Result<()> {
+        if !self.validate {
+            return Ok(());
+        }
+
+        positions[i] += 1;
+        let index = positions[i];
+        let error = match tree.head(i) {
+            Some(next) if next < record => RunError::OutOfOrder { index },
+            Some(next) if unique && next == record => RunError::Duplicate { index },
+            _ => return Ok(()),
+        };
+        let corrupt = CorruptRun {
+            run: i,
+            path: None,
+            error,
+        };
+        Err(io::Error::new(io::ErrorKind::InvalidData, corrupt))
+    }
+}
+
+/// The default memory budget of a `Sorter`.
+pub const DEFAULT_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+
+/// The default number of runs a `Sorter` merges at once.
+pub const DEFAULT_MAX_OPEN_RUNS: usize = 64;
+
+/// Sorts and deduplicates record streams too large to fit in memory.
+///
+/// The input is read in chunks of at most `memory_budget` bytes, as measured by
+/// `Codec::record_size`. Each chunk is sorted and spilled to a temporary file as a run. If there
+/// are more than `max_open_runs` runs, groups of neighbouring runs are merged into longer runs until
+/// there are few enough to merge into the output in one pass. The groups are chosen as by
+/// `kway::merge_uniq_fan_in`, by the size of the runs on disk.
+#[derive(Clone, Debug)]
+pub struct Sorter<C> {
+    merger: Merger<C>,
+    memory_budget: usize,
+    max_open_runs: usize,
+    temp_dir: PathBuf,
+    #[cfg(feature = "zstd")]
+    compression_level: Option<i32>,
+}
+
+impl<C: Codec> Sorter<C> {
+    pub fn new(codec: C) -> Self {
+        Sorter {
+            merger: Merger::new(codec),
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            max_open_runs: DEFAULT_MAX_OPEN_RUNS,
+            temp_dir: std::env::temp_dir(),
+            #[cfg(feature = "zstd")]
+            compression_level: None,
+        }
+    }
+
+    /// Sets the capacity of the read buffer of each run, and of the output buffer.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.merger = self.merger.buffer_size(buffer_size);
+        self
+    }
+
+    /// Makes sorting fail with a `progress::Cancelled` error soon after `cancel` is cancelled. The
+    /// spilled runs are removed as with any other error.
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.merger = self.merger.cancel_token(cancel);
+        self
+    }
+
+    /// Makes `merge_uniq_paths` read files written by `write_run`, and `sort_uniq`,
+    /// `merge_uniq_paths` and `BoundedMerger::finish` write their output as one. See
+    /// `Merger::run_format`. Spilled runs are always written in this format.
+    pub fn run_format(mut self, run_format: bool) -> Self {
+        self.merger = self.merger.run_format(run_format);
+        self
+    }
+
+    /// Makes `merge_uniq_paths` check that each file is sorted and deduplicated as it reads it,
+    /// failing with a `CorruptRun` error that names the first file that isn't. See
+    /// `Merger::validate_runs`.
+    pub fn validate_runs(mut self, validate: bool) -> Self {
+        self.merger = self.merger.validate_runs(validate);
+        self
+    }
+
+    /// Sets the number of bytes of records held in memory before a chunk is spilled.
+    ///
+    /// This doesn't include the buffers used while merging, which take up `buffer_size` bytes
+    /// for each open run.
+    pub fn memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// Sets the maximum number of runs open at the same time during a merge.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_open_runs` is less than two, since no progress could be made.
+    pub fn max_open_runs(mut self, max_open_runs: usize) -> Self {
+        assert!(max_open_runs >= 2, "a merge needs at least two runs");
+        self.max_open_runs = max_open_runs;
+        self
+    }
+
+    /// Sets the directory where runs are spilled. Defaults to `std::env::temp_dir()`.
+    pub fn temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = temp_dir.into();
+        self
+    }
+
+    /// Compresses spilled runs with zstd at `level`, trading CPU time for disk bandwidth.
+    ///
+    /// Runs are decompressed as they are streamed back in, which takes a zstd window of memory
+    /// per open run on top of `buffer_size`.
+    #[cfg(feature = "zstd")]
+    pub fn compress(mut self, level: i32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    /// Plans a `sort_uniq` or `merge_uniq_paths`, for `memory::estimate_peak_memory`, assuming
+    /// the input is large enough to fill `max_open_runs` runs. `record_size` is the largest
+    /// `Codec::record_size` of any record in the input.
+    pub fn plan(&self, record_size: usize) -> MergePlan {
+        // Spilled runs and intermediate merges always use the run format.
+        let merge = self
+            .merger
+            .external_plan(self.max_open_runs, record_size, true, false);
+        MergePlan::sort(
+            self.memory_budget,
+            record_size,
+            mem::size_of::<C::Record>(),
+            self.merger.buffer_size,
+            merge,
+        )
+    }
+
+    /// Sorts the records read from `input` into `out`, returning the number of records written.
+    ///
+    /// When a record appears several times, the first occurrence is kept. Spilled runs are
+    /// removed before this returns, even on error.
+    pub fn sort_uniq<R: Read, W: Write>(&self, input: R, out: W) -> io::Result<u64> {
+        let codec = &self.merger.codec;
+        let mut input = BufReader::with_capacity(self.merger.buffer_size, input);
+        let mut runs = Vec::new();
+        let mut chunk = Vec::new();
+        let mut chunk_size = 0;
+        let mut read = 0u64;
+        while let Some(record) = codec.read(&mut input)? {
+            read += 1;
+            if read.is_multiple_of(PROGRESS_INTERVAL) {
+                self.merger.check_cancelled()?;
+            }
+            chunk_size += codec.record_size(&record);
+            chunk.push(record);
+            if chunk_size >= self.memory_budget {
+                runs.push(self.spill(&mut chunk)?);
+                chunk_size = 0;
+            }
+        }
+
+        // Everything fit in memory, so there's nothing to merge.
+        if runs.is_empty() {
+            sort_chunk(&mut chunk);
+            return self.write_records(chunk, out, self.merger.run_format);
+        }
+        if !chunk.is_empty() {
+            runs.push(self.spill(&mut chunk)?);
+        }
+        drop(chunk);
+
+        self.merge_all(runs.into_iter().map(SorterRun::Temp).collect(), out)
+    }
+
+    /// Merges the sorted, deduplicated files at `paths` into `out`, returning the number of records
+    /// written. Of several equal records, the one from the earliest file is kept.
+    ///
+    /// No more than `max_open_runs` files are open at once. If there are more, groups of them are
+    /// first merged into runs in `temp_dir`, as when sorting, and those are removed before this
+    /// returns, even on error.
+    pub fn merge_uniq_paths<P: AsRef<Path>, W: Write>(
+        &self,
+        paths: &[P],
+        out: W,
+    ) -> io::Result<u64> {
+        let runs = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| SorterRun::Input(i, path.as_ref().to_owned()))
+            .collect();
+        self.merge_all(runs, out)
+    }
+
+    /// Merges `runs` into `out`, first merging groups of them into longer runs if there are more
+    /// than `max_open_runs`.
+    fn merge_all<W: Write>(&self, runs: Vec<SorterRun>, out: W) -> io::Result<u64> {
+        let sized = |run: SorterRun| Ok((fs::metadata(run.path())?.len(), run));
+        let runs = runs.into_iter().map(sized).collect::<io::Result<_>>()?;
+        let runs = kway::reduce_runs(runs, self.max_open_runs, |group| {
+            sized(SorterRun::Temp(
+                self.create_run(|w| self.merge_runs(group, w, true))?,
+            ))
+        })?;
+        self.merge_runs(runs, out, self.merger.run_format)
+    }
+
+    /// Sorts `chunk` and writes it to a new run, leaving `chunk` empty.
+    fn spill(&self, chunk: &mut Vec<C::Record>) -> io::Result<TempRun> {
+        sort_chunk(chunk);
+        self.write_run(chunk.drain(..))
+    }
+
+    /// Writes records that are already sorted and deduplicated to a new run.
+    fn write_run(&self, records: impl IntoIterator<Item = C::Record>) -> io::Result<TempRun> {
+        self.create_run(|w| self.write_records(records, w, true))
+    }
+
+    /// Creates a new run and fills it with `write`, compressing it if enabled.
+    fn create_run<F>(&self, write: F) -> io::Result<TempRun>
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<u64>,
+    {
+        let mut run = TempRun::create(&self.temp_dir)?;
+        let file = run.file.as_mut().unwrap();
+
+        #[cfg(feature = "zstd")]
+        if let Some(level) = self.compression_level {
+            let mut encoder = zstd::stream::Encoder::new(file, level)?;
+            write(&mut encoder)?;
+            encoder.finish()?;
+            return Ok(run.close());
+        }
+
+        write(file)?;
+        Ok(run.close())
+    }
+
+    /// Writes `records` to `out`, as a run in the format of `write_run` if `formatted`.
+    fn write_records<W: Write>(
+        &self,
+        records: impl IntoIterator<Item = C::Record>,
+        out: W,
+        formatted: bool,
+    ) -> io::Result<u64> {
+        let mut out = self.merger.output(out, formatted)?;
+        let mut written = 0;
+        for record in records {
+            out.push(&self.merger.codec, &record)?;
+            written += 1;
+        }
+        out.finish()?;
+        Ok(written)
+    }
+
+    /// Merges `runs` into `out`, writing a run in the format of `write_run` if `formatted`.
+    /// Spilled runs are removed once they have been merged.
+    fn merge_runs<W: Write>(
+        &self,
+        runs: Vec<SorterRun>,
+        out: W,
+        formatted: bool,
+    ) -> io::Result<u64> {
+        let merged = runs
+            .iter()
+            .map(|run| {
+                let formatted = matches!(run, SorterRun::Temp(_)) || self.merger.run_format;
+                self.merger.open(self.open_run(run)?, formatted)
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .and_then(|readers| {
+                let out = self.merger.output(out, formatted)?;
+                self.merger.merge_inputs(readers, out, true, &mut ())
+            });
+        merged.map_err(|mut e| {
+            // Report a corrupt input by its path and its position among all the inputs.
+            let (run, path) = match e.get_mut() {
+                Some(e) if e.is::<CorruptRun>() => {
+                    let corrupt = e.downcast_mut::<CorruptRun>().unwrap();
+                    (&mut corrupt.run, &mut corrupt.path)
+                }
+                Some(e) if e.is::<ChecksumMismatch>() => {
+                    let mismatch = e.downcast_mut::<ChecksumMismatch>().unwrap();
+                    (&mut mismatch.run, &mut mismatch.path)
+                }
+                _ => return e,
+            };
+            if let SorterRun::Input(index, input) = &runs[*run] {
+                *run = *index;
+                *path = Some(input.clone());
+            }
+            e
+        })
+    }
+
+    /// Opens `run` for reading, decompressing it if it was spilled with compression enabled.
+    fn open_run(&self, run: &SorterRun) -> io::Result<Box<dyn Read>> {
+        let file = File::open(run.path())?;
+
+        #[cfg(feature = "zstd")]
+        if let (SorterRun::Temp(_), Some(_)) = (run, self.compression_level) {
+            return Ok(Box::new(zstd::stream::Decoder::new(file)?));
+        }
+
+        Ok(Box::new(file))
+    }
+}
+
+/// Sorts and deduplicates the records read from `input` into `out` using a `Sorter` with the
+/// default settings, returning the number of records written.
+pub fn sort_uniq<C: Codec, R: Read, W: Write>(codec: C, input: R, out: W) -> io::Result<u64> {
+    Sorter::new(codec).sort_uniq(input, out)
+}
+
+/// Merges sorted, deduplicated inputs of any size within a memory budget.
+///
+/// Inputs are merged in memory as they are pushed. An input that would take the records held in
+/// memory past half of the `Sorter`'s memory budget (the other half is needed to merge into) is
+/// instead streamed to a temporary file, after spilling everything merged so far. `finish` then
+/// merges the spilled runs with whatever is left in memory.
+pub struct BoundedMerger<C: Codec> {
+    sorter: Sorter<C>,
+    merged: Vec<C::Record>,
+    merged_size: usize,
+    runs: Vec<TempRun>,
+}
+
+impl<C: Codec> BoundedMerger<C> {
+    /// Creates a merger with the default `Sorter` settings.
+    pub fn new(codec: C) -> Self {
+        Self::from_sorter(Sorter::new(codec))
+    }
+
+    /// Creates a merger that spills runs and merges them with the settings of `sorter`.
+    pub fn from_sorter(sorter: Sorter<C>) -> Self {
+        BoundedMerger {
+            sorter,
+            merged: Vec::new(),
+            merged_size: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Returns the number of runs spilled so far.
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Adds a sorted, deduplicated input to the merge.
+    ///
+    /// When a record appears in several inputs, the one from the earliest input is kept.
+    pub fn push<I: IntoIterator<Item = C::Record>>(&mut self, input: I) -> io::Result<()> {
+        let codec = &self.sorter.merger.codec;
+        let budget = self.sorter.memory_budget / 2;
+        let mut input = input.into_iter();
+        let mut chunk = Vec::new();
+        let mut size = self.merged_size;
+        for record in &mut input {
+            size += codec.record_size(&record);
+            chunk.push(record);
+            if size > budget {
+                // Spill what's in memory first, so that the runs stay in the order of the inputs.
+                if !self.merged.is_empty() {
+                    let merged = mem::take(&mut self.merged);
+                    self.merged_size = 0;
+                    self.runs.push(self.sorter.write_run(merged)?);
+                }
+                let run = self.sorter.write_run(chunk.into_iter().chain(input))?;
+                self.runs.push(run);
+                return Ok(());
+            }
+        }
+
+        self.merged = crate::merge_uniq(mem::take(&mut self.merged), chunk);
+        self.merged_size = self.merged.iter().map(|r| codec.record_size(r)).sum();
+        Ok(())
+    }
+
+    /// Writes the merged inputs to `out`, returning the number of records written.
+    ///
+    /// Spilled runs are removed before this returns, even on error.
+    pub fn finish<W: Write>(mut self, out: W) -> io::Result<u64> {
+        if self.runs.is_empty() {
+            let formatted = self.sorter.merger.run_format;
+            return self.sorter.write_records(self.merged, out, formatted);
+        }
+        if !self.merged.is_empty() {
+            let run = self.sorter.write_run(self.merged)?;
+            self.runs.push(run);
+        }
+        let runs = self.runs.into_iter().map(SorterRun::Temp).collect();
+        self.sorter.merge_all(runs, out)
+    }
+}
+
+/// Sorts `chunk`, keeping only the first of each group of equal records.
+fn sort_chunk<T: Ord>(chunk: &mut Vec<T>) {
+    chunk.sort();
+    chunk.dedup();
+}
+
+/// A run merged by a `Sorter`: either one of the caller's files, with its position among them, or
+/// one that it spilled.
+enum SorterRun {
+    Input(usize, PathBuf),
+    Temp(TempRun),
+}
+
+impl SorterRun {
+    fn path(&self) -> &Path {
+        match self {
+            SorterRun::Input(_, path) => path,
+            SorterRun::Temp(run) => &run.path,
+        }
+    }
+}
+
+/// A spilled run, which is removed when dropped.
+struct TempRun {
+    path: PathBuf,
+    /// The file is only kept open while the run is being written.
+    file: Option<File>,
+}
+
+impl TempRun {
+    fn create(dir: &Path) -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("kmerge-{}-{}.run", process::id(), n));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    return Ok(TempRun {
+                        path,
+                        file: Some(file),
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn close(mut self) -> Self {
+        self.file = None;
+        self
+    }
+}
+
+impl Drop for TempRun {
+    fn drop(&mut self) {
+        self.file = None;
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The part of each line that `LineMerger` compares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKey {
+    /// The whole line.
+    Line,
+    /// The field with the given index, counting from zero. Fields are separated by `separator`, or
+    /// by runs of ASCII whitespace if it's `None`. Lines with fewer fields have an empty key.
+    Field { index: usize, separator: Option<u8> },
+}
+
+impl LineKey {
+    /// Returns the range of `line` that holds its key.
+    fn extract(&self, line: &[u8]) -> Range<usize> {
+        let (index, separator) = match *self {
+            LineKey::Line => return 0..line.len(),
+            LineKey::Field { index, separator } => (index, separator),
+        };
+
+        let is_separator = |b: u8| match separator {
+            Some(sep) => b == sep,
+            None => b.is_ascii_whitespace(),
+        };
+        let mut fields = 0;
+        let mut i = 0;
+        loop {
+            if separator.is_none() {
+                while i < line.len() && is_separator(line[i]) {
+                    i += 1;
+                }
+            }
+            let start = i;
+            while i < line.len() && !is_separator(line[i]) {
+                i += 1;
+            }
+            if fields == index {
+                return start..i;
+            }
+            if i == line.len() {
+                return line.len()..line.len();
+            }
+            fields += 1;
+            i += 1;
+        }
+    }
+}
+
+/// Merges sorted text, line by line, like `sort -m`.
+///
+/// Lines are compared byte by byte, like `sort` in the C locale, and lines with equal keys are
+/// written in the order of their sources. Every line written ends in a newline, even if it didn't
+/// in its source.
+#[derive(Clone, Debug)]
+pub struct LineMerger {
+    key: LineKey,
+    unique: bool,
+}
+
+impl Default for LineMerger {
+    fn default() -> Self {
+        LineMerger::new()
+    }
+}
+
+impl LineMerger {
+    pub fn new() -> Self {
+        LineMerger {
+            key: LineKey::Line,
+            unique: false,
+        }
+    }
+
+    /// Sets the part of each line that is compared. Defaults to `LineKey::Line`.
+    pub fn key(mut self, key: LineKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Keeps only the first of each group of lines with equal keys, like `sort -m -u`.
+    pub fn unique(mut self, unique: bool) -> Self {
+        self.unique = unique;
+        self
+    }
+
+    /// Merges the lines of sources sorted by the key into `out`, returning the number of lines
+    /// written.
+    pub fn merge<R: BufRead, W: Write>(&self, sources: Vec<R>, out: W) -> io::Result<u64> {
+        let sources: Vec<_> = sources
+            .into_iter()
+            .map(|reader| LineSource {
+                reader,
+                key: self.key,
+                error: None,
+            })
+            .collect();
+
+        let k = sources.len();
+        let mut tree = LoserTree::new(sources);
+        for i in 0..k {
+            tree.source_mut(i).check()?;
+        }
+
+        let mut out = BufWriter::new(out);
+        let mut written = 0;
+        while let Some((i, line)) = tree.pop() {
+            tree.source_mut(i).check()?;
+            if self.unique {
+                while tree.peek() == Some(&line) {
+                    let (j, _) = tree.pop().unwrap();
+                    tree.source_mut(j).check()?;
+                }
+            }
+
+            out.write_all(&line.bytes)?;
+            out.write_all(b"\n")?;
+            written += 1;
+        }
+
+        out.flush()?;
+        Ok(written)
+    }
+}
+
+/// Merges the lines of sorted text sources into `out`, returning the number of lines written.
+///
+/// See `LineMerger` to compare lines by a field or drop duplicates.
+pub fn merge_lines<R: BufRead, W: Write>(sources: Vec<R>, out: W) -> io::Result<u64> {
+    LineMerger::new().merge(sources, out)
+}
+
+/// A line without its terminator, which compares by its key alone.
+struct Line {
+    bytes: Vec<u8>,
+    key: Range<usize>,
+}
+
+impl Line {
+    fn key(&self) -> &[u8] {
+        &self.bytes[self.key.clone()]
+    }
+}
+
+impl Ord for Line {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(other.key())
+    }
+}
+
+impl PartialOrd for Line {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Line {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Line {}
+
+/// A text source being read by a `LineMerger`. Like `Run`, it keeps read errors for the merge to
+/// check.
+struct LineSource<R> {
+    reader: R,
+    key: LineKey,
+    error: Option<io::Error>,
+}
+
+impl<R> LineSource<R> {
+    fn check(&mut self) -> io::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
+
+impl<R: BufRead> Iterator for LineSource<R> {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        let mut bytes = Vec::new();
+        match self.reader.read_until(b'\n', &mut bytes) {
+            Ok(0) => None,
+            Ok(_) => {
+                if bytes.last() == Some(&b'\n') {
+                    bytes.pop();
+                }
+                let key = self.key.extract(&bytes);
+                Some(Line { bytes, key })
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// The records of a run, as the merge sees them. Errors end the run early and are kept until the
+/// merge checks for them.
+trait RunSource: Iterator {
+    /// Returns the error that ended the run, if any.
+    fn check(&mut self) -> io::Result<()>;
+}
+
+/// Returns the error that ended run `i` of `tree`, if any, naming the run if it's a
+/// `ChecksumMismatch`.
+fn check_run<S>(tree: &mut LoserTree<S>, i: usize) -> io::Result<()>
+where
+    S: RunSource,
+    S::Item: Ord,
+{
+    tree.source_mut(i).check().map_err(|mut e| {
+        let mismatch = e
+            .get_mut()
+            .and_then(|e| e.downcast_mut::<ChecksumMismatch>());
+        if let Some(mismatch) = mismatch {
+            mismatch.run = i;
+        }
+        e
+    })
+}
+
+/// A run being read by a merge.
+struct Run<'a, R, C> {
+    reader: RunInput<R>,
+    codec: &'a C,
+    error: Option<io::Error>,
+}
+
+impl<R: Read, C: Codec> RunSource for Run<'_, R, C> {
+    fn check(&mut self) -> io::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
+
+impl<R: Read, C: Codec> Iterator for Run<'_, R, C> {
+    type Item = C::Record;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        match self.reader.read(self.codec) {
+            Ok(record) => record,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// The records of a run, either bare or in the format of `write_run`.
+enum RunInput<R> {
+    Records(BufReader<R>),
+    Formatted(RunReader<BufReader<R>>),
+}
+
+impl<R: Read> RunInput<R> {
+    fn read<C: Codec>(&mut self, codec: &C) -> io::Result<Option<C::Record>> {
+        match self {
+            RunInput::Records(reader) => codec.read(reader),
+            RunInput::Formatted(run) => run.read(codec),
+        }
+    }
+}
+
+/// Where a merge writes its records, either bare or as a run in the format of `write_run`.
+enum RunOutput<W: Write> {
+    Records(BufWriter<W>),
+    Formatted(RunWriter<BufWriter<W>>),
+}
+
+impl<W: Write> RunOutput<W> {
+    fn push<C: Codec>(&mut self, codec: &C, record: &C::Record) -> io::Result<()> {
+        match self {
+            RunOutput::Records(out) => codec.write(out, record),
+            RunOutput::Formatted(run) => run.push(codec, record),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            RunOutput::Records(mut out) => out.flush(),
+            RunOutput::Formatted(run) => run.finish().map(drop),
+        }
+    }
+}
+
+/// A run being read by its own thread for `Merger::merge_uniq_pipelined`.
+struct PipedRun<T> {
+    batch: std::vec::IntoIter<T>,
+    rx: Receiver<io::Result<Vec<T>>>,
+    error: Option<io::Error>,
+}
+
+impl<T> Iterator for PipedRun<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(record) = self.batch.next() {
+                return Some(record);
+            }
+            // The reader hangs up once it has sent the whole run.
+            match self.rx.recv() {
+                Ok(Ok(batch)) => self.batch = batch.into_iter(),
+                Ok(Err(e)) => {
+                    self.error = Some(e);
+                    return None;
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+impl<T> RunSource for PipedRun<T> {
+    fn check(&mut self) -> io::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
+
+/// Sends what is written to it to the writer thread of `Merger::merge_uniq_pipelined`.
+struct ChannelWriter(SyncSender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the writer thread stopped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checked::validate_run;
+    use crate::progress::Cancelled;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    fn encode<C: Codec>(codec: &C, records: &[C::Record]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for record in records {
+            codec.write(&mut buf, record).unwrap();
+        }
+        buf
+    }
+
+    fn decode<C: Codec>(codec: &C, mut buf: &[u8]) -> Vec<C::Record> {
+        let mut records = Vec::new();
+        while let Some(record) = codec.read(&mut buf).unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[quickcheck]
+    fn merge_uniq_length_prefixed(runs: Vec<Vec<Vec<u8>>>, buffer_size: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        let encoded: Vec<_> = runs.iter().map(|r| encode(&LengthPrefixed, r)).collect();
+        let mut out = Vec::new();
+        let written = Merger::new(LengthPrefixed)
+            .buffer_size(buffer_size as usize + 1)
+            .merge_uniq(encoded.iter().map(Vec::as_slice).collect(), &mut out)
+            .unwrap();
+
+        written == expected.len() as u64 && decode(&LengthPrefixed, &out) == expected
+    }
+
+    #[quickcheck]
+    fn merge_uniq_fixed_size(runs: Vec<Vec<u32>>, buffer_size: u8) -> bool {
+        let codec = FixedSize::new(4);
+        let runs: Vec<Vec<_>> = runs
+            .into_iter()
+            .map(|r| {
+                let r = sorted_unique(r);
+                r.into_iter().map(|x| x.to_be_bytes().to_vec()).collect()
+            })
+            .collect();
+        let expected = sorted_unique(runs.concat());
+
+        let encoded: Vec<_> = runs.iter().map(|r| encode(&codec, r)).collect();
+        let mut out = Vec::new();
+        Merger::new(codec)
+            .buffer_size(buffer_size as usize + 1)
+            .merge_uniq(encoded.iter().map(Vec::as_slice).collect(), &mut out)
+            .unwrap();
+
+        decode(&codec, &out) == expected
+    }
+
+    #[quickcheck]
+    fn merge_uniq_pipelined_impl(runs: Vec<Vec<Vec<u8>>>, buffer_size: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let encoded: Vec<_> = runs.iter().map(|r| encode(&LengthPrefixed, r)).collect();
+        let merger = Merger::new(LengthPrefixed).buffer_size(buffer_size as usize + 1);
+
+        let mut expected = Vec::new();
+        let expected_written = merger
+            .merge_uniq(encoded.iter().map(Vec::as_slice).collect(), &mut expected)
+            .unwrap();
+        let mut out = Vec::new();
+        let written = merger
+            .merge_uniq_pipelined(encoded.iter().map(Vec::as_slice).collect(), &mut out)
+            .unwrap();
+
+        written == expected_written && out == expected
+    }
+
+    /// Fails every read or write.
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken run"))
+        }
+    }
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken output"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn merge_uniq_pipelined_errors() {
+        let merger = Merger::new(FixedSize::new(1)).buffer_size(1);
+        let run: Vec<u8> = (0..=255).collect();
+
+        let runs: Vec<Box<dyn Read + Send>> = vec![Box::new(&run[..]), Box::new(Broken)];
+        let e = merger.merge_uniq_pipelined(runs, io::sink()).unwrap_err();
+        assert_eq!(e.to_string(), "broken run");
+
+        let e = merger
+            .merge_uniq_pipelined(vec![&run[..], &run[..]], Broken)
+            .unwrap_err();
+        assert_eq!(e.to_string(), "broken output");
+    }
+
+    /// Creates an empty directory to spill runs into.
+    fn spill_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("kmerge-test-{}-{}", process::id(), n));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[quickcheck]
+    fn sort_uniq_impl(records: Vec<Vec<u8>>, memory_budget: u8, max_open_runs: u8) -> bool {
+        let expected = sorted_unique(records.clone());
+
+        let dir = spill_dir();
+        let mut out = Vec::new();
+        let written = Sorter::new(LengthPrefixed)
+            .buffer_size(16)
+            .memory_budget(memory_budget as usize * 8)
+            .max_open_runs(max_open_runs as usize % 4 + 2)
+            .temp_dir(&dir)
+            .sort_uniq(&encode(&LengthPrefixed, &records)[..], &mut out)
+            .unwrap();
+
+        let cleaned_up = fs::read_dir(&dir).unwrap().next().is_none();
+        fs::remove_dir(&dir).unwrap();
+        cleaned_up && written == expected.len() as u64 && decode(&LengthPrefixed, &out) == expected
+    }
+
+    #[quickcheck]
+    fn validate_runs_impl(runs: Vec<Vec<u8>>, sort: bool) -> bool {
+        let runs: Vec<_> = match sort {
+            true => runs.into_iter().map(sorted_unique).collect(),
+            false => runs,
+        };
+        // Records of one byte are encoded as themselves.
+        let result = Merger::new(FixedSize::new(1))
+            .validate_runs(true)
+            .merge_uniq(runs.iter().map(Vec::as_slice).collect(), io::sink());
+
+        // Each run is read in order, so the error is the first one in whichever run it's from.
+        match result {
+            Ok(_) => runs.iter().all(|run| validate_run(run).is_ok()),
+            Err(e) => {
+                let corrupt = e.get_ref().unwrap().downcast_ref::<CorruptRun>().unwrap();
+                e.kind() == io::ErrorKind::InvalidData
+                    && corrupt.path.is_none()
+                    && validate_run(&runs[corrupt.run]) == Err(corrupt.error)
+            }
+        }
+    }
+
+    #[test]
+    fn validate_runs_by_path() {
+        let dir = spill_dir();
+        let paths: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        for (path, run) in paths.iter().zip([&[1, 4][..], &[2, 3], &[5, 5], &[3, 6]]) {
+            fs::write(path, run).unwrap();
+        }
+
+        // Sorted runs may repeat records when duplicates are kept.
+        let files = paths.iter().map(|path| File::open(path).unwrap()).collect();
+        let merger = Merger::new(FixedSize::new(1)).validate_runs(true);
+        assert_eq!(merger.merge(files, io::sink()).unwrap(), 8);
+
+        let e = Sorter::new(FixedSize::new(1))
+            .max_open_runs(2)
+            .temp_dir(&dir)
+            .validate_runs(true)
+            .merge_uniq_paths(&paths, io::sink())
+            .unwrap_err();
+        let expected = CorruptRun {
+            run: 2,
+            path: Some(paths[2].clone()),
+            error: RunError::Duplicate { index: 1 },
+        };
+        assert_eq!(e.get_ref().unwrap().downcast_ref(), Some(&expected));
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "run 2 ({}) is corrupt: element 1 is a duplicate",
+                paths[2].display()
+            )
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[quickcheck]
+    fn merge_uniq_paths_impl(inputs: Vec<Vec<u8>>, max_open_runs: u8) -> bool {
+        // Each record is a key followed by the index of its file, to check that ties go to the
+        // earliest file.
+        let inputs: Vec<Vec<[u8; 2]>> = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, keys)| {
+                sorted_unique(keys)
+                    .into_iter()
+                    .map(|key| [key, i as u8])
+                    .collect()
+            })
+            .collect();
+        let mut expected: Vec<_> = inputs.concat();
+        expected.sort_by_key(|&[key, _]| key);
+        expected.dedup_by_key(|&mut [key, _]| key);
+
+        let (input_dir, dir) = (spill_dir(), spill_dir());
+        let paths: Vec<_> = (0..inputs.len())
+            .map(|i| input_dir.join(format!("{}.run", i)))
+            .collect();
+        for (path, input) in paths.iter().zip(&inputs) {
+            fs::write(path, input.concat()).unwrap();
+        }
+        let schema = BinarySchema::new(2, vec![KeyField::new(0, FieldType::U8)]);
+        let mut out = Vec::new();
+        let written = Sorter::new(schema)
+            .buffer_size(16)
+            .max_open_runs(max_open_runs as usize % 4 + 2)
+            .temp_dir(&dir)
+            .merge_uniq_paths(&paths, &mut out)
+            .unwrap();
+
+        let cleaned_up = fs::read_dir(&dir).unwrap().next().is_none();
+        fs::remove_dir(&dir).unwrap();
+        fs::remove_dir_all(&input_dir).unwrap();
+        cleaned_up && written == expected.len() as u64 && out == expected.concat()
+    }
+
+    #[quickcheck]
+    fn bounded_merger_impl(inputs: Vec<Vec<Vec<u8>>>, memory_budget: u8) -> bool {
+        let inputs: Vec<_> = inputs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(inputs.concat());
+
+        let dir = spill_dir();
+        let sorter = Sorter::new(LengthPrefixed)
+            .buffer_size(16)
+            .memory_budget(memory_budget as usize * 16)
+            .max_open_runs(3)
+            .temp_dir(&dir);
+        let mut merger = BoundedMerger::from_sorter(sorter);
+        for input in inputs {
+            merger.push(input).unwrap();
+        }
+        let mut out = Vec::new();
+        let written = merger.finish(&mut out).unwrap();
+
+        let cleaned_up = fs::read_dir(&dir).unwrap().next().is_none();
+        fs::remove_dir(&dir).unwrap();
+        cleaned_up && written == expected.len() as u64 && decode(&LengthPrefixed, &out) == expected
+    }
+
+    #[quickcheck]
+    fn sort_uniq_binary_schema(records: Vec<(u16, i32, i16, u16)>, memory_budget: u8) -> bool {
+        // Small ranges make ties common, so that every field gets compared.
+        let records: Vec<_> = records
+            .into_iter()
+            .map(|(a, b, c, d)| (a % 3, b % 3, (f32::from(c) / 8.0).to_bits(), d % 3))
+            .collect();
+
+        // Keyed by the `i32` descending, then the `u16`, then the `f32`, then the bytes.
+        let schema = BinarySchema::new(
+            12,
+            vec![
+                KeyField::new(2, FieldType::I32).direction(Direction::Descending),
+                KeyField::new(0, FieldType::U16).endian(Endian::Little),
+                KeyField::new(6, FieldType::F32).endian(Endian::Little),
+                KeyField::new(10, FieldType::Bytes(2)),
+            ],
+        );
+        let encode_record = |&(a, b, c, d): &(u16, i32, u32, u16)| {
+            [
+                &a.to_le_bytes()[..],
+                &b.to_be_bytes(),
+                &c.to_le_bytes(),
+                &d.to_be_bytes(),
+            ]
+            .concat()
+        };
+
+        let mut expected = records.clone();
+        expected.sort_by(|x, y| {
+            (y.1.cmp(&x.1))
+                .then(x.0.cmp(&y.0))
+                .then(f32::from_bits(x.2).total_cmp(&f32::from_bits(y.2)))
+                .then(x.3.cmp(&y.3))
+        });
+        expected.dedup();
+        let expected: Vec<u8> = expected.iter().flat_map(encode_record).collect();
+
+        let dir = spill_dir();
+        let mut out = Vec::new();
+        Sorter::new(schema)
+            .buffer_size(16)
+            .memory_budget(memory_budget as usize * 8)
+            .max_open_runs(3)
+            .temp_dir(&dir)
+            .sort_uniq(
+                &records.iter().flat_map(encode_record).collect::<Vec<_>>()[..],
+                &mut out,
+            )
+            .unwrap();
+
+        fs::remove_dir(&dir).unwrap();
+        out == expected
+    }
+
+    #[test]
+    #[should_panic(expected = "key field at offset 6 doesn't fit in a record")]
+    fn binary_schema_field_out_of_bounds() {
+        BinarySchema::new(8, vec![KeyField::new(6, FieldType::U32)]);
+    }
+
+    #[test]
+    fn sort_uniq_cancelled() {
+        let records: Vec<_> = (0..300_000u32).rev().map(u32::to_be_bytes).collect();
+        let input = records.concat();
+
+        // Cancel at each check in turn, covering both reading the input and each merge pass.
+        for checks in 1.. {
+            let dir = spill_dir();
+            let left = AtomicUsize::new(checks);
+            let cancel = CancelToken::from_fn(move || left.fetch_sub(1, Ordering::Relaxed) == 1);
+            let result = Sorter::new(FixedSize::new(4))
+                .memory_budget(1 << 20)
+                .max_open_runs(3)
+                .temp_dir(&dir)
+                .cancel_token(cancel)
+                .sort_uniq(&input[..], io::sink());
+
+            let cleaned_up = fs::read_dir(&dir).unwrap().next().is_none();
+            fs::remove_dir(&dir).unwrap();
+            assert!(cleaned_up);
+            match result {
+                Ok(written) => {
+                    assert!(checks > 8);
+                    assert_eq!(written, 300_000);
+                    break;
+                }
+                Err(e) => assert!(e.get_ref().is_some_and(|e| e.is::<Cancelled>())),
+            }
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[quickcheck]
+    fn sort_uniq_compressed(records: Vec<Vec<u8>>, memory_budget: u8) -> bool {
+        let expected = sorted_unique(records.clone());
+
+        let dir = spill_dir();
+        let mut out = Vec::new();
+        Sorter::new(LengthPrefixed)
+            .memory_budget(memory_budget as usize * 8)
+            .max_open_runs(2)
+            .compress(3)
+            .temp_dir(&dir)
+            .sort_uniq(&encode(&LengthPrefixed, &records)[..], &mut out)
+            .unwrap();
+
+        let cleaned_up = fs::read_dir(&dir).unwrap().next().is_none();
+        fs::remove_dir(&dir).unwrap();
+        cleaned_up && decode(&LengthPrefixed, &out) == expected
+    }
+
+    #[cfg(feature = "serde")]
+    #[quickcheck]
+    fn sort_uniq_bincode(records: Vec<(u16, String)>, memory_budget: u8) -> bool {
+        let codec = Bincode::new();
+        let expected = sorted_unique(records.clone());
+
+        let dir = spill_dir();
+        let mut out = Vec::new();
+        Sorter::new(codec)
+            .memory_budget(memory_budget as usize * 8)
+            .max_open_runs(2)
+            .temp_dir(&dir)
+            .sort_uniq(&encode(&codec, &records)[..], &mut out)
+            .unwrap();
+
+        let cleaned_up = fs::read_dir(&dir).unwrap().next().is_none();
+        fs::remove_dir(&dir).unwrap();
+        cleaned_up && decode(&codec, &out) == expected
+    }
+
+    #[test]
+    fn line_key_fields() {
+        let whitespace = |index| LineKey::Field {
+            index,
+            separator: None,
+        };
+        let comma = |index| LineKey::Field {
+            index,
+            separator: Some(b','),
+        };
+        let line = b"  a bb\tccc ";
+        let key = |k: LineKey| &line[k.extract(line)];
+
+        assert_eq!(key(LineKey::Line), line);
+        assert_eq!(key(whitespace(0)), b"a");
+        assert_eq!(key(whitespace(2)), b"ccc");
+        assert_eq!(key(whitespace(3)), b"");
+
+        let line = b"x,,z";
+        let key = |k: LineKey| &line[k.extract(line)];
+        assert_eq!(key(comma(0)), b"x");
+        assert_eq!(key(comma(1)), b"");
+        assert_eq!(key(comma(2)), b"z");
+        assert_eq!(key(comma(3)), b"");
+    }
+
+    #[quickcheck]
+    fn merge_lines_by_field(sources: Vec<Vec<(u8, u8)>>, unique: bool) -> bool {
+        // Lines are "<key> <value>", sorted by key only, so lines with equal keys stay in the
+        // order they were generated in.
+        let sources: Vec<Vec<String>> = sources
+            .into_iter()
+            .map(|mut source| {
+                source.sort_by_key(|&(k, _)| k);
+                source
+                    .into_iter()
+                    .map(|(k, v)| format!("{:03} {}", k, v))
+                    .collect()
+            })
+            .collect();
+
+        let mut expected = sources.concat();
+        expected.sort_by(|x, y| x[..3].cmp(&y[..3]));
+        if unique {
+            expected.dedup_by(|x, y| x[..3] == y[..3]);
+        }
+
+        let mut out = Vec::new();
+        let written = LineMerger::new()
+            .key(LineKey::Field {
+                index: 0,
+                separator: None,
+            })
+            .unique(unique)
+            .merge(
+                sources
+                    .iter()
+                    .map(|s| io::Cursor::new(s.join("\n")))
+                    .collect(),
+                &mut out,
+            )
+            .unwrap();
+
+        let actual: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        written == expected.len() as u64 && actual == expected
+    }
+
+    #[quickcheck]
+    fn merge_keeps_duplicates(runs: Vec<Vec<u8>>) -> bool {
+        let codec = FixedSize::new(1);
+        let runs: Vec<Vec<_>> = runs
+            .into_iter()
+            .map(|mut r| {
+                r.sort_unstable();
+                r.into_iter().map(|x| vec![x]).collect()
+            })
+            .collect();
+        let mut expected = runs.concat();
+        expected.sort();
+
+        let encoded: Vec<_> = runs.iter().map(|r| encode(&codec, r)).collect();
+        let mut out = Vec::new();
+        Merger::new(codec)
+            .merge(encoded.iter().map(Vec::as_slice).collect(), &mut out)
+            .unwrap();
+
+        decode(&codec, &out) == expected
+    }
+
+    #[quickcheck]
+    fn write_run_round_trip(records: Vec<Vec<u8>>, stride: u8) -> bool {
+        let records = sorted_unique(records);
+        let stride = Some(stride as usize).filter(|&stride| stride > 0);
+        let mut run = Vec::new();
+        let written = write_run(&LengthPrefixed, &mut run, &records, stride).unwrap();
+
+        let info = read_run_info(&LengthPrefixed, io::Cursor::new(&run)).unwrap();
+        let index = match stride {
+            Some(stride) => records.iter().step_by(stride).cloned().collect(),
+            None => Vec::new(),
+        };
+        written == records.len() as u64
+            && read_run(&LengthPrefixed, &run[..]).unwrap() == records
+            && info
+                == RunInfo {
+                    count: written,
+                    min: records.first().cloned(),
+                    max: records.last().cloned(),
+                    index_stride: stride,
+                    index,
+                }
+    }
+
+    #[test]
+    fn read_run_rejects_damage() {
+        let codec = FixedSize::new(4);
+        let records: Vec<_> = (0..40_000u32).map(|x| x.to_be_bytes().to_vec()).collect();
+        let mut run = Vec::new();
+        write_run(&codec, &mut run, &records, Some(100)).unwrap();
+
+        let message = |run: &[u8]| read_run(&codec, run).unwrap_err().to_string();
+        assert_eq!(
+            message(&run[..run.len() / 2]),
+            "corrupt run: run is truncated"
+        );
+        assert_eq!(
+            message(&run[..run.len() - 1]),
+            "corrupt run: trailer length does not match"
+        );
+        assert_eq!(message(b"records"), "corrupt run: not a kmerge run");
+
+        let mut flipped = run.clone();
+        flipped[1000] ^= 1;
+        assert_eq!(message(&flipped), "run 0 has a corrupt block at byte 8");
+        let mut flipped = run.clone();
+        flipped[run.len() - 20] ^= 1;
+        assert_eq!(
+            message(&flipped),
+            "corrupt run: trailer does not match its checksum"
+        );
+        let mut version = run.clone();
+        version[6] = 9;
+        assert_eq!(message(&version), "unsupported run format version 9");
+    }
+
+    #[test]
+    fn merge_reports_corrupt_block() {
+        let codec = FixedSize::new(4);
+        let records: Vec<_> = (0..40_000u32).map(|x| x.to_be_bytes().to_vec()).collect();
+        let mut run = Vec::new();
+        write_run(&codec, &mut run, &records, None).unwrap();
+        // The second block starts after the header and the first block of 64KiB.
+        let mut flipped = run.clone();
+        flipped[70_000] ^= 1;
+
+        let err = Merger::new(codec)
+            .run_format(true)
+            .merge_uniq(vec![&run[..], &flipped[..]], io::sink())
+            .unwrap_err();
+        let expected = ChecksumMismatch {
+            run: 1,
+            path: None,
+            offset: 8 + 4 + 65_536 + 4,
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.get_ref().unwrap().downcast_ref(), Some(&expected));
+        assert_eq!(err.to_string(), "run 1 has a corrupt block at byte 65552");
+    }
+
+    #[quickcheck]
+    fn merge_uniq_run_format(runs: Vec<Vec<Vec<u8>>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        let encoded: Vec<_> = runs
+            .iter()
+            .map(|r| {
+                let mut run = Vec::new();
+                write_run(&LengthPrefixed, &mut run, r, None).unwrap();
+                run
+            })
+            .collect();
+        let mut out = Vec::new();
+        Merger::new(LengthPrefixed)
+            .run_format(true)
+            .merge_uniq(encoded.iter().map(Vec::as_slice).collect(), &mut out)
+            .unwrap();
+
+        read_run(&LengthPrefixed, &out[..]).unwrap() == expected
+    }
+
+    #[quickcheck]
+    fn sort_uniq_run_format(records: Vec<Vec<u8>>, memory_budget: u8) -> bool {
+        let expected = sorted_unique(records.clone());
+
+        let dir = spill_dir();
+        let mut out = Vec::new();
+        Sorter::new(LengthPrefixed)
+            .buffer_size(16)
+            .memory_budget(memory_budget as usize * 8)
+            .max_open_runs(3)
+            .temp_dir(&dir)
+            .run_format(true)
+            .sort_uniq(&encode(&LengthPrefixed, &records)[..], &mut out)
+            .unwrap();
+
+        fs::remove_dir(&dir).unwrap();
+        read_run(&LengthPrefixed, &out[..]).unwrap() == expected
+    }
+
+    #[test]
+    fn merge_truncated_run_format() {
+        let codec = FixedSize::new(1);
+        let mut run = Vec::new();
+        write_run(&codec, &mut run, &[vec![1], vec![2], vec![3]], None).unwrap();
+
+        let err = Merger::new(codec)
+            .run_format(true)
+            .merge_uniq(vec![&run[..], &run[..run.len() - 20]], io::sink())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_run() {
+        let codec = FixedSize::new(4);
+        let good = encode(&codec, &[vec![0, 0, 0, 1], vec![0, 0, 0, 3]]);
+        let truncated = [0, 0, 0, 2, 0, 0];
+
+        let err = Merger::new(codec)
+            .merge_uniq(vec![&good[..], &truncated[..]], io::sink())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn corrupt_length_prefix() {
+        let mut run = u32::MAX.to_le_bytes().to_vec();
+        run.extend_from_slice(b"short");
+
+        let err = LengthPrefixed.read(&mut &run[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,277 @@
+//! Merging sorted runs with tokio's asynchronous I/O.
+//!
+//! This is the asynchronous counterpart of `external::Merger`, and reads the same `Codec`s. Each
+//! run is read and decoded by its own task, which sends batches of records ahead of the merge
+//! over a bounded channel, so reads from different runs overlap with each other and with the
+//! merge itself.
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::external::{Codec, DEFAULT_BUFFER_SIZE};
+use crate::kway::LoserTree;
+
+/// The number of batches each run's task decodes ahead of the merge.
+const BATCHES_AHEAD: usize = 2;
+
+/// A batch of records decoded by a run's task. An empty batch marks the end of the run.
+type Batch<T> = io::Result<Vec<T>>;
+
+/// Merges sorted runs of records framed by a `Codec`, asynchronously.
+#[derive(Clone, Debug)]
+pub struct AsyncMerger<C> {
+    codec: C,
+    buffer_size: usize,
+}
+
+impl<C> AsyncMerger<C>
+where
+    C: Codec + Clone + Send + 'static,
+    C::Record: Send + 'static,
+{
+    pub fn new(codec: C) -> Self {
+        AsyncMerger {
+            codec,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets the size of the reads from each run, and of the writes to the output.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// Merges sorted, deduplicated runs into `out`, returning the number of records written.
+    ///
+    /// When a record appears in several runs, the one from the earliest run is kept. The first
+    /// error from any run or from `out` stops the merge and is returned. Must be called from
+    /// within a tokio runtime, since the runs are read by spawned tasks.
+    pub async fn merge_uniq<R, W>(&self, runs: Vec<R>, mut out: W) -> io::Result<u64>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin,
+    {
+        let mut sources = Vec::with_capacity(runs.len());
+        for run in runs {
+            let (tx, rx) = mpsc::channel(BATCHES_AHEAD);
+            tokio::spawn(read_run(run, self.codec.clone(), self.buffer_size, tx));
+            sources.push(Source {
+                batch: VecDeque::new(),
+                rx,
+                done: false,
+            });
+        }
+        for source in &mut sources {
+            source.refill().await?;
+        }
+
+        let mut tree = LoserTree::new(sources);
+        let mut staged = Vec::with_capacity(self.buffer_size);
+        let mut last = None;
+        let mut written = 0;
+        while let Some(i) = tree.peek_source() {
+            // `pop` takes the next record from the same source, so it has to be on hand.
+            tree.source_mut(i).refill().await?;
+            let (_, record) = tree.pop().unwrap();
+            if last.as_ref() == Some(&record) {
+                continue;
+            }
+
+            self.codec.write(&mut staged, &record)?;
+            written += 1;
+            last = Some(record);
+            if staged.len() >= self.buffer_size {
+                out.write_all(&staged).await?;
+                staged.clear();
+            }
+        }
+
+        out.write_all(&staged).await?;
+        out.flush().await?;
+        Ok(written)
+    }
+
+    /// Merges the sorted, deduplicated runs stored in the files at `paths` into a new file at
+    /// `out`, returning the number of records written.
+    pub async fn merge_uniq_files<P, Q>(&self, paths: &[P], out: Q) -> io::Result<u64>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut runs = Vec::with_capacity(paths.len());
+        for path in paths {
+            runs.push(File::open(path).await?);
+        }
+        let out = File::create(out).await?;
+        self.merge_uniq(runs, out).await
+    }
+}
+
+/// Reads `run`, sending its records to the merge in batches until it ends, fails, or the merge
+/// stops listening.
+async fn read_run<R, C>(
+    mut run: R,
+    codec: C,
+    buffer_size: usize,
+    tx: mpsc::Sender<Batch<C::Record>>,
+) where
+    R: AsyncRead + Unpin,
+    C: Codec,
+{
+    let mut buf = Vec::with_capacity(buffer_size);
+    loop {
+        buf.reserve(buffer_size);
+        let n = match run.read_buf(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+
+        let batch = match decode_complete(&codec, &mut buf) {
+            Ok(batch) => batch,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+
+        if n == 0 {
+            let end = if buf.is_empty() {
+                Ok(Vec::new())
+            } else {
+                Err(io::ErrorKind::UnexpectedEof.into())
+            };
+            let _ = tx.send(end).await;
+            return;
+        }
+        if !batch.is_empty() && tx.send(Ok(batch)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Decodes every complete record at the front of `buf`, leaving any partial record behind.
+fn decode_complete<C: Codec>(codec: &C, buf: &mut Vec<u8>) -> io::Result<Vec<C::Record>> {
+    let mut batch = Vec::new();
+    let mut rest = &buf[..];
+    loop {
+        let before = rest;
+        match codec.read(&mut rest) {
+            Ok(Some(record)) => batch.push(record),
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                rest = before;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let consumed = buf.len() - rest.len();
+    buf.drain(..consumed);
+    Ok(batch)
+}
+
+/// The merge's end of a run: the batch being merged and the channel the next ones arrive on.
+struct Source<T> {
+    batch: VecDeque<T>,
+    rx: mpsc::Receiver<Batch<T>>,
+    done: bool,
+}
+
+impl<T> Source<T> {
+    /// Waits for the next batch if the current one is used up.
+    async fn refill(&mut self) -> io::Result<()> {
+        if !self.batch.is_empty() || self.done {
+            return Ok(());
+        }
+
+        match self.rx.recv().await {
+            Some(Ok(batch)) if batch.is_empty() => self.done = true,
+            Some(Ok(batch)) => self.batch = batch.into(),
+            Some(Err(e)) => return Err(e),
+            None => {
+                return Err(io::Error::other(
+                    "a run's reader stopped before the end of the run",
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Iterator for Source<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.batch.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external::{FixedSize, LengthPrefixed};
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+    use std::io::Cursor;
+
+    fn encode<C: Codec>(codec: &C, records: &[C::Record]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for record in records {
+            codec.write(&mut buf, record).unwrap();
+        }
+        buf
+    }
+
+    fn decode<C: Codec>(codec: &C, mut buf: &[u8]) -> Vec<C::Record> {
+        let mut records = Vec::new();
+        while let Some(record) = codec.read(&mut buf).unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[quickcheck]
+    fn merge_uniq_impl(runs: Vec<Vec<Vec<u8>>>, buffer_size: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        let encoded = runs
+            .iter()
+            .map(|r| Cursor::new(encode(&LengthPrefixed, r)))
+            .collect();
+        let mut out = Vec::new();
+        let merger = AsyncMerger::new(LengthPrefixed).buffer_size(buffer_size as usize);
+        let written = block_on(merger.merge_uniq(encoded, &mut out)).unwrap();
+
+        written == expected.len() as u64 && decode(&LengthPrefixed, &out) == expected
+    }
+
+    #[test]
+    fn truncated_run() {
+        let codec = FixedSize::new(4);
+        let good = encode(&codec, &[vec![0, 0, 0, 1], vec![0, 0, 0, 3]]);
+        let truncated = vec![0, 0, 0, 2, 0, 0];
+
+        let runs = vec![Cursor::new(good), Cursor::new(truncated)];
+        let err =
+            block_on(AsyncMerger::new(codec).merge_uniq(runs, tokio::io::sink())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,1268 @@
+//! An incrementally built set, kept as a few sorted runs of geometrically decreasing length.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::kway::{self, KMergeUniq};
+use crate::resume::MergeState;
+use crate::set_ops;
+
+/// When a `MergeForest` merges its runs.
+///
+/// The policies trade the cost of inserting for the cost of looking up: the fewer runs a policy
+/// keeps, the more often each element is merged again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compaction {
+    /// Merges each batch with the newest runs until every run is more than `ratio` times as long
+    /// as the next, so there are `O(log n)` runs and every element takes part in `O(log n)`
+    /// merges. A ratio of 2, the default, is the scheme datafrog's `Variable` uses.
+    Geometric { ratio: usize },
+    /// Keeps at most one run per level, where level 0 holds up to `base` elements and each level
+    /// holds `ratio` times as many as the one before. Each batch is merged into the newest run,
+    /// which is merged into the next level whenever it outgrows its own, like leveled compaction
+    /// in an LSM tree. Lookups search few runs, but an element is merged about `ratio / 2` times
+    /// on each level.
+    Leveled { base: usize, ratio: usize },
+    /// Groups runs into tiers by the same levels as `Leveled`, and only merges the newest runs once
+    /// `threshold` of them are in one tier, like size-tiered compaction in an LSM tree. Inserts
+    /// merge far less, but lookups search up to `threshold - 1` runs per tier.
+    SizeTiered {
+        base: usize,
+        ratio: usize,
+        threshold: usize,
+    },
+}
+
+impl Default for Compaction {
+    fn default() -> Self {
+        Compaction::Geometric { ratio: 2 }
+    }
+}
+
+impl Compaction {
+    /// Panics if a ratio or threshold is less than two, or a base is zero.
+    fn check(self) {
+        let ratio = match self {
+            Compaction::Geometric { ratio } => ratio,
+            Compaction::Leveled { base, ratio } => {
+                assert!(base > 0, "the first level must hold at least one element");
+                ratio
+            }
+            Compaction::SizeTiered {
+                base,
+                ratio,
+                threshold,
+            } => {
+                assert!(base > 0, "the first level must hold at least one element");
+                assert!(threshold >= 2, "a compaction merges at least two runs");
+                ratio
+            }
+        };
+        assert!(ratio >= 2, "runs must grow by a ratio of at least two");
+    }
+}
+
+/// Returns the level of a run of `len` elements: the first whose capacity is at least `len`.
+fn level(len: usize, base: usize, ratio: usize) -> u32 {
+    let mut capacity = base;
+    let mut level = 0;
+    while len > capacity {
+        capacity = capacity.saturating_mul(ratio);
+        level += 1;
+    }
+    level
+}
+
+/// Counts the garbage in `runs` for `MergeForest::stats_by` and `Snapshot::stats_by`.
+fn garbage_stats<T: Ord>(
+    runs: Vec<&[T]>,
+    mut is_tombstone: impl FnMut(&T) -> bool,
+) -> GarbageStats {
+    let len: usize = runs.iter().map(|run| run.len()).sum();
+    let (mut distinct, mut tombstones) = (0, 0);
+    for x in KMergeUniq::new(runs.iter().map(|run| run.iter()).collect()) {
+        distinct += 1;
+        tombstones += usize::from(is_tombstone(x));
+    }
+    GarbageStats {
+        runs: runs.len(),
+        len,
+        distinct,
+        tombstones,
+        reclaimable_bytes: (len - distinct) * std::mem::size_of::<T>(),
+    }
+}
+
+/// A run as stored by a forest: owned by `MergeForest`, shared with readers by
+/// `SharedMergeForest`.
+trait Run<T>: Sized {
+    fn as_slice(&self) -> &[T];
+    fn len(&self) -> usize;
+    fn into_vec(self) -> Vec<T>;
+    fn from_vec(v: Vec<T>) -> Self;
+}
+
+impl<T> Run<T> for Vec<T> {
+    fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn into_vec(self) -> Vec<T> {
+        self
+    }
+
+    fn from_vec(v: Vec<T>) -> Self {
+        v
+    }
+}
+
+/// Taking a run that a snapshot still holds copies it.
+impl<T: Clone> Run<T> for Arc<Vec<T>> {
+    fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn into_vec(self) -> Vec<T> {
+        Arc::unwrap_or_clone(self)
+    }
+
+    fn from_vec(v: Vec<T>) -> Self {
+        Arc::new(v)
+    }
+}
+
+/// How far a compaction started by `MergeForest::compact_step` has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactProgress {
+    /// The number of elements of the runs being compacted that have been merged, duplicates
+    /// included.
+    pub merged: usize,
+    /// The number of elements of those runs left to merge.
+    pub remaining: usize,
+}
+
+impl CompactProgress {
+    /// Returns `true` if there is nothing left to compact.
+    pub fn is_done(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// A compaction of the oldest `runs` runs of a forest, started by `compact_step`. Until it is
+/// done, inserts leave those runs alone.
+#[derive(Clone, Debug)]
+struct PendingCompaction<T> {
+    runs: usize,
+    state: MergeState,
+    out: Vec<T>,
+}
+
+/// Merges up to `budget` more elements into the compaction of the oldest runs, starting one over
+/// all of `runs` if none is pending, and replaces those runs by the merged one once it is done.
+fn compact_step<T, R>(
+    runs: &mut Vec<R>,
+    pending: &mut Option<PendingCompaction<T>>,
+    budget: usize,
+) -> CompactProgress
+where
+    T: Ord + Clone,
+    R: Run<T>,
+{
+    let compaction = match pending {
+        Some(compaction) => compaction,
+        None if runs.len() > 1 => pending.insert(PendingCompaction {
+            runs: runs.len(),
+            state: MergeState::new(runs.len()),
+            out: Vec::new(),
+        }),
+        None => {
+            return CompactProgress {
+                merged: 0,
+                remaining: 0,
+            }
+        }
+    };
+
+    let inputs: Vec<&[T]> = runs[..compaction.runs].iter().map(R::as_slice).collect();
+    compaction
+        .state
+        .run_for(&inputs, budget, &mut compaction.out);
+    let len: usize = inputs.iter().map(|run| run.len()).sum();
+    let merged: usize = compaction.state.positions().iter().sum();
+
+    if merged == len {
+        let compaction = pending.take().unwrap();
+        runs.splice(..compaction.runs, Some(R::from_vec(compaction.out)));
+    }
+    CompactProgress {
+        merged,
+        remaining: len - merged,
+    }
+}
+
+/// Like `insert_run`, but leaves alone the runs of a pending compaction.
+fn insert_run_after<T, R, O>(
+    runs: &mut Vec<R>,
+    pending: &Option<PendingCompaction<T>>,
+    compaction: Compaction,
+    batch: Vec<T>,
+    observer: &mut O,
+) where
+    T: Ord,
+    R: Run<T>,
+    O: CompactionObserver + ?Sized,
+{
+    match pending {
+        Some(pending) => {
+            let mut newer = runs.split_off(pending.runs);
+            insert_run(&mut newer, compaction, batch, observer);
+            runs.append(&mut newer);
+        }
+        None => insert_run(runs, compaction, batch, observer),
+    }
+}
+
+/// Sorts and deduplicates `batch`, appends it to `runs` and merges runs as `compaction` says.
+fn insert_run<T, R, O>(
+    runs: &mut Vec<R>,
+    compaction: Compaction,
+    mut batch: Vec<T>,
+    observer: &mut O,
+) where
+    T: Ord,
+    R: Run<T>,
+    O: CompactionObserver + ?Sized,
+{
+    batch.sort();
+    batch.dedup();
+    if batch.is_empty() {
+        return;
+    }
+
+    if let Compaction::SizeTiered {
+        base,
+        ratio,
+        threshold,
+    } = compaction
+    {
+        runs.push(R::from_vec(batch));
+        merge_tiers(runs, base, ratio, threshold, observer);
+        return;
+    }
+
+    // Older runs come first, so elements that are already present win over `batch`'s.
+    let mut compacted = Compacted {
+        runs: 1,
+        input_len: batch.len(),
+        output_len: 0,
+    };
+    while let Some(last) = runs.last() {
+        let merge = match compaction {
+            Compaction::Geometric { ratio } => last.len() <= ratio.saturating_mul(batch.len()),
+            Compaction::Leveled { base, ratio } => {
+                level(last.len(), base, ratio) <= level(batch.len(), base, ratio)
+            }
+            Compaction::SizeTiered { .. } => false,
+        };
+        if !merge {
+            break;
+        }
+        let last = runs.pop().unwrap();
+        compacted.runs += 1;
+        compacted.input_len += last.len();
+        batch = crate::merge_uniq(last.into_vec(), batch);
+    }
+    if compacted.runs > 1 {
+        compacted.output_len = batch.len();
+        observer.on_compaction(&compacted);
+    }
+    runs.push(R::from_vec(batch));
+}
+
+/// Merges the newest runs for `Compaction::SizeTiered` for as long as `threshold` of them are in
+/// the same tier.
+fn merge_tiers<T, R, O>(
+    runs: &mut Vec<R>,
+    base: usize,
+    ratio: usize,
+    threshold: usize,
+    observer: &mut O,
+) where
+    T: Ord,
+    R: Run<T>,
+    O: CompactionObserver + ?Sized,
+{
+    loop {
+        let tier = |run: &R| level(run.len(), base, ratio);
+        let newest = tier(runs.last().unwrap());
+        let n = runs
+            .iter()
+            .rev()
+            .take_while(|&run| tier(run) == newest)
+            .count();
+        if n < threshold {
+            return;
+        }
+
+        let newest: Vec<_> = runs.drain(runs.len() - n..).map(R::into_vec).collect();
+        let input_len = newest.iter().map(Vec::len).sum();
+        let merged = kway::merge_uniq(newest);
+        observer.on_compaction(&Compacted {
+            runs: n,
+            input_len,
+            output_len: merged.len(),
+        });
+        runs.push(R::from_vec(merged));
+    }
+}
+
+/// A merge made by a `MergeForest` as it inserted a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compacted {
+    /// The number of runs merged, counting the batch if it was one of them.
+    pub runs: usize,
+    /// The number of elements in the runs merged, duplicates included.
+    pub input_len: usize,
+    /// The number of elements in the run they were merged into.
+    pub output_len: usize,
+}
+
+/// Receives the merges a `MergeForest` makes. See `MergeForest::insert_observed`.
+pub trait CompactionObserver {
+    fn on_compaction(&mut self, compacted: &Compacted);
+}
+
+/// Ignores all merges.
+impl CompactionObserver for () {
+    fn on_compaction(&mut self, _: &Compacted) {}
+}
+
+impl<F: FnMut(&Compacted)> CompactionObserver for F {
+    fn on_compaction(&mut self, compacted: &Compacted) {
+        self(compacted)
+    }
+}
+
+/// How much of a `MergeForest` a full compaction would get rid of. See `MergeForest::stats`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GarbageStats {
+    pub runs: usize,
+    /// The number of elements in every run, counting each copy of an element that is in several.
+    pub len: usize,
+    /// The number of distinct elements, which is what a compaction would leave.
+    pub distinct: usize,
+    /// The number of distinct elements that mark deletions, as counted by the predicate passed to
+    /// `MergeForest::stats_by`. A `MergeForest` keeps them like any other element; they are only
+    /// reclaimable by a compaction that knows to drop them, like `compact::compact`.
+    pub tombstones: usize,
+    /// The bytes taken up by the extra copies of elements that are in several runs.
+    pub reclaimable_bytes: usize,
+}
+
+impl GarbageStats {
+    /// Returns the number of extra copies of elements that are in several runs.
+    pub fn duplicates(&self) -> usize {
+        self.len - self.distinct
+    }
+
+    /// Returns the fraction of the elements that are extra copies, from 0 up to (but excluding) 1.
+    pub fn duplicate_ratio(&self) -> f64 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        self.duplicates() as f64 / self.len as f64
+    }
+
+    /// Returns the fraction of the distinct elements that are tombstones.
+    pub fn tombstone_ratio(&self) -> f64 {
+        if self.distinct == 0 {
+            return 0.0;
+        }
+        self.tombstones as f64 / self.distinct as f64
+    }
+}
+
+/// Heuristics for deciding when a `MergeForest` is worth compacting, for services that would
+/// rather compact when there is garbage to collect than on a timer.
+///
+/// A forest with more than one run should be compacted once any of the limits is exceeded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionAdvisor {
+    /// The most runs a lookup should have to search.
+    pub max_runs: usize,
+    /// The largest tolerable `GarbageStats::duplicate_ratio`.
+    pub max_duplicate_ratio: f64,
+    /// The largest tolerable `GarbageStats::tombstone_ratio`.
+    pub max_tombstone_ratio: f64,
+}
+
+impl Default for CompactionAdvisor {
+    /// Allows 16 runs, and a quarter of the elements to be extra copies or tombstones.
+    fn default() -> Self {
+        CompactionAdvisor {
+            max_runs: 16,
+            max_duplicate_ratio: 0.25,
+            max_tombstone_ratio: 0.25,
+        }
+    }
+}
+
+impl CompactionAdvisor {
+    /// Returns `true` if a forest with these statistics should be compacted.
+    pub fn should_compact(&self, stats: &GarbageStats) -> bool {
+        stats.runs > 1
+            && (stats.runs > self.max_runs
+                || stats.duplicate_ratio() > self.max_duplicate_ratio
+                || stats.tombstone_ratio() > self.max_tombstone_ratio)
+    }
+}
+
+/// A set built from batches of elements, stored as a few sorted, deduplicated runs.
+///
+/// Like the levels of an LSM tree, the runs get shorter from the oldest to the newest, and
+/// inserting a batch merges it with some of the newest runs. When it does so is set by a
+/// `Compaction` policy. By default each run is more than twice as long as the next, so every
+/// element takes part in `O(log n)` merges over the life of the forest, and lookups only have to
+/// search `O(log n)` runs. This is the scheme datafrog's `Variable` uses for its stable relations.
+#[derive(Clone, Debug)]
+pub struct MergeForest<T> {
+    /// Ordered from oldest to newest, which is also from longest to shortest unless the policy is
+    /// `SizeTiered`.
+    runs: Vec<Vec<T>>,
+    compaction: Compaction,
+    pending: Option<PendingCompaction<T>>,
+}
+
+impl<T> Default for MergeForest<T> {
+    fn default() -> Self {
+        MergeForest::new()
+    }
+}
+
+impl<T> MergeForest<T> {
+    pub fn new() -> Self {
+        MergeForest::with_compaction(Compaction::default())
+    }
+
+    /// Creates a forest that merges its runs according to `compaction`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a ratio or threshold is less than two, or a base is zero.
+    pub fn with_compaction(compaction: Compaction) -> Self {
+        compaction.check();
+        MergeForest {
+            runs: Vec::new(),
+            compaction,
+            pending: None,
+        }
+    }
+
+    pub fn compaction(&self) -> Compaction {
+        self.compaction
+    }
+
+    /// Returns the runs, from oldest to newest. Each is sorted and deduplicated, but the same
+    /// element may appear in several of them.
+    pub fn runs(&self) -> &[Vec<T>] {
+        &self.runs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+impl<T: Ord> MergeForest<T> {
+    /// Adds the elements of `batch`, which needn't be sorted.
+    pub fn insert(&mut self, batch: Vec<T>) {
+        self.insert_observed(batch, &mut ());
+    }
+
+    /// Like `insert`, but tells `observer` about each merge it makes.
+    pub fn insert_observed<O>(&mut self, batch: Vec<T>, observer: &mut O)
+    where
+        O: CompactionObserver + ?Sized,
+    {
+        insert_run_after(
+            &mut self.runs,
+            &self.pending,
+            self.compaction,
+            batch,
+            observer,
+        );
+    }
+
+    /// Returns `true` if any run contains `x`.
+    pub fn contains(&self, x: &T) -> bool {
+        self.runs.iter().any(|run| run.binary_search(x).is_ok())
+    }
+
+    /// Returns an iterator over the distinct elements of every run, in ascending order.
+    pub fn iter(&self) -> KMergeUniq<std::slice::Iter<'_, T>> {
+        KMergeUniq::new(self.runs.iter().map(|run| run.iter()).collect())
+    }
+
+    /// Merges every run into one, abandoning any compaction started by `compact_step`.
+    pub fn compact(&mut self) {
+        self.pending = None;
+        if self.runs.len() > 1 {
+            let runs = std::mem::take(&mut self.runs);
+            self.runs.push(kway::merge_uniq(runs));
+        }
+    }
+
+    /// Does a bounded part of a compaction, for spreading one over idle moments instead of
+    /// stopping for all of it.
+    ///
+    /// The first call starts compacting every run there is, and each call merges up to `budget`
+    /// more elements into the compacted run, which replaces the runs it was merged from once it is
+    /// complete. Lookups keep searching those runs until then, and batches inserted in the meantime
+    /// are merged among the newer runs only, so the work of a compaction isn't lost to an insert.
+    /// Returns the progress of the compaction, which is done when there is at most one run to begin
+    /// with.
+    pub fn compact_step(&mut self, budget: usize) -> CompactProgress
+    where
+        T: Clone,
+    {
+        compact_step(&mut self.runs, &mut self.pending, budget)
+    }
+
+    /// Counts the garbage in the runs: extra copies of elements that are in several of them.
+    ///
+    /// This merges the runs without storing the result, so it costs about as many comparisons as a
+    /// compaction but allocates nothing.
+    pub fn stats(&self) -> GarbageStats {
+        self.stats_by(|_| false)
+    }
+
+    /// Like `stats`, but also counts the distinct elements for which `is_tombstone` returns
+    /// `true`.
+    pub fn stats_by(&self, is_tombstone: impl FnMut(&T) -> bool) -> GarbageStats {
+        garbage_stats(self.runs.iter().map(Vec::as_slice).collect(), is_tombstone)
+    }
+
+    /// Returns `true` if `advisor` recommends compacting the forest, judging by `stats`. To take
+    /// tombstones into account, pass the result of `stats_by` to `CompactionAdvisor::should_compact`
+    /// instead.
+    pub fn should_compact(&self, advisor: &CompactionAdvisor) -> bool {
+        // Skip the merge when the answer doesn't depend on it.
+        match self.runs.len() {
+            0 | 1 => false,
+            n if n > advisor.max_runs => true,
+            _ => advisor.should_compact(&self.stats()),
+        }
+    }
+
+    /// Compacts the forest and returns its elements.
+    pub fn into_vec(mut self) -> Vec<T> {
+        self.compact();
+        self.runs.pop().unwrap_or_default()
+    }
+}
+
+impl<T: Ord> Extend<T> for MergeForest<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.insert(iter.into_iter().collect());
+    }
+}
+
+/// A `MergeForest` that threads can search while another inserts into it.
+///
+/// Readers take a `Snapshot` of the runs, which stays unchanged however many batches are inserted
+/// after it was taken. Each insert builds its new list of runs on the side and publishes it in one
+/// step, so a snapshot holds all of a batch or none of it, and readers never wait for a merge.
+/// Snapshots share their runs through `Arc`s, which means a merge copies the runs it takes instead
+/// of reusing their allocations: inserts cost up to twice as much as `MergeForest`'s.
+#[derive(Debug)]
+pub struct SharedMergeForest<T> {
+    current: RwLock<Arc<Snapshot<T>>>,
+    /// Held while inserting, so that concurrent inserts don't lose each other's batches, along
+    /// with the compaction started by `compact_step`.
+    writer: Mutex<Option<PendingCompaction<T>>>,
+    compaction: Compaction,
+}
+
+/// The runs of a `SharedMergeForest` at one point in time. See `SharedMergeForest::snapshot`.
+#[derive(Debug)]
+pub struct Snapshot<T> {
+    runs: Vec<Arc<Vec<T>>>,
+}
+
+impl<T> Snapshot<T> {
+    /// Returns the runs, from oldest to newest, as `MergeForest::runs` does.
+    pub fn runs(&self) -> &[Arc<Vec<T>>] {
+        &self.runs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+impl<T: Ord> Snapshot<T> {
+    /// Returns `true` if any run contains `x`.
+    pub fn contains(&self, x: &T) -> bool {
+        self.runs.iter().any(|run| run.binary_search(x).is_ok())
+    }
+
+    /// Returns an iterator over the distinct elements of every run, in ascending order.
+    pub fn iter(&self) -> KMergeUniq<std::slice::Iter<'_, T>> {
+        KMergeUniq::new(self.runs.iter().map(|run| run.iter()).collect())
+    }
+
+    /// Counts the garbage in the runs, as `MergeForest::stats` does.
+    pub fn stats(&self) -> GarbageStats {
+        self.stats_by(|_| false)
+    }
+
+    /// Like `stats`, but also counts the distinct elements for which `is_tombstone` returns
+    /// `true`.
+    pub fn stats_by(&self, is_tombstone: impl FnMut(&T) -> bool) -> GarbageStats {
+        garbage_stats(
+            self.runs.iter().map(|run| run.as_slice()).collect(),
+            is_tombstone,
+        )
+    }
+}
+
+impl<T> Default for SharedMergeForest<T> {
+    fn default() -> Self {
+        SharedMergeForest::new()
+    }
+}
+
+impl<T> SharedMergeForest<T> {
+    pub fn new() -> Self {
+        SharedMergeForest::with_compaction(Compaction::default())
+    }
+
+    /// Creates a forest that merges its runs according to `compaction`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a ratio or threshold is less than two, or a base is zero.
+    pub fn with_compaction(compaction: Compaction) -> Self {
+        compaction.check();
+        SharedMergeForest {
+            current: RwLock::new(Arc::new(Snapshot { runs: Vec::new() })),
+            writer: Mutex::new(None),
+            compaction,
+        }
+    }
+
+    pub fn compaction(&self) -> Compaction {
+        self.compaction
+    }
+
+    /// Returns the runs as of the last insert or compaction to finish.
+    pub fn snapshot(&self) -> Arc<Snapshot<T>> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+}
+
+impl<T: Ord + Clone> SharedMergeForest<T> {
+    /// Adds the elements of `batch`, which needn't be sorted. Snapshots taken from now on contain
+    /// them.
+    pub fn insert(&self, batch: Vec<T>) {
+        self.insert_observed(batch, &mut ());
+    }
+
+    /// Like `insert`, but tells `observer` about each merge it makes.
+    pub fn insert_observed<O>(&self, batch: Vec<T>, observer: &mut O)
+    where
+        O: CompactionObserver + ?Sized,
+    {
+        let pending = self.writer.lock().unwrap();
+        let mut runs = self.snapshot().runs.clone();
+        insert_run_after(&mut runs, &pending, self.compaction, batch, observer);
+        self.publish(runs);
+    }
+
+    /// Merges every run into one, abandoning any compaction started by `compact_step`.
+    pub fn compact(&self) {
+        let mut pending = self.writer.lock().unwrap();
+        *pending = None;
+        let runs = self.snapshot().runs.clone();
+        if runs.len() > 1 {
+            let merged = kway::merge_uniq(runs.into_iter().map(Run::into_vec).collect());
+            self.publish(vec![Arc::new(merged)]);
+        }
+    }
+
+    /// Merges every run into one, keeping only the elements for which `keep` returns `true`. This
+    /// is how tombstoned elements are dropped for good.
+    pub fn compact_retain(&self, keep: impl FnMut(&T) -> bool) {
+        let mut pending = self.writer.lock().unwrap();
+        *pending = None;
+        let runs = self.snapshot().runs.clone();
+        let mut merged = kway::merge_uniq(runs.into_iter().map(Run::into_vec).collect());
+        merged.retain(keep);
+        self.publish(if merged.is_empty() {
+            Vec::new()
+        } else {
+            vec![Arc::new(merged)]
+        });
+    }
+
+    /// Does a bounded part of a compaction, like `MergeForest::compact_step`. Inserts wait for
+    /// the step to finish, but readers don't.
+    pub fn compact_step(&self, budget: usize) -> CompactProgress {
+        let mut pending = self.writer.lock().unwrap();
+        let mut runs = self.snapshot().runs.clone();
+        let progress = compact_step(&mut runs, &mut pending, budget);
+        if pending.is_none() {
+            self.publish(runs);
+        }
+        progress
+    }
+
+    fn publish(&self, runs: Vec<Arc<Vec<T>>>) {
+        *self.current.write().unwrap() = Arc::new(Snapshot { runs });
+    }
+}
+
+/// A set that grows in rounds, for semi-naive evaluation of datalog-style fixpoints.
+///
+/// Each round's batches are queued by `insert` and become `recent` once `changed` is called, minus
+/// anything already known. The previous round's `recent` elements move to `stable` at the same
+/// time. A fixpoint loop joins `recent` against the other relations' `stable` and `recent`
+/// elements until `changed` returns `false`. This mirrors datafrog's `Variable`.
+#[derive(Clone, Debug)]
+pub struct Incremental<T> {
+    stable: MergeForest<T>,
+    recent: Vec<T>,
+    to_add: Vec<Vec<T>>,
+}
+
+impl<T> Default for Incremental<T> {
+    fn default() -> Self {
+        Incremental::new()
+    }
+}
+
+impl<T> Incremental<T> {
+    pub fn new() -> Self {
+        Incremental {
+            stable: MergeForest::new(),
+            recent: Vec::new(),
+            to_add: Vec::new(),
+        }
+    }
+
+    /// Returns the elements found before the last round.
+    pub fn stable(&self) -> &MergeForest<T> {
+        &self.stable
+    }
+
+    /// Returns the elements first found in the last round, sorted and deduplicated.
+    pub fn recent(&self) -> &[T] {
+        &self.recent
+    }
+}
+
+impl<T: Ord> Incremental<T> {
+    /// Queues the elements of `batch`, which needn't be sorted, for the next round.
+    pub fn insert(&mut self, mut batch: Vec<T>) {
+        batch.sort();
+        batch.dedup();
+        if !batch.is_empty() {
+            self.to_add.push(batch);
+        }
+    }
+
+    /// Starts a new round, returning `true` if it found any elements that weren't already known.
+    pub fn changed(&mut self) -> bool {
+        if !self.recent.is_empty() {
+            let recent = std::mem::take(&mut self.recent);
+            self.stable.insert(recent);
+        }
+
+        let mut recent = kway::merge_uniq(std::mem::take(&mut self.to_add));
+        for run in self.stable.runs() {
+            if recent.is_empty() {
+                break;
+            }
+            recent = set_ops::difference(recent, run);
+        }
+        self.recent = recent;
+        !self.recent.is_empty()
+    }
+
+    /// Merges the runs of `stable` into one, as when `stable().should_compact(..)` advises it.
+    pub fn compact(&mut self) {
+        self.stable.compact();
+    }
+
+    /// Does a bounded part of a compaction of `stable`, like `MergeForest::compact_step`.
+    pub fn compact_step(&mut self, budget: usize) -> CompactProgress
+    where
+        T: Clone,
+    {
+        self.stable.compact_step(budget)
+    }
+
+    /// Returns every element, including those still queued for the next round.
+    pub fn complete(mut self) -> Vec<T> {
+        self.stable.insert(self.recent);
+        for batch in self.to_add {
+            self.stable.insert(batch);
+        }
+        self.stable.into_vec()
+    }
+}
+
+/// The number of elements `Deduper::insert_if_new` collects before merging them into the runs.
+/// Lengths from 16 to 1024 were all within noise of each other.
+const PENDING_LEN: usize = 256;
+
+/// Answers "has this been seen before?" for elements that arrive one at a time or in unsorted
+/// batches.
+///
+/// Seen elements are kept in a `MergeForest`, so each one takes part in `O(log n)` merges and a
+/// lookup searches `O(log n)` runs. Elements inserted one at a time are collected in a short sorted
+/// buffer first, and merged into the forest as a batch once it fills up.
+///
+/// Lookups dominate, since each one binary searches every run, so this is slower than a
+/// `BTreeSet`, even in batches, and much slower than a `HashSet`. Use a `HashSet` where hashing is
+/// possible and order doesn't matter; this only needs `Ord`, and `into_vec` returns the elements
+/// sorted without sorting them.
+#[derive(Clone, Debug)]
+pub struct Deduper<T> {
+    /// Only new elements are inserted, so no element is in more than one run.
+    seen: MergeForest<T>,
+    /// Sorted and deduplicated, and disjoint from `seen`.
+    pending: Vec<T>,
+}
+
+impl<T> Default for Deduper<T> {
+    fn default() -> Self {
+        Deduper::new()
+    }
+}
+
+impl<T> Deduper<T> {
+    pub fn new() -> Self {
+        Deduper {
+            seen: MergeForest::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the number of distinct elements seen.
+    pub fn len(&self) -> usize {
+        self.seen.runs().iter().map(Vec::len).sum::<usize>() + self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty() && self.pending.is_empty()
+    }
+}
+
+impl<T: Ord> Deduper<T> {
+    pub fn contains(&self, x: &T) -> bool {
+        self.pending.binary_search(x).is_ok() || self.seen.contains(x)
+    }
+
+    /// Records `x`, returning `true` if it hadn't been seen before.
+    pub fn insert_if_new(&mut self, x: T) -> bool {
+        if self.seen.contains(&x) {
+            return false;
+        }
+        match self.pending.binary_search(&x) {
+            Ok(_) => return false,
+            Err(i) => self.pending.insert(i, x),
+        }
+        if self.pending.len() >= PENDING_LEN {
+            self.seen.insert(std::mem::take(&mut self.pending));
+        }
+        true
+    }
+
+    /// Records the elements of `batch`, which needn't be sorted, and returns the ones that hadn't
+    /// been seen before, sorted and deduplicated.
+    ///
+    /// The batch is sorted and subtracted from each run with `set_ops::difference`, which gallops,
+    /// so a small batch is cheap to check against long runs.
+    pub fn insert_new(&mut self, mut batch: Vec<T>) -> Vec<T>
+    where
+        T: Clone,
+    {
+        batch.sort();
+        batch.dedup();
+        for run in self.seen.runs().iter().chain(Some(&self.pending)) {
+            batch = set_ops::difference(batch, run);
+        }
+        self.seen.insert(batch.clone());
+        batch
+    }
+
+    /// Returns the seen elements in ascending order.
+    pub fn into_vec(self) -> Vec<T> {
+        crate::merge_uniq(self.seen.into_vec(), self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeSet;
+
+    #[quickcheck]
+    fn merge_forest_impl(batches: Vec<Vec<u16>>) -> bool {
+        let mut expected = BTreeSet::new();
+        let mut forest = MergeForest::new();
+        for batch in batches {
+            expected.extend(batch.iter().copied());
+            forest.insert(batch);
+
+            let geometric = forest
+                .runs()
+                .windows(2)
+                .all(|w| w[0].len() > 2 * w[1].len());
+            if !geometric || !forest.iter().eq(expected.iter()) {
+                return false;
+            }
+        }
+
+        let contains = expected.iter().all(|x| forest.contains(x));
+        forest.compact();
+        contains && forest.runs().len() <= 1 && forest.into_vec().iter().eq(expected.iter())
+    }
+
+    #[quickcheck]
+    fn garbage_stats(batches: Vec<Vec<u16>>) -> bool {
+        let mut forest = MergeForest::with_compaction(Compaction::SizeTiered {
+            base: 4,
+            ratio: 4,
+            threshold: 4,
+        });
+        for batch in batches {
+            forest.insert(batch);
+        }
+
+        let runs: Vec<_> = forest.runs().concat();
+        let distinct: BTreeSet<_> = runs.iter().copied().collect();
+        let stats = forest.stats_by(|x| x % 2 == 0);
+        let expected = GarbageStats {
+            runs: forest.runs().len(),
+            len: runs.len(),
+            distinct: distinct.len(),
+            tombstones: distinct.iter().filter(|&x| x % 2 == 0).count(),
+            reclaimable_bytes: (runs.len() - distinct.len()) * 2,
+        };
+
+        let advisor = CompactionAdvisor::default();
+        let advised = forest.should_compact(&advisor) == advisor.should_compact(&forest.stats());
+        forest.compact();
+        stats == expected
+            && advised
+            && !forest.should_compact(&advisor)
+            && forest.stats().duplicates() == 0
+    }
+
+    #[test]
+    fn compaction_advisor() {
+        let stats = GarbageStats {
+            runs: 3,
+            len: 100,
+            distinct: 90,
+            tombstones: 30,
+            reclaimable_bytes: 80,
+        };
+        let advisor = CompactionAdvisor::default();
+        assert!((stats.duplicate_ratio() - 0.1).abs() < 1e-9);
+        assert!(advisor.should_compact(&stats));
+        assert!(!advisor.should_compact(&GarbageStats {
+            tombstones: 0,
+            ..stats
+        }));
+        assert!(!advisor.should_compact(&GarbageStats { runs: 1, ..stats }));
+        assert!(advisor.should_compact(&GarbageStats {
+            runs: 17,
+            tombstones: 0,
+            ..stats
+        }));
+    }
+
+    #[quickcheck]
+    fn shared_matches_forest(batches: Vec<Vec<u16>>, ratio: u8) -> bool {
+        let compaction = Compaction::SizeTiered {
+            base: 4,
+            ratio: ratio as usize % 4 + 2,
+            threshold: 3,
+        };
+        let mut forest = MergeForest::with_compaction(compaction);
+        let shared = SharedMergeForest::with_compaction(compaction);
+        let before = shared.snapshot();
+        for batch in batches {
+            forest.insert(batch.clone());
+            shared.insert(batch);
+
+            let snapshot = shared.snapshot();
+            let same_runs = snapshot.runs().iter().map(|run| &***run).eq(forest.runs());
+            if !same_runs || !snapshot.iter().eq(forest.iter()) {
+                return false;
+            }
+        }
+
+        let snapshot = shared.snapshot();
+        shared.compact();
+        forest.compact();
+        before.is_empty()
+            && forest.iter().all(|x| snapshot.contains(x))
+            && shared.snapshot().runs().len() == forest.runs().len()
+            && shared.snapshot().iter().eq(forest.iter())
+    }
+
+    #[test]
+    fn shared_snapshots_are_consistent() {
+        const BATCHES: u32 = 200;
+        const BATCH_LEN: u32 = 10;
+
+        let forest = SharedMergeForest::new();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| loop {
+                    // Batch `i` holds the elements `i * BATCH_LEN..(i + 1) * BATCH_LEN`, so a
+                    // snapshot that has all or none of each batch holds a prefix of them.
+                    let snapshot = forest.snapshot();
+                    let elements: Vec<u32> = snapshot.iter().copied().collect();
+                    let len = elements.len() as u32;
+                    assert_eq!(len % BATCH_LEN, 0);
+                    assert!(elements.into_iter().eq(0..len));
+                    if len == BATCHES * BATCH_LEN {
+                        break;
+                    }
+                });
+            }
+            for i in 0..BATCHES {
+                forest.insert((i * BATCH_LEN..(i + 1) * BATCH_LEN).rev().collect());
+            }
+        });
+    }
+
+    #[quickcheck]
+    fn compact_step_impl(ops: Vec<(bool, Vec<u16>)>) -> bool {
+        let mut expected = BTreeSet::new();
+        let mut forest = MergeForest::new();
+        let shared = SharedMergeForest::new();
+        for (step, batch) in ops {
+            // A step's budget is the length of its batch, which may be zero.
+            if step {
+                if forest.compact_step(batch.len()) != shared.compact_step(batch.len()) {
+                    return false;
+                }
+            } else {
+                expected.extend(batch.iter().copied());
+                forest.insert(batch.clone());
+                shared.insert(batch);
+            }
+
+            let snapshot = shared.snapshot();
+            let same_runs = snapshot.runs().iter().map(|run| &***run).eq(forest.runs());
+            if !same_runs || !forest.iter().eq(expected.iter()) {
+                return false;
+            }
+        }
+
+        while forest.runs().len() > 1 {
+            forest.compact_step(7);
+        }
+        forest.runs().len() <= 1 && forest.into_vec().iter().eq(expected.iter())
+    }
+
+    #[test]
+    fn compact_step_is_bounded() {
+        let mut forest = MergeForest::with_compaction(Compaction::SizeTiered {
+            base: 1000,
+            ratio: 2,
+            threshold: 8,
+        });
+        for i in 0..4 {
+            forest.insert((0..1000).map(|x| x * 4 + i).collect());
+        }
+
+        // The runs are disjoint, so each merged element uses up one element of one run.
+        assert_eq!(
+            forest.compact_step(100),
+            CompactProgress {
+                merged: 100,
+                remaining: 3900
+            }
+        );
+        forest.insert(vec![5000]);
+        assert_eq!(forest.runs().len(), 5);
+        // The other 39 steps merge 100 elements each.
+        let mut steps = 1;
+        loop {
+            steps += 1;
+            if forest.compact_step(100).is_done() {
+                break;
+            }
+        }
+        assert_eq!(steps, 40);
+        assert_eq!(forest.runs().len(), 2);
+        assert_eq!(forest.runs()[0], (0..4000).collect::<Vec<_>>());
+        assert!(forest.contains(&5000));
+    }
+
+    #[quickcheck]
+    fn compaction_policies(batches: Vec<Vec<u16>>, base: u8, ratio: u8, threshold: u8) -> bool {
+        let (base, ratio, threshold) = (
+            base as usize % 8 + 1,
+            ratio as usize % 4 + 2,
+            threshold as usize % 4 + 2,
+        );
+        let policies = [
+            Compaction::Geometric { ratio },
+            Compaction::Leveled { base, ratio },
+            Compaction::SizeTiered {
+                base,
+                ratio,
+                threshold,
+            },
+        ];
+        policies.iter().all(|&compaction| {
+            let mut expected = BTreeSet::new();
+            let mut forest = MergeForest::with_compaction(compaction);
+            // Every run is either a batch or the result of a merge, so the merges account for
+            // the difference.
+            let mut batches_in = 0;
+            let mut merged_away = 0;
+            for batch in &batches {
+                expected.extend(batch.iter().copied());
+                batches_in += usize::from(!batch.is_empty());
+                forest.insert_observed(batch.clone(), &mut |c: &Compacted| {
+                    merged_away += c.runs - 1;
+                });
+            }
+
+            let levels: Vec<_> = forest
+                .runs()
+                .iter()
+                .map(|run| level(run.len(), base, ratio))
+                .collect();
+            let shaped = match compaction {
+                Compaction::Geometric { ratio } => forest
+                    .runs()
+                    .windows(2)
+                    .all(|w| w[0].len() > ratio * w[1].len()),
+                Compaction::Leveled { .. } => levels.windows(2).all(|w| w[0] > w[1]),
+                Compaction::SizeTiered { .. } => {
+                    let newest = levels.last();
+                    levels
+                        .iter()
+                        .rev()
+                        .take_while(|&l| Some(l) == newest)
+                        .count()
+                        < threshold
+                }
+            };
+            shaped
+                && forest.runs().len() == batches_in - merged_away
+                && forest.iter().eq(expected.iter())
+        })
+    }
+
+    #[quickcheck]
+    fn deduper_impl(ops: Vec<(bool, Vec<u16>)>) -> bool {
+        let mut expected = BTreeSet::new();
+        let mut deduper = Deduper::new();
+        for (one_at_a_time, batch) in ops {
+            let ok = match one_at_a_time {
+                true => batch.into_iter().all(|x| {
+                    deduper.contains(&x) == expected.contains(&x)
+                        && deduper.insert_if_new(x) == expected.insert(x)
+                }),
+                false => {
+                    let new: BTreeSet<_> = batch
+                        .iter()
+                        .copied()
+                        .filter(|x| !expected.contains(x))
+                        .collect();
+                    expected.extend(batch.iter().copied());
+                    deduper.insert_new(batch).iter().eq(&new)
+                }
+            };
+            if !ok || deduper.len() != expected.len() {
+                return false;
+            }
+        }
+        deduper.into_vec().iter().eq(&expected)
+    }
+
+    #[test]
+    fn deduper_many_single_inserts() {
+        let mut deduper = Deduper::new();
+        let n = 3 * PENDING_LEN as u32;
+        assert!((0..n).rev().all(|x| deduper.insert_if_new(2 * x)));
+        assert!((0..2 * n).all(|x| deduper.contains(&x) == x.is_multiple_of(2)));
+        let odd: Vec<_> = (0..n).map(|x| 2 * x + 1).collect();
+        assert_eq!(deduper.insert_new((0..2 * n).collect()), odd);
+        assert!(!deduper.insert_if_new(5));
+        assert_eq!(deduper.into_vec(), (0..2 * n).collect::<Vec<_>>());
+    }
+
+    #[quickcheck]
+    fn incremental_impl(rounds: Vec<Vec<Vec<u8>>>) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut all = BTreeSet::new();
+        let mut var = Incremental::new();
+        for batches in rounds {
+            let round: BTreeSet<_> = batches.iter().flatten().copied().collect();
+            for batch in batches {
+                var.insert(batch);
+            }
+
+            let changed = var.changed();
+            let expected: Vec<_> = round.difference(&seen).copied().collect();
+            if changed == expected.is_empty() || var.recent() != &expected[..] {
+                return false;
+            }
+            if !var.stable().iter().eq(seen.iter()) {
+                return false;
+            }
+            seen.extend(round);
+            all.extend(expected);
+        }
+
+        var.complete().iter().eq(all.iter())
+    }
+
+    /// Transitive closure of a graph, the canonical semi-naive fixpoint.
+    #[test]
+    fn incremental_transitive_closure() {
+        let edges = [(1u8, 2u8), (2, 3), (3, 4), (4, 2), (5, 6)];
+        let mut paths = Incremental::new();
+        paths.insert(edges.to_vec());
+        while paths.changed() {
+            let mut found = Vec::new();
+            for &(a, b) in paths.recent() {
+                for &(c, d) in &edges {
+                    if b == c {
+                        found.push((a, d));
+                    }
+                }
+            }
+            paths.insert(found);
+        }
+
+        let mut expected = vec![(5, 6)];
+        for a in 1..=4 {
+            for b in 2..=4 {
+                expected.push((a, b));
+            }
+        }
+        expected.sort();
+        assert_eq!(paths.complete(), expected);
+    }
+}
@@ -0,0 +1,82 @@
+/// Returns the length of the longest prefix of `slice` whose elements all satisfy `pred`.
+///
+/// `pred` must be monotone: once it returns `false` for some element, it must return `false` for
+/// every element after it. Runs in `O(log n)` where `n` is the returned length, so skipping a
+/// short prefix is nearly as cheap as a single comparison.
+pub(crate) fn gallop<T>(slice: &[T], mut pred: impl FnMut(&T) -> bool) -> usize {
+    if slice.is_empty() || !pred(&slice[0]) {
+        return 0;
+    }
+
+    // Invariant: `pred(&slice[lo])` holds.
+    let mut lo = 0;
+    let mut step = 1;
+    while lo + step < slice.len() && pred(&slice[lo + step]) {
+        lo += step;
+        step <<= 1;
+    }
+
+    // Binary search in the half-open window `(lo, lo + step)` for the first failing element.
+    step >>= 1;
+    while step > 0 {
+        if lo + step < slice.len() && pred(&slice[lo + step]) {
+            lo += step;
+        }
+        step >>= 1;
+    }
+
+    lo + 1
+}
+
+/// Returns the length of the longest suffix of `slice` whose elements all satisfy `pred`, the
+/// mirror image of `gallop`.
+///
+/// `pred` must be monotone: once it returns `true` for some element, it must return `true` for
+/// every element after it.
+pub(crate) fn gallop_back<T>(slice: &[T], mut pred: impl FnMut(&T) -> bool) -> usize {
+    let n = slice.len();
+    if n == 0 || !pred(&slice[n - 1]) {
+        return 0;
+    }
+
+    // Invariant: `pred(&slice[n - 1 - lo])` holds.
+    let mut lo = 0;
+    let mut step = 1;
+    while lo + step < n && pred(&slice[n - 1 - lo - step]) {
+        lo += step;
+        step <<= 1;
+    }
+
+    step >>= 1;
+    while step > 0 {
+        if lo + step < n && pred(&slice[n - 1 - lo - step]) {
+            lo += step;
+        }
+        step >>= 1;
+    }
+
+    lo + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn gallop_matches_linear_scan(mut v: Vec<usize>, x: usize) -> bool {
+        v.sort_unstable();
+
+        let expected = v.iter().take_while(|&&y| y < x).count();
+        let actual = gallop(&v, |&y| y < x);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn gallop_back_matches_linear_scan(mut v: Vec<usize>, x: usize) -> bool {
+        v.sort_unstable();
+
+        let expected = v.iter().rev().take_while(|&&y| y > x).count();
+        gallop_back(&v, |&y| y > x) == expected
+    }
+}
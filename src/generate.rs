@@ -0,0 +1,260 @@
+//! Generating inputs for benchmarking and testing merges.
+//!
+//! Which merge is fastest depends less on the size of the inputs than on how they interleave:
+//! how many elements they share, and how long the stretches are where one input's elements all
+//! precede the other's. `Generator` controls both. Generation is deterministic for a given seed,
+//! and doesn't depend on `rand`, so its output doesn't change with the version of a dependency.
+
+/// How the elements that only one input holds are spread between the inputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Distribution {
+    /// Each element is equally likely to come from either input, so they alternate often.
+    #[default]
+    Uniform,
+    /// Elements come in runs from the same input, with lengths drawn uniformly from
+    /// `1..2 * mean_run`. Long runs favor galloping.
+    Clustered { mean_run: usize },
+    /// Elements come in runs from the same input, with lengths drawn from a Zipf distribution with
+    /// the given exponent: most runs are short, but a few are very long. The exponent must be
+    /// greater than 1, and lower exponents give longer runs.
+    Zipf { exponent: f64 },
+}
+
+/// Generates pairs of sorted, deduplicated vectors of `u64`s with a chosen overlap and
+/// interleaving.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    len_a: usize,
+    len_b: usize,
+    overlap: f64,
+    distribution: Distribution,
+    seed: u64,
+}
+
+impl Generator {
+    /// Generates inputs of `len_a` and `len_b` elements.
+    pub fn new(len_a: usize, len_b: usize) -> Self {
+        Generator {
+            len_a,
+            len_b,
+            overlap: 0.0,
+            distribution: Distribution::Uniform,
+            seed: 0,
+        }
+    }
+
+    /// Sets the fraction of the shorter input that the longer one also holds, from `0.0` to
+    /// `1.0`. Defaults to `0.0`.
+    pub fn overlap(mut self, overlap: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&overlap),
+            "overlap must be between 0 and 1"
+        );
+        self.overlap = overlap;
+        self
+    }
+
+    /// Sets how the elements that aren't shared are spread between the inputs. Defaults to
+    /// `Distribution::Uniform`.
+    pub fn distribution(mut self, distribution: Distribution) -> Self {
+        match distribution {
+            Distribution::Clustered { mean_run } => assert!(mean_run > 0, "runs must be nonempty"),
+            Distribution::Zipf { exponent } => assert!(exponent > 1.0, "exponent must exceed 1"),
+            Distribution::Uniform => {}
+        }
+        self.distribution = distribution;
+        self
+    }
+
+    /// Sets the seed of the generator. Defaults to `0`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Generates a pair of inputs.
+    pub fn pair(&self) -> (Vec<u64>, Vec<u64>) {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Side {
+            A,
+            B,
+            Both,
+        }
+
+        let mut rng = Rng(self.seed);
+        let shared = (self.overlap * self.len_a.min(self.len_b) as f64).round() as usize;
+        let mut left = [self.len_a - shared, self.len_b - shared];
+
+        // The order in which the elements held by only one input appear in the union.
+        let mut sides = Vec::with_capacity(left[0] + left[1]);
+        match self.distribution {
+            Distribution::Uniform => {
+                sides.extend((0..left[0]).map(|_| Side::A));
+                sides.extend((0..left[1]).map(|_| Side::B));
+                for i in (1..sides.len()).rev() {
+                    sides.swap(i, rng.below(i as u64 + 1) as usize);
+                }
+            }
+            Distribution::Clustered { .. } | Distribution::Zipf { .. } => {
+                let mut side = rng.below(2) as usize;
+                while left[0] + left[1] > 0 {
+                    if left[side] == 0 {
+                        side ^= 1;
+                    }
+                    let run = match self.distribution {
+                        Distribution::Clustered { mean_run } => {
+                            1 + rng.below(2 * mean_run as u64 - 1)
+                        }
+                        Distribution::Zipf { exponent } => {
+                            // Inverts the tail `P(run >= k) = k^(1 - exponent)`.
+                            rng.unit().powf(1.0 / (1.0 - exponent)) as u64
+                        }
+                        Distribution::Uniform => unreachable!(),
+                    };
+                    let run = run.min(left[side] as u64) as usize;
+                    sides.extend((0..run).map(|_| if side == 0 { Side::A } else { Side::B }));
+                    left[side] -= run;
+                    side ^= 1;
+                }
+            }
+        }
+
+        // Scatter the shared elements uniformly among the others.
+        let (mut a, mut b) = (
+            Vec::with_capacity(self.len_a),
+            Vec::with_capacity(self.len_b),
+        );
+        let mut sides = sides.into_iter();
+        let mut shared_left = shared as u64;
+        let mut total_left = (sides.len() + shared) as u64;
+        let mut x = 0u64;
+        while total_left > 0 {
+            let side = if rng.below(total_left) < shared_left {
+                shared_left -= 1;
+                Side::Both
+            } else {
+                sides.next().unwrap()
+            };
+            total_left -= 1;
+
+            // Gaps between values keep them from being dense, as real keys rarely are.
+            x += 1 + rng.below(4);
+            if side != Side::B {
+                a.push(x);
+            }
+            if side != Side::A {
+                b.push(x);
+            }
+        }
+
+        (a, b)
+    }
+}
+
+/// Generates an unsorted vector of `len` `u64`s, of which the fraction `duplicates` repeat an
+/// earlier element, for benchmarking sorting and deduplication.
+pub fn unsorted(len: usize, duplicates: f64, seed: u64) -> Vec<u64> {
+    assert!(
+        (0.0..=1.0).contains(&duplicates),
+        "duplicates must be between 0 and 1"
+    );
+    let mut rng = Rng(seed);
+    let distinct = len - (duplicates * len as f64).round() as usize;
+    let distinct = if len > 0 { distinct.max(1) } else { 0 };
+
+    // `distinct` different values, followed by repeats of them.
+    let mut out: Vec<u64> = (0..distinct as u64).map(|i| i * 4 + rng.below(4)).collect();
+    for _ in distinct..len {
+        out.push(out[rng.below(distinct as u64) as usize]);
+    }
+    for i in (1..out.len()).rev() {
+        out.swap(i, rng.below(i as u64 + 1) as usize);
+    }
+    out
+}
+
+/// The SplitMix64 generator, which is small and fast and passes the usual statistical tests.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`, with negligible bias for the sizes used here.
+    fn below(&mut self, n: u64) -> u64 {
+        ((u128::from(self.next()) * u128::from(n)) >> 64) as u64
+    }
+
+    /// Returns a number in `(0, 1]`.
+    fn unit(&mut self) -> f64 {
+        ((self.next() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn is_sorted_unique(v: &[u64]) -> bool {
+        v.windows(2).all(|w| w[0] < w[1])
+    }
+
+    #[quickcheck]
+    fn pair_impl(len_a: u16, len_b: u16, overlap: u8, distribution: u8, seed: u64) -> bool {
+        let (len_a, len_b) = (usize::from(len_a % 2000), usize::from(len_b % 2000));
+        let overlap = f64::from(overlap % 101) / 100.0;
+        let distribution = match distribution % 3 {
+            0 => Distribution::Uniform,
+            1 => Distribution::Clustered { mean_run: 16 },
+            _ => Distribution::Zipf { exponent: 1.5 },
+        };
+        let generator = Generator::new(len_a, len_b)
+            .overlap(overlap)
+            .distribution(distribution)
+            .seed(seed);
+
+        let (a, b) = generator.pair();
+        let shared = crate::set_ops::intersection(a.clone(), &b).len();
+        let expected = (overlap * len_a.min(len_b) as f64).round() as usize;
+        a.len() == len_a
+            && b.len() == len_b
+            && is_sorted_unique(&a)
+            && is_sorted_unique(&b)
+            && shared == expected
+            && generator.pair() == (a, b)
+    }
+
+    #[test]
+    fn clustered_runs() {
+        let runs = |distribution| {
+            let (a, b) = Generator::new(10000, 10000)
+                .distribution(distribution)
+                .pair();
+            // Counts the places where the merged order switches between the inputs.
+            crate::kway::merge_uniq_tagged(vec![a, b])
+                .windows(2)
+                .filter(|w| w[0].1 != w[1].1)
+                .count()
+        };
+        assert!(runs(Distribution::Clustered { mean_run: 100 }) < runs(Distribution::Uniform) / 10);
+    }
+
+    #[quickcheck]
+    fn unsorted_impl(len: u16, duplicates: u8, seed: u64) -> bool {
+        let len = usize::from(len % 2000);
+        let duplicates = f64::from(duplicates % 101) / 100.0;
+
+        let mut v = unsorted(len, duplicates, seed);
+        let expected = len - (duplicates * len as f64).round() as usize;
+        let out_len = v.len();
+        v.sort_unstable();
+        v.dedup();
+        out_len == len && v.len() == expected.max(len.min(1))
+    }
+}
@@ -0,0 +1,159 @@
+//! A priority queue for elements that arrive in sorted batches.
+
+use std::vec;
+
+use crate::kway::{self, KMergeStable};
+
+/// A min-priority queue for elements that are pushed in sorted runs and taken out all at once.
+///
+/// Pushing a run only stores it, and `drain_sorted` merges the runs with a loser tree. Compared to
+/// pushing every element into a `BinaryHeap` and popping them all, this skips the sift on each
+/// push and moves each element out exactly once, so the whole cycle costs `O(n log k)` for `k`
+/// runs instead of `O(n log n)`.
+///
+/// For a few long runs, that is about twice as fast as a `BinaryHeap`. The gap closes as runs get
+/// shorter, since the merge then makes about as many comparisons per element as the heap.
+#[derive(Clone, Debug)]
+pub struct RunHeap<T> {
+    runs: Vec<Vec<T>>,
+    len: usize,
+}
+
+impl<T> Default for RunHeap<T> {
+    fn default() -> Self {
+        RunHeap::new()
+    }
+}
+
+impl<T> RunHeap<T> {
+    pub fn new() -> Self {
+        RunHeap {
+            runs: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of elements in every run.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the runs, in the order they were pushed.
+    pub fn runs(&self) -> &[Vec<T>] {
+        &self.runs
+    }
+
+    pub fn clear(&mut self) {
+        self.runs.clear();
+        self.len = 0;
+    }
+}
+
+impl<T: Ord> RunHeap<T> {
+    /// Adds `run`, which must be sorted. It may hold duplicates, of its own elements or of those of
+    /// other runs.
+    pub fn push_run(&mut self, run: Vec<T>) {
+        debug_assert!(run.windows(2).all(|w| w[0] <= w[1]), "run is not sorted");
+        if !run.is_empty() {
+            self.len += run.len();
+            self.runs.push(run);
+        }
+    }
+
+    /// Returns the smallest element, looking at the head of each run.
+    pub fn peek(&self) -> Option<&T> {
+        self.runs.iter().map(|run| &run[0]).min()
+    }
+
+    /// Removes every element, returning them in ascending order. Equal elements come out in the
+    /// order their runs were pushed.
+    ///
+    /// The heap is empty as soon as this returns, even if the iterator isn't used up.
+    pub fn drain_sorted(&mut self) -> KMergeStable<vec::IntoIter<T>> {
+        self.len = 0;
+        let runs = self.runs.drain(..).map(Vec::into_iter).collect();
+        KMergeStable::new(runs)
+    }
+
+    /// Returns every element in ascending order, as by `drain_sorted`.
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        kway::merge_stable(self.runs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use std::cmp::Ordering;
+
+    /// An element that compares by its key alone, and remembers the run it was pushed in.
+    #[derive(Clone, Copy, Debug)]
+    struct Tagged {
+        key: u8,
+        run: usize,
+    }
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Self) -> bool {
+            self.key == other.key
+        }
+    }
+
+    impl Eq for Tagged {}
+
+    impl PartialOrd for Tagged {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Tagged {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.key.cmp(&other.key)
+        }
+    }
+
+    #[quickcheck]
+    fn drain_sorted_impl(runs: Vec<Vec<u8>>, round_len: u8) -> bool {
+        // The heap is drained every few runs, to check that draining leaves it empty.
+        let mut heap = RunHeap::new();
+        runs.chunks(round_len as usize % 4 + 1).all(|round| {
+            let mut expected = Vec::new();
+            for (i, run) in round.iter().enumerate() {
+                let mut run = run.clone();
+                run.sort_unstable();
+                let run: Vec<_> = run.into_iter().map(|key| Tagged { key, run: i }).collect();
+                expected.extend_from_slice(&run);
+                heap.push_run(run);
+            }
+            // Ties go to the earlier run, as with a stable sort.
+            expected.sort();
+
+            let peeked = heap.peek().map(|x| x.key);
+            let len = heap.len();
+            let drained: Vec<_> = heap.drain_sorted().map(|x| (x.key, x.run)).collect();
+            len == expected.len()
+                && heap.is_empty()
+                && peeked == expected.first().map(|x| x.key)
+                && drained == expected.iter().map(|x| (x.key, x.run)).collect::<Vec<_>>()
+        })
+    }
+
+    #[quickcheck]
+    fn into_sorted_vec_impl(runs: Vec<Vec<u32>>) -> bool {
+        let mut heap = RunHeap::new();
+        let mut expected = Vec::new();
+        for mut run in runs {
+            run.sort_unstable();
+            expected.extend_from_slice(&run);
+            heap.push_run(run);
+        }
+        expected.sort_unstable();
+        heap.into_sorted_vec() == expected
+    }
+}
@@ -0,0 +1,270 @@
+//! An inverted index of posting lists, as an example of a small system built on the crate.
+//!
+//! Each term maps to the sorted IDs of the documents that contain it, kept in a
+//! `forest::SharedMergeForest` so that each batch of documents becomes one run per term. Queries
+//! read a snapshot of each term's runs and combine the posting lists with `set_ops::intersect_k`,
+//! `kway::merge_uniq` or `set_ops::t_overlap`. Removing a document records a tombstone, which
+//! `set_ops::difference_k` subtracts from every answer until a compaction drops the document from
+//! the posting lists for good. Inserts, queries and compactions all take `&self`, so compaction can
+//! run on a background thread while the others go on.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use crate::forest::{Compaction, CompactionAdvisor, SharedMergeForest};
+use crate::{kway, set_ops};
+
+/// A map from terms of type `K` to the sorted IDs of type `D` of the documents that contain them.
+///
+/// A query reads each of its terms' posting lists at some point while it runs, so a query that
+/// overlaps an insert may see the new batch for some of its terms and not for others.
+///
+/// IDs must not be reused once their documents are removed, since tombstones are never dropped.
+#[derive(Debug)]
+pub struct InvertedIndex<K, D> {
+    postings: RwLock<BTreeMap<K, Arc<SharedMergeForest<D>>>>,
+    /// The IDs of the removed documents.
+    removed: SharedMergeForest<D>,
+    compaction: Compaction,
+}
+
+impl<K, D> Default for InvertedIndex<K, D> {
+    fn default() -> Self {
+        InvertedIndex::new()
+    }
+}
+
+impl<K, D> InvertedIndex<K, D> {
+    pub fn new() -> Self {
+        InvertedIndex::with_compaction(Compaction::default())
+    }
+
+    /// Creates an index whose posting lists merge their runs according to `compaction`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a ratio or threshold is less than two, or a base is zero.
+    pub fn with_compaction(compaction: Compaction) -> Self {
+        InvertedIndex {
+            postings: RwLock::new(BTreeMap::new()),
+            removed: SharedMergeForest::with_compaction(compaction),
+            compaction,
+        }
+    }
+
+    pub fn compaction(&self) -> Compaction {
+        self.compaction
+    }
+}
+
+impl<K: Ord, D: Ord + Clone> InvertedIndex<K, D> {
+    /// Adds a batch of documents, each given by its ID and its terms, in any order.
+    pub fn insert<I, T>(&self, docs: I)
+    where
+        I: IntoIterator<Item = (D, T)>,
+        T: IntoIterator<Item = K>,
+    {
+        let mut pairs: Vec<(K, D)> = docs
+            .into_iter()
+            .flat_map(|(doc, terms)| terms.into_iter().map(move |term| (term, doc.clone())))
+            .collect();
+        pairs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        // Each term's documents become one run of its posting list.
+        let mut pairs = pairs.into_iter().peekable();
+        while let Some((term, doc)) = pairs.next() {
+            let mut docs = vec![doc];
+            while let Some((_, doc)) = pairs.next_if(|(t, _)| *t == term) {
+                docs.push(doc);
+            }
+            self.posting_list(term).insert(docs);
+        }
+    }
+
+    /// Removes the documents in `docs` from the answers of every query from now on.
+    pub fn remove(&self, docs: Vec<D>) {
+        self.removed.insert(docs);
+    }
+
+    /// Returns the documents that contain `term`.
+    pub fn postings(&self, term: &K) -> Vec<D> {
+        self.any_of(std::slice::from_ref(term))
+    }
+
+    /// Returns the documents that contain every one of `terms`, or none if `terms` is empty.
+    pub fn all_of(&self, terms: &[K]) -> Vec<D> {
+        self.live(set_ops::intersect_k(self.lists(terms)))
+    }
+
+    /// Returns the documents that contain any of `terms`.
+    pub fn any_of(&self, terms: &[K]) -> Vec<D> {
+        self.live(kway::merge_uniq(self.lists(terms)))
+    }
+
+    /// Returns the documents that contain at least `m` of the distinct `terms`.
+    pub fn at_least(&self, terms: &[K], m: usize) -> Vec<D> {
+        self.live(set_ops::t_overlap(self.lists(terms), m))
+    }
+
+    /// Compacts the posting lists that `advisor` recommends compacting, and drops the removed
+    /// documents from them. Returns the number of lists compacted.
+    ///
+    /// Removed documents count as tombstones, so a list with too many of them is compacted even
+    /// if it is a single run.
+    pub fn compact(&self, advisor: &CompactionAdvisor) -> usize {
+        let removed = self.removed.snapshot();
+        let lists: Vec<_> = self.postings.read().unwrap().values().cloned().collect();
+        let mut compacted = 0;
+        for list in lists {
+            let stats = list.snapshot().stats_by(|doc| removed.contains(doc));
+            if advisor.should_compact(&stats)
+                || stats.tombstone_ratio() > advisor.max_tombstone_ratio
+            {
+                list.compact_retain(|doc| !removed.contains(doc));
+                compacted += 1;
+            }
+        }
+        compacted
+    }
+
+    /// Returns the posting list of `term`, creating it if it doesn't exist yet.
+    fn posting_list(&self, term: K) -> Arc<SharedMergeForest<D>> {
+        if let Some(list) = self.postings.read().unwrap().get(&term) {
+            return Arc::clone(list);
+        }
+        let mut postings = self.postings.write().unwrap();
+        let list = postings
+            .entry(term)
+            .or_insert_with(|| Arc::new(SharedMergeForest::with_compaction(self.compaction)));
+        Arc::clone(list)
+    }
+
+    /// Returns the posting lists of the distinct `terms`, removed documents included.
+    fn lists(&self, terms: &[K]) -> Vec<Vec<D>> {
+        let mut terms: Vec<&K> = terms.iter().collect();
+        terms.sort_unstable();
+        terms.dedup();
+
+        let lists: Vec<_> = {
+            let postings = self.postings.read().unwrap();
+            terms
+                .iter()
+                .map(|&term| postings.get(term).cloned())
+                .collect()
+        };
+        lists
+            .into_iter()
+            .map(|list| {
+                list.map_or_else(Vec::new, |list| list.snapshot().iter().cloned().collect())
+            })
+            .collect()
+    }
+
+    /// Subtracts the removed documents from the answer to a query.
+    fn live(&self, docs: Vec<D>) -> Vec<D> {
+        let removed = self.removed.snapshot();
+        let runs: Vec<&[D]> = removed.runs().iter().map(|run| run.as_slice()).collect();
+        set_ops::difference_k(docs, &runs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeSet;
+
+    #[quickcheck]
+    fn index_impl(batches: Vec<Vec<(u16, Vec<u8>)>>, removed: Vec<u16>, query: Vec<u8>) -> bool {
+        let index = InvertedIndex::new();
+        let mut expected: BTreeMap<u8, BTreeSet<u16>> = BTreeMap::new();
+        for mut batch in batches {
+            // A few terms per document is plenty, and keeps the batches small.
+            for (_, terms) in &mut batch {
+                terms.truncate(3);
+            }
+            for (doc, terms) in &batch {
+                for &term in terms {
+                    expected.entry(term % 8).or_default().insert(*doc);
+                }
+            }
+            index.insert(
+                batch
+                    .into_iter()
+                    .map(|(doc, terms)| (doc, terms.into_iter().map(|t| t % 8))),
+            );
+        }
+        let removed: BTreeSet<_> = removed.into_iter().collect();
+        index.remove(removed.iter().copied().collect());
+
+        let query: BTreeSet<u8> = query.into_iter().map(|t| t % 8).collect();
+        let query_terms: Vec<u8> = query.iter().copied().collect();
+        // How many of the query's terms each live document contains.
+        let mut counts: BTreeMap<u16, usize> = BTreeMap::new();
+        for docs in query.iter().filter_map(|t| expected.get(t)) {
+            for &doc in docs.difference(&removed) {
+                *counts.entry(doc).or_default() += 1;
+            }
+        }
+        let at_least = |m: usize| -> Vec<u16> {
+            let m = m.max(1);
+            counts
+                .iter()
+                .filter(|&(_, &n)| n >= m)
+                .map(|(&doc, _)| doc)
+                .collect()
+        };
+
+        let check = |index: &InvertedIndex<u8, u16>| {
+            let all = if query.is_empty() {
+                Vec::new()
+            } else {
+                at_least(query.len())
+            };
+            (0..=query.len() + 1).all(|m| index.at_least(&query_terms, m) == at_least(m))
+                && index.all_of(&query_terms) == all
+                && index.any_of(&query_terms) == at_least(1)
+                && (0..8).all(|t| {
+                    let docs = expected.get(&t).into_iter().flatten();
+                    index
+                        .postings(&t)
+                        .into_iter()
+                        .eq(docs.copied().filter(|doc| !removed.contains(doc)))
+                })
+        };
+
+        let before = check(&index);
+        index.compact(&CompactionAdvisor {
+            max_runs: 0,
+            ..CompactionAdvisor::default()
+        });
+        before && check(&index)
+    }
+
+    #[test]
+    fn compaction_drops_removed_documents() {
+        let index = InvertedIndex::new();
+        index.insert((0..100u32).map(|doc| (doc, [if doc % 2 == 0 { "even" } else { "odd" }])));
+        index.remove((0..20).collect());
+
+        let advisor = CompactionAdvisor::default();
+        // A tenth of each list is removed, which is within the advisor's limits.
+        assert_eq!(index.compact(&advisor), 0);
+        index.remove((20..60).collect());
+        assert_eq!(index.compact(&advisor), 2);
+        let postings = index.postings.read().unwrap();
+        assert!(postings
+            .values()
+            .all(|list| list.snapshot().iter().all(|&doc| doc >= 60)));
+        drop(postings);
+        assert_eq!(
+            index.postings(&"even"),
+            (60..100).step_by(2).collect::<Vec<_>>()
+        );
+        assert_eq!(index.all_of(&["even", "odd"]), Vec::<u32>::new());
+        assert_eq!(
+            index.any_of(&["even", "odd", "none"]),
+            (60..100).collect::<Vec<_>>()
+        );
+    }
+}
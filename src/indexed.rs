@@ -0,0 +1,199 @@
+//! Sorted runs with a sparse index of their keys.
+
+use std::borrow::Borrow;
+
+use crate::partition::sample_merged;
+
+/// A sorted, deduplicated run, along with a copy of every `stride`th element.
+///
+/// The index is small enough to stay in cache when the run isn't, so a seek searches it first and
+/// then only one block of `stride` elements of the run. That beats a binary search of the whole
+/// run most at strides of 16 to 64, and less at longer ones. The index can be read with `index`
+/// and stored alongside a run that's kept on disk, then passed back to `from_parts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedRun<T> {
+    run: Vec<T>,
+    /// `run[0]`, `run[stride]`, `run[2 * stride]` and so on.
+    index: Vec<T>,
+    stride: usize,
+}
+
+impl<T> IndexedRun<T> {
+    pub fn as_slice(&self) -> &[T] {
+        &self.run
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.run
+    }
+
+    /// Returns the indexed elements, which are the first of each block of `stride` elements.
+    pub fn index(&self) -> &[T] {
+        &self.index
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn len(&self) -> usize {
+        self.run.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.run.is_empty()
+    }
+}
+
+impl<T: Ord + Clone> IndexedRun<T> {
+    /// Indexes every `stride`th element of the sorted, deduplicated `run`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `stride` is zero.
+    pub fn new(run: Vec<T>, stride: usize) -> Self {
+        assert!(stride > 0, "cannot index every zeroth element");
+        crate::checked::debug_validate(&[&run]);
+        let index = run.iter().step_by(stride).cloned().collect();
+        IndexedRun { run, index, stride }
+    }
+}
+
+impl<T: Ord> IndexedRun<T> {
+    /// Reassembles a run from its parts, such as an index that was stored with it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` doesn't hold exactly every `stride`th element of `run`.
+    pub fn from_parts(run: Vec<T>, index: Vec<T>, stride: usize) -> Self {
+        assert!(stride > 0, "cannot index every zeroth element");
+        assert!(
+            run.iter().step_by(stride).eq(&index),
+            "index does not match the run"
+        );
+        crate::checked::debug_validate(&[&run]);
+        IndexedRun { run, index, stride }
+    }
+
+    /// Returns the position of the first element that isn't less than `key`.
+    pub fn lower_bound<Q>(&self, key: &Q) -> usize
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // Every element before block `block` is less than `key`, and every element from its end on
+        // isn't, so only the rest of the previous block needs to be searched.
+        let block = self.index.partition_point(|x| x.borrow() < key);
+        if block == 0 {
+            return 0;
+        }
+        let start = (block - 1) * self.stride + 1;
+        let end = (block * self.stride).min(self.run.len());
+        start + self.run[start..end].partition_point(|x| x.borrow() < key)
+    }
+
+    /// Returns the elements that aren't less than `key`, ready to be merged with other runs.
+    pub fn seek<Q>(&self, key: &Q) -> &[T]
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        &self.run[self.lower_bound(key)..]
+    }
+}
+
+/// Splits the merge of `runs` into at most `parts` ranges that can be merged independently,
+/// choosing the splitters from the indexes instead of the runs.
+///
+/// Returns one row of positions per split, each holding a position in every run, starting with
+/// all zeros and ending with the lengths of the runs. Merging `runs[i][row[i]..next[i]]` for every
+/// run `i` and each pair of consecutive rows `row` and `next`, then concatenating the results,
+/// gives the same result as merging `runs`. Equal elements always land in the same range.
+///
+/// Each index entry stands for `stride` elements, so the ranges are balanced to within about one
+/// stride per run. Ranges can come out empty, or fewer than `parts`, when the runs are too short
+/// for their strides. This is several times faster than finding splitters in the runs themselves
+/// with `partition::sample_merged`.
+///
+/// # Panics
+///
+/// Panics if `parts` is zero.
+pub fn split_runs<T: Ord>(runs: &[&IndexedRun<T>], parts: usize) -> Vec<Vec<usize>> {
+    let indexes: Vec<&[T]> = runs.iter().map(|run| run.index()).collect();
+    let splitters = sample_merged(&indexes, parts);
+
+    let mut rows = vec![vec![0; runs.len()]];
+    rows.extend(
+        splitters
+            .iter()
+            .skip(1)
+            .map(|&key| runs.iter().map(|run| run.lower_bound(key)).collect()),
+    );
+    rows.push(runs.iter().map(|run| run.len()).collect());
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kway;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn lower_bound_impl(run: Vec<u16>, stride: u8, keys: Vec<u16>) -> bool {
+        let run = IndexedRun::new(sorted_unique(run), stride as usize % 16 + 1);
+        keys.iter().all(|key| {
+            let expected = run.as_slice().partition_point(|x| x < key);
+            run.lower_bound(key) == expected && run.seek(key) == &run.as_slice()[expected..]
+        })
+    }
+
+    #[quickcheck]
+    fn split_runs_impl(runs: Vec<Vec<u16>>, stride: u8, parts: u8) -> bool {
+        let stride = stride as usize % 16 + 1;
+        let parts = parts as usize % 8 + 1;
+        let runs: Vec<_> = runs
+            .into_iter()
+            .map(|run| IndexedRun::new(sorted_unique(run), stride))
+            .collect();
+        let refs: Vec<_> = runs.iter().collect();
+
+        let rows = split_runs(&refs, parts);
+        let merged: Vec<_> = rows
+            .windows(2)
+            .flat_map(|w| {
+                let parts = runs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, run)| run.as_slice()[w[0][i]..w[1][i]].to_vec())
+                    .collect();
+                kway::merge_uniq(parts)
+            })
+            .collect();
+
+        let monotone = rows
+            .windows(2)
+            .all(|w| w[0].iter().zip(&w[1]).all(|(x, y)| x <= y));
+        let all = runs.into_iter().map(IndexedRun::into_vec).collect();
+        rows.len() <= parts + 1 && monotone && merged == kway::merge_uniq(all)
+    }
+
+    #[test]
+    fn from_parts() {
+        let run = IndexedRun::new(vec![1, 3, 5, 7, 9], 2);
+        assert_eq!(run.index(), [1, 5, 9]);
+        let stride = run.stride();
+        let index = run.index().to_vec();
+        assert_eq!(
+            IndexedRun::from_parts(run.clone().into_vec(), index, stride),
+            run
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "index does not match the run")]
+    fn from_parts_mismatch() {
+        IndexedRun::from_parts(vec![1, 3, 5, 7, 9], vec![1, 7], 2);
+    }
+}
@@ -0,0 +1,213 @@
+//! Counting the work a merge does, for comparing merge kernels analytically rather than by
+//! wall-clock time.
+//!
+//! Wrap elements in `Instrumented` and run a merge inside `record`, and the installed `Sink`
+//! receives the outcome of every comparison the merge makes. The raw-pointer kernels also report
+//! each move of elements to their output; the safe kernels move every output element exactly once.
+//!
+//! Sinks are installed per thread, so comparisons made on other threads (for example by the
+//! `parallel` merges) aren't recorded.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+
+/// Receives the events recorded while it is installed by `record`.
+pub trait Sink {
+    /// Called with the result of each comparison between two `Instrumented` elements.
+    fn compared(&mut self, ord: Ordering);
+
+    /// Called when a kernel moves `n` elements to its output with a single copy.
+    fn moved(&mut self, n: usize) {
+        let _ = n;
+    }
+}
+
+/// A `Sink` that counts events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Counts {
+    /// Comparisons that returned `Less`.
+    pub less: usize,
+    /// Comparisons that returned `Equal`.
+    pub equal: usize,
+    /// Comparisons that returned `Greater`.
+    pub greater: usize,
+    /// Elements moved to the output.
+    pub moved: usize,
+    /// Copies that moved elements to the output. Bulk copies of a run of elements count once.
+    pub copies: usize,
+}
+
+impl Counts {
+    /// Returns the total number of comparisons.
+    pub fn comparisons(&self) -> usize {
+        self.less + self.equal + self.greater
+    }
+}
+
+impl Sink for Counts {
+    fn compared(&mut self, ord: Ordering) {
+        match ord {
+            Ordering::Less => self.less += 1,
+            Ordering::Equal => self.equal += 1,
+            Ordering::Greater => self.greater += 1,
+        }
+    }
+
+    fn moved(&mut self, n: usize) {
+        self.moved += n;
+        self.copies += 1;
+    }
+}
+
+/// A `Sink` that can be taken back out of the thread-local stack as its concrete type.
+trait AnySink: Sink {
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<S: Sink + 'static> AnySink for S {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+thread_local! {
+    static SINKS: RefCell<Vec<Box<dyn AnySink>>> = RefCell::new(Vec::new());
+    /// Whether events are being dropped by `unrecorded`.
+    static PAUSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Passes the innermost installed sink to `f`, if there is one.
+///
+/// Events raised by the sink itself (say, by comparing `Instrumented` elements) are dropped.
+fn with_sink(f: impl FnOnce(&mut dyn Sink)) {
+    if PAUSED.with(Cell::get) {
+        return;
+    }
+    SINKS.with(|sinks| {
+        if let Ok(mut sinks) = sinks.try_borrow_mut() {
+            if let Some(sink) = sinks.last_mut() {
+                f(&mut **sink);
+            }
+        }
+    })
+}
+
+/// Records a move of `n` elements to the output of a merge.
+#[cfg(not(feature = "force-safe"))]
+#[inline]
+pub(crate) fn moved(n: usize) {
+    with_sink(|sink| sink.moved(n));
+}
+
+/// Runs `f` without reporting its events to the installed sink, so that the comparisons made to
+/// check a merge's inputs with the `debug-validate` feature aren't counted as the merge's own.
+#[cfg(all(feature = "debug-validate", debug_assertions))]
+pub(crate) fn unrecorded<R>(f: impl FnOnce() -> R) -> R {
+    /// Restores the previous state when dropped, even if `f` panics.
+    struct Resume(bool);
+
+    impl Drop for Resume {
+        fn drop(&mut self) {
+            PAUSED.with(|paused| paused.set(self.0));
+        }
+    }
+
+    let _resume = Resume(PAUSED.with(|paused| paused.replace(true)));
+    f()
+}
+
+/// Removes the innermost sink when dropped, even if the recorded closure panics.
+struct Installed;
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        SINKS.with(|sinks| sinks.borrow_mut().pop());
+    }
+}
+
+/// Runs `f` with `sink` installed on this thread, and returns its result along with the sink.
+///
+/// Calls to `record` can nest, in which case only the innermost sink receives events.
+pub fn record<S, R, F>(sink: S, f: F) -> (R, S)
+where
+    S: Sink + 'static,
+    F: FnOnce() -> R,
+{
+    SINKS.with(|sinks| sinks.borrow_mut().push(Box::new(sink)));
+    let installed = Installed;
+    let res = f();
+
+    let sink = SINKS.with(|sinks| sinks.borrow_mut().pop()).unwrap();
+    std::mem::forget(installed);
+    let sink = *sink.into_any().downcast::<S>().unwrap();
+    (res, sink)
+}
+
+/// Compares by `T`'s order, reporting each comparison to the sink installed by `record`.
+///
+/// `==` counts as a comparison too, since the merges use it to find duplicates.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(transparent)]
+pub struct Instrumented<T>(pub T);
+
+impl<T: Ord> Ord for Instrumented<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ord = self.0.cmp(&other.0);
+        with_sink(|sink| sink.compared(ord));
+        ord
+    }
+}
+
+impl<T: Ord> PartialOrd for Instrumented<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> PartialEq for Instrumented<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for Instrumented<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq;
+    use quickcheck_macros::quickcheck;
+
+    fn instrumented(mut v: Vec<u8>) -> Vec<Instrumented<u8>> {
+        v.sort_unstable();
+        v.dedup();
+        v.into_iter().map(Instrumented).collect()
+    }
+
+    #[quickcheck]
+    fn record_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (instrumented(a), instrumented(b));
+        let (alen, blen) = (a.len(), b.len());
+
+        let (out, counts) = record(Counts::default(), || merge2_uniq::raw_ptr(a, b));
+
+        // A linear merge compares at most once per input element, and drops one element of
+        // `b` for each comparison that found a duplicate.
+        let linear = counts.comparisons() <= alen + blen && alen + blen - out.len() == counts.equal;
+        // When either input is empty, the other is returned without moving its elements.
+        let expected_moves = if alen == 0 || blen == 0 { 0 } else { out.len() };
+        let moves = cfg!(feature = "force-safe") || counts.moved == expected_moves;
+        linear && moves
+    }
+
+    #[test]
+    fn record_nested() {
+        let ((_, inner), outer) = record(Counts::default(), || {
+            let _ = Instrumented(1) < Instrumented(2);
+            record(Counts::default(), || Instrumented(2) == Instrumented(2))
+        });
+        assert_eq!((outer.less, outer.equal), (1, 0));
+        assert_eq!((inner.less, inner.equal), (0, 1));
+    }
+}
@@ -0,0 +1,70 @@
+//! Merging sorted lists of ranges.
+
+use std::ops::Range;
+
+/// Merges two lists of ranges, each sorted by start, into a sorted list of disjoint ranges.
+///
+/// Ranges that overlap or are adjacent, whether from the same input or not, are coalesced into
+/// one, so the result has a gap between every pair of consecutive ranges. Empty ranges are
+/// dropped.
+pub fn merge_ranges<T: Ord>(a: Vec<Range<T>>, b: Vec<Range<T>>) -> Vec<Range<T>> {
+    let mut out: Vec<Range<T>> = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+
+    loop {
+        let next = match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) if y.start < x.start => b.next(),
+            (Some(_), _) => a.next(),
+            (None, _) => b.next(),
+        };
+        let r = match next {
+            Some(r) if r.start < r.end => r,
+            Some(_) => continue,
+            None => break,
+        };
+
+        match out.last_mut() {
+            Some(last) if r.start <= last.end => {
+                if r.end > last.end {
+                    last.end = r.end;
+                }
+            }
+            _ => out.push(r),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn ranges(mut v: Vec<(u8, u8)>) -> Vec<Range<u16>> {
+        v.sort_unstable();
+        v.into_iter()
+            .map(|(start, len)| start.into()..u16::from(start) + u16::from(len % 8))
+            .collect()
+    }
+
+    #[quickcheck]
+    fn merge_ranges_impl(a: Vec<(u8, u8)>, b: Vec<(u8, u8)>) -> bool {
+        let (a, b) = (ranges(a), ranges(b));
+        let covered = |x: u16| a.iter().chain(&b).any(|r| r.contains(&x));
+
+        let merged = merge_ranges(a.clone(), b.clone());
+        let disjoint = merged.windows(2).all(|w| w[0].end < w[1].start);
+        let exact = (0..300).all(|x| covered(x) == merged.iter().any(|r| r.contains(&x)));
+        disjoint && merged.iter().all(|r| r.start < r.end) && exact
+    }
+
+    #[test]
+    fn coalesces_adjacent() {
+        assert_eq!(
+            merge_ranges(vec![0..2, 6..8], vec![2..4, 5..6]),
+            vec![0..4, 5..8]
+        );
+    }
+}
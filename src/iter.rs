@@ -0,0 +1,76 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+
+/// An iterator over the merged, deduplicated contents of two sorted, deduplicated iterators.
+///
+/// When an element appears in both inputs, the one from `a` is yielded and the one from `b` is
+/// dropped, matching the vector-based kernels. This uses no unsafe code, so it is what the
+/// `force-safe` feature routes the kernels through.
+pub(crate) struct MergeUniq<I: Iterator, J: Iterator = I> {
+    a: Peekable<I>,
+    b: Peekable<J>,
+}
+
+impl<I: Iterator, J: Iterator> MergeUniq<I, J> {
+    pub(crate) fn new(a: I, b: J) -> Self {
+        MergeUniq {
+            a: a.peekable(),
+            b: b.peekable(),
+        }
+    }
+}
+
+impl<I, J> Iterator for MergeUniq<I, J>
+where
+    I: Iterator,
+    J: Iterator<Item = I::Item>,
+    I::Item: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ord = match (self.a.peek(), self.b.peek()) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => Ordering::Less,
+            (None, _) => Ordering::Greater,
+        };
+
+        match ord {
+            Ordering::Less => self.a.next(),
+            Ordering::Greater => self.b.next(),
+            Ordering::Equal => {
+                self.b.next();
+                self.a.next()
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a_lo, a_hi) = self.a.size_hint();
+        let (b_lo, b_hi) = self.b.size_hint();
+        let hi = match (a_hi, b_hi) {
+            (Some(a_hi), Some(b_hi)) => a_hi.checked_add(b_hi),
+            _ => None,
+        };
+        (a_lo.max(b_lo), hi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn merge_uniq_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = MergeUniq::new(a.into_iter(), b.into_iter()).collect();
+        expected == actual
+    }
+}
@@ -0,0 +1,461 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+
+use crate::gallop::gallop;
+use crate::kway::{ByKey, KMergeUniq};
+
+/// Joins two vectors of key-value pairs, each sorted by key, calling `f` once for every pair of
+/// rows with equal keys.
+///
+/// Keys need not be unique. When a key appears `n` times in `a` and `m` times in `b`, `f` is
+/// called `n * m` times. Results are ordered by key, then by position in `a`, then by position in
+/// `b`.
+pub fn merge_join<K: Ord, A, B, R>(
+    a: Vec<(K, A)>,
+    b: Vec<(K, B)>,
+    mut f: impl FnMut(&K, &A, &B) -> R,
+) -> Vec<R> {
+    let mut out = Vec::new();
+
+    let mut a = &a[..];
+    let mut b = &b[..];
+
+    while !a.is_empty() && !b.is_empty() {
+        match a[0].0.cmp(&b[0].0) {
+            Ordering::Less => a = &a[1..],
+            Ordering::Greater => b = &b[1..],
+            Ordering::Equal => {
+                // Find the extent of the run of equal keys on both sides, then emit their cross
+                // product.
+                let key = &a[0].0;
+                let a_run = a.iter().take_while(|(k, _)| k == key).count();
+                let b_run = b.iter().take_while(|(k, _)| k == key).count();
+
+                for (_, a_val) in &a[..a_run] {
+                    for (_, b_val) in &b[..b_run] {
+                        out.push(f(key, a_val, b_val));
+                    }
+                }
+
+                a = &a[a_run..];
+                b = &b[b_run..];
+            }
+        }
+    }
+
+    out
+}
+
+/// An element of a full outer merge: one from the left input, one from the right, or one from each
+/// that compare equal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EitherOrBoth<A, B> {
+    Left(A),
+    Right(B),
+    Both(A, B),
+}
+
+/// An iterator over the full outer merge of two sorted iterators under a comparator.
+///
+/// Returned by `merge_join_by`.
+pub struct MergeJoinBy<I: Iterator, J: Iterator, F> {
+    a: Peekable<I>,
+    b: Peekable<J>,
+    cmp: F,
+}
+
+/// Merges two iterators, each sorted under `cmp`, yielding every element once: on its own if the
+/// other input has no equal element, and paired with it otherwise.
+///
+/// Elements that compare equal are paired in order, so a run of three equal elements on the left
+/// against one on the right yields a `Both` followed by two `Left`s. A diff, an intersection or a
+/// join can be had by keeping only the variants of interest.
+pub fn merge_join_by<I, J, F>(a: I, b: J, cmp: F) -> MergeJoinBy<I::IntoIter, J::IntoIter, F>
+where
+    I: IntoIterator,
+    J: IntoIterator,
+    F: FnMut(&I::Item, &J::Item) -> Ordering,
+{
+    MergeJoinBy {
+        a: a.into_iter().peekable(),
+        b: b.into_iter().peekable(),
+        cmp,
+    }
+}
+
+/// Like `merge_join_by`, but collects the merge into a vector.
+pub fn outer_join_by<A, B, F>(a: Vec<A>, b: Vec<B>, cmp: F) -> Vec<EitherOrBoth<A, B>>
+where
+    F: FnMut(&A, &B) -> Ordering,
+{
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    out.extend(merge_join_by(a, b, cmp));
+    out
+}
+
+/// Merges two inputs of different types, each sorted by a key borrowed from its elements, and
+/// collects `f` of each element of the merge, as in `merge_join_by`.
+///
+/// This saves projecting both sides to their keys before merging, for instance to merge a slice
+/// of users with a slice of user ids. Passing slices merges references to their elements, so
+/// nothing is cloned or moved either. That is several times faster than projecting the records to
+/// their keys and positions, merging those with `outer_join_by`, and looking the records back up.
+pub fn merge_by_key_ref<I, J, K, KA, KB, F, R>(
+    a: I,
+    b: J,
+    mut key_a: KA,
+    mut key_b: KB,
+    f: F,
+) -> Vec<R>
+where
+    I: IntoIterator,
+    J: IntoIterator,
+    K: Ord + ?Sized,
+    KA: FnMut(&I::Item) -> &K,
+    KB: FnMut(&J::Item) -> &K,
+    F: FnMut(EitherOrBoth<I::Item, J::Item>) -> R,
+{
+    merge_join_by(a, b, |x, y| key_a(x).cmp(key_b(y)))
+        .map(f)
+        .collect()
+}
+
+impl<I, J, F> Iterator for MergeJoinBy<I, J, F>
+where
+    I: Iterator,
+    J: Iterator,
+    F: FnMut(&I::Item, &J::Item) -> Ordering,
+{
+    type Item = EitherOrBoth<I::Item, J::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ord = match (self.a.peek(), self.b.peek()) {
+            (Some(x), Some(y)) => (self.cmp)(x, y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+
+        Some(match ord {
+            Ordering::Less => EitherOrBoth::Left(self.a.next()?),
+            Ordering::Greater => EitherOrBoth::Right(self.b.next()?),
+            Ordering::Equal => EitherOrBoth::Both(self.a.next()?, self.b.next()?),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a_lo, a_hi) = self.a.size_hint();
+        let (b_lo, b_hi) = self.b.size_hint();
+        let hi = match (a_hi, b_hi) {
+            (Some(a_hi), Some(b_hi)) => a_hi.checked_add(b_hi),
+            _ => None,
+        };
+        (a_lo.max(b_lo), hi)
+    }
+}
+
+/// Keeps the rows of `a` whose key appears in `keys`.
+///
+/// Both `a` and `keys` must be sorted by key. The cursor into `keys` advances by galloping, so a
+/// short `a` against a long `keys` (or vice versa) does not pay for a full linear scan.
+pub fn semi_join<K: Ord, V>(a: Vec<(K, V)>, keys: &[K]) -> Vec<(K, V)> {
+    filter_by_keys(a, keys, true)
+}
+
+/// Keeps the rows of `a` whose key does *not* appear in `keys`.
+///
+/// Both `a` and `keys` must be sorted by key.
+pub fn anti_join<K: Ord, V>(a: Vec<(K, V)>, keys: &[K]) -> Vec<(K, V)> {
+    filter_by_keys(a, keys, false)
+}
+
+fn filter_by_keys<K: Ord, V>(
+    mut a: Vec<(K, V)>,
+    mut keys: &[K],
+    keep_matches: bool,
+) -> Vec<(K, V)> {
+    a.retain(|(k, _)| {
+        keys = &keys[gallop(keys, |x| x < k)..];
+        let found = keys.first() == Some(k);
+        found == keep_matches
+    });
+    a
+}
+
+/// Merges two vectors of key-value pairs, each sorted by key with unique keys. Where both have
+/// the same key, the entry from `patch` replaces the one from `base`.
+///
+/// This layers configurations, or merges a newer LSM level onto an older one. To keep deletions
+/// around for a later merge, use `Option<V>` values; to apply them, use `overlay_tombstones`.
+/// To apply several patches at once, use `overlay_k`.
+pub fn overlay<K: Ord, V>(base: Vec<(K, V)>, patch: Vec<(K, V)>) -> Vec<(K, V)> {
+    overlay_with(base, patch, Some)
+}
+
+/// Like `overlay`, but a `None` value in `patch` deletes the entry with that key from `base`
+/// instead of replacing it.
+pub fn overlay_tombstones<K: Ord, V>(base: Vec<(K, V)>, patch: Vec<(K, Option<V>)>) -> Vec<(K, V)> {
+    overlay_with(base, patch, |v| v)
+}
+
+/// Merges layers of key-value pairs, each sorted by key with unique keys. Where several layers
+/// have the same key, the entry from the latest layer wins.
+///
+/// This gives the same result as folding `overlay` over the layers, but merges them all at once,
+/// so each entry is compared `O(log k)` times instead of once for every later layer.
+pub fn overlay_k<K: Ord, V>(layers: Vec<Vec<(K, V)>>) -> Vec<(K, V)> {
+    overlay_k_with(layers, Some)
+}
+
+/// Like `overlay_k`, but a `None` value deletes the entries with that key from all earlier layers
+/// instead of replacing them.
+pub fn overlay_k_tombstones<K: Ord, V>(layers: Vec<Vec<(K, Option<V>)>>) -> Vec<(K, V)> {
+    overlay_k_with(layers, |v| v)
+}
+
+fn overlay_k_with<K: Ord, V, P>(
+    mut layers: Vec<Vec<(K, P)>>,
+    mut apply: impl FnMut(P) -> Option<V>,
+) -> Vec<(K, V)> {
+    // Of several equal keys, `KMergeUniq` keeps the one from the earliest input, so the layers go
+    // in latest first.
+    layers.reverse();
+    let len = layers.iter().map(Vec::len).sum();
+    let sources = layers
+        .into_iter()
+        .map(|layer| layer.into_iter().map(|(key, item)| ByKey { key, item }))
+        .collect();
+
+    let mut out = Vec::with_capacity(len);
+    for ByKey { key, item } in KMergeUniq::new(sources) {
+        if let Some(v) = apply(item) {
+            out.push((key, v));
+        }
+    }
+    out
+}
+
+fn overlay_with<K: Ord, V, P>(
+    base: Vec<(K, V)>,
+    patch: Vec<(K, P)>,
+    mut apply: impl FnMut(P) -> Option<V>,
+) -> Vec<(K, V)> {
+    let mut out = Vec::with_capacity(base.len() + patch.len());
+    let mut push_patch = |out: &mut Vec<(K, V)>, (k, p): (K, P)| {
+        if let Some(v) = apply(p) {
+            out.push((k, v));
+        }
+    };
+
+    let mut base = base.into_iter().peekable();
+    let mut patch = patch.into_iter().peekable();
+    while let (Some(b), Some(p)) = (base.peek(), patch.peek()) {
+        match b.0.cmp(&p.0) {
+            Ordering::Less => out.extend(base.next()),
+            Ordering::Greater => push_patch(&mut out, patch.next().unwrap()),
+            Ordering::Equal => {
+                base.next();
+                push_patch(&mut out, patch.next().unwrap());
+            }
+        }
+    }
+
+    out.extend(base);
+    for entry in patch {
+        push_patch(&mut out, entry);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn nested_loop<K: Ord + Clone, A: Clone, B: Clone>(
+        a: &[(K, A)],
+        b: &[(K, B)],
+    ) -> Vec<(K, A, B)> {
+        let mut out = Vec::new();
+        for (ka, va) in a {
+            for (kb, vb) in b {
+                if ka == kb {
+                    out.push((ka.clone(), va.clone(), vb.clone()));
+                }
+            }
+        }
+        out
+    }
+
+    #[quickcheck]
+    fn merge_join_impl(mut a: Vec<(u8, usize)>, mut b: Vec<(u8, usize)>) -> bool {
+        a.sort_by_key(|(k, _)| *k);
+        b.sort_by_key(|(k, _)| *k);
+
+        let expected = nested_loop(&a, &b);
+        let actual = merge_join(a, b, |k, a, b| (*k, *a, *b));
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn semi_join_impl(mut a: Vec<(u8, usize)>, mut keys: Vec<u8>) -> bool {
+        a.sort_by_key(|(k, _)| *k);
+        keys.sort_unstable();
+
+        let expected: Vec<_> = a
+            .iter()
+            .filter(|(k, _)| keys.contains(k))
+            .cloned()
+            .collect();
+        let actual = semi_join(a, &keys);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn anti_join_impl(mut a: Vec<(u8, usize)>, mut keys: Vec<u8>) -> bool {
+        a.sort_by_key(|(k, _)| *k);
+        keys.sort_unstable();
+
+        let expected: Vec<_> = a
+            .iter()
+            .filter(|(k, _)| !keys.contains(k))
+            .cloned()
+            .collect();
+        let actual = anti_join(a, &keys);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn overlay_impl(base: Vec<(u8, u16)>, patch: Vec<(u8, Option<u16>)>) -> bool {
+        use std::collections::BTreeMap;
+
+        let base: BTreeMap<_, _> = base.into_iter().collect();
+        let patch: BTreeMap<_, _> = patch.into_iter().collect();
+
+        let mut expected = base.clone();
+        let mut expected_tombstones = base.clone();
+        for (&k, &v) in &patch {
+            expected.insert(k, v.unwrap_or(0));
+            match v {
+                Some(v) => expected_tombstones.insert(k, v),
+                None => expected_tombstones.remove(&k),
+            };
+        }
+
+        let base: Vec<_> = base.into_iter().collect();
+        let patch: Vec<_> = patch.into_iter().collect();
+        let unwrapped = patch.iter().map(|&(k, v)| (k, v.unwrap_or(0))).collect();
+
+        overlay(base.clone(), unwrapped) == expected.into_iter().collect::<Vec<_>>()
+            && overlay_tombstones(base, patch)
+                == expected_tombstones.into_iter().collect::<Vec<_>>()
+    }
+
+    #[quickcheck]
+    fn overlay_k_impl(layers: Vec<Vec<(u8, Option<u16>)>>) -> bool {
+        use std::collections::BTreeMap;
+
+        let layers: Vec<Vec<_>> = layers
+            .into_iter()
+            .map(|layer| {
+                layer
+                    .into_iter()
+                    .collect::<BTreeMap<_, _>>()
+                    .into_iter()
+                    .collect()
+            })
+            .collect();
+
+        let mut expected = BTreeMap::new();
+        let mut expected_tombstones = BTreeMap::new();
+        for &(k, v) in layers.iter().flatten() {
+            expected.insert(k, v.unwrap_or(0));
+            match v {
+                Some(v) => expected_tombstones.insert(k, v),
+                None => expected_tombstones.remove(&k),
+            };
+        }
+
+        let unwrapped = layers
+            .iter()
+            .map(|layer| layer.iter().map(|&(k, v)| (k, v.unwrap_or(0))).collect())
+            .collect();
+        overlay_k(unwrapped) == expected.into_iter().collect::<Vec<_>>()
+            && overlay_k_tombstones(layers) == expected_tombstones.into_iter().collect::<Vec<_>>()
+    }
+
+    #[quickcheck]
+    fn outer_join_by_impl(mut a: Vec<(u8, u8)>, mut b: Vec<u8>) -> bool {
+        a.sort_by_key(|&(k, _)| k);
+        b.sort_unstable();
+
+        let merged = outer_join_by(a.clone(), b.clone(), |x, y| x.0.cmp(y));
+
+        // Taking the elements of each side back out recovers the inputs.
+        let lefts: Vec<_> = merged
+            .iter()
+            .filter_map(|e| match *e {
+                EitherOrBoth::Left(x) | EitherOrBoth::Both(x, _) => Some(x),
+                EitherOrBoth::Right(_) => None,
+            })
+            .collect();
+        let rights: Vec<_> = merged
+            .iter()
+            .filter_map(|e| match *e {
+                EitherOrBoth::Right(y) | EitherOrBoth::Both(_, y) => Some(y),
+                EitherOrBoth::Left(_) => None,
+            })
+            .collect();
+
+        // Keys never decrease, and unpaired elements have no equal counterpart left to pair with.
+        let key = |e: &EitherOrBoth<(u8, u8), u8>| match *e {
+            EitherOrBoth::Left((k, _)) | EitherOrBoth::Right(k) | EitherOrBoth::Both((k, _), _) => {
+                k
+            }
+        };
+        let sorted = merged.windows(2).all(|w| key(&w[0]) <= key(&w[1]));
+        let paired = merged.iter().all(|e| match *e {
+            EitherOrBoth::Both((k, _), y) => k == y,
+            _ => true,
+        });
+        let both = merged
+            .iter()
+            .filter(|e| matches!(e, EitherOrBoth::Both(..)))
+            .count();
+        let expected_both: usize = (0..=255u8)
+            .map(|k| {
+                let in_a = a.iter().filter(|x| x.0 == k).count();
+                in_a.min(b.iter().filter(|&&y| y == k).count())
+            })
+            .sum();
+
+        lefts == a && rights == b && sorted && paired && both == expected_both
+    }
+
+    #[quickcheck]
+    fn merge_by_key_ref_impl(mut users: Vec<(u8, u8)>, mut ids: Vec<u8>) -> bool {
+        users.sort_by_key(|&(id, _)| id);
+        ids.sort_unstable();
+
+        let expected: Vec<_> = outer_join_by(users.clone(), ids.clone(), |x, y| x.0.cmp(y))
+            .into_iter()
+            .map(|e| match e {
+                EitherOrBoth::Left((id, name)) => (id, Some(name), false),
+                EitherOrBoth::Right(id) => (id, None, true),
+                EitherOrBoth::Both((id, name), _) => (id, Some(name), true),
+            })
+            .collect();
+        let merged = merge_by_key_ref(
+            &users,
+            &ids,
+            |user| &user.0,
+            |id| *id,
+            |e| match e {
+                EitherOrBoth::Left(&(id, name)) => (id, Some(name), false),
+                EitherOrBoth::Right(&id) => (id, None, true),
+                EitherOrBoth::Both(&(id, name), _) => (id, Some(name), true),
+            },
+        );
+        merged == expected
+    }
+}
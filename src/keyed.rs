@@ -0,0 +1,232 @@
+//! Merging runs of different types that are sorted by a key they share, such as several kinds of
+//! events sorted by timestamp.
+//!
+//! Each run is converted to the output type one item at a time as the merge reaches it, either
+//! into an enum with a variant per type through `Into`, or into a trait object through
+//! `KeyedRuns::run_map`. Nothing is collected into a vector of the output type first.
+
+use std::marker::PhantomData;
+
+use crate::kway::{Less, LoserTree};
+
+/// A value with a key it is sorted by.
+pub trait Keyed<K: ?Sized> {
+    fn key(&self) -> &K;
+}
+
+impl<K: ?Sized, T: Keyed<K> + ?Sized> Keyed<K> for &T {
+    fn key(&self) -> &K {
+        (**self).key()
+    }
+}
+
+impl<K: ?Sized, T: Keyed<K> + ?Sized> Keyed<K> for Box<T> {
+    fn key(&self) -> &K {
+        (**self).key()
+    }
+}
+
+/// Orders items by their keys alone.
+struct KeyOrder<K: ?Sized>(PhantomData<fn(&K)>);
+
+impl<K: Ord + ?Sized, E: Keyed<K>> Less<E> for KeyOrder<K> {
+    #[inline]
+    fn less(&mut self, a: &E, b: &E) -> bool {
+        a.key() < b.key()
+    }
+}
+
+type Source<'a, E> = Box<dyn Iterator<Item = E> + 'a>;
+
+/// The runs of a merge over items of different types, each converted to `E` as it is merged.
+pub struct KeyedRuns<'a, E> {
+    sources: Vec<Source<'a, E>>,
+}
+
+impl<'a, E: 'a> Default for KeyedRuns<'a, E> {
+    fn default() -> Self {
+        KeyedRuns::new()
+    }
+}
+
+impl<'a, E: 'a> KeyedRuns<'a, E> {
+    pub fn new() -> Self {
+        KeyedRuns {
+            sources: Vec::new(),
+        }
+    }
+
+    /// Adds a run whose items convert into `E`, such as one of the variants of an enum.
+    pub fn run<I>(self, run: I) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: 'a,
+        I::Item: Into<E> + 'a,
+    {
+        self.run_map(run, Into::into)
+    }
+
+    /// Adds a run whose items are converted into `E` by `f`, such as `|x| Box::new(x) as Box<dyn
+    /// Event>` for a trait `Event` that has `Keyed` as a supertrait.
+    pub fn run_map<I, F>(mut self, run: I, f: F) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: 'a,
+        F: FnMut(I::Item) -> E + 'a,
+    {
+        self.sources.push(Box::new(run.into_iter().map(f)));
+        self
+    }
+
+    /// Merges the runs, each of which must be sorted by key. Items with equal keys are yielded in
+    /// the order their runs were added, and none are dropped.
+    pub fn merge<K>(self) -> KeyedMerge<'a, K, E>
+    where
+        K: Ord + ?Sized,
+        E: Keyed<K>,
+    {
+        KeyedMerge {
+            tree: LoserTree::with_less(self.sources, KeyOrder(PhantomData)),
+        }
+    }
+}
+
+/// An iterator over the merged items of `KeyedRuns`. See `KeyedRuns::merge`.
+pub struct KeyedMerge<'a, K: ?Sized, E> {
+    tree: LoserTree<Source<'a, E>, KeyOrder<K>>,
+}
+
+impl<K, E> Iterator for KeyedMerge<'_, K, E>
+where
+    K: Ord + ?Sized,
+    E: Keyed<K>,
+{
+    type Item = E;
+
+    fn next(&mut self) -> Option<E> {
+        self.tree.pop().map(|(_, item)| item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.tree.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    struct Click {
+        at: u32,
+        button: u8,
+    }
+
+    struct Scroll {
+        at: u32,
+        delta: i8,
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Click(u32, u8),
+        Scroll(u32, i8),
+    }
+
+    impl Keyed<u32> for Click {
+        fn key(&self) -> &u32 {
+            &self.at
+        }
+    }
+
+    impl Keyed<u32> for Scroll {
+        fn key(&self) -> &u32 {
+            &self.at
+        }
+    }
+
+    impl Keyed<u32> for Event {
+        fn key(&self) -> &u32 {
+            match self {
+                Event::Click(at, _) | Event::Scroll(at, _) => at,
+            }
+        }
+    }
+
+    impl From<Click> for Event {
+        fn from(c: Click) -> Self {
+            Event::Click(c.at, c.button)
+        }
+    }
+
+    impl From<Scroll> for Event {
+        fn from(s: Scroll) -> Self {
+            Event::Scroll(s.at, s.delta)
+        }
+    }
+
+    trait Describe: Keyed<u32> {
+        fn describe(&self) -> String;
+    }
+
+    impl Describe for Click {
+        fn describe(&self) -> String {
+            format!("click {} at {}", self.button, self.at)
+        }
+    }
+
+    impl Describe for Scroll {
+        fn describe(&self) -> String {
+            format!("scroll {} at {}", self.delta, self.at)
+        }
+    }
+
+    #[quickcheck]
+    fn merge_impl(mut clicks: Vec<(u8, u8)>, mut scrolls: Vec<(u8, i8)>) -> bool {
+        clicks.sort_by_key(|c| c.0);
+        scrolls.sort_by_key(|s| s.0);
+
+        // Ties go to clicks, whose run is added first.
+        let mut expected: Vec<_> = clicks
+            .iter()
+            .map(|&(at, b)| Event::Click(at.into(), b))
+            .chain(scrolls.iter().map(|&(at, d)| Event::Scroll(at.into(), d)))
+            .collect();
+        expected.sort_by_key(|e| *e.key());
+
+        let click_run = || {
+            clicks.iter().map(|&(at, button)| Click {
+                at: at.into(),
+                button,
+            })
+        };
+        let scroll_run = || {
+            scrolls.iter().map(|&(at, delta)| Scroll {
+                at: at.into(),
+                delta,
+            })
+        };
+
+        let merged: Vec<Event> = KeyedRuns::new()
+            .run(click_run())
+            .run(scroll_run())
+            .merge()
+            .collect();
+
+        let described: Vec<_> = KeyedRuns::new()
+            .run_map(click_run(), |c| Box::new(c) as Box<dyn Describe>)
+            .run_map(scroll_run(), |s| Box::new(s) as Box<dyn Describe>)
+            .merge::<u32>()
+            .map(|e| e.describe())
+            .collect();
+        let expected_described: Vec<_> = expected
+            .iter()
+            .map(|e| match *e {
+                Event::Click(at, button) => Click { at, button }.describe(),
+                Event::Scroll(at, delta) => Scroll { at, delta }.describe(),
+            })
+            .collect();
+
+        merged == expected && described == expected_described
+    }
+}
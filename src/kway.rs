@@ -0,0 +1,1540 @@
+//! Merging more than two inputs at once.
+
+use std::borrow::Borrow;
+use std::ops::{Bound, RangeBounds};
+use std::slice;
+use std::sync::mpsc;
+
+use crate::gallop::gallop;
+use crate::progress::{CancelToken, Cancelled, Observer, PROGRESS_INTERVAL};
+
+/// A tournament tree that repeatedly selects the smallest head among `k` sorted iterators.
+///
+/// Each internal node remembers the loser of the match played there, so replacing the winner only
+/// replays the matches on the path from its leaf to the root: `log2(k)` comparisons per element,
+/// compared to roughly twice that for a binary heap. Ties go to the source with the lower index,
+/// so merging runs in order yields equal elements in the order of their runs.
+pub(crate) struct LoserTree<I: Iterator, L = NaturalOrder> {
+    sources: Vec<I>,
+    heads: Vec<Option<I::Item>>,
+
+    /// `losers[0]` is the index of the overall winner. For `n` in `1..k`, `losers[n]` is the
+    /// index of the loser of the match at internal node `n`, whose children are nodes `2n` and
+    /// `2n + 1`. Leaf `i` is node `k + i`.
+    losers: Vec<usize>,
+    less: L,
+}
+
+/// The order a `LoserTree` merges its sources in, as a strict "less than" predicate.
+pub(crate) trait Less<T> {
+    fn less(&mut self, a: &T, b: &T) -> bool;
+}
+
+/// Orders items by their `Ord` implementation.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct NaturalOrder;
+
+impl<T: Ord> Less<T> for NaturalOrder {
+    #[inline]
+    fn less(&mut self, a: &T, b: &T) -> bool {
+        a < b
+    }
+}
+
+impl<I> LoserTree<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    pub(crate) fn new(sources: Vec<I>) -> Self {
+        LoserTree::with_less(sources, NaturalOrder)
+    }
+}
+
+impl<I, L> LoserTree<I, L>
+where
+    I: Iterator,
+    L: Less<I::Item>,
+{
+    /// Like `new`, but merges the sources in the order given by `less`.
+    pub(crate) fn with_less(mut sources: Vec<I>, less: L) -> Self {
+        let k = sources.len();
+        let heads: Vec<_> = sources.iter_mut().map(Iterator::next).collect();
+        let mut tree = LoserTree {
+            sources,
+            heads,
+            losers: vec![0; k.max(1)],
+            less,
+        };
+
+        // Play every match bottom-up, recording the winners in a scratch tree.
+        let mut winners = vec![0; 2 * k];
+        for i in 0..k {
+            winners[k + i] = i;
+        }
+        for n in (1..k).rev() {
+            let (l, r) = (winners[2 * n], winners[2 * n + 1]);
+            let (w, l) = if tree.beats(l, r) { (l, r) } else { (r, l) };
+            winners[n] = w;
+            tree.losers[n] = l;
+        }
+        if k > 1 {
+            tree.losers[0] = winners[1];
+        }
+
+        tree
+    }
+
+    /// Returns the head that `pop` would return next, without removing it.
+    pub(crate) fn peek(&self) -> Option<&I::Item> {
+        self.heads.get(self.losers[0])?.as_ref()
+    }
+
+    /// Returns the index of the source whose head `pop` would return next, without removing it.
+    pub(crate) fn peek_source(&self) -> Option<usize> {
+        let w = self.losers[0];
+        self.heads.get(w)?.as_ref().map(|_| w)
+    }
+
+    /// Returns the next item of source `i`.
+    pub(crate) fn head(&self, i: usize) -> Option<&I::Item> {
+        self.heads[i].as_ref()
+    }
+
+    /// Returns `true` if source `i` has no items left, including its head.
+    pub(crate) fn is_exhausted(&self, i: usize) -> bool {
+        self.heads[i].is_none()
+    }
+
+    /// Returns a mutable reference to source `i`, so that state kept in the sources (such as an
+    /// error that cut them short) can be inspected.
+    pub(crate) fn source_mut(&mut self, i: usize) -> &mut I {
+        &mut self.sources[i]
+    }
+
+    /// Returns `true` if the head of source `x` should be yielded before the head of source `y`.
+    /// Exhausted sources lose to everything.
+    fn beats(&mut self, x: usize, y: usize) -> bool {
+        match (&self.heads[x], &self.heads[y]) {
+            (Some(a), Some(b)) => self.less.less(a, b) || (x < y && !self.less.less(b, a)),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Returns the bounds on the number of items left, including the heads.
+    pub(crate) fn size_hint(&self) -> (usize, Option<usize>) {
+        let heads = self.heads.iter().filter(|h| h.is_some()).count();
+        self.sources.iter().map(Iterator::size_hint).fold(
+            (heads, Some(heads)),
+            |(lo, hi), (l, h)| {
+                (
+                    lo.saturating_add(l),
+                    hi.and_then(|hi| h.and_then(|h| hi.checked_add(h))),
+                )
+            },
+        )
+    }
+
+    /// Removes the smallest head, returning it along with the index of its source.
+    pub(crate) fn pop(&mut self) -> Option<(usize, I::Item)> {
+        let w = self.losers[0];
+        let item = self.heads.get_mut(w)?.take()?;
+        self.heads[w] = self.sources[w].next();
+        self.replay(w);
+        Some((w, item))
+    }
+
+    /// Returns the index of the source with the smallest head but for the one `pop` would return
+    /// next, or `None` if every other source is exhausted.
+    ///
+    /// Only the winner's path to the root is searched, since the runner-up must have lost to the
+    /// winner itself.
+    pub(crate) fn runner_up(&mut self) -> Option<usize> {
+        let k = self.sources.len();
+        let mut best: Option<usize> = None;
+        let mut node = (k + self.losers[0]) / 2;
+        while node >= 1 {
+            let l = self.losers[node];
+            if best.is_none_or(|b| self.beats(l, b)) {
+                best = Some(l);
+            }
+            node /= 2;
+        }
+        best.filter(|&b| self.heads[b].is_some())
+    }
+
+    /// Discards the first `n` items of the winner's source, starting with its head. The item after
+    /// them must not come before the runner-up's head.
+    pub(crate) fn skip_winner(&mut self, n: usize) {
+        let w = self.losers[0];
+        if n == 0 || self.heads.get(w).is_none() {
+            return;
+        }
+        self.heads[w] = self.sources[w].nth(n - 1);
+        self.replay(w);
+    }
+
+    /// Replays the matches on the path from source `w`, the last winner, to the root after its
+    /// head changed.
+    fn replay(&mut self, w: usize) {
+        let k = self.sources.len();
+        let mut winner = w;
+        let mut node = (k + w) / 2;
+        while node >= 1 {
+            if self.beats(self.losers[node], winner) {
+                std::mem::swap(&mut self.losers[node], &mut winner);
+            }
+            node /= 2;
+        }
+        self.losers[0] = winner;
+    }
+}
+
+impl<I, L> Clone for LoserTree<I, L>
+where
+    I: Iterator + Clone,
+    I::Item: Clone,
+    L: Clone,
+{
+    fn clone(&self) -> Self {
+        LoserTree {
+            sources: self.sources.clone(),
+            heads: self.heads.clone(),
+            losers: self.losers.clone(),
+            less: self.less.clone(),
+        }
+    }
+}
+
+impl<I> Iterator for LoserTree<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pop().map(|(_, item)| item)
+    }
+}
+
+/// An iterator over the merged, deduplicated contents of any number of sorted, deduplicated
+/// iterators.
+///
+/// When an item appears in several inputs, the one from the earliest input is yielded. Inputs are
+/// only advanced as far as needed to produce the next item, so they can be lazy or even block,
+/// like the receiving ends of channels.
+///
+/// A merge of inputs that can be cloned can be cloned too, forking it into two merges that continue
+/// from the same point. That costs as much as cloning the inputs, which for slice iterators is a
+/// pair of pointers each.
+///
+/// The tree of `k` heads is allocated up front; `lending::LendingKMergeUniq` merges a fixed number
+/// of runs without allocating at all.
+pub struct KMergeUniq<I: Iterator> {
+    tree: LoserTree<I>,
+}
+
+impl<I> KMergeUniq<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    pub fn new(sources: Vec<I>) -> Self {
+        KMergeUniq {
+            tree: LoserTree::new(sources),
+        }
+    }
+
+    /// Returns the item that `next` would return next, without advancing the merge.
+    pub fn peek(&self) -> Option<&I::Item> {
+        self.tree.peek()
+    }
+}
+
+impl<I> Clone for KMergeUniq<I>
+where
+    I: Iterator + Clone,
+    I::Item: Clone,
+{
+    fn clone(&self) -> Self {
+        KMergeUniq {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<I> Iterator for KMergeUniq<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.tree.next()?;
+        // Copies from later inputs come right after the first one, since ties go to earlier inputs.
+        while self.tree.peek() == Some(&item) {
+            self.tree.next();
+        }
+        Some(item)
+    }
+}
+
+impl<I> KMergeUniq<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    /// Yields the merged output in vectors of `size` items, except for the last, which may be
+    /// shorter.
+    ///
+    /// Only one chunk is in memory at a time, so downstream stages can consume each one while the
+    /// next is merged, and peak memory stays flat however long the output is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn chunks(self, size: usize) -> KMergeUniqChunks<I> {
+        assert!(size != 0, "chunk size must be non-zero");
+        KMergeUniqChunks { inner: self, size }
+    }
+}
+
+/// An iterator over the merged output of a `KMergeUniq` in fixed-size vectors.
+///
+/// Returned by `KMergeUniq::chunks`.
+pub struct KMergeUniqChunks<I: Iterator> {
+    inner: KMergeUniq<I>,
+    size: usize,
+}
+
+impl<I> Iterator for KMergeUniqChunks<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.inner.next()?;
+        let mut chunk = Vec::with_capacity(self.size);
+        chunk.push(first);
+        chunk.extend(self.inner.by_ref().take(self.size - 1));
+        Some(chunk)
+    }
+}
+
+/// Merges any number of sorted, deduplicated vectors, yielding the output in vectors of `size`
+/// elements instead of one allocation the size of the whole.
+///
+/// The inputs are freed only once the whole merge is done. See `KMergeUniq::chunks`.
+pub fn merge_uniq_chunks<T: Ord>(
+    runs: Vec<Vec<T>>,
+    size: usize,
+) -> KMergeUniqChunks<std::vec::IntoIter<T>> {
+    KMergeUniq::new(runs.into_iter().map(Vec::into_iter).collect()).chunks(size)
+}
+
+/// Like `KMergeUniq`, but yields each item along with the index of the input it came from.
+///
+/// When an item appears in several inputs, the copy from the earliest input is yielded, tagged
+/// with that input's index. For LSM-style compaction, where the newest version of a key should
+/// win, pass the runs newest first.
+pub struct KMergeUniqTagged<I: Iterator> {
+    tree: LoserTree<I>,
+}
+
+impl<I> KMergeUniqTagged<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    pub fn new(sources: Vec<I>) -> Self {
+        KMergeUniqTagged {
+            tree: LoserTree::new(sources),
+        }
+    }
+}
+
+impl<I> Iterator for KMergeUniqTagged<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = (I::Item, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (i, item) = self.tree.pop()?;
+        while self.tree.peek() == Some(&item) {
+            self.tree.pop();
+        }
+        Some((item, i))
+    }
+}
+
+/// Merges sorted, deduplicated vectors like `merge_uniq`, tagging each element with the index of
+/// the run it came from. Of several equal elements, the one from the earliest run is kept.
+pub fn merge_uniq_tagged<T: Ord>(runs: Vec<Vec<T>>) -> Vec<(T, usize)> {
+    let len = runs.iter().map(Vec::len).sum();
+    let mut out = Vec::with_capacity(len);
+    out.extend(KMergeUniqTagged::new(
+        runs.into_iter().map(Vec::into_iter).collect(),
+    ));
+    out
+}
+
+/// An iterator over the merged contents of any number of sorted iterators, keeping duplicates.
+///
+/// Equal items are yielded in the order of their inputs, and items from the same input in the
+/// order of that input, so the result is the same as concatenating the inputs and stably sorting
+/// them. This makes merging logs from several shards deterministic.
+pub struct KMergeStable<I: Iterator> {
+    tree: LoserTree<I>,
+}
+
+impl<I> KMergeStable<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    pub fn new(sources: Vec<I>) -> Self {
+        KMergeStable {
+            tree: LoserTree::new(sources),
+        }
+    }
+}
+
+impl<I> Iterator for KMergeStable<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tree.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.tree.size_hint()
+    }
+}
+
+/// Merges sorted vectors without deduplicating, breaking ties by the index of the run. See
+/// `KMergeStable`.
+pub fn merge_stable<T: Ord>(runs: Vec<Vec<T>>) -> Vec<T> {
+    let len = runs.iter().map(Vec::len).sum();
+    let mut out = Vec::with_capacity(len);
+    out.extend(KMergeStable::new(
+        runs.into_iter().map(Vec::into_iter).collect(),
+    ));
+    out
+}
+
+/// Like `KMergeStable`, but yields each item along with the index of its input and its index
+/// within that input.
+///
+/// This maps positions in the merged output back to the inputs, such as the offsets of postings
+/// in their documents, without tagging every element before the merge.
+pub struct KMergeStableIndexed<I: Iterator> {
+    tree: LoserTree<I>,
+    /// The index of the next item of each input.
+    positions: Vec<usize>,
+}
+
+impl<I> KMergeStableIndexed<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    pub fn new(sources: Vec<I>) -> Self {
+        KMergeStableIndexed {
+            positions: vec![0; sources.len()],
+            tree: LoserTree::new(sources),
+        }
+    }
+}
+
+impl<I> Iterator for KMergeStableIndexed<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    /// The item, the index of its input, and its index within that input.
+    type Item = (I::Item, usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (run, item) = self.tree.pop()?;
+        let index = self.positions[run];
+        self.positions[run] += 1;
+        Some((item, run, index))
+    }
+}
+
+/// Merges sorted vectors like `merge_stable`, pairing each element with the index of its run and
+/// its index within that run. See `KMergeStableIndexed`.
+pub fn merge_stable_indexed<T: Ord>(runs: Vec<Vec<T>>) -> Vec<(T, usize, usize)> {
+    let len = runs.iter().map(Vec::len).sum();
+    let mut out = Vec::with_capacity(len);
+    out.extend(KMergeStableIndexed::new(
+        runs.into_iter().map(Vec::into_iter).collect(),
+    ));
+    out
+}
+
+/// An item paired with its key, ordered by the key alone.
+pub(crate) struct ByKey<K, T> {
+    pub(crate) key: K,
+    pub(crate) item: T,
+}
+
+impl<K: PartialEq, T> PartialEq for ByKey<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<K: Eq, T> Eq for ByKey<K, T> {}
+
+impl<K: PartialOrd, T> PartialOrd for ByKey<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.key.partial_cmp(&other.key)
+    }
+}
+
+impl<K: Ord, T> Ord for ByKey<K, T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// Merges runs of key-value pairs that are sorted and deduplicated by key, comparing and
+/// deduplicating by key alone, like `crate::merge2_uniq_pairs`.
+///
+/// The values for a key are folded together in the order of their runs: `resolve` is called
+/// with the key, the value so far and the value from the next run that has the key.
+pub fn merge_uniq_pairs<K, V, F>(runs: Vec<Vec<(K, V)>>, mut resolve: F) -> Vec<(K, V)>
+where
+    K: Ord,
+    F: FnMut(&K, V, V) -> V,
+{
+    let len = runs.iter().map(Vec::len).sum();
+    let sources = runs
+        .into_iter()
+        .map(|run| run.into_iter().map(|(key, item)| ByKey { key, item }))
+        .collect();
+
+    let mut out: Vec<(K, V)> = Vec::with_capacity(len);
+    for ByKey { key, item } in KMergeStable::new(sources) {
+        match out.pop() {
+            Some((k, v)) if k == key => {
+                let v = resolve(&k, v, item);
+                out.push((k, v));
+            }
+            last => {
+                out.extend(last);
+                out.push((key, item));
+            }
+        }
+    }
+    out
+}
+
+/// Like `merge_stable`, but for runs sorted by `key`, such as events sorted by timestamp. Items
+/// with equal keys are yielded in the order of their runs.
+///
+/// The key of each item is computed once.
+pub fn merge_stable_by_key<T, K, F>(runs: Vec<Vec<T>>, key: F) -> Vec<T>
+where
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    let len = runs.iter().map(Vec::len).sum();
+    let key = &key;
+    let sources = runs
+        .into_iter()
+        .map(|run| {
+            run.into_iter().map(move |item| ByKey {
+                key: key(&item),
+                item,
+            })
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(len);
+    out.extend(KMergeStable::new(sources).map(|x| x.item));
+    out
+}
+
+/// Merges sorted runs, dropping each item that `same` says is a near-duplicate of an item kept
+/// before it, such as two sensors' readings of one event a few microseconds apart.
+///
+/// `same(kept, item)` may be coarser than equality, but may only hold while `in_window(kept, item)`
+/// does, and once `in_window(kept, item)` is false it must stay false for every later item, as when
+/// it checks that their timestamps are within some epsilon. Each item is only compared with the
+/// kept items still in its window, so this takes `O(n * w)` calls to `same` for windows of `w`
+/// kept items, where a scan of the merged output would compare every pair.
+///
+/// Of a group of near-duplicates, the first in merged order is kept, and equal items are merged
+/// in the order of their runs. The runs may contain duplicates.
+pub fn merge_uniq_within<T, W, S>(runs: Vec<Vec<T>>, mut in_window: W, mut same: S) -> Vec<T>
+where
+    T: Ord,
+    W: FnMut(&T, &T) -> bool,
+    S: FnMut(&T, &T) -> bool,
+{
+    let len = runs.iter().map(Vec::len).sum();
+    let sources = runs.into_iter().map(Vec::into_iter).collect();
+
+    let mut out: Vec<T> = Vec::with_capacity(len);
+    // The kept items before `start` are out of the window of every item still to come.
+    let mut start = 0;
+    for item in KMergeStable::new(sources) {
+        while start < out.len() && !in_window(&out[start], &item) {
+            start += 1;
+        }
+        if !out[start..].iter().any(|kept| same(kept, &item)) {
+            out.push(item);
+        }
+    }
+    out
+}
+
+/// Stops a source of `Result`s at its first error, keeping the error for the merge to report.
+struct Fallible<I, E> {
+    inner: I,
+    error: Option<E>,
+}
+
+impl<T, E, I: Iterator<Item = Result<T, E>>> Iterator for Fallible<I, E> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self.inner.next()? {
+            Ok(x) => Some(x),
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Like `KMergeUniq`, but for inputs that can fail, such as records read from disk.
+///
+/// Yields `Ok` items in merged, deduplicated order until any input yields an `Err`. That error is
+/// yielded next, and the iterator ends after it. Collect into a `Result<Vec<T>, E>` to get either
+/// the whole merge or the first error.
+pub struct TryKMergeUniq<I, T, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+{
+    tree: LoserTree<Fallible<I, E>>,
+    error: Option<E>,
+    failed: bool,
+}
+
+impl<I, T, E> TryKMergeUniq<I, T, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+    T: Ord,
+{
+    pub fn new(sources: Vec<I>) -> Self {
+        let k = sources.len();
+        let sources = sources
+            .into_iter()
+            .map(|inner| Fallible { inner, error: None })
+            .collect();
+        let mut merge = TryKMergeUniq {
+            tree: LoserTree::new(sources),
+            error: None,
+            failed: false,
+        };
+
+        // Building the tree reads the first item of every source.
+        merge.error = (0..k).find_map(|i| merge.tree.source_mut(i).error.take());
+        merge
+    }
+
+    /// Checks whether source `i` failed while the tree refilled its head.
+    fn check(&mut self, i: usize) -> Result<(), E> {
+        match self.tree.source_mut(i).error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn next_item(&mut self) -> Option<Result<T, E>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        let (i, item) = self.tree.pop()?;
+        if let Err(e) = self.check(i) {
+            return Some(Err(e));
+        }
+        while self.tree.peek() == Some(&item) {
+            let (i, _) = self.tree.pop().unwrap();
+            if let Err(e) = self.check(i) {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(item))
+    }
+}
+
+impl<I, T, E> Iterator for TryKMergeUniq<I, T, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+    T: Ord,
+{
+    type Item = Result<T, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_item();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+/// Merges sorted, deduplicated data sent over channels, blocking until each item can be placed.
+///
+/// Each sender must send its items in sorted order without duplicates. The returned iterator ends
+/// once every sender has hung up and all items have been yielded.
+pub fn merge_uniq_receivers<T: Ord>(
+    receivers: Vec<mpsc::Receiver<T>>,
+) -> KMergeUniq<mpsc::IntoIter<T>> {
+    KMergeUniq::new(
+        receivers
+            .into_iter()
+            .map(mpsc::Receiver::into_iter)
+            .collect(),
+    )
+}
+
+/// Merges the elements of sorted, deduplicated `runs` that fall within `range`, yielding
+/// references to them.
+///
+/// Each run is cut down to `range` with two binary searches before the merge starts, so a range
+/// query over many runs only touches the elements it returns. Pass `..` to merge all of every run.
+/// When an element appears in several runs, the one from the earliest run is yielded.
+pub fn merge_uniq_range<'a, T, Q, R>(runs: &[&'a [T]], range: R) -> KMergeUniq<slice::Iter<'a, T>>
+where
+    T: Ord + Borrow<Q>,
+    Q: Ord + ?Sized,
+    R: RangeBounds<Q>,
+{
+    crate::checked::debug_validate(runs);
+    let sources = runs
+        .iter()
+        .map(|run| {
+            let start = match range.start_bound() {
+                Bound::Included(key) => run.partition_point(|x| x.borrow() < key),
+                Bound::Excluded(key) => run.partition_point(|x| x.borrow() <= key),
+                Bound::Unbounded => 0,
+            };
+            let end = match range.end_bound() {
+                Bound::Included(key) => run.partition_point(|x| x.borrow() <= key),
+                Bound::Excluded(key) => run.partition_point(|x| x.borrow() < key),
+                Bound::Unbounded => run.len(),
+            };
+            run[start..end.max(start)].iter()
+        })
+        .collect();
+    KMergeUniq::new(sources)
+}
+
+/// Merges any number of sorted, deduplicated vectors.
+///
+/// When an element appears in several inputs, the one from the earliest input is kept. Two inputs
+/// are merged with `crate::merge_uniq` and three with `crate::merge3_uniq`; more than that go
+/// through a loser tree.
+pub fn merge_uniq<T: Ord>(mut runs: Vec<Vec<T>>) -> Vec<T> {
+    if cfg!(all(feature = "debug-validate", debug_assertions)) {
+        let slices: Vec<&[T]> = runs.iter().map(|run| &run[..]).collect();
+        crate::checked::debug_validate(&slices);
+    }
+    match runs.len() {
+        0 => return Vec::new(),
+        1 => return runs.pop().unwrap(),
+        2 => {
+            let b = runs.pop().unwrap();
+            let a = runs.pop().unwrap();
+            return crate::merge_uniq(a, b);
+        }
+        3 => {
+            let c = runs.pop().unwrap();
+            let b = runs.pop().unwrap();
+            let a = runs.pop().unwrap();
+            return crate::merge3_uniq(a, b, c);
+        }
+        _ => {}
+    }
+
+    let len = runs.iter().map(Vec::len).sum();
+    let mut out: Vec<T> = Vec::with_capacity(len);
+    for x in LoserTree::new(runs.into_iter().map(Vec::into_iter).collect()) {
+        if out.last() != Some(&x) {
+            out.push(x);
+        }
+    }
+    out
+}
+
+/// Like `merge_uniq`, but reports its progress to `observer`, always through a loser tree.
+///
+/// Each run is reported as exhausted once its last element has been merged. See
+/// `progress::Observer`.
+pub fn merge_uniq_observed<T, O>(runs: Vec<Vec<T>>, observer: &mut O) -> Vec<T>
+where
+    T: Ord,
+    O: Observer + ?Sized,
+{
+    let len = runs.iter().map(Vec::len).sum();
+    let mut remaining = len as u64;
+    let mut out: Vec<T> = Vec::with_capacity(len);
+    let mut tree = LoserTree::new(runs.into_iter().map(Vec::into_iter).collect());
+    for i in (0..tree.sources.len()).filter(|&i| tree.is_exhausted(i)) {
+        observer.on_run_exhausted(i);
+    }
+
+    while let Some((i, x)) = tree.pop() {
+        remaining -= 1;
+        if tree.is_exhausted(i) {
+            observer.on_run_exhausted(i);
+        }
+        if out.last() == Some(&x) {
+            continue;
+        }
+        out.push(x);
+        if (out.len() as u64).is_multiple_of(PROGRESS_INTERVAL) {
+            observer.on_progress(out.len() as u64, Some(remaining));
+        }
+    }
+    observer.on_progress(out.len() as u64, Some(0));
+    out
+}
+
+/// Like `merge_uniq`, but stops with `Err(Cancelled)` soon after `cancel` is cancelled, dropping
+/// the elements merged so far along with the rest of the runs. Always merges through a loser tree.
+pub fn merge_uniq_cancellable<T: Ord>(
+    runs: Vec<Vec<T>>,
+    cancel: &CancelToken,
+) -> Result<Vec<T>, Cancelled> {
+    cancel.check()?;
+    let len = runs.iter().map(Vec::len).sum();
+    let mut out: Vec<T> = Vec::with_capacity(len);
+    for x in LoserTree::new(runs.into_iter().map(Vec::into_iter).collect()) {
+        if out.last() != Some(&x) {
+            out.push(x);
+            if (out.len() as u64).is_multiple_of(PROGRESS_INTERVAL) {
+                cancel.check()?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Like `merge_uniq`, but merges at most `fan_in` runs at a time, in several passes if needed.
+///
+/// Each pass merges the neighbouring runs with the fewest elements between them, and the first
+/// pass merges only as many as it takes for every later one to merge `fan_in`, so elements go
+/// through as few merges as they can. Ties still go to the earliest run.
+///
+/// A small fan-in is often faster than merging every run at once, since each merge then fits its
+/// heads in cache and takes fewer comparisons per element. On random `u64`s, a fan-in of 4 did
+/// best, and 64 no better than merging every run at once.
+///
+/// # Panics
+///
+/// Panics if `fan_in` is less than two.
+pub fn merge_uniq_fan_in<T: Ord>(runs: Vec<Vec<T>>, fan_in: usize) -> Vec<T> {
+    assert!(fan_in >= 2, "a merge needs at least two runs");
+    let runs = runs
+        .into_iter()
+        .map(|run| (run.len() as u64, run))
+        .collect();
+    let runs = reduce_runs(runs, fan_in, |group| {
+        let merged = merge_uniq(group);
+        Ok::<_, std::convert::Infallible>((merged.len() as u64, merged))
+    });
+    match runs {
+        Ok(runs) => merge_uniq(runs),
+        Err(never) => match never {},
+    }
+}
+
+/// Merges groups of neighbouring runs with `merge` until at most `fan_in` runs are left, for a
+/// final merge to combine.
+///
+/// Each run comes with its size, in any unit, and `merge` returns the size of the run it made. The
+/// groups are scheduled as described on `merge_uniq_fan_in`.
+pub(crate) fn reduce_runs<R, E>(
+    mut runs: Vec<(u64, R)>,
+    fan_in: usize,
+    mut merge: impl FnMut(Vec<R>) -> Result<(u64, R), E>,
+) -> Result<Vec<R>, E> {
+    if runs.len() > fan_in {
+        let mut group = (runs.len() - 2) % (fan_in - 1) + 2;
+        while runs.len() > fan_in {
+            let mut sum: u64 = runs[..group].iter().map(|run| run.0).sum();
+            let (mut best, mut start) = (sum, 0);
+            for i in group..runs.len() {
+                sum = sum + runs[i].0 - runs[i - group].0;
+                if sum < best {
+                    best = sum;
+                    start = i + 1 - group;
+                }
+            }
+
+            let merged = merge(runs.drain(start..start + group).map(|run| run.1).collect())?;
+            runs.insert(start, merged);
+            group = fan_in;
+        }
+    }
+    Ok(runs.into_iter().map(|run| run.1).collect())
+}
+
+/// Returns the first `n` elements of the merged, deduplicated contents of `runs`.
+///
+/// Each run is cut down to its first `n` elements before the merge, which stops as soon as it has
+/// produced `n` elements.
+pub fn merge_uniq_take<T: Ord>(runs: Vec<Vec<T>>, n: usize) -> Vec<T> {
+    let len: usize = runs.iter().map(|run| run.len().min(n)).sum();
+    let sources = runs
+        .into_iter()
+        .map(|mut run| {
+            run.truncate(n);
+            run.into_iter()
+        })
+        .collect();
+    let mut out = Vec::with_capacity(len.min(n));
+    out.extend(KMergeUniq::new(sources).take(n));
+    out
+}
+
+/// Sorted, deduplicated runs that share no elements with each other, as when they come from
+/// range-partitioning a set, so merging them can skip the duplicate checks of `merge_uniq`.
+///
+/// The checks themselves are cheap next to the loser tree, so runs whose elements interleave merge
+/// no faster than with `merge_uniq`. What pays off is that disjoint runs are often disjoint
+/// ranges, which `merge` concatenates in a fraction of the time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisjointRuns<T> {
+    runs: Vec<Vec<T>>,
+}
+
+impl<T: Ord> DisjointRuns<T> {
+    /// Wraps `runs` if they are sorted, deduplicated and disjoint, or returns them unchanged if
+    /// not. This merges the runs to look for shared elements, so it only pays for itself if the
+    /// wrapped runs are merged more than once or the check is needed anyway.
+    pub fn new(runs: Vec<Vec<T>>) -> Result<Self, Vec<Vec<T>>> {
+        let len: usize = runs.iter().map(Vec::len).sum();
+        let valid = runs.iter().all(|run| crate::checked::is_sorted_unique(run))
+            && KMergeUniq::new(runs.iter().map(|run| run.iter()).collect()).count() == len;
+        if valid {
+            Ok(DisjointRuns { runs })
+        } else {
+            Err(runs)
+        }
+    }
+
+    /// Wraps `runs` without checking that they are sorted, deduplicated and disjoint. If they
+    /// aren't, `merge` returns a vector that isn't either, though it is memory-safe.
+    pub fn new_unchecked(runs: Vec<Vec<T>>) -> Self {
+        debug_assert!(
+            runs.iter().all(|run| crate::checked::is_sorted_unique(run)),
+            "input is not sorted and deduplicated"
+        );
+        DisjointRuns { runs }
+    }
+
+    pub fn runs(&self) -> &[Vec<T>] {
+        &self.runs
+    }
+
+    pub fn into_runs(self) -> Vec<Vec<T>> {
+        self.runs
+    }
+
+    /// Merges the runs into one sorted, deduplicated vector.
+    ///
+    /// If the ranges of the runs don't overlap, they are concatenated in order after sorting them
+    /// by their first elements. Otherwise they go through a loser tree that never compares an
+    /// item with the one before it, since no two are equal.
+    pub fn merge(self) -> Vec<T> {
+        let mut runs = self.runs;
+        runs.retain(|run| !run.is_empty());
+        runs.sort_unstable_by(|a, b| a[0].cmp(&b[0]));
+        if runs.windows(2).all(|w| w[0].last() < w[1].first()) {
+            let len: usize = runs.iter().map(Vec::len).sum();
+            let mut runs = runs.into_iter();
+            let mut out = runs.next().unwrap_or_default();
+            out.reserve_exact(len - out.len());
+            for mut run in runs {
+                out.append(&mut run);
+            }
+            return out;
+        }
+        merge_stable(runs)
+    }
+}
+
+/// Receives the items merged by `kmerge_to`, one at a time or in stretches of one run.
+pub trait MergeSink<T> {
+    /// Receives the next item.
+    fn push(&mut self, item: &T);
+
+    /// Receives the next items, which are contiguous in one of the runs. By default, pushes each
+    /// of them in turn.
+    fn extend_from_slice(&mut self, items: &[T]) {
+        for item in items {
+            self.push(item);
+        }
+    }
+}
+
+impl<T: Clone> MergeSink<T> for Vec<T> {
+    fn push(&mut self, item: &T) {
+        Vec::push(self, item.clone());
+    }
+
+    fn extend_from_slice(&mut self, items: &[T]) {
+        Vec::extend_from_slice(self, items);
+    }
+}
+
+/// Merges sorted, deduplicated `runs` into `sink`, with no iterator between the merge and the
+/// sink.
+///
+/// Items are passed by reference, so a sink can encode, compress or send them straight out of the
+/// runs. When a run wins twice in a row, the stretch of it that comes before every other run's
+/// head is found by galloping and passed to `MergeSink::extend_from_slice` in one call, so runs
+/// that barely interleave arrive mostly as slices. When an item appears in several runs, the one
+/// from the earliest run is passed.
+pub fn kmerge_to<T, S>(runs: &[&[T]], sink: &mut S)
+where
+    T: Ord,
+    S: MergeSink<T> + ?Sized,
+{
+    crate::checked::debug_validate(runs);
+    let mut tree = LoserTree::new(runs.iter().map(|run| run.iter()).collect());
+    let mut last = None;
+    while let Some(w) = tree.peek_source() {
+        if last == Some(w) {
+            // The rest of run `w`, starting with its head.
+            let rest = &runs[w][runs[w].len() - tree.source_mut(w).as_slice().len() - 1..];
+            let bound = tree.runner_up().and_then(|r| tree.head(r).copied());
+            let n = gallop(rest, |x| bound.is_none_or(|b| x < b));
+            if n > 1 {
+                sink.extend_from_slice(&rest[..n]);
+                tree.skip_winner(n);
+                continue;
+            }
+        }
+
+        let (_, item) = tree.pop().unwrap();
+        while tree.peek() == Some(&item) {
+            tree.pop();
+        }
+        sink.push(item);
+        last = Some(w);
+    }
+}
+
+/// Merges sorted runs and folds each group of consecutive items with equal keys into one
+/// aggregate, as they stream past.
+///
+/// `key` must be consistent with the order of the items: items with equal keys have to be
+/// adjacent once the runs are merged, as when the key is a prefix of a tuple. Unlike the other
+/// merges, the runs may contain duplicates, and every item is passed to `fold`. The merged
+/// sequence is never materialized, so the runs can be lazy iterators over shards of any size.
+pub fn kmerge_group_by<R, K, A>(
+    runs: Vec<R>,
+    mut key: impl FnMut(&R::Item) -> K,
+    mut fold: impl FnMut(&mut A, R::Item),
+) -> Vec<(K, A)>
+where
+    R: IntoIterator,
+    R::Item: Ord,
+    K: PartialEq,
+    A: Default,
+{
+    let mut out: Vec<(K, A)> = Vec::new();
+    for item in LoserTree::new(runs.into_iter().map(R::into_iter).collect()) {
+        let k = key(&item);
+        match out.last_mut() {
+            Some((last, acc)) if *last == k => fold(acc, item),
+            _ => {
+                let mut acc = A::default();
+                fold(&mut acc, item);
+                out.push((k, acc));
+            }
+        }
+    }
+    out
+}
+
+/// Adds `kmerge_uniq` to iterators over sorted, deduplicated vectors.
+pub trait KMergeUniqExt<T>: Iterator<Item = Vec<T>> + Sized {
+    /// Collects the vectors and merges them with `merge_uniq`.
+    fn kmerge_uniq(self) -> Vec<T>
+    where
+        T: Ord,
+    {
+        merge_uniq(self.collect())
+    }
+}
+
+impl<T, I: Iterator<Item = Vec<T>>> KMergeUniqExt<T> for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn disjoint_runs_impl(elements: Vec<u8>, assignment: Vec<u8>, k: u8, split: bool) -> bool {
+        let elements = sorted_unique(elements);
+        let k = usize::from(k % 8) + 1;
+        let mut runs = vec![Vec::new(); k];
+        for (i, &x) in elements.iter().enumerate() {
+            // Either interleave the elements across the runs or give each run a range of them.
+            let run = if split {
+                i * k / elements.len()
+            } else {
+                assignment.get(i).map_or(0, |&a| usize::from(a) % k)
+            };
+            runs[run].push(x);
+        }
+        runs.reverse();
+
+        let disjoint = DisjointRuns::new(runs.clone()).unwrap();
+        disjoint.runs() == &runs[..]
+            && disjoint.merge() == elements
+            && DisjointRuns::new_unchecked(runs).merge() == elements
+    }
+
+    #[quickcheck]
+    fn disjoint_runs_rejects(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let len: usize = runs.iter().map(Vec::len).sum();
+        let disjoint = merge_uniq(runs.clone()).len() == len;
+        DisjointRuns::new(runs).is_ok() == disjoint
+    }
+
+    #[quickcheck]
+    fn merge_uniq_pairs_impl(runs: Vec<Vec<(u8, u8)>>) -> bool {
+        use std::collections::BTreeMap;
+
+        let runs: Vec<BTreeMap<u8, u8>> = runs
+            .into_iter()
+            .map(|run| run.into_iter().collect())
+            .collect();
+
+        // The resolution isn't symmetric, to check that values are folded in run order.
+        let resolve = |&k: &u8, x: u8, y: u8| k ^ x.wrapping_mul(3).wrapping_add(y);
+        let mut expected = BTreeMap::new();
+        for run in &runs {
+            for (&k, &y) in run {
+                let v = expected.get(&k).map_or(y, |&x| resolve(&k, x, y));
+                expected.insert(k, v);
+            }
+        }
+        let expected: Vec<_> = expected.into_iter().collect();
+
+        let runs = runs
+            .into_iter()
+            .map(|run| run.into_iter().collect())
+            .collect();
+        merge_uniq_pairs(runs, resolve) == expected
+    }
+
+    #[quickcheck]
+    fn merge_uniq_range_impl(runs: Vec<Vec<u8>>, lo: u8, hi: u8, kind: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let slices: Vec<&[u8]> = runs.iter().map(|run| &run[..]).collect();
+        let merged = merge_uniq(runs.clone());
+
+        let expected = |range: (Bound<&u8>, Bound<&u8>)| {
+            merged
+                .iter()
+                .filter(|x| range.contains(x))
+                .collect::<Vec<_>>()
+        };
+        let (lo, hi) = (&lo, &hi);
+        match kind % 6 {
+            0 => merge_uniq_range(&slices, lo..hi)
+                .eq(expected((Bound::Included(lo), Bound::Excluded(hi)))),
+            1 => merge_uniq_range(&slices, lo..=hi)
+                .eq(expected((Bound::Included(lo), Bound::Included(hi)))),
+            2 => merge_uniq_range(&slices, lo..)
+                .eq(expected((Bound::Included(lo), Bound::Unbounded))),
+            3 => merge_uniq_range(&slices, ..hi)
+                .eq(expected((Bound::Unbounded, Bound::Excluded(hi)))),
+            4 => merge_uniq_range::<_, u8, _>(&slices, ..).eq(merged.iter()),
+            _ => {
+                let range = (Bound::Excluded(lo), Bound::Included(hi));
+                merge_uniq_range(&slices, range).eq(expected(range))
+            }
+        }
+    }
+
+    #[quickcheck]
+    fn merge_uniq_chunks_impl(runs: Vec<Vec<u8>>, size: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let size = usize::from(size % 8) + 1;
+        let expected = sorted_unique(runs.concat());
+
+        let chunks: Vec<Vec<u8>> = merge_uniq_chunks(runs, size).collect();
+        let sizes_ok = match chunks.split_last() {
+            Some((last, full)) => {
+                full.iter().all(|chunk| chunk.len() == size) && (1..=size).contains(&last.len())
+            }
+            None => true,
+        };
+        sizes_ok && chunks.concat() == expected
+    }
+
+    #[quickcheck]
+    fn loser_tree_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<Vec<u8>> = runs
+            .into_iter()
+            .map(|mut run| {
+                run.sort_unstable();
+                run
+            })
+            .collect();
+
+        // A stable sort of the concatenated runs, tagged with their source.
+        let mut expected: Vec<_> = runs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, run)| run.iter().map(move |&x| (x, i)))
+            .collect();
+        expected.sort();
+
+        let mut tree = LoserTree::new(runs.into_iter().map(Vec::into_iter).collect());
+        let mut actual = Vec::new();
+        while let Some((i, x)) = tree.pop() {
+            actual.push((x, i));
+        }
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn kmerge_uniq_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+        let actual: Vec<_> =
+            KMergeUniq::new(runs.into_iter().map(Vec::into_iter).collect()).collect();
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn kmerge_uniq_clone_peek(runs: Vec<Vec<u8>>, fork: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        let mut merge = KMergeUniq::new(runs.iter().map(|run| run.iter()).collect());
+        let fork = usize::from(fork) % (expected.len() + 1);
+        merge.by_ref().take(fork).for_each(drop);
+        let forked = merge.clone();
+
+        let mut peeked = Vec::new();
+        while let Some(&&x) = merge.peek() {
+            peeked.push(x);
+            merge.next();
+        }
+        peeked == expected[fork..] && forked.copied().eq(peeked)
+    }
+
+    #[quickcheck]
+    fn merge_uniq_take_impl(runs: Vec<Vec<u8>>, n: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let mut expected = sorted_unique(runs.concat());
+        expected.truncate(n.into());
+        expected == merge_uniq_take(runs, n.into())
+    }
+
+    #[quickcheck]
+    fn merge_stable_size_hint(runs: Vec<Vec<u8>>, taken: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let len: usize = runs.iter().map(Vec::len).sum();
+        let taken = usize::from(taken).min(len);
+
+        let mut merge = KMergeStable::new(runs.into_iter().map(Vec::into_iter).collect());
+        merge.by_ref().take(taken).for_each(drop);
+        merge.size_hint() == (len - taken, Some(len - taken))
+    }
+
+    #[quickcheck]
+    fn merge_stable_indexed_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs
+            .into_iter()
+            .map(|mut run| {
+                run.sort_unstable();
+                run
+            })
+            .collect();
+        // Stability means ties are broken by run, then by index within the run.
+        let mut expected: Vec<_> = runs
+            .iter()
+            .enumerate()
+            .flat_map(|(r, run)| run.iter().enumerate().map(move |(i, &x)| (x, r, i)))
+            .collect();
+        expected.sort_unstable();
+        merge_stable_indexed(runs) == expected
+    }
+
+    #[quickcheck]
+    fn merge_uniq_fan_in_impl(runs: Vec<Vec<u8>>, fan_in: u8) -> bool {
+        // Elements are tagged with their run, to check that ties go to the earliest one.
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let tagged = || -> Vec<Vec<_>> {
+            let tag = |(i, run): (usize, &Vec<u8>)| {
+                run.iter().map(|&key| ByKey { key, item: i }).collect()
+            };
+            runs.iter().enumerate().map(tag).collect()
+        };
+        let untag = |v: Vec<ByKey<u8, usize>>| -> Vec<_> {
+            v.into_iter().map(|x| (x.key, x.item)).collect()
+        };
+        let fan_in = usize::from(fan_in % 4) + 2;
+        untag(merge_uniq_fan_in(tagged(), fan_in)) == untag(merge_uniq(tagged()))
+    }
+
+    #[test]
+    fn reduce_runs_schedule() {
+        // Each run is the list of the original runs it holds, so merges record their groups.
+        let reduce = |sizes: &[u64], fan_in| {
+            let mut groups = Vec::new();
+            let runs = sizes
+                .iter()
+                .enumerate()
+                .map(|(i, &size)| (size, vec![i]))
+                .collect();
+            let runs = reduce_runs(runs, fan_in, |group: Vec<Vec<usize>>| {
+                groups.push(group.len());
+                let size = group.iter().flatten().map(|&i| sizes[i]).sum();
+                Ok::<_, ()>((size, group.concat()))
+            });
+            (runs.unwrap(), groups)
+        };
+
+        // One run too many takes one merge of two, rather than a pass over every run.
+        let (runs, groups) = reduce(&[1; 65], 64);
+        assert_eq!((runs.len(), groups), (64, vec![2]));
+
+        let (runs, groups) = reduce(&[1; 10], 3);
+        assert_eq!(runs.concat(), (0..10).collect::<Vec<_>>());
+        assert_eq!((runs.len(), groups), (3, vec![2, 3, 3, 3]));
+
+        // The smallest neighbours are merged first.
+        let (runs, groups) = reduce(&[5, 1, 1, 5], 3);
+        assert_eq!(
+            (runs, groups),
+            (vec![vec![0], vec![1, 2], vec![3]], vec![2])
+        );
+    }
+
+    #[quickcheck]
+    fn merge_uniq_within_impl(runs: Vec<Vec<(u8, bool)>>, epsilon: u8) -> bool {
+        // Readings of a sensor (the `bool`) at a time (the `u8`) are the same event if they're at
+        // most `epsilon` apart.
+        let epsilon = epsilon % 8;
+        let runs: Vec<_> = runs
+            .into_iter()
+            .map(|mut run| {
+                run.sort_unstable();
+                run
+            })
+            .collect();
+        let in_window = |a: &(u8, bool), b: &(u8, bool)| b.0 - a.0 <= epsilon;
+        let same = |a: &(u8, bool), b: &(u8, bool)| a.1 == b.1 && in_window(a, b);
+
+        let mut expected: Vec<(u8, bool)> = Vec::new();
+        for item in merge_stable(runs.clone()) {
+            if !expected.iter().any(|kept| same(kept, &item)) {
+                expected.push(item);
+            }
+        }
+        merge_uniq_within(runs, in_window, same) == expected
+    }
+
+    /// Records how `kmerge_to` passes its output, to check that it uses slices.
+    #[derive(Default)]
+    struct Recorder {
+        items: Vec<u32>,
+        slices: usize,
+    }
+
+    impl MergeSink<u32> for Recorder {
+        fn push(&mut self, item: &u32) {
+            self.items.push(*item);
+        }
+
+        fn extend_from_slice(&mut self, items: &[u32]) {
+            self.items.extend_from_slice(items);
+            self.slices += 1;
+        }
+    }
+
+    #[quickcheck]
+    fn kmerge_to_impl(runs: Vec<Vec<u32>>) -> bool {
+        let runs: Vec<_> = runs
+            .into_iter()
+            .map(|mut run| {
+                run.sort_unstable();
+                run.dedup();
+                run
+            })
+            .collect();
+        let refs: Vec<&[u32]> = runs.iter().map(Vec::as_slice).collect();
+        let mut out = Vec::new();
+        kmerge_to(&refs, &mut out);
+        out == merge_uniq(runs)
+    }
+
+    #[test]
+    fn kmerge_to_slices() {
+        // Runs that don't interleave arrive as one slice each, but for their first item.
+        let runs: Vec<Vec<u32>> = (0..4).map(|i| (i * 100..i * 100 + 100).collect()).collect();
+        let refs: Vec<&[u32]> = runs.iter().map(Vec::as_slice).collect();
+        let mut sink = Recorder::default();
+        kmerge_to(&refs, &mut sink);
+        assert_eq!(sink.items, (0..400).collect::<Vec<_>>());
+        assert_eq!(sink.slices, 4);
+    }
+
+    #[quickcheck]
+    fn kmerge_group_by_impl(runs: Vec<Vec<(u8, u8)>>) -> bool {
+        use std::collections::BTreeMap;
+
+        let runs: Vec<Vec<_>> = runs
+            .into_iter()
+            .map(|mut run| {
+                run.sort_unstable();
+                run
+            })
+            .collect();
+
+        let mut expected = BTreeMap::new();
+        for &(k, v) in runs.iter().flatten() {
+            *expected.entry(k).or_insert(0) += u32::from(v);
+        }
+
+        let actual = kmerge_group_by(
+            runs,
+            |&(k, _)| k,
+            |sum: &mut u32, (_, v)| *sum += u32::from(v),
+        );
+        actual == expected.into_iter().collect::<Vec<_>>()
+    }
+
+    #[quickcheck]
+    fn merge_uniq_tagged_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+
+        let expected: Vec<_> = sorted_unique(runs.concat())
+            .into_iter()
+            .map(|x| (x, runs.iter().position(|run| run.contains(&x)).unwrap()))
+            .collect();
+        expected == merge_uniq_tagged(runs)
+    }
+
+    #[quickcheck]
+    fn merge_stable_by_key_impl(runs: Vec<Vec<(u8, u8)>>) -> bool {
+        // Tag each event with its run and position, so that ties can be told apart.
+        let runs: Vec<Vec<_>> = runs
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut run)| {
+                run.sort_by_key(|&(key, _)| key);
+                run.into_iter()
+                    .enumerate()
+                    .map(|(j, (key, _))| (key, i, j))
+                    .collect()
+            })
+            .collect();
+
+        let mut expected = runs.concat();
+        expected.sort_by_key(|&(key, _, _)| key);
+
+        let mut sorted = runs.concat();
+        sorted.sort_unstable();
+        merge_stable(runs.clone()) == sorted
+            && merge_stable_by_key(runs, |&(key, _, _)| key) == expected
+    }
+
+    #[quickcheck]
+    fn try_kmerge_uniq_impl(runs: Vec<Vec<u8>>, fail: Option<(usize, usize)>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        // Optionally replace one element of one run with an error.
+        let fail = fail.filter(|&(r, i)| r < runs.len() && i < runs[r].len());
+        let sources = runs
+            .into_iter()
+            .enumerate()
+            .map(|(r, run)| {
+                let run: Vec<Result<u8, usize>> = run
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, x)| if fail == Some((r, i)) { Err(r) } else { Ok(x) })
+                    .collect();
+                run.into_iter()
+            })
+            .collect();
+
+        let mut merge = TryKMergeUniq::new(sources);
+        let actual: Result<Vec<_>, _> = merge.by_ref().collect();
+        let fused = merge.next().is_none();
+        match fail {
+            Some((r, _)) => fused && actual == Err(r),
+            None => fused && actual == Ok(expected),
+        }
+    }
+
+    #[quickcheck]
+    fn merge_uniq_receivers_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        let receivers = runs
+            .into_iter()
+            .map(|run| {
+                let (tx, rx) = mpsc::sync_channel(1);
+                std::thread::spawn(move || {
+                    for x in run {
+                        tx.send(x).unwrap();
+                    }
+                });
+                rx
+            })
+            .collect();
+        let actual: Vec<_> = merge_uniq_receivers(receivers).collect();
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn kmerge_uniq_ext(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+        expected == runs.into_iter().kmerge_uniq()
+    }
+
+    #[quickcheck]
+    fn merge_uniq_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+        let actual = merge_uniq(runs);
+        expected == actual
+    }
+}
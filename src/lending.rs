@@ -0,0 +1,238 @@
+//! Merging without allocating, through an iterator that lends out its items.
+//!
+//! `kway::KMergeUniq` keeps its tree of heads and the heads themselves in vectors, and yields items
+//! by value, so scanning owned runs of a type that can't be cloned means merging iterators of
+//! references over them. `LendingKMergeUniq` owns its runs instead, keeps its tree in an array
+//! sized by the number of runs, and yields references that borrow from the merge itself. A scan of
+//! any length makes no allocations at all, not even up front.
+//!
+//! Since each item borrows from the merge, it must be dropped before the next one is asked for,
+//! which `Iterator` can't express. `LendingIterator` can, with a generic associated type.
+
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+/// An iterator whose items may borrow from the iterator itself.
+pub trait LendingIterator {
+    type Item<'a>
+    where
+        Self: 'a;
+
+    /// Advances the iterator and returns the next item, which must be dropped before the iterator
+    /// is used again.
+    fn next(&mut self) -> Option<Self::Item<'_>>;
+}
+
+/// A merge of `K` sorted, deduplicated runs that yields a reference to each distinct element. See
+/// the module docs.
+///
+/// The runs can be anything that dereferences to a slice, such as `Vec<T>`, `Box<[T]>` or `&[T]`.
+/// When an element appears in several runs, the one from the earliest run is yielded, as by
+/// `kway::KMergeUniq`. The number of runs must be known when the merge is built; for a number
+/// only known at run time, merge the runs' `iter()`s with `KMergeUniq` instead.
+pub struct LendingKMergeUniq<T, S, const K: usize> {
+    runs: [S; K],
+    /// The index in each run of its head.
+    positions: [usize; K],
+
+    /// `losers[0]` is the index of the overall winner, and `losers[n]` the loser of the match at
+    /// internal node `n`, as in `kway::LoserTree`.
+    losers: [usize; K],
+    /// Lets items borrow from the merge for no longer than the elements live.
+    elements: PhantomData<T>,
+}
+
+impl<T, S, const K: usize> LendingKMergeUniq<T, S, K>
+where
+    T: Ord,
+    S: Deref<Target = [T]>,
+{
+    pub fn new(runs: [S; K]) -> Self {
+        let mut merge = LendingKMergeUniq {
+            runs,
+            positions: [0; K],
+            losers: [0; K],
+            elements: PhantomData,
+        };
+        if K > 1 {
+            merge.losers[0] = merge.play(1);
+        }
+        merge
+    }
+
+    /// Returns the element that `next` would return next, without advancing the merge.
+    pub fn peek(&self) -> Option<&T> {
+        self.head(self.winner()?)
+    }
+
+    fn head(&self, i: usize) -> Option<&T> {
+        self.runs[i].get(self.positions[i])
+    }
+
+    fn winner(&self) -> Option<usize> {
+        if K == 0 {
+            None
+        } else {
+            Some(self.losers[0])
+        }
+    }
+
+    /// Returns `true` if the head of run `x` should be yielded before the head of run `y`.
+    /// Exhausted runs lose to everything.
+    fn beats(&self, x: usize, y: usize) -> bool {
+        match (self.head(x), self.head(y)) {
+            (Some(a), Some(b)) => a < b || (x < y && a == b),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Plays every match in the subtree at node `n`, recording the losers, and returns the winner.
+    fn play(&mut self, n: usize) -> usize {
+        if n >= K {
+            return n - K;
+        }
+        let (l, r) = (self.play(2 * n), self.play(2 * n + 1));
+        let (w, l) = if self.beats(l, r) { (l, r) } else { (r, l) };
+        self.losers[n] = l;
+        w
+    }
+
+    /// Moves run `w`, the last winner, past its head and replays its matches.
+    fn advance(&mut self, w: usize) {
+        self.positions[w] += 1;
+        let mut winner = w;
+        let mut node = (K + w) / 2;
+        while node >= 1 {
+            if self.beats(self.losers[node], winner) {
+                std::mem::swap(&mut self.losers[node], &mut winner);
+            }
+            node /= 2;
+        }
+        self.losers[0] = winner;
+    }
+}
+
+impl<T, S, const K: usize> LendingIterator for LendingKMergeUniq<T, S, K>
+where
+    T: Ord,
+    S: Deref<Target = [T]>,
+{
+    type Item<'a>
+        = &'a T
+    where
+        Self: 'a;
+
+    fn next(&mut self) -> Option<&T> {
+        let w = self.winner()?;
+        let pos = self.positions[w];
+        self.head(w)?;
+
+        // The runs don't move as the merge advances, so the element stays where it is while the
+        // copies of it in later runs, which come right after it since ties go to earlier runs,
+        // are skipped.
+        self.advance(w);
+        while self.head(self.losers[0]) == self.runs[w].get(pos) {
+            self.advance(self.losers[0]);
+        }
+        self.runs[w].get(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kway;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    fn collect<S, const K: usize>(mut merge: LendingKMergeUniq<u8, S, K>) -> Vec<u8>
+    where
+        S: Deref<Target = [u8]>,
+    {
+        let mut out = Vec::new();
+        while let Some(&x) = merge.next() {
+            out.push(x);
+        }
+        out
+    }
+
+    #[quickcheck]
+    fn lending_kmerge_uniq_impl(
+        a: Vec<u8>,
+        b: Vec<u8>,
+        c: Vec<u8>,
+        d: Vec<u8>,
+        e: Vec<u8>,
+    ) -> bool {
+        let runs = [a, b, c, d, e].map(sorted_unique);
+        let expected = kway::merge_uniq(runs.to_vec());
+        collect(LendingKMergeUniq::new(runs.clone())) == expected
+            && collect(LendingKMergeUniq::new([&runs[0][..], &runs[3], &runs[4]]))
+                == kway::merge_uniq(vec![runs[0].clone(), runs[3].clone(), runs[4].clone()])
+    }
+
+    #[quickcheck]
+    fn lending_kmerge_uniq_peek(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let mut merge = LendingKMergeUniq::new([sorted_unique(a), sorted_unique(b)]);
+        loop {
+            let peeked = merge.peek().copied();
+            if merge.next().copied() != peeked {
+                return false;
+            }
+            if peeked.is_none() {
+                return merge.next().is_none();
+            }
+        }
+    }
+
+    #[test]
+    fn lending_kmerge_uniq_edges() {
+        let mut empty = LendingKMergeUniq::<u8, Vec<u8>, 0>::new([]);
+        assert_eq!(empty.next(), None);
+        assert_eq!(collect(LendingKMergeUniq::new([vec![1, 2, 3]])), [1, 2, 3]);
+        assert_eq!(
+            collect(LendingKMergeUniq::new([vec![], vec![2], vec![]])),
+            [2]
+        );
+    }
+
+    #[test]
+    fn lending_kmerge_uniq_borrows() {
+        // Not `Clone`, so the merge can only lend out the elements it owns. Ordered by the
+        // number alone, to tell which run each element came from.
+        struct Key(u8, &'static str);
+
+        impl PartialEq for Key {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl Eq for Key {}
+
+        impl PartialOrd for Key {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Key {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+
+        let mut merge = LendingKMergeUniq::new([
+            vec![Key(1, "a"), Key(4, "a")],
+            vec![Key(2, "b"), Key(4, "b")],
+            vec![Key(3, "c")],
+        ]);
+        let mut keys = Vec::new();
+        while let Some(key) = merge.next() {
+            keys.push((key.0, key.1));
+        }
+        assert_eq!(keys, [(1, "a"), (2, "b"), (3, "c"), (4, "a")]);
+        assert!(merge.next().is_none());
+    }
+}
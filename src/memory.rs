@@ -0,0 +1,316 @@
+//! Estimating how much memory a merge will need before running it.
+//!
+//! A service that runs many merges at once can admit them against a memory limit only if it knows
+//! what each will take. A `MergePlan` describes a merge by the sizes of its inputs and the settings
+//! of whatever will run it, and is made by `MergeBuilder::plan`, `MergePlan::kway`,
+//! `external::Merger::plan`, `external::Merger::plan_pipelined` or `external::Sorter::plan`.
+//! `estimate_peak_memory` then returns an upper bound on the bytes the merge holds at once, split
+//! by what they hold.
+//!
+//! The estimates count the crate's own allocations and the inputs it is given. They leave out the
+//! allocator's overhead, whatever the readers and writers passed to a merge allocate themselves,
+//! and the windows of zstd when spilled runs are compressed.
+
+use std::mem;
+use std::vec;
+
+use crate::builder::MergeBuilder;
+
+/// The bytes of bookkeeping for each run of an external merge: its reader and its slot in the
+/// tree of heads, with room to spare.
+pub(crate) const RUN_OVERHEAD: usize = 256;
+
+/// The peak memory use of a merge, split by what it holds. See `estimate_peak_memory`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MemoryEstimate {
+    /// The elements of the inputs, or for external merges the records decoded from them.
+    pub inputs: usize,
+    /// The output, if it is built in memory.
+    pub output: usize,
+    /// Working memory: the merge's tree of heads, sort buffers, and copies made while shrinking.
+    pub scratch: usize,
+    /// Read and write buffers, and the batches queued between the threads of a pipelined merge.
+    pub buffers: usize,
+}
+
+impl MemoryEstimate {
+    /// Returns the sum of the parts, saturating at `usize::MAX`.
+    pub fn total(&self) -> usize {
+        self.inputs
+            .saturating_add(self.output)
+            .saturating_add(self.scratch)
+            .saturating_add(self.buffers)
+    }
+}
+
+/// A merge to be estimated by `estimate_peak_memory`.
+#[derive(Clone, Debug)]
+pub struct MergePlan {
+    kind: PlanKind,
+}
+
+#[derive(Clone, Debug)]
+enum PlanKind {
+    InMemory {
+        run_lens: Vec<usize>,
+        elem_size: usize,
+        /// The size of each run's source and its slot in the tree of heads.
+        run_size: usize,
+        /// Whether the output is shrunk to fit once merged.
+        shrink: bool,
+    },
+    External(ExternalPlan),
+    Sort {
+        memory_budget: usize,
+        record_size: usize,
+        /// The size of a record itself, without its heap allocations.
+        inline_size: usize,
+        buffer_size: usize,
+        merge: ExternalPlan,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ExternalPlan {
+    pub(crate) runs: usize,
+    pub(crate) record_size: usize,
+    pub(crate) buffer_size: usize,
+    /// The size of runs' blocks if they are read and written in the format of
+    /// `external::write_run`.
+    pub(crate) block_size: Option<usize>,
+    pub(crate) pipelined: bool,
+}
+
+impl MergePlan {
+    /// Plans a merge of runs of `T` with the given lengths by `kway::merge_uniq`, or by
+    /// `crate::merge_uniq` for two runs.
+    pub fn kway<T>(run_lens: &[usize]) -> Self {
+        MergeBuilder::new().plan::<T>(run_lens)
+    }
+
+    pub(crate) fn in_memory<T>(run_lens: &[usize], shrink: bool) -> Self {
+        let run_size = mem::size_of::<vec::IntoIter<T>>()
+            + mem::size_of::<Option<T>>()
+            + mem::size_of::<usize>();
+        MergePlan {
+            kind: PlanKind::InMemory {
+                run_lens: run_lens.to_vec(),
+                elem_size: mem::size_of::<T>(),
+                run_size,
+                shrink,
+            },
+        }
+    }
+
+    pub(crate) fn external(plan: ExternalPlan) -> Self {
+        MergePlan {
+            kind: PlanKind::External(plan),
+        }
+    }
+
+    pub(crate) fn sort(
+        memory_budget: usize,
+        record_size: usize,
+        inline_size: usize,
+        buffer_size: usize,
+        merge: ExternalPlan,
+    ) -> Self {
+        MergePlan {
+            kind: PlanKind::Sort {
+                memory_budget,
+                record_size,
+                inline_size,
+                buffer_size,
+                merge,
+            },
+        }
+    }
+}
+
+/// Returns an upper bound on the memory `plan` holds at its peak.
+///
+/// For an in-memory merge, the inputs are held until the merge ends, alongside an output with
+/// room for all of their elements. For an external merge, the inputs are a few records per run, and
+/// most of the memory is in buffers. A `Sorter` first fills chunks of up to its memory budget
+/// and then merges up to `max_open_runs` runs at once, and the estimate is the larger of the two.
+pub fn estimate_peak_memory(plan: &MergePlan) -> MemoryEstimate {
+    match &plan.kind {
+        PlanKind::InMemory {
+            run_lens,
+            elem_size,
+            run_size,
+            shrink,
+        } => {
+            let len = run_lens
+                .iter()
+                .fold(0usize, |len, &run| len.saturating_add(run));
+            let bytes = len.saturating_mul(*elem_size);
+            let tree = run_lens.len().saturating_mul(*run_size);
+            MemoryEstimate {
+                inputs: bytes,
+                output: bytes,
+                scratch: if *shrink {
+                    tree.saturating_add(bytes)
+                } else {
+                    tree
+                },
+                buffers: 0,
+            }
+        }
+        PlanKind::External(merge) => estimate_external(merge),
+        PlanKind::Sort {
+            memory_budget,
+            record_size,
+            inline_size,
+            buffer_size,
+            merge,
+        } => {
+            // A chunk is spilled once it reaches the budget, so it can overshoot by one record.
+            let chunk = memory_budget.saturating_add(*record_size);
+            // The chunk's vector has at most as many spare slots as records, and sorting it
+            // takes at most one slot per record.
+            let records = chunk / (*inline_size).max(1);
+            let slots = records.saturating_mul(*inline_size);
+            let block = merge.block_size.unwrap_or(0);
+            let spill = MemoryEstimate {
+                inputs: chunk,
+                output: 0,
+                scratch: slots.saturating_mul(2),
+                buffers: buffer_size.saturating_mul(2).saturating_add(block),
+            };
+            let merge = estimate_external(merge);
+            if spill.total() >= merge.total() {
+                spill
+            } else {
+                merge
+            }
+        }
+    }
+}
+
+fn estimate_external(plan: &ExternalPlan) -> MemoryEstimate {
+    let runs = plan.runs;
+    // Each run holds its head in the tree, and a formatted run keeps copies of its first and last
+    // records to check against its trailer.
+    let per_run = match plan.block_size {
+        Some(_) => 3,
+        None => 1,
+    };
+    let block = plan.block_size.unwrap_or(0);
+    let mut buffers = runs
+        .saturating_add(1)
+        .saturating_mul(plan.buffer_size.saturating_add(block));
+    if plan.pipelined {
+        // Each reader thread has a batch queued and one being filled while the merge works
+        // through a third, and each batch can overshoot by a record. The writer has a chunk
+        // queued and one being written.
+        let batch = plan.buffer_size.saturating_add(plan.record_size);
+        buffers = buffers
+            .saturating_add(runs.saturating_mul(batch).saturating_mul(3))
+            .saturating_add(plan.buffer_size.saturating_mul(2));
+    }
+    MemoryEstimate {
+        inputs: runs.saturating_mul(plan.record_size.saturating_mul(per_run)),
+        output: 0,
+        scratch: runs.saturating_mul(RUN_OVERHEAD),
+        buffers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{Direction, Keep};
+    use crate::capacity::Capacity;
+    use crate::external::{FixedSize, Merger, Sorter, DEFAULT_BUFFER_SIZE};
+    use quickcheck_macros::quickcheck;
+
+    fn sorted(mut v: Vec<u32>) -> Vec<u32> {
+        v.sort_unstable();
+        v.dedup();
+        v
+    }
+
+    #[quickcheck]
+    fn in_memory_bounds_output(runs: Vec<Vec<u32>>, dedup: bool, last: bool, desc: bool) -> bool {
+        let runs: Vec<_> = runs
+            .into_iter()
+            .map(|run| {
+                let mut run = sorted(run);
+                if desc {
+                    run.reverse();
+                }
+                // The estimate counts the inputs by their lengths.
+                run.shrink_to_fit();
+                run
+            })
+            .collect();
+        let lens: Vec<_> = runs.iter().map(Vec::len).collect();
+
+        [Capacity::Sum, Capacity::Exact, Capacity::Shrink]
+            .iter()
+            .all(|&capacity| {
+                let builder = MergeBuilder::new()
+                    .dedup(dedup)
+                    .keep(if last { Keep::Last } else { Keep::First })
+                    .direction(if desc {
+                        Direction::Descending
+                    } else {
+                        Direction::Ascending
+                    })
+                    .capacity(capacity);
+                let estimate = estimate_peak_memory(&builder.plan::<u32>(&lens));
+                let out = builder.merge_k(runs.clone());
+                let len: usize = lens.iter().sum();
+                out.capacity() * 4 <= estimate.output
+                    && estimate.inputs == len * 4
+                    && (!dedup || capacity != Capacity::Shrink || estimate.scratch >= len * 4)
+            })
+    }
+
+    #[test]
+    fn kway_matches_builder() {
+        let lens = [3, 0, 100];
+        let estimate = estimate_peak_memory(&MergePlan::kway::<u64>(&lens));
+        assert_eq!(
+            estimate,
+            estimate_peak_memory(&MergeBuilder::new().plan::<u64>(&lens))
+        );
+        assert_eq!(estimate.inputs, 824);
+        assert_eq!(estimate.output, 824);
+        assert_eq!(estimate.buffers, 0);
+        assert!(estimate.scratch > 0);
+        assert_eq!(estimate.total(), 1648 + estimate.scratch);
+    }
+
+    #[test]
+    fn external_grows_with_runs_and_buffers() {
+        let merger = Merger::new(FixedSize::new(16)).buffer_size(1024);
+        let two = estimate_peak_memory(&merger.plan(2, 40));
+        let eight = estimate_peak_memory(&merger.plan(8, 40));
+        assert_eq!(two.buffers, 3 * 1024);
+        assert_eq!(eight.buffers, 9 * 1024);
+        assert_eq!(eight.inputs, 8 * 40);
+        assert_eq!(eight.output, 0);
+
+        let formatted = estimate_peak_memory(&merger.clone().run_format(true).plan(8, 40));
+        assert!(formatted.buffers > eight.buffers && formatted.inputs > eight.inputs);
+        let pipelined = estimate_peak_memory(&merger.plan_pipelined(8, 40));
+        assert!(pipelined.buffers >= eight.buffers + 8 * 3 * 1024);
+    }
+
+    #[test]
+    fn sorter_covers_its_budget() {
+        let sorter = Sorter::new(FixedSize::new(16)).memory_budget(1 << 26);
+        let estimate = estimate_peak_memory(&sorter.plan(40));
+        assert!(estimate.inputs >= 1 << 26);
+
+        // With a tiny budget, merging the runs takes more than filling a chunk.
+        let sorter = Sorter::new(FixedSize::new(16))
+            .memory_budget(64)
+            .max_open_runs(16);
+        let estimate = estimate_peak_memory(&sorter.plan(40));
+        assert_eq!(estimate.inputs, 16 * 3 * 40);
+        assert!(estimate.buffers >= 17 * DEFAULT_BUFFER_SIZE);
+    }
+}
@@ -1,4 +1,142 @@
+#[cfg(all(feature = "allocator_api", not(feature = "force-safe")))]
+use std::alloc::Allocator;
 use std::cmp::Ordering;
+#[cfg(not(feature = "force-safe"))]
+use std::mem::MaybeUninit;
+
+#[cfg(not(feature = "force-safe"))]
+use crate::gallop::{gallop, gallop_back};
+
+/// `into_iter` switches to `galloping` when one input is at least this many times longer than the
+/// other.
+pub(crate) const GALLOP_RATIO: usize = 32;
+
+/// `crate::merge_append` uses `binary_insert` when the accumulator is at least this many times
+/// longer than the new elements, and `in_place` otherwise. This is about where the two break even.
+pub(crate) const BINARY_INSERT_RATIO: usize = 16;
+
+/// `adaptive` starts galloping once one input has won this many comparisons in a row.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) const MIN_GALLOP: usize = 7;
+
+/// Returns `a + b`, the most elements that merging inputs of these lengths can produce.
+///
+/// # Panics
+///
+/// Panics if the sum overflows `usize`. Only vectors of zero-sized types are long enough for that,
+/// since the length of any other vector is at most `isize::MAX`.
+pub(crate) fn merged_len(a: usize, b: usize) -> usize {
+    a.checked_add(b).expect("capacity overflow")
+}
+
+/// Returns the capacity that the raw-pointer kernels allocate to merge inputs of these lengths.
+///
+/// Every offset the kernels compute lies within this output buffer or one of the inputs, so it
+/// fits in an `isize`, and a single `copy_nonoverlapping` can move any run of elements without
+/// splitting it into chunks. Zero-sized types need no room, so this is zero for them, even for
+/// lengths whose sum overflows.
+///
+/// # Panics
+///
+/// Panics if the output would take more than `isize::MAX` bytes, like `Vec::with_capacity`, but
+/// before anything is allocated or any pointer is offset.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) fn raw_capacity<T>(a: usize, b: usize) -> usize {
+    let size = std::mem::size_of::<T>();
+    if size == 0 {
+        return 0;
+    }
+    match a.checked_add(b) {
+        Some(len) if len <= isize::MAX as usize / size => len,
+        _ => panic!("capacity overflow"),
+    }
+}
+
+/// An exact-size iterator over a sorted sequence that can show the elements it has left as a
+/// slice, which `merge_sources` compares in place.
+///
+/// This trait is sealed. It is implemented for `vec::IntoIter` (which `Box<[T]>` also converts
+/// into), `array::IntoIter`, and `slice::Iter`, which yields references to the elements.
+pub trait SortedSource: ExactSizeIterator + private::Sealed {
+    /// The type of the elements that are compared.
+    type Elem;
+
+    /// Returns the elements that haven't been yielded yet.
+    fn remaining(&self) -> &[Self::Elem];
+}
+
+mod private {
+    pub trait Sealed {}
+
+    impl<T> Sealed for std::vec::IntoIter<T> {}
+    impl<T, const N: usize> Sealed for std::array::IntoIter<T, N> {}
+    impl<'a, T> Sealed for std::slice::Iter<'a, T> {}
+}
+
+impl<T> SortedSource for std::vec::IntoIter<T> {
+    type Elem = T;
+
+    fn remaining(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> SortedSource for std::array::IntoIter<T, N> {
+    type Elem = T;
+
+    fn remaining(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<'a, T> SortedSource for std::slice::Iter<'a, T> {
+    type Elem = T;
+
+    fn remaining(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+/// Merges into an output with exactly the capacity it needs, counted by a first pass that only
+/// compares the inputs.
+///
+/// The other kernels allocate room for `a.len() + b.len()` elements, nearly twice what they need
+/// when the inputs overlap heavily. The second pass is a safe loop rather than a raw-pointer
+/// kernel, so an inconsistent `Ord` can't make it overrun the count. The extra pass makes it
+/// slower than `crate::merge_uniq`, barely for small elements and more for large ones, so it's for
+/// when the memory matters more.
+pub fn exact<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                i += 1;
+                j += 1;
+                common += 1;
+            }
+        }
+    }
+
+    let mut out = Vec::with_capacity(a.len() + b.len() - common);
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        match x.cmp(y) {
+            Ordering::Less => out.push(a.next().unwrap()),
+            Ordering::Greater => out.push(b.next().unwrap()),
+            Ordering::Equal => {
+                out.push(a.next().unwrap());
+                b.next();
+            }
+        }
+    }
+    out.extend(a);
+    out.extend(b);
+    out
+}
 
 pub fn naive<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
     a.append(&mut b);
@@ -7,7 +145,9 @@ pub fn naive<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
     a
 }
 
+#[cfg(not(feature = "force-safe"))]
 pub fn into_iter<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
     // If one of the lists is zero-length, we don't need to do any work.
     if a.is_empty() {
         return b;
@@ -16,16 +156,36 @@ pub fn into_iter<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
         return a;
     }
 
+    // When one input is much longer than the other, most of the long input is copied over in runs
+    // that fall between consecutive elements of the short one. Galloping finds the end of each run
+    // in logarithmic time instead of comparing every element.
+    if a.len() / GALLOP_RATIO >= b.len() || b.len() / GALLOP_RATIO >= a.len() {
+        return galloping(a, b);
+    }
+
+    merge_sources(a.into_iter(), b.into_iter())
+}
+
+/// Merges two sorted, deduplicated sources with the loop of `into_iter`, without collecting them
+/// into vectors first.
+///
+/// Merging slice iterators yields references into the slices. Unlike `into_iter`, this never
+/// switches to galloping.
+#[cfg(not(feature = "force-safe"))]
+pub fn merge_sources<A, B>(mut a: A, mut b: B) -> Vec<A::Item>
+where
+    A: SortedSource,
+    B: SortedSource<Item = A::Item, Elem = A::Elem>,
+    A::Elem: Ord,
+{
+    crate::checked::debug_validate(&[a.remaining(), b.remaining()]);
     // Ensure that `out` always has sufficient capacity.
     //
     // SAFETY: The calls to `push_unchecked` below are safe because of this.
-    let mut out = Vec::with_capacity(a.len() + b.len());
-
-    let mut a = a.into_iter();
-    let mut b = b.into_iter();
+    let mut out = Vec::with_capacity(merged_len(a.len(), b.len()));
 
     // While both inputs have elements remaining, copy the lesser element to the output vector.
-    while !a.is_empty() && !b.is_empty() {
+    while !iter_is_empty(&a) && !iter_is_empty(&b) {
         // SAFETY: The following calls to `get_unchecked` and `next_unchecked` are safe because we
         // ensure that `a.len() > 0` and `b.len() > 0` inside the loop.
         //
@@ -33,8 +193,8 @@ pub fn into_iter<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
         // don't get optimized away. Using `ExactSizeIterator::is_empty` instead of checking `len`
         // seemed to help, but that method is unstable.
 
-        let a_elem = unsafe { a.as_slice().get_unchecked(0) };
-        let b_elem = unsafe { b.as_slice().get_unchecked(0) };
+        let a_elem = unsafe { a.remaining().get_unchecked(0) };
+        let b_elem = unsafe { b.remaining().get_unchecked(0) };
         match a_elem.cmp(b_elem) {
             Ordering::Less => unsafe { push_unchecked(&mut out, next_unchecked(&mut a)) },
             Ordering::Greater => unsafe { push_unchecked(&mut out, next_unchecked(&mut b)) },
@@ -52,7 +212,7 @@ pub fn into_iter<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
     //
     // Calling `push_unchecked` in a loop was slightly faster than `out.extend(...)`
     // despite the fact that `std::vec::IntoIter` implements `TrustedLen`.
-    if !a.is_empty() {
+    if !iter_is_empty(&a) {
         for elem in a {
             unsafe {
                 push_unchecked(&mut out, elem);
@@ -69,7 +229,9 @@ pub fn into_iter<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
     out
 }
 
+#[cfg(not(feature = "force-safe"))]
 pub fn into_iter_safer<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
     // If one of the lists is zero-length, we don't need to do any work.
     if a.is_empty() {
         return b;
@@ -81,13 +243,13 @@ pub fn into_iter_safer<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
     // Ensure that `out` always has sufficient capacity.
     //
     // SAFETY: The calls to `push_unchecked` below are safe because of this.
-    let mut out = Vec::with_capacity(a.len() + b.len());
+    let mut out = Vec::with_capacity(merged_len(a.len(), b.len()));
 
     let mut a = a.into_iter();
     let mut b = b.into_iter();
 
     // While both inputs have elements remaining, copy the lesser element to the output vector.
-    while !a.is_empty() && !b.is_empty() {
+    while !iter_is_empty(&a) && !iter_is_empty(&b) {
         // SAFETY: The following calls to `get_unchecked` and `next_unchecked` are safe because we
         // ensure that `a.len() > 0` and `b.len() > 0` inside the loop.
         //
@@ -111,7 +273,7 @@ pub fn into_iter_safer<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
     // directly to the back of the output list.
     //
     // This branch is free because we have to check `a.is_empty()` above anyways.
-    if !a.is_empty() {
+    if !iter_is_empty(&a) {
         out.extend(a);
     } else {
         out.extend(b);
@@ -120,9 +282,26 @@ pub fn into_iter_safer<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
     out
 }
 
+/// Equivalent to `ExactSizeIterator::is_empty`, which is unstable.
+///
+/// Checking `len() == 0` instead seemed to leave more bounds checks in the merge loop, so nightly
+/// builds use the real thing.
+#[cfg(not(feature = "force-safe"))]
+#[cfg(feature = "nightly")]
+fn iter_is_empty<I: ExactSizeIterator>(iter: &I) -> bool {
+    iter.is_empty()
+}
+
+#[cfg(not(feature = "force-safe"))]
+#[cfg(not(feature = "nightly"))]
+fn iter_is_empty<I: ExactSizeIterator>(iter: &I) -> bool {
+    iter.len() == 0
+}
+
 /// Pushes `value` to `vec` without checking that the vector has sufficient capacity.
 ///
 /// If `vec.len() == vec.cap()`, calling this function is UB.
+#[cfg(not(feature = "force-safe"))]
 unsafe fn push_unchecked<T>(vec: &mut Vec<T>, value: T) {
     let end = vec.as_mut_ptr().add(vec.len());
     std::ptr::write(end, value);
@@ -130,7 +309,8 @@ unsafe fn push_unchecked<T>(vec: &mut Vec<T>, value: T) {
 }
 
 /// Equivalent to `iter.next().unwrap()` that is UB to call when `iter` is empty.
-unsafe fn next_unchecked<T>(iter: &mut std::vec::IntoIter<T>) -> T {
+#[cfg(not(feature = "force-safe"))]
+unsafe fn next_unchecked<I: Iterator>(iter: &mut I) -> I::Item {
     match iter.next() {
         Some(x) => x,
         None => std::hint::unreachable_unchecked(),
@@ -175,26 +355,190 @@ pub fn old_datafrog<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
     out
 }
 
-struct RawIter<T> {
-    start: *mut T,
+/// How far ahead of a cursor `prefetch` reads, in bytes.
+#[cfg(all(
+    feature = "prefetch",
+    target_arch = "x86_64",
+    not(feature = "force-safe")
+))]
+const PREFETCH_DISTANCE: usize = 512;
+
+/// Hints that the memory a few cache lines past `p` will be read soon.
+///
+/// Once the inputs are much larger than the L2 cache, the hardware prefetcher alone can't keep
+/// both cursors fed. This is a no-op unless the `prefetch` feature is enabled on x86-64.
+#[cfg(not(feature = "force-safe"))]
+#[inline(always)]
+pub(crate) fn prefetch<T>(p: *const T) {
+    #[cfg(all(feature = "prefetch", target_arch = "x86_64"))]
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+        // Prefetching never faults, so it doesn't matter if this points past the end of the
+        // input.
+        let ahead = (p as *const i8).wrapping_add(PREFETCH_DISTANCE);
+        _mm_prefetch::<_MM_HINT_T0>(ahead);
+    }
+
+    #[cfg(not(all(feature = "prefetch", target_arch = "x86_64")))]
+    let _ = p;
+}
+
+/// The elements of a buffer that have not yet been moved to the output of a merge.
+///
+/// Dropping a `RawIter` drops the elements it still holds. A completed merge moves every element
+/// out, so this only matters when a comparison panics partway through.
+///
+/// The pointer arithmetic here is meaningless for zero-sized types. Kernels must divert those to
+/// `append_merged_zst` before creating a `RawIter`. For any other type, `start..end` lies within
+/// a single allocation, so its length in bytes fits in an `isize`, and `offset_from` is defined.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) struct RawIter<T> {
+    pub(crate) start: *mut T,
     end: *mut T,
 }
 
+#[cfg(not(feature = "force-safe"))]
 impl<T> RawIter<T> {
-    fn is_empty(&self) -> bool {
+    /// Takes ownership of the elements of `v`, leaving it empty but with its allocation intact.
+    ///
+    /// The returned iterator borrows the allocation of `v`, which must outlive it.
+    pub(crate) unsafe fn from_vec(v: &mut Vec<T>) -> Self {
+        let it = RawIter::from_raw_parts(v.as_mut_ptr(), v.len());
+        v.set_len(0);
+        it
+    }
+
+    /// Takes ownership of the `len` elements starting at `start`.
+    unsafe fn from_raw_parts(start: *mut T, len: usize) -> Self {
+        RawIter {
+            start,
+            end: start.add(len),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
         self.start == self.end
     }
 
     fn len(&self) -> usize {
+        debug_assert!(self.start <= self.end);
         unsafe { self.end.offset_from(self.start) as usize }
     }
 
-    unsafe fn advance(&mut self) {
+    pub(crate) unsafe fn advance(&mut self) {
         self.start = self.start.add(1);
     }
+
+    /// Returns the elements that have not yet been moved out of the iterator.
+    pub(crate) unsafe fn as_slice(&self) -> &[T] {
+        std::slice::from_raw_parts(self.start, self.len())
+    }
+
+    /// Gives up ownership of the remaining elements, which the caller has moved elsewhere.
+    fn forget_remaining(&mut self) {
+        self.start = self.end;
+    }
+}
+
+#[cfg(not(feature = "force-safe"))]
+impl<T> Drop for RawIter<T> {
+    fn drop(&mut self) {
+        unsafe {
+            std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(self.start, self.len()));
+        }
+    }
+}
+
+/// The initialized region `start..end` of the output buffer of a merge.
+///
+/// If a merge panics partway through, dropping this drops the elements written so far instead of
+/// leaking them. Once the merge completes, `finish` hands them off to the owner of the buffer.
+///
+/// The buffer must have room for everything written to it. The kernels either allocate it with
+/// `raw_capacity` or check that an existing buffer is big enough, so `start..end` also stays
+/// within one allocation.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) struct Written<T> {
+    start: *mut T,
+    end: *mut T,
+}
+
+#[cfg(not(feature = "force-safe"))]
+impl<T> Written<T> {
+    pub(crate) fn new(at: *mut T) -> Self {
+        Written { start: at, end: at }
+    }
+
+    fn len(&self) -> usize {
+        debug_assert!(self.start <= self.end);
+        unsafe { self.end.offset_from(self.start) as usize }
+    }
+
+    /// Moves `n` elements starting at `src` to the end of the output.
+    unsafe fn push_n(&mut self, src: *const T, n: usize) {
+        #[cfg(feature = "instrument")]
+        crate::instrument::moved(n);
+        std::ptr::copy_nonoverlapping(src, self.end, n);
+        self.end = self.end.add(n);
+    }
+
+    /// Moves the element at `src` to the end of the output.
+    pub(crate) unsafe fn push(&mut self, src: *const T) {
+        self.push_n(src, 1);
+    }
+
+    /// Writes `x` to the end of the output.
+    unsafe fn write(&mut self, x: T) {
+        #[cfg(feature = "instrument")]
+        crate::instrument::moved(1);
+        self.end.write(x);
+        self.end = self.end.add(1);
+    }
+
+    /// Moves the element at `src` to the front of the output, for merges that run backwards.
+    unsafe fn push_front(&mut self, src: *const T) {
+        #[cfg(feature = "instrument")]
+        crate::instrument::moved(1);
+        self.start = self.start.sub(1);
+        std::ptr::copy_nonoverlapping(src, self.start, 1);
+    }
+
+    /// Moves `n` elements starting at `src` to the front of the output, for merges that run
+    /// backwards. The elements may overlap their destination.
+    unsafe fn push_front_n(&mut self, src: *const T, n: usize) {
+        #[cfg(feature = "instrument")]
+        crate::instrument::moved(n);
+        self.start = self.start.sub(n);
+        std::ptr::copy(src, self.start, n);
+    }
+
+    /// Moves all remaining elements of `it` to the end of the output.
+    pub(crate) unsafe fn push_rest(&mut self, it: &mut RawIter<T>) {
+        self.push_n(it.start, it.len());
+        it.forget_remaining();
+    }
+
+    /// Gives up ownership of the written elements, returning how many there are.
+    pub(crate) fn finish(self) -> usize {
+        let len = self.len();
+        std::mem::forget(self);
+        len
+    }
 }
 
-pub fn raw_ptr<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+#[cfg(not(feature = "force-safe"))]
+impl<T> Drop for Written<T> {
+    fn drop(&mut self) {
+        unsafe {
+            std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(self.start, self.len()));
+        }
+    }
+}
+
+#[cfg(not(feature = "force-safe"))]
+pub fn raw_ptr<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
     if a.is_empty() {
         return b;
     }
@@ -202,50 +546,48 @@ pub fn raw_ptr<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
         return a;
     }
 
-    let (aptr, alen, acap) = a.into_raw_parts();
-    let (bptr, blen, bcap) = b.into_raw_parts();
-
-    let mut ait = RawIter {
-        start: aptr,
-        end: unsafe { aptr.add(alen) },
-    };
+    if std::mem::size_of::<T>() == 0 {
+        let mut out = Vec::new();
+        append_merged_zst(&mut out, &mut a, &mut b);
+        return out;
+    }
 
-    let mut bit = RawIter {
-        start: bptr,
-        end: unsafe { bptr.add(blen) },
-    };
+    let mut out: Vec<T> = Vec::with_capacity(raw_capacity::<T>(a.len(), b.len()));
 
-    let mut out: Vec<T> = Vec::with_capacity(alen + blen);
-    let mut o = out.as_mut_ptr();
+    // If `cmp` panics, these guards drop the elements that haven't been merged yet as well as the
+    // ones already written to `out`. The allocations of `a` and `b` are freed along with them.
+    let mut ait = unsafe { RawIter::from_vec(&mut a) };
+    let mut bit = unsafe { RawIter::from_vec(&mut b) };
+    let mut o = Written::new(out.as_mut_ptr());
 
     // While elements remain in both `a` and `b`.
     while !ait.is_empty() && !bit.is_empty() {
+        prefetch(ait.start);
+        prefetch(bit.start);
+
         let ord = unsafe { (*ait.start).cmp(&*bit.start) };
         match ord {
             // a[i] < b[j]: o[k++] = a[i++]
             Ordering::Less => unsafe {
-                std::ptr::copy_nonoverlapping(ait.start, o, 1);
+                o.push(ait.start);
                 ait.advance();
-                o = o.add(1);
             },
 
             // a[i] > b[j]: o[k++] = b[j++]
             Ordering::Greater => unsafe {
-                std::ptr::copy_nonoverlapping(bit.start, o, 1);
+                o.push(bit.start);
                 bit.advance();
-                o = o.add(1);
             },
 
             // a[i] == b[j]: o[k++] = a[i++]; drop(b[j++])
             Ordering::Equal => unsafe {
-                std::ptr::copy_nonoverlapping(ait.start, o, 1);
+                o.push(ait.start);
                 ait.advance();
 
                 // Drop the duplicate element, since it is not copied to the output vector.
-                std::ptr::drop_in_place(bit.start);
+                let dup = bit.start;
                 bit.advance();
-
-                o = o.add(1);
+                std::ptr::drop_in_place(dup);
             },
         }
     }
@@ -256,67 +598,1607 @@ pub fn raw_ptr<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
         //
         // NOTE: This branch is free because we have to check `ait.is_empty()` above anyways.
         if !ait.is_empty() {
-            std::ptr::copy_nonoverlapping(ait.start, o, ait.len());
-            o = o.add(ait.len());
+            o.push_rest(&mut ait);
         } else {
-            std::ptr::copy_nonoverlapping(bit.start, o, bit.len());
-            o = o.add(bit.len());
+            o.push_rest(&mut bit);
         }
 
-        // Free the capacity for `a` and `b` but not the individual elements, since those have been
-        // copied into `out`.
-        std::mem::drop(Vec::from_raw_parts(aptr, 0, acap));
-        std::mem::drop(Vec::from_raw_parts(bptr, 0, bcap));
-
         // Update `out` now that all the elements have been copied.
+        out.set_len(o.finish());
+    }
+
+    out
+}
+
+/// The bit of an entry of `index_gather`'s merged order that marks an index into `b`.
+#[cfg(not(feature = "force-safe"))]
+const FROM_B: u32 = 1 << 31;
+
+/// Merges the positions of the elements first, and then moves each element to the output in one
+/// gather pass.
+///
+/// The comparison loop writes a four-byte position per element instead of the element itself,
+/// and the gather moves each element once, in order. That suits large elements, for which the
+/// stores of the comparison loop in `raw_ptr` are the bulk of its work. The gather reads each
+/// element a second time, though, so whether it pays off depends on the element and the machine;
+/// `bench_merge_large` compares the two. Inputs of `2^31` elements or more go to `raw_ptr`.
+#[cfg(not(feature = "force-safe"))]
+pub fn index_gather<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
+    if a.is_empty() {
+        return b;
+    }
+    if b.is_empty() {
+        return a;
+    }
+    if std::mem::size_of::<T>() == 0 || a.len() >= FROM_B as usize || b.len() >= FROM_B as usize {
+        return raw_ptr(a, b);
+    }
+
+    // Nothing is moved until the order is known, so a comparison that panics leaves the inputs
+    // as they were, to be dropped along with them.
+    let mut order: Vec<u32> = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => {
+                order.push(i as u32);
+                i += 1;
+            }
+            Ordering::Greater => {
+                order.push(j as u32 | FROM_B);
+                j += 1;
+            }
+            // The duplicate in `b` is left out of the order, and dropped by the gather.
+            Ordering::Equal => {
+                order.push(i as u32);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    order.extend((i..a.len()).map(|i| i as u32));
+    order.extend((j..b.len()).map(|j| j as u32 | FROM_B));
+
+    let mut out: Vec<T> = Vec::with_capacity(order.len());
+    let mut ait = unsafe { RawIter::from_vec(&mut a) };
+    let mut bit = unsafe { RawIter::from_vec(&mut b) };
+    let mut o = Written::new(out.as_mut_ptr());
+
+    // Every element of `a` is kept, so the entries for `a` come in order. Those for `b` skip its
+    // duplicates, which are dropped on the way.
+    let mut next_b = 0;
+    for &entry in &order {
+        unsafe {
+            if entry & FROM_B == 0 {
+                o.push(ait.start);
+                ait.advance();
+            } else {
+                let j = (entry & !FROM_B) as usize;
+                while next_b < j {
+                    let dup = bit.start;
+                    bit.advance();
+                    next_b += 1;
+                    std::ptr::drop_in_place(dup);
+                }
+                o.push(bit.start);
+                bit.advance();
+                next_b += 1;
+            }
+        }
+    }
+
+    unsafe {
+        // The duplicates after the last element of `b` that was kept are dropped by `bit`.
+        out.set_len(o.finish());
+    }
+    out
+}
+
+/// Like `raw_ptr`, but for `Copy` types, which need no cleanup if a comparison panics.
+///
+/// The loop walks plain pointers instead of the `RawIter` and `Written` guards, and skips dropping
+/// duplicates. Since nothing needs dropping, each step copies whichever head is smaller and
+/// advances the cursors by the result of the comparison, without branching on it. Once either
+/// input runs out, the rest of the other is copied to the output with a single
+/// `copy_nonoverlapping`. `crate::merge_uniq_copied` uses it for inputs of similar lengths.
+#[cfg(not(feature = "force-safe"))]
+pub fn raw_ptr_copied<T: Ord + Copy>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
+    if a.is_empty() {
+        return b;
+    }
+    if b.is_empty() {
+        return a;
+    }
+
+    if std::mem::size_of::<T>() == 0 {
+        return from_slices_zst(&a, &b);
+    }
+
+    let mut out: Vec<T> = Vec::with_capacity(a.len() + b.len());
+
+    // If `cmp` panics, `out` is dropped with a length of zero. Since `T: Copy`, there is nothing
+    // else to clean up.
+    unsafe {
+        let (mut ap, aend) = (a.as_ptr(), a.as_ptr().add(a.len()));
+        let (mut bp, bend) = (b.as_ptr(), b.as_ptr().add(b.len()));
+        let mut o = out.as_mut_ptr();
+
+        while ap != aend && bp != bend {
+            prefetch(ap);
+            prefetch(bp);
+
+            let ord = (*ap).cmp(&*bp);
+            let src = if ord == Ordering::Greater { bp } else { ap };
+            *o = *src;
+            ap = ap.add((ord != Ordering::Greater) as usize);
+            bp = bp.add((ord != Ordering::Less) as usize);
+            o = o.add(1);
+            #[cfg(feature = "instrument")]
+            crate::instrument::moved(1);
+        }
+
+        // At most one of these is non-empty.
+        for (p, end) in [(ap, aend), (bp, bend)] {
+            let n = end.offset_from(p) as usize;
+            #[cfg(feature = "instrument")]
+            crate::instrument::moved(n);
+            std::ptr::copy_nonoverlapping(p, o, n);
+            o = o.add(n);
+        }
+
         out.set_len(o.offset_from(out.as_ptr()) as usize);
     }
 
     out
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use quickcheck_macros::quickcheck;
+/// Like `raw_ptr`, but the body of the merge loop has no data-dependent branches.
+///
+/// The result of each comparison selects which element to move with a conditional move, and
+/// advances each cursor by `0` or `1`. On random interleavings of the inputs, this avoids the
+/// branch mispredictions that dominate the running time of `raw_ptr`. Dropping duplicates still
+/// requires a branch, but only for types that need to be dropped.
+#[cfg(not(feature = "force-safe"))]
+pub fn branchless<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
+    if a.is_empty() {
+        return b;
+    }
+    if b.is_empty() {
+        return a;
+    }
 
-    #[quickcheck]
-    fn into_iter_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
-        a.sort_unstable();
-        a.dedup();
-        b.sort_unstable();
-        b.dedup();
+    if std::mem::size_of::<T>() == 0 {
+        let mut out = Vec::new();
+        append_merged_zst(&mut out, &mut a, &mut b);
+        return out;
+    }
 
-        let expected: Vec<_> = naive(a.clone(), b.clone());
-        let actual: Vec<_> = into_iter(a, b);
-        expected == actual
+    let mut out: Vec<T> = Vec::with_capacity(raw_capacity::<T>(a.len(), b.len()));
+
+    unsafe {
+        let mut ait = RawIter::from_vec(&mut a);
+        let mut bit = RawIter::from_vec(&mut b);
+        let mut o = Written::new(out.as_mut_ptr());
+
+        while !ait.is_empty() && !bit.is_empty() {
+            let ord = (*ait.start).cmp(&*bit.start);
+            let src = if ord == Ordering::Greater {
+                bit.start
+            } else {
+                ait.start
+            };
+            o.push(src);
+
+            let dup = bit.start;
+            ait.start = ait.start.add((ord != Ordering::Greater) as usize);
+            bit.start = bit.start.add((ord != Ordering::Less) as usize);
+            if std::mem::needs_drop::<T>() && ord == Ordering::Equal {
+                std::ptr::drop_in_place(dup);
+            }
+        }
+
+        o.push_rest(&mut ait);
+        o.push_rest(&mut bit);
+        out.set_len(o.finish());
     }
 
-    #[quickcheck]
-    fn old_datafrog_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
-        a.sort_unstable();
-        a.dedup();
-        b.sort_unstable();
-        b.dedup();
+    out
+}
 
-        dbg!(&a, &b);
+/// Merges `a` and `b` by galloping through the longer input.
+///
+/// For each element of the shorter input, an exponential search finds how many elements of the
+/// longer input precede it, and that whole run is moved to the output with a single
+/// `copy_nonoverlapping`. This performs `O(m log(n / m))` comparisons instead of `O(n + m)`, which
+/// is a big win when the input lengths are heavily skewed.
+#[cfg(not(feature = "force-safe"))]
+pub fn galloping<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
+    if a.is_empty() {
+        return b;
+    }
+    if b.is_empty() {
+        return a;
+    }
 
-        let expected: Vec<_> = naive(a.clone(), b.clone());
-        let actual: Vec<_> = old_datafrog(a, b);
-        expected == actual
+    if std::mem::size_of::<T>() == 0 {
+        let mut out = Vec::new();
+        append_merged_zst(&mut out, &mut a, &mut b);
+        return out;
     }
 
-    #[quickcheck]
-    fn raw_ptr_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
-        a.sort_unstable();
-        a.dedup();
-        b.sort_unstable();
-        b.dedup();
+    // On equal elements, we keep the one from `a` to match the other implementations.
+    let a_is_long = a.len() >= b.len();
 
-        dbg!(&a, &b);
+    let mut out: Vec<T> = Vec::with_capacity(raw_capacity::<T>(a.len(), b.len()));
 
-        let expected: Vec<_> = naive(a.clone(), b.clone());
-        let actual: Vec<_> = raw_ptr(a, b);
+    let mut ait = unsafe { RawIter::from_vec(&mut a) };
+    let mut bit = unsafe { RawIter::from_vec(&mut b) };
+    let mut o = Written::new(out.as_mut_ptr());
+
+    let (long, short) = if a_is_long {
+        (&mut ait, &mut bit)
+    } else {
+        (&mut bit, &mut ait)
+    };
+
+    while !short.is_empty() && !long.is_empty() {
+        unsafe {
+            let s = short.start;
+
+            // Move every element of `long` that is less than `*s` to the output.
+            let run = gallop(long.as_slice(), |x| x < &*s);
+            o.push_n(long.start, run);
+            long.start = long.start.add(run);
+
+            if !long.is_empty() && *long.start == *s {
+                let (keep, dup) = if a_is_long {
+                    (long.start, s)
+                } else {
+                    (s, long.start)
+                };
+
+                o.push(keep);
+                long.advance();
+                short.advance();
+                std::ptr::drop_in_place(dup);
+            } else {
+                o.push(s);
+                short.advance();
+            }
+        }
+    }
+
+    unsafe {
+        // At most one of `long` and `short` still has elements. Move them to the back of the
+        // output list.
+        o.push_rest(long);
+        o.push_rest(short);
+
+        out.set_len(o.finish());
+    }
+
+    out
+}
+
+/// Merges `a` and `b` one element at a time like `raw_ptr`, but switches to galloping whenever one
+/// input wins `MIN_GALLOP` comparisons in a row, as TimSort does.
+///
+/// An exponential search then finds how far the winning run extends, and moves all of it to the
+/// output with a single `copy_nonoverlapping`, before going back to comparing one element at a
+/// time. This keeps inputs that interleave in long blocks from paying a comparison per element,
+/// without the caller choosing `galloping` up front. The threshold rises after gallops that skip
+/// only a few elements, so inputs that interleave finely rarely try. That keeps it as fast as
+/// `raw_ptr` on inputs that alternate every few elements, and faster once they alternate in
+/// blocks of dozens, while `galloping` is slower than both on inputs of similar lengths.
+#[cfg(not(feature = "force-safe"))]
+pub fn adaptive<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
+    if a.is_empty() {
+        return b;
+    }
+    if b.is_empty() {
+        return a;
+    }
+
+    if std::mem::size_of::<T>() == 0 {
+        let mut out = Vec::new();
+        append_merged_zst(&mut out, &mut a, &mut b);
+        return out;
+    }
+
+    let mut out: Vec<T> = Vec::with_capacity(raw_capacity::<T>(a.len(), b.len()));
+
+    let mut ait = unsafe { RawIter::from_vec(&mut a) };
+    let mut bit = unsafe { RawIter::from_vec(&mut b) };
+    let mut o = Written::new(out.as_mut_ptr());
+
+    // How many comparisons in a row each input has won.
+    let (mut a_wins, mut b_wins) = (0, 0);
+    let mut min_gallop = MIN_GALLOP;
+    while !ait.is_empty() && !bit.is_empty() {
+        prefetch(ait.start);
+        prefetch(bit.start);
+
+        let ord = unsafe { (*ait.start).cmp(&*bit.start) };
+        match ord {
+            Ordering::Less => unsafe {
+                o.push(ait.start);
+                ait.advance();
+                a_wins += 1;
+                b_wins = 0;
+            },
+            Ordering::Greater => unsafe {
+                o.push(bit.start);
+                bit.advance();
+                b_wins += 1;
+                a_wins = 0;
+            },
+            Ordering::Equal => unsafe {
+                o.push(ait.start);
+                ait.advance();
+                let dup = bit.start;
+                bit.advance();
+                std::ptr::drop_in_place(dup);
+                a_wins = 0;
+                b_wins = 0;
+            },
+        }
+
+        // The losing input still has its head, since only the winner advanced.
+        let run = if a_wins >= min_gallop {
+            a_wins = 0;
+            unsafe {
+                let head = bit.start;
+                let run = gallop(ait.as_slice(), |x| x < &*head);
+                o.push_n(ait.start, run);
+                ait.start = ait.start.add(run);
+                run
+            }
+        } else if b_wins >= min_gallop {
+            b_wins = 0;
+            unsafe {
+                let head = ait.start;
+                let run = gallop(bit.as_slice(), |y| y < &*head);
+                o.push_n(bit.start, run);
+                bit.start = bit.start.add(run);
+                run
+            }
+        } else {
+            continue;
+        };
+
+        // Gallop sooner after one that skipped a long run, and later after one that didn't.
+        if run >= MIN_GALLOP {
+            min_gallop = min_gallop.saturating_sub(1).max(2);
+        } else {
+            min_gallop += 2;
+        }
+    }
+
+    unsafe {
+        o.push_rest(&mut ait);
+        o.push_rest(&mut bit);
+        out.set_len(o.finish());
+    }
+
+    out
+}
+
+/// Merges `b` into `a`, reusing the spare capacity at the end of `a` when there is enough of it.
+///
+/// If `a.capacity() >= a.len() + b.len()`, the merge runs backwards from the end of `a`'s
+/// allocation so that no element of `a` is overwritten before it is read, and no new buffer is
+/// allocated. Otherwise, this falls back to `raw_ptr`.
+#[cfg(not(feature = "force-safe"))]
+pub fn in_place<T: Ord>(a: &mut Vec<T>, mut b: Vec<T>) {
+    crate::checked::debug_validate(&[a, &b]);
+    if b.is_empty() {
+        return;
+    }
+
+    if std::mem::size_of::<T>() == 0 {
+        let mut prefix = std::mem::take(a);
+        append_merged_zst(a, &mut prefix, &mut b);
+        return;
+    }
+
+    let alen = a.len();
+    let blen = b.len();
+    if a.capacity() - alen < blen {
+        *a = raw_ptr(std::mem::take(a), b);
+        return;
+    }
+
+    unsafe {
+        let p = a.as_mut_ptr();
+
+        // The elements of `a` and `b` that have yet to be merged are consumed from the back. The
+        // output is written backwards from the end of the buffer, so `o` always ends at
+        // `p + alen + blen`.
+        //
+        // Since `o.start >= arest.end + brest.len()` inside the loop, writing to the element before
+        // `o.start` never clobbers an element of `a` that hasn't been read yet.
+        let mut arest = RawIter::from_vec(a);
+        let mut brest = RawIter::from_vec(&mut b);
+        let mut o = Written::new(p.add(alen + blen));
+
+        while !arest.is_empty() && !brest.is_empty() {
+            let a_elem = arest.end.sub(1);
+            let b_elem = brest.end.sub(1);
+            match (*a_elem).cmp(&*b_elem) {
+                Ordering::Greater => {
+                    o.push_front(a_elem);
+                    arest.end = a_elem;
+                }
+                Ordering::Less => {
+                    o.push_front(b_elem);
+                    brest.end = b_elem;
+                }
+                Ordering::Equal => {
+                    o.push_front(a_elem);
+                    arest.end = a_elem;
+                    brest.end = b_elem;
+                    std::ptr::drop_in_place(b_elem);
+                }
+            }
+        }
+
+        // Any elements left in `b` go right before the merged suffix. Any elements left in `a` are
+        // already at the start of the buffer, where they belong.
+        let bi = brest.len();
+        o.start = o.start.sub(bi);
+        std::ptr::copy_nonoverlapping(brest.start, o.start, bi);
+        brest.forget_remaining();
+
+        let ai = arest.len();
+        arest.forget_remaining();
+
+        // Each duplicate leaves a one-element gap between the leftover prefix of `a` and the merged
+        // suffix. Close it.
+        let w = o.start;
+        let suffix = o.finish();
+        std::ptr::copy(w, p.add(ai), suffix);
+        a.set_len(ai + suffix);
+    }
+}
+
+/// Merges `b` into `a` by binary searching `a` for where each element of `b` goes, for when `b` is
+/// much shorter than `a`.
+///
+/// Working backwards from the end of `a`, the elements of `a` between consecutive insertion points
+/// are moved up as one block, so each element of `a` moves at most once and the merge takes
+/// `O(b.len() * log(a.len()))` comparisons. Elements of `b` equal to one in `a` are dropped.
+#[cfg(not(feature = "force-safe"))]
+pub fn binary_insert<T: Ord>(a: &mut Vec<T>, mut b: Vec<T>) {
+    crate::checked::debug_validate(&[a, &b]);
+    if b.is_empty() {
+        return;
+    }
+
+    if std::mem::size_of::<T>() == 0 {
+        in_place(a, b);
+        return;
+    }
+
+    let alen = a.len();
+    let blen = b.len();
+    a.reserve(blen);
+
+    unsafe {
+        let p = a.as_mut_ptr();
+
+        // As in `in_place`, `o.start >= arest.end + brest.len()` throughout, so moving the block of
+        // `a` after each insertion point never clobbers an element that hasn't been moved yet.
+        let mut arest = RawIter::from_vec(a);
+        let mut brest = RawIter::from_vec(&mut b);
+        let mut o = Written::new(p.add(alen + blen));
+
+        while !brest.is_empty() {
+            let b_elem = brest.end.sub(1);
+            let n = gallop_back(arest.as_slice(), |x| x.cmp(&*b_elem) == Ordering::Greater);
+            o.push_front_n(arest.end.sub(n), n);
+            arest.end = arest.end.sub(n);
+
+            if !arest.is_empty() && (*arest.end.sub(1)).cmp(&*b_elem) == Ordering::Equal {
+                std::ptr::drop_in_place(b_elem);
+            } else {
+                o.push_front(b_elem);
+            }
+            brest.end = b_elem;
+        }
+
+        // Each duplicate leaves a one-element gap between the leftover prefix of `a` and the merged
+        // suffix. Close it.
+        let ai = arest.len();
+        arest.forget_remaining();
+        let w = o.start;
+        let suffix = o.finish();
+        std::ptr::copy(w, p.add(ai), suffix);
+        a.set_len(ai + suffix);
+    }
+}
+
+/// Merges the sorted, deduplicated halves `v[..mid]` and `v[mid..]` in place, removing elements
+/// that appear in both.
+///
+/// The left half is moved to a scratch buffer, then merged forwards with the right half into the
+/// front of `v`. The write cursor never passes the read cursor of the right half, so no element is
+/// overwritten before it is read.
+///
+/// # Panics
+///
+/// Panics if `mid > v.len()`.
+#[cfg(not(feature = "force-safe"))]
+pub fn merge_adjacent_uniq<T: Ord>(v: &mut Vec<T>, mid: usize) {
+    let len = v.len();
+    assert!(mid <= len, "`mid` is out of bounds");
+    crate::checked::debug_validate(&[&v[..mid], &v[mid..]]);
+
+    // If the halves are already in order, there is nothing to do.
+    if mid == 0 || mid == len || v[mid - 1] < v[mid] {
+        return;
+    }
+
+    // For zero-sized types, each element of the left half absorbs one duplicate from the right
+    // half. See `append_merged_zst`.
+    if std::mem::size_of::<T>() == 0 {
+        v.truncate(len - mid.min(len - mid));
+        return;
+    }
+
+    unsafe {
+        let p = v.as_mut_ptr();
+        v.set_len(0);
+
+        let mut scratch: Vec<T> = Vec::with_capacity(mid);
+        std::ptr::copy_nonoverlapping(p, scratch.as_mut_ptr(), mid);
+
+        let mut left = RawIter {
+            start: scratch.as_mut_ptr(),
+            end: scratch.as_mut_ptr().add(mid),
+        };
+        let mut right = RawIter {
+            start: p.add(mid),
+            end: p.add(len),
+        };
+        let mut o = Written::new(p);
+
+        while !left.is_empty() && !right.is_empty() {
+            match (*left.start).cmp(&*right.start) {
+                Ordering::Less => {
+                    o.push(left.start);
+                    left.advance();
+                }
+                Ordering::Greater => {
+                    o.push(right.start);
+                    right.advance();
+                }
+                Ordering::Equal => {
+                    o.push(left.start);
+                    left.advance();
+
+                    let dup = right.start;
+                    right.advance();
+                    std::ptr::drop_in_place(dup);
+                }
+            }
+        }
+
+        // Move whatever remains of either half to the back of the output. The rest of the right
+        // half may overlap its destination, so it needs a `copy` instead of a
+        // `copy_nonoverlapping`.
+        o.push_rest(&mut left);
+        std::ptr::copy(right.start, o.end, right.len());
+        o.end = o.end.add(right.len());
+        right.forget_remaining();
+
+        // `scratch` still has length zero, so dropping it frees only the buffer.
+        v.set_len(o.finish());
+    }
+}
+
+/// Merges `a` and `b` without allocating a separate output buffer.
+///
+/// `b` is appended to `a` (growing `a`'s allocation if needed), and the two sorted halves are
+/// merged with a rotation-based algorithm that needs only `O(log n)` stack space. Duplicates end up
+/// adjacent and are removed with `Vec::dedup`. This does `O(n log n)` work instead of `O(n)`, so it
+/// is only worthwhile when memory for a full-size scratch buffer is not available.
+pub fn low_memory<T: Ord>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b]);
+    let mid = a.len();
+    a.append(&mut b);
+    merge_by_rotation(&mut a, mid);
+    a.dedup();
+    a
+}
+
+/// Stably merges the sorted halves `v[..mid]` and `v[mid..]` using rotations.
+///
+/// This is the classic "merge without buffer" algorithm: split the longer half in two, find where
+/// its middle element belongs in the other half, rotate the two middle blocks past each other, and
+/// recurse on both sides. Equal elements from the left half stay before those from the right half.
+fn merge_by_rotation<T: Ord>(v: &mut [T], mid: usize) {
+    let left = mid;
+    let right = v.len() - mid;
+    if left == 0 || right == 0 || v[mid - 1] <= v[mid] {
+        return;
+    }
+
+    if left + right == 2 {
+        v.swap(0, 1);
+        return;
+    }
+
+    let (cut_left, cut_right) = if left > right {
+        let cut_left = left / 2;
+        let pivot = &v[cut_left];
+        (cut_left, mid + v[mid..].partition_point(|x| x < pivot))
+    } else {
+        let cut_right = mid + right / 2;
+        let pivot = &v[cut_right];
+        (v[..mid].partition_point(|x| x <= pivot), cut_right)
+    };
+
+    v[cut_left..cut_right].rotate_left(mid - cut_left);
+
+    let new_mid = cut_left + (cut_right - mid);
+    merge_by_rotation(&mut v[..new_mid], cut_left);
+    merge_by_rotation(&mut v[new_mid..], cut_right - new_mid);
+}
+
+/// Merges two sorted, deduplicated slices into a new vector, cloning each element of the output.
+///
+/// Like `raw_ptr`, this writes into the output's spare capacity without per-element capacity
+/// checks.
+#[cfg(not(feature = "force-safe"))]
+pub fn from_slices<T: Ord + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    crate::checked::debug_validate(&[a, b]);
+    if std::mem::size_of::<T>() == 0 {
+        return from_slices_zst(a, b);
+    }
+
+    let mut out: Vec<T> = Vec::with_capacity(raw_capacity::<T>(a.len(), b.len()));
+
+    unsafe {
+        let mut o = Written::new(out.as_mut_ptr());
+        let (a, b) = clone_merged_prefix(a, b, &mut o);
+        for x in a.iter().chain(b) {
+            o.write(x.clone());
+        }
+        out.set_len(o.finish());
+    }
+
+    out
+}
+
+/// Like `from_slices`, but for `Copy` types. Once either input runs out, the rest of the other is
+/// copied to the output with a single `copy_nonoverlapping`.
+#[cfg(not(feature = "force-safe"))]
+pub fn from_slices_copied<T: Ord + Copy>(a: &[T], b: &[T]) -> Vec<T> {
+    crate::checked::debug_validate(&[a, b]);
+    if std::mem::size_of::<T>() == 0 {
+        return from_slices_zst(a, b);
+    }
+
+    let mut out: Vec<T> = Vec::with_capacity(raw_capacity::<T>(a.len(), b.len()));
+
+    unsafe {
+        let mut o = Written::new(out.as_mut_ptr());
+        let (a, b) = clone_merged_prefix(a, b, &mut o);
+        o.push_n(a.as_ptr(), a.len());
+        o.push_n(b.as_ptr(), b.len());
+        out.set_len(o.finish());
+    }
+
+    out
+}
+
+/// Clones the merged contents of `a` and `b` to the end of `o` until either one runs out, then
+/// returns what is left of both. At most one of the returned slices is non-empty.
+///
+/// # Safety
+///
+/// `o` must be valid for writes of `a.len() + b.len()` more elements.
+#[cfg(not(feature = "force-safe"))]
+unsafe fn clone_merged_prefix<'a, T: Ord + Clone>(
+    mut a: &'a [T],
+    mut b: &'a [T],
+    o: &mut Written<T>,
+) -> (&'a [T], &'a [T]) {
+    while let (Some(x), Some(y)) = (a.first(), b.first()) {
+        match x.cmp(y) {
+            Ordering::Less => {
+                o.write(x.clone());
+                a = &a[1..];
+            }
+            Ordering::Greater => {
+                o.write(y.clone());
+                b = &b[1..];
+            }
+            Ordering::Equal => {
+                o.write(x.clone());
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+
+    (a, b)
+}
+
+/// See `append_merged_zst`.
+#[cfg(not(feature = "force-safe"))]
+fn from_slices_zst<T: Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    let dups = a.len().min(b.len());
+    let mut out = a.to_vec();
+    out.extend_from_slice(&b[dups..]);
+    out
+}
+
+/// Moves the merged contents of `a` and `b` to the end of `out`.
+///
+/// `a` and `b` are left empty, but keep their allocations so the caller can reuse them.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) fn append_merged<T: Ord>(out: &mut Vec<T>, a: &mut Vec<T>, b: &mut Vec<T>) {
+    if std::mem::size_of::<T>() == 0 {
+        return append_merged_zst(out, a, b);
+    }
+
+    out.reserve(raw_capacity::<T>(a.len(), b.len()));
+
+    unsafe {
+        let mut ait = RawIter::from_vec(a);
+        let mut bit = RawIter::from_vec(b);
+        let mut o = Written::new(out.as_mut_ptr().add(out.len()));
+
+        merge_raw(&mut ait, &mut bit, &mut o);
+        out.set_len(out.len() + o.finish());
+    }
+}
+
+/// Moves the merged contents of `a` and `b` into the front of `out`, returning the number of
+/// elements written.
+///
+/// # Panics
+///
+/// Panics if `out.len() < a.len() + b.len()`. Nothing is written in that case.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) fn merge_into_uninit<T: Ord>(
+    mut a: Vec<T>,
+    mut b: Vec<T>,
+    out: &mut [MaybeUninit<T>],
+) -> usize {
+    assert!(
+        a.len()
+            .checked_add(b.len())
+            .is_some_and(|len| out.len() >= len),
+        "output buffer is too small for the merged inputs"
+    );
+
+    unsafe {
+        let alen = a.len();
+        let blen = b.len();
+        a.set_len(0);
+        b.set_len(0);
+
+        merge_raw_parts(
+            a.as_mut_ptr(),
+            alen,
+            b.as_mut_ptr(),
+            blen,
+            out.as_mut_ptr() as *mut T,
+        )
+    }
+}
+
+/// Like `raw_ptr`, but for vectors from any allocator. The output is allocated from `alloc`.
+#[cfg(feature = "allocator_api")]
+#[cfg(not(feature = "force-safe"))]
+pub fn raw_ptr_in<T, A, B, O>(mut a: Vec<T, A>, mut b: Vec<T, B>, alloc: O) -> Vec<T, O>
+where
+    T: Ord,
+    A: Allocator,
+    B: Allocator,
+    O: Allocator,
+{
+    let mut out = Vec::with_capacity_in(raw_capacity::<T>(a.len(), b.len()), alloc);
+
+    unsafe {
+        let alen = a.len();
+        let blen = b.len();
+        a.set_len(0);
+        b.set_len(0);
+
+        let len = merge_raw_parts(a.as_mut_ptr(), alen, b.as_mut_ptr(), blen, out.as_mut_ptr());
+        out.set_len(len);
+    }
+
+    out
+}
+
+/// Moves the merged contents of the `alen` elements at `a` and the `blen` elements at `b` to `out`,
+/// returning the number of elements written.
+///
+/// This lets containers other than `Vec` use the raw-pointer kernel.
+///
+/// # Safety
+///
+/// The caller must own the input elements, and must give up ownership of them (for example, by
+/// setting the length of their container to zero) before calling this. `out` must be valid for
+/// writes of `alen + blen` elements and must not overlap either input. If a comparison panics, the
+/// input elements are dropped and nothing is left in `out`.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) unsafe fn merge_raw_parts<T: Ord>(
+    a: *mut T,
+    alen: usize,
+    b: *mut T,
+    blen: usize,
+    out: *mut T,
+) -> usize {
+    if std::mem::size_of::<T>() == 0 {
+        // See `append_merged_zst`. Moving a zero-sized value is a no-op, so only the duplicates
+        // need any attention. The `alen + blen - dups` elements left are counted as `max`, which
+        // can't overflow.
+        let dups = alen.min(blen);
+        std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(b, dups));
+        return alen.max(blen);
+    }
+
+    let mut ait = RawIter::from_raw_parts(a, alen);
+    let mut bit = RawIter::from_raw_parts(b, blen);
+    let mut o = Written::new(out);
+
+    merge_raw(&mut ait, &mut bit, &mut o);
+    o.finish()
+}
+
+/// Merges inputs of a zero-sized type, leaving `a` and `b` empty.
+///
+/// All values of a zero-sized type are identical, so every element compares equal to every other
+/// and the merge degenerates to length arithmetic: each element of `a` absorbs one duplicate from
+/// `b`, exactly as the main loop of `raw_ptr` would. For sorted, deduplicated inputs, this just
+/// means the output has one element unless both inputs are empty.
+#[cfg(not(feature = "force-safe"))]
+fn append_merged_zst<T>(out: &mut Vec<T>, a: &mut Vec<T>, b: &mut Vec<T>) {
+    let dups = a.len().min(b.len());
+    b.truncate(b.len() - dups);
+    out.append(a);
+    out.append(b);
+}
+
+/// Moves the merged contents of `ait` and `bit` to the end of `o`.
+///
+/// # Safety
+///
+/// `o` must be valid for writes of `ait.len() + bit.len()` more elements, and must not overlap
+/// either input.
+#[cfg(not(feature = "force-safe"))]
+pub(crate) unsafe fn merge_raw<T: Ord>(
+    ait: &mut RawIter<T>,
+    bit: &mut RawIter<T>,
+    o: &mut Written<T>,
+) {
+    while !ait.is_empty() && !bit.is_empty() {
+        prefetch(ait.start);
+        prefetch(bit.start);
+
+        match (*ait.start).cmp(&*bit.start) {
+            Ordering::Less => {
+                o.push(ait.start);
+                ait.advance();
+            }
+            Ordering::Greater => {
+                o.push(bit.start);
+                bit.advance();
+            }
+            Ordering::Equal => {
+                o.push(ait.start);
+                ait.advance();
+
+                let dup = bit.start;
+                bit.advance();
+                std::ptr::drop_in_place(dup);
+            }
+        }
+    }
+
+    // At most one of `ait` and `bit` still has elements. Move them to the back of the output.
+    o.push_rest(ait);
+    o.push_rest(bit);
+}
+
+/// Safe stand-ins for the unsafe kernels, used when the `force-safe` feature forbids unsafe code.
+///
+/// They all route through the same safe merge iterator, so they keep the signatures and results of
+/// the kernels they replace, but not their performance characteristics.
+#[cfg(feature = "force-safe")]
+mod force_safe {
+    use super::SortedSource;
+    use crate::iter::MergeUniq;
+    #[cfg(feature = "allocator_api")]
+    use std::alloc::Allocator;
+    use std::cmp::Ordering;
+    use std::mem::MaybeUninit;
+
+    pub fn into_iter<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        crate::checked::debug_validate(&[&a, &b]);
+        let mut out = Vec::with_capacity(super::merged_len(a.len(), b.len()));
+        out.extend(MergeUniq::new(a.into_iter(), b.into_iter()));
+        out
+    }
+
+    pub fn into_iter_safer<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        into_iter(a, b)
+    }
+
+    pub fn merge_sources<A, B>(a: A, b: B) -> Vec<A::Item>
+    where
+        A: SortedSource,
+        B: SortedSource<Item = A::Item, Elem = A::Elem>,
+        A::Elem: Ord,
+    {
+        crate::checked::debug_validate(&[a.remaining(), b.remaining()]);
+        let mut out = Vec::with_capacity(super::merged_len(a.len(), b.len()));
+        let (mut a, mut b) = (a, b);
+        while let (Some(x), Some(y)) = (a.remaining().first(), b.remaining().first()) {
+            match x.cmp(y) {
+                Ordering::Less => out.extend(a.next()),
+                Ordering::Greater => out.extend(b.next()),
+                Ordering::Equal => {
+                    out.extend(a.next());
+                    b.next();
+                }
+            }
+        }
+        out.extend(a);
+        out.extend(b);
+        out
+    }
+
+    pub fn raw_ptr<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        into_iter(a, b)
+    }
+
+    pub fn raw_ptr_copied<T: Ord + Copy>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        into_iter(a, b)
+    }
+
+    pub fn index_gather<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        into_iter(a, b)
+    }
+
+    pub fn galloping<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        into_iter(a, b)
+    }
+
+    pub fn branchless<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        into_iter(a, b)
+    }
+
+    pub fn adaptive<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        into_iter(a, b)
+    }
+
+    pub fn in_place<T: Ord>(a: &mut Vec<T>, b: Vec<T>) {
+        *a = into_iter(std::mem::take(a), b);
+    }
+
+    pub fn binary_insert<T: Ord>(a: &mut Vec<T>, b: Vec<T>) {
+        in_place(a, b);
+    }
+
+    pub fn merge_adjacent_uniq<T: Ord>(v: &mut Vec<T>, mid: usize) {
+        assert!(mid <= v.len(), "`mid` is out of bounds");
+
+        let right = v.split_off(mid);
+        *v = into_iter(std::mem::take(v), right);
+    }
+
+    pub(crate) fn append_merged<T: Ord>(out: &mut Vec<T>, a: &mut Vec<T>, b: &mut Vec<T>) {
+        out.reserve(a.len() + b.len());
+        out.extend(MergeUniq::new(a.drain(..), b.drain(..)));
+    }
+
+    #[cfg(feature = "allocator_api")]
+    pub fn raw_ptr_in<T, A, B, O>(a: Vec<T, A>, b: Vec<T, B>, alloc: O) -> Vec<T, O>
+    where
+        T: Ord,
+        A: Allocator,
+        B: Allocator,
+        O: Allocator,
+    {
+        let mut out = Vec::with_capacity_in(a.len() + b.len(), alloc);
+        out.extend(MergeUniq::new(a.into_iter(), b.into_iter()));
+        out
+    }
+
+    pub fn from_slices<T: Ord + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+        crate::checked::debug_validate(&[a, b]);
+        let mut out = Vec::with_capacity(a.len() + b.len());
+        out.extend(MergeUniq::new(a.iter(), b.iter()).cloned());
+        out
+    }
+
+    pub fn from_slices_copied<T: Ord + Copy>(a: &[T], b: &[T]) -> Vec<T> {
+        from_slices(a, b)
+    }
+
+    pub(crate) fn merge_into_uninit<T: Ord>(
+        a: Vec<T>,
+        b: Vec<T>,
+        out: &mut [MaybeUninit<T>],
+    ) -> usize {
+        assert!(
+            a.len()
+                .checked_add(b.len())
+                .is_some_and(|len| out.len() >= len),
+            "output buffer is too small for the merged inputs"
+        );
+
+        let mut len = 0;
+        for (slot, x) in out
+            .iter_mut()
+            .zip(MergeUniq::new(a.into_iter(), b.into_iter()))
+        {
+            *slot = MaybeUninit::new(x);
+            len += 1;
+        }
+        len
+    }
+}
+
+#[cfg(feature = "force-safe")]
+pub use self::force_safe::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{check_merge2, DropCounter};
+    use quickcheck_macros::quickcheck;
+
+    /// Runs `merge` with a comparator that panics after `fuel` comparisons.
+    ///
+    /// Returns `true` if every input element was dropped exactly once and, if the merge finished
+    /// without panicking, its output was correct.
+    fn check_panic_safety(
+        mut a: Vec<usize>,
+        mut b: Vec<usize>,
+        fuel: usize,
+        merge: impl FnOnce(Vec<DropCounter<usize>>, Vec<DropCounter<usize>>) -> Vec<DropCounter<usize>>,
+    ) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        match check_merge2(a, b, fuel, merge) {
+            Some(out) => out == expected,
+            None => true,
+        }
+    }
+
+    #[quickcheck]
+    fn exact_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual = exact(a, b);
+        actual.capacity() == actual.len() && expected == actual
+    }
+
+    #[quickcheck]
+    fn into_iter_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = into_iter(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn merge_sources_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let refs: Vec<&usize> = merge_sources(a.iter(), b.iter());
+        let refs_ok = refs.into_iter().eq(&expected);
+
+        let boxed = IntoIterator::into_iter(b.into_boxed_slice());
+        refs_ok && merge_sources(a.into_iter(), boxed) == expected
+    }
+
+    #[test]
+    fn merge_sources_arrays() {
+        let a = IntoIterator::into_iter([1, 3]);
+        let b = IntoIterator::into_iter([2, 3]);
+        assert_eq!(merge_sources(a, b), [1, 2, 3]);
+    }
+
+    #[quickcheck]
+    fn old_datafrog_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        dbg!(&a, &b);
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = old_datafrog(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn raw_ptr_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        dbg!(&a, &b);
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = raw_ptr(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn index_gather_impl(mut a: Vec<(u8, String)>, mut b: Vec<(u8, String)>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = index_gather(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn branchless_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = branchless(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn raw_ptr_copied_impl(mut a: Vec<(u8, u8)>, mut b: Vec<(u8, u8)>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = raw_ptr_copied(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn raw_ptr_copied_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        let expected = usize::from(!a.is_empty() || !b.is_empty());
+        let (a, b) = (a.len().min(1), b.len().min(1));
+        raw_ptr_copied(vec![(); a], vec![(); b]).len() == expected
+    }
+
+    #[quickcheck]
+    fn galloping_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = galloping(a, b);
+        expected == actual
+    }
+
+    #[cfg(not(feature = "force-safe"))]
+    #[test]
+    fn raw_capacity_bounds() {
+        type Big = [u8; 1 << 20];
+        let max = isize::MAX as usize >> 20;
+        assert_eq!(raw_capacity::<Big>(max - 3, 3), max);
+        assert_eq!(raw_capacity::<()>(usize::MAX, usize::MAX), 0);
+
+        let overflows = |a, b| std::panic::catch_unwind(|| raw_capacity::<Big>(a, b)).is_err();
+        assert!(overflows(max, 1));
+        assert!(overflows(usize::MAX, 1));
+    }
+
+    #[quickcheck]
+    fn adaptive_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = adaptive(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn adaptive_blocks(blocks: Vec<(u8, bool)>) -> bool {
+        // Consecutive values go to the same input in blocks, some of them long enough to gallop
+        // over, and some values go to both.
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        let mut next = 0usize;
+        for (len, to_a) in blocks {
+            for _ in 0..len {
+                match (to_a, next % 5) {
+                    (_, 0) => {
+                        a.push(next);
+                        b.push(next);
+                    }
+                    (true, _) => a.push(next),
+                    (false, _) => b.push(next),
+                }
+                next += 1;
+            }
+        }
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        adaptive(a, b) == expected
+    }
+
+    #[quickcheck]
+    fn into_iter_skewed(mut b: Vec<usize>) -> bool {
+        // Long enough that `into_iter` dispatches to `galloping`.
+        let a: Vec<usize> = (0..10_000).step_by(3).collect();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = into_iter(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn in_place_impl(mut a: Vec<usize>, mut b: Vec<usize>, spare: bool) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        // Exercise both the in-place path and the allocating fallback.
+        if spare {
+            a.reserve_exact(b.len());
+        } else {
+            a.shrink_to_fit();
+        }
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        in_place(&mut a, b);
+        expected == a
+    }
+
+    #[quickcheck]
+    fn binary_insert_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        binary_insert(&mut a, b);
+        expected == a
+    }
+
+    #[quickcheck]
+    fn merge_adjacent_uniq_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+
+        let mid = a.len();
+        let mut actual = a;
+        actual.extend(b);
+        merge_adjacent_uniq(&mut actual, mid);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn low_memory_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_> = low_memory(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn from_slices_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        expected == from_slices(&a, &b) && expected == from_slices_copied(&a, &b)
+    }
+
+    // Reading back the output requires unsafe code.
+    #[cfg(not(feature = "force-safe"))]
+    #[quickcheck]
+    fn merge_into_uninit_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+
+        let mut out: Vec<usize> = Vec::with_capacity(a.len() + b.len());
+        let len = merge_into_uninit(a, b, out.spare_capacity_mut());
+        unsafe { out.set_len(len) };
+        expected == out
+    }
+
+    #[quickcheck]
+    fn into_iter_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, into_iter)
+    }
+
+    #[quickcheck]
+    fn raw_ptr_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, raw_ptr)
+    }
+
+    #[quickcheck]
+    fn index_gather_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, index_gather)
+    }
+
+    #[quickcheck]
+    fn branchless_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, branchless)
+    }
+
+    #[quickcheck]
+    fn adaptive_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, adaptive)
+    }
+
+    #[quickcheck]
+    fn galloping_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, galloping)
+    }
+
+    #[quickcheck]
+    fn in_place_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, |mut a, b| {
+            a.reserve_exact(b.len());
+            in_place(&mut a, b);
+            a
+        })
+    }
+
+    #[quickcheck]
+    fn binary_insert_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, |mut a, b| {
+            binary_insert(&mut a, b);
+            a
+        })
+    }
+
+    #[quickcheck]
+    fn merge_adjacent_uniq_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, |mut a, b| {
+            let mid = a.len();
+            a.extend(b);
+            merge_adjacent_uniq(&mut a, mid);
+            a
+        })
+    }
+
+    #[quickcheck]
+    fn append_merged_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, |mut a, mut b| {
+            let mut out = Vec::new();
+            append_merged(&mut out, &mut a, &mut b);
+            out
+        })
+    }
+
+    #[quickcheck]
+    fn from_slices_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, |a, b| from_slices(&a, &b))
+    }
+
+    // Reading back the output requires unsafe code.
+    #[cfg(not(feature = "force-safe"))]
+    #[quickcheck]
+    fn merge_into_uninit_panic_safety(a: Vec<usize>, b: Vec<usize>, fuel: usize) -> bool {
+        check_panic_safety(a, b, fuel, |a, b| {
+            let mut out = Vec::with_capacity(a.len() + b.len());
+            let len = merge_into_uninit(a, b, out.spare_capacity_mut());
+            unsafe { out.set_len(len) };
+            out
+        })
+    }
+
+    /// Checks a kernel against `naive` on sorted, deduplicated inputs of a zero-sized type, and
+    /// checks that it agrees with the length arithmetic of the linear merge loop on arbitrary ones.
+    fn check_zst(a: Vec<()>, b: Vec<()>, merge: impl Fn(Vec<()>, Vec<()>) -> Vec<()>) -> bool {
+        // Inputs with duplicates aren't allowed under `debug-validate`, which would panic here.
+        let any_len_ok = cfg!(all(feature = "debug-validate", debug_assertions))
+            || merge(a.clone(), b.clone()).len() == a.len().max(b.len());
+
+        let a: Vec<_> = a.into_iter().take(1).collect();
+        let b: Vec<_> = b.into_iter().take(1).collect();
+        let expected = naive(a.clone(), b.clone());
+        let actual = merge(a, b);
+
+        expected == actual && any_len_ok
+    }
+
+    #[quickcheck]
+    fn into_iter_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, into_iter)
+    }
+
+    #[quickcheck]
+    fn raw_ptr_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, raw_ptr)
+    }
+
+    #[quickcheck]
+    fn index_gather_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, index_gather)
+    }
+
+    #[quickcheck]
+    fn branchless_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, branchless)
+    }
+
+    #[quickcheck]
+    fn adaptive_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, adaptive)
+    }
+
+    #[quickcheck]
+    fn galloping_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, galloping)
+    }
+
+    #[quickcheck]
+    fn in_place_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, |mut a, b| {
+            in_place(&mut a, b);
+            a
+        })
+    }
+
+    #[quickcheck]
+    fn binary_insert_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, |mut a, b| {
+            binary_insert(&mut a, b);
+            a
+        })
+    }
+
+    #[quickcheck]
+    fn merge_adjacent_uniq_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, |mut a, b| {
+            let mid = a.len();
+            a.extend(b);
+            merge_adjacent_uniq(&mut a, mid);
+            a
+        })
+    }
+
+    #[quickcheck]
+    fn append_merged_zst_impl(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, |mut a, mut b| {
+            let mut out = Vec::new();
+            append_merged(&mut out, &mut a, &mut b);
+            out
+        })
+    }
+
+    #[quickcheck]
+    fn from_slices_zst_impl(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a.clone(), b.clone(), |a, b| from_slices(&a, &b))
+            && check_zst(a, b, |a, b| from_slices_copied(&a, &b))
+    }
+
+    // Reading back the output requires unsafe code.
+    #[cfg(not(feature = "force-safe"))]
+    #[quickcheck]
+    fn merge_into_uninit_zst(a: Vec<()>, b: Vec<()>) -> bool {
+        check_zst(a, b, |a, b| {
+            let mut out = vec![MaybeUninit::uninit(); a.len() + b.len()];
+            let len = merge_into_uninit(a, b, &mut out);
+            out.truncate(len);
+            out.into_iter()
+                .map(|x| unsafe { x.assume_init() })
+                .collect()
+        })
+    }
+
+    /// Returns a vector of `len` zero-sized elements without looping over them.
+    ///
+    /// Only vectors of zero-sized types are long enough for their lengths to add up past
+    /// `usize::MAX`, so these are what the overflow checks are tested with.
+    #[cfg(not(feature = "force-safe"))]
+    #[allow(clippy::uninit_vec)]
+    fn units(len: usize) -> Vec<()> {
+        let mut v = Vec::new();
+        // SAFETY: The capacity of a vector of zero-sized elements is `usize::MAX`, and there is
+        // nothing to initialize.
+        unsafe { v.set_len(len) };
+        v
+    }
+
+    #[cfg(not(feature = "force-safe"))]
+    #[test]
+    #[should_panic(expected = "output buffer is too small")]
+    fn merge_into_uninit_len_overflow() {
+        let mut out = [MaybeUninit::uninit(); 1];
+        merge_into_uninit(units(usize::MAX), units(2), &mut out);
+    }
+
+    // Runs of many copies of `()` aren't deduplicated, so validation would reject them first.
+    #[cfg(not(any(
+        feature = "force-safe",
+        all(feature = "debug-validate", debug_assertions)
+    )))]
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn into_iter_len_overflow() {
+        into_iter(units(usize::MAX), units(usize::MAX));
+    }
+
+    // Runs of many copies of `()` aren't deduplicated, so validation would reject them first.
+    #[cfg(not(any(
+        feature = "force-safe",
+        all(feature = "debug-validate", debug_assertions)
+    )))]
+    #[test]
+    fn raw_ptr_len_overflow() {
+        // Each element of `b` is a duplicate of one in `a`, so the output is no longer than `a`.
+        let out = raw_ptr(units(usize::MAX), units(usize::MAX));
+        assert_eq!(out.len(), usize::MAX);
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[quickcheck]
+    fn raw_ptr_in_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        use std::alloc::Global;
+
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+        let actual: Vec<_, Global> = raw_ptr_in(a, b, Global);
         expected == actual
     }
 }
@@ -0,0 +1,216 @@
+//! Merging three sorted, deduplicated vectors in a single pass.
+//!
+//! Merging previous state with two deltas is common enough to deserve its own kernel. Chaining two
+//! two-way merges moves every element of the first pair twice, and the loser tree in `kway` has
+//! more bookkeeping per element than three inputs need.
+
+#[cfg(not(feature = "force-safe"))]
+use std::cmp::Ordering;
+
+#[cfg(not(feature = "force-safe"))]
+use crate::merge2_uniq::{self, merge_raw, prefetch, RawIter, Written};
+
+/// Merges `a`, `b` and `c` with a raw-pointer loop that keeps a cursor into each.
+///
+/// Each step finds the smallest head with a two-comparison network: the heads of `a` and `b` are
+/// compared, then the smaller one is compared with the head of `c`. Of several equal elements,
+/// the one from the earliest input is kept. Once any input runs out, the other two are finished
+/// with the two-way `merge2_uniq::raw_ptr` loop.
+#[cfg(not(feature = "force-safe"))]
+pub fn raw_ptr<T: Ord>(mut a: Vec<T>, mut b: Vec<T>, mut c: Vec<T>) -> Vec<T> {
+    crate::checked::debug_validate(&[&a, &b, &c]);
+    if std::mem::size_of::<T>() == 0 || a.is_empty() || b.is_empty() || c.is_empty() {
+        return merge2_uniq::raw_ptr(merge2_uniq::raw_ptr(a, b), c);
+    }
+
+    let mut out: Vec<T> = Vec::with_capacity(a.len() + b.len() + c.len());
+
+    // As in `merge2_uniq::raw_ptr`, these guards clean up after a panicking comparison.
+    let mut ait = unsafe { RawIter::from_vec(&mut a) };
+    let mut bit = unsafe { RawIter::from_vec(&mut b) };
+    let mut cit = unsafe { RawIter::from_vec(&mut c) };
+    let mut o = Written::new(out.as_mut_ptr());
+
+    while !ait.is_empty() && !bit.is_empty() && !cit.is_empty() {
+        prefetch(ait.start);
+        prefetch(bit.start);
+        prefetch(cit.start);
+
+        unsafe {
+            let ab = (*ait.start).cmp(&*bit.start);
+            let min = if ab == Ordering::Greater {
+                bit.start
+            } else {
+                ait.start
+            };
+
+            match (*min).cmp(&*cit.start) {
+                Ordering::Greater => {
+                    o.push(cit.start);
+                    cit.advance();
+                }
+                ord => {
+                    o.push(min);
+
+                    // Advance past the head that was moved, and drop any heads equal to it.
+                    match ab {
+                        Ordering::Less => ait.advance(),
+                        Ordering::Greater => bit.advance(),
+                        Ordering::Equal => {
+                            ait.advance();
+                            let dup = bit.start;
+                            bit.advance();
+                            std::ptr::drop_in_place(dup);
+                        }
+                    }
+                    if ord == Ordering::Equal {
+                        let dup = cit.start;
+                        cit.advance();
+                        std::ptr::drop_in_place(dup);
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe {
+        // Finish the two inputs that remain, keeping them in order so ties still go to the earlier
+        // one.
+        if ait.is_empty() {
+            merge_raw(&mut bit, &mut cit, &mut o);
+        } else if bit.is_empty() {
+            merge_raw(&mut ait, &mut cit, &mut o);
+        } else {
+            merge_raw(&mut ait, &mut bit, &mut o);
+        }
+
+        out.set_len(o.finish());
+    }
+
+    out
+}
+
+/// Safe stand-in for `raw_ptr`, used when the `force-safe` feature forbids unsafe code.
+#[cfg(feature = "force-safe")]
+pub fn raw_ptr<T: Ord>(a: Vec<T>, b: Vec<T>, c: Vec<T>) -> Vec<T> {
+    use crate::merge2_uniq::into_iter;
+
+    into_iter(into_iter(a, b), c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::panic::{self, AssertUnwindSafe};
+
+    thread_local! {
+        static LIVE: Cell<isize> = const { Cell::new(0) };
+        static CMP_FUEL: Cell<usize> = const { Cell::new(usize::MAX) };
+    }
+
+    /// An element that remembers which input it came from, but compares by `value` alone. Tracks
+    /// how many are alive, and panics on comparison once `CMP_FUEL` runs out.
+    #[derive(Debug)]
+    struct Tagged {
+        value: u8,
+        input: usize,
+    }
+
+    impl Tagged {
+        fn new(value: u8, input: usize) -> Self {
+            LIVE.with(|live| live.set(live.get() + 1));
+            Tagged { value, input }
+        }
+    }
+
+    impl Drop for Tagged {
+        fn drop(&mut self) {
+            LIVE.with(|live| live.set(live.get() - 1));
+        }
+    }
+
+    impl Ord for Tagged {
+        fn cmp(&self, other: &Self) -> Ordering {
+            CMP_FUEL.with(|fuel| match fuel.get() {
+                0 => panic!("comparison fuel exhausted"),
+                n => fuel.set(n - 1),
+            });
+            self.value.cmp(&other.value)
+        }
+    }
+
+    impl PartialOrd for Tagged {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Self) -> bool {
+            self.value == other.value
+        }
+    }
+
+    impl Eq for Tagged {}
+
+    fn tagged(runs: &[Vec<u8>; 3]) -> Vec<Vec<Tagged>> {
+        (0..3)
+            .map(|i| runs[i].iter().map(|&x| Tagged::new(x, i)).collect())
+            .collect()
+    }
+
+    #[quickcheck]
+    fn raw_ptr_impl(a: Vec<u8>, b: Vec<u8>, c: Vec<u8>) -> bool {
+        let runs = [sorted_unique(a), sorted_unique(b), sorted_unique(c)];
+        let expected = sorted_unique(runs.concat());
+
+        let mut inputs = tagged(&runs);
+        let (c, b, a) = (
+            inputs.pop().unwrap(),
+            inputs.pop().unwrap(),
+            inputs.pop().unwrap(),
+        );
+        let actual = raw_ptr(a, b, c);
+
+        // Each element must come from the earliest input that has it.
+        let earliest = actual
+            .iter()
+            .all(|x| !runs[..x.input].iter().any(|run| run.contains(&x.value)));
+        earliest && actual.iter().map(|x| x.value).eq(expected)
+    }
+
+    #[quickcheck]
+    fn raw_ptr_panic_safety(a: Vec<u8>, b: Vec<u8>, c: Vec<u8>, fuel: usize) -> bool {
+        let runs = [sorted_unique(a), sorted_unique(b), sorted_unique(c)];
+        let expected = sorted_unique(runs.concat());
+
+        LIVE.with(|live| live.set(0));
+        let mut inputs = tagged(&runs);
+        let (c, b, a) = (
+            inputs.pop().unwrap(),
+            inputs.pop().unwrap(),
+            inputs.pop().unwrap(),
+        );
+
+        CMP_FUEL.with(|f| f.set(fuel % 64));
+        let res = panic::catch_unwind(AssertUnwindSafe(|| raw_ptr(a, b, c)));
+        CMP_FUEL.with(|f| f.set(usize::MAX));
+
+        let output_ok = match res {
+            Ok(out) => out.iter().map(|x| x.value).eq(expected),
+            Err(_) => true,
+        };
+        output_ok && LIVE.with(|live| live.get()) == 0
+    }
+
+    #[quickcheck]
+    fn raw_ptr_zst(a: Vec<()>, b: Vec<()>, c: Vec<()>) -> bool {
+        let expected = usize::from(!a.is_empty() || !b.is_empty() || !c.is_empty());
+        let (a, b, c) = (a.len().min(1), b.len().min(1), c.len().min(1));
+        raw_ptr(vec![(); a], vec![(); b], vec![(); c]).len() == expected
+    }
+}
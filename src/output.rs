@@ -0,0 +1,264 @@
+//! Merge kernels that are generic over where their output goes.
+//!
+//! A kernel written against `MergeOutput` works with every backend that implements it: `Vec`,
+//! `SegmentedVec`, `SmallVec` (with the `smallvec` feature), an uninitialized slice through
+//! `UninitSlice`, and any `kway::MergeSink` through `SinkOutput`. Backends only have to say how to
+//! append one element and, if they can do better than one at a time, how to append what is left of
+//! an input once the other is exhausted.
+
+use crate::kway::{KMergeUniq, MergeSink};
+use crate::segmented::SegmentedVec;
+use std::cmp::Ordering;
+use std::mem::MaybeUninit;
+use std::vec;
+
+/// A container that merged elements can be appended to, in order.
+pub trait MergeOutput<T> {
+    /// Makes room for `additional` more elements, if the container has a notion of capacity. Does
+    /// nothing by default.
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// Appends the next merged element.
+    fn push(&mut self, x: T);
+
+    /// Appends the remaining elements of one input, all of which come after everything pushed so
+    /// far. By default, pushes each of them in turn.
+    fn extend_run(&mut self, rest: vec::IntoIter<T>) {
+        for x in rest {
+            self.push(x);
+        }
+    }
+}
+
+impl<T> MergeOutput<T> for Vec<T> {
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional);
+    }
+
+    fn push(&mut self, x: T) {
+        Vec::push(self, x);
+    }
+
+    fn extend_run(&mut self, rest: vec::IntoIter<T>) {
+        self.extend(rest);
+    }
+}
+
+impl<T> MergeOutput<T> for SegmentedVec<T> {
+    fn push(&mut self, x: T) {
+        SegmentedVec::push(self, x);
+    }
+
+    fn extend_run(&mut self, rest: vec::IntoIter<T>) {
+        self.extend(rest);
+    }
+}
+
+#[cfg(feature = "smallvec")]
+impl<A: smallvec::Array> MergeOutput<A::Item> for smallvec::SmallVec<A> {
+    fn reserve(&mut self, additional: usize) {
+        smallvec::SmallVec::reserve(self, additional);
+    }
+
+    fn push(&mut self, x: A::Item) {
+        smallvec::SmallVec::push(self, x);
+    }
+
+    fn extend_run(&mut self, rest: vec::IntoIter<A::Item>) {
+        self.extend(rest);
+    }
+}
+
+/// Writes merged elements into the front of an uninitialized slice, like
+/// `merge_uniq_into_uninit`.
+///
+/// The elements written stay in the slice when the writer is dropped, and the caller becomes
+/// responsible for dropping them. `len` tells how many there are.
+#[derive(Debug)]
+pub struct UninitSlice<'a, T> {
+    out: &'a mut [MaybeUninit<T>],
+    len: usize,
+}
+
+impl<'a, T> UninitSlice<'a, T> {
+    pub fn new(out: &'a mut [MaybeUninit<T>]) -> Self {
+        UninitSlice { out, len: 0 }
+    }
+
+    /// Returns the number of elements written to the front of the slice.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> MergeOutput<T> for UninitSlice<'_, T> {
+    /// # Panics
+    ///
+    /// Panics if the slice doesn't have room for `additional` more elements. The kernels reserve
+    /// room for both inputs before writing anything, so nothing is written in that case.
+    fn reserve(&mut self, additional: usize) {
+        assert!(
+            self.out.len() - self.len >= additional,
+            "output buffer is too small for the merged inputs"
+        );
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the slice is full.
+    fn push(&mut self, x: T) {
+        self.out[self.len] = MaybeUninit::new(x);
+        self.len += 1;
+    }
+}
+
+/// Passes merged elements to a `kway::MergeSink` by reference, dropping each one afterwards.
+///
+/// The rest of an input is passed to `MergeSink::extend_from_slice` in one call.
+#[derive(Debug)]
+pub struct SinkOutput<'s, S: ?Sized>(pub &'s mut S);
+
+impl<T, S: MergeSink<T> + ?Sized> MergeOutput<T> for SinkOutput<'_, S> {
+    fn push(&mut self, x: T) {
+        self.0.push(&x);
+    }
+
+    fn extend_run(&mut self, rest: vec::IntoIter<T>) {
+        self.0.extend_from_slice(rest.as_slice());
+    }
+}
+
+/// Merges two sorted, deduplicated vectors into `out`. When an element appears in both, the one
+/// from `a` is kept.
+pub fn merge_uniq_into<T, O>(a: Vec<T>, b: Vec<T>, out: &mut O)
+where
+    T: Ord,
+    O: MergeOutput<T> + ?Sized,
+{
+    crate::checked::debug_validate(&[&a, &b]);
+    out.reserve(a.len().checked_add(b.len()).expect("capacity overflow"));
+
+    let mut a = a.into_iter();
+    let mut b = b.into_iter();
+    while let (Some(x), Some(y)) = (a.as_slice().first(), b.as_slice().first()) {
+        match x.cmp(y) {
+            Ordering::Less => out.push(a.next().unwrap()),
+            Ordering::Greater => out.push(b.next().unwrap()),
+            Ordering::Equal => {
+                out.push(a.next().unwrap());
+                b.next();
+            }
+        }
+    }
+
+    // At most one of `a` and `b` still has elements.
+    out.extend_run(a);
+    out.extend_run(b);
+}
+
+/// Merges any number of sorted, deduplicated vectors into `out`. Of several equal elements, the
+/// one from the earliest run is kept.
+pub fn kmerge_uniq_into<T, O>(runs: Vec<Vec<T>>, out: &mut O)
+where
+    T: Ord,
+    O: MergeOutput<T> + ?Sized,
+{
+    crate::checked::debug_validate(&runs.iter().map(Vec::as_slice).collect::<Vec<_>>());
+    let len = runs
+        .iter()
+        .try_fold(0usize, |len, run| len.checked_add(run.len()))
+        .expect("capacity overflow");
+    out.reserve(len);
+
+    let mut runs: Vec<_> = runs.into_iter().filter(|run| !run.is_empty()).collect();
+    if runs.len() == 1 {
+        return out.extend_run(runs.pop().unwrap().into_iter());
+    }
+    for x in KMergeUniq::new(runs.into_iter().map(Vec::into_iter).collect()) {
+        out.push(x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    fn sorted(mut v: Vec<u8>) -> Vec<u8> {
+        v.sort_unstable();
+        v.dedup();
+        v
+    }
+
+    #[quickcheck]
+    fn merge_uniq_into_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted(a), sorted(b));
+        let expected = naive(a.clone(), b.clone());
+
+        let mut vec = vec![0];
+        merge_uniq_into(a.clone(), b.clone(), &mut vec);
+
+        let mut segmented = SegmentedVec::new(3);
+        merge_uniq_into(a.clone(), b.clone(), &mut segmented);
+
+        let mut sink = Vec::new();
+        merge_uniq_into(a, b, &mut SinkOutput(&mut sink));
+
+        vec[1..] == expected[..] && segmented.iter().eq(&expected) && sink == expected
+    }
+
+    #[quickcheck]
+    fn kmerge_uniq_into_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted).collect();
+        let expected = sorted(runs.concat());
+
+        let mut vec = Vec::new();
+        kmerge_uniq_into(runs.clone(), &mut vec);
+
+        let mut segmented = SegmentedVec::new(3);
+        kmerge_uniq_into(runs, &mut segmented);
+
+        vec == expected && segmented.iter().eq(&expected)
+    }
+
+    // Reading back the output requires unsafe code.
+    #[cfg(not(feature = "force-safe"))]
+    #[quickcheck]
+    fn merge_uniq_into_uninit_slice(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted(a), sorted(b));
+        let expected = naive(a.clone(), b.clone());
+
+        let mut buf = vec![MaybeUninit::uninit(); a.len() + b.len()];
+        let mut slice = UninitSlice::new(&mut buf);
+        merge_uniq_into(a, b, &mut slice);
+        let len = slice.len();
+        let written: Vec<_> = buf[..len]
+            .iter()
+            .map(|x| unsafe { x.assume_init() })
+            .collect();
+        written == expected
+    }
+
+    #[cfg(feature = "smallvec")]
+    #[quickcheck]
+    fn merge_uniq_into_smallvec(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted(a), sorted(b));
+        let expected = naive(a.clone(), b.clone());
+
+        let mut out = smallvec::SmallVec::<[u8; 8]>::new();
+        merge_uniq_into(a, b, &mut out);
+        out[..] == expected[..]
+    }
+
+    #[test]
+    #[should_panic(expected = "output buffer is too small")]
+    fn uninit_slice_too_small() {
+        let mut buf = [MaybeUninit::uninit(); 2];
+        merge_uniq_into(vec![1, 2], vec![3], &mut UninitSlice::new(&mut buf));
+    }
+}
@@ -0,0 +1,876 @@
+//! Parallel merging.
+//!
+//! A large merge is split into independent parts with the merge-path technique: a binary search
+//! along each of a few evenly spaced diagonals of the (conceptual) merge matrix finds how many
+//! elements of each input precede that point in the output. The parts are then merged
+//! concurrently, each into its own region of a preallocated output buffer.
+//!
+//! Merges of more than two inputs are split by multi-sequence selection instead, which finds a
+//! key for each split such that the right number of elements precede it across all inputs.
+//!
+//! The output doesn't depend on the number of threads or on the order the parts run in. All copies
+//! of an element go to the same part, and each part keeps the same copy the sequential merge would,
+//! so every function here returns exactly what its sequential counterpart does, down to which of
+//! several equal elements is kept. Merged artifacts are reproducible across machines with different
+//! numbers of cores.
+//!
+//! The parts run on an `Executor`. With the `rayon` feature, `Rayon` runs them on the rayon thread
+//! pool. With the `threads` feature, `ScopedThreads` runs them on threads spawned with
+//! `std::thread::scope`, for those who can't depend on rayon. The functions without an explicit
+//! executor use `Rayon` if it's available and `ScopedThreads` otherwise.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+#[cfg(any(feature = "threads", not(feature = "force-safe")))]
+use std::panic;
+#[cfg(not(feature = "force-safe"))]
+use std::panic::AssertUnwindSafe;
+#[cfg(any(feature = "threads", not(feature = "force-safe")))]
+use std::thread;
+
+use crate::kway;
+#[cfg(not(feature = "force-safe"))]
+use crate::merge2_uniq;
+use crate::partition::{radix_split_points, select_nth, split_points, RadixKey};
+
+/// Merges with fewer elements than this per thread are not worth splitting up.
+const MIN_PART_LEN: usize = 1 << 14;
+
+const LOST_TASK: &str = "the executor didn't return the result of every task";
+
+/// Runs the parts of a parallel merge.
+pub trait Executor {
+    /// Returns the number of parts to split a large merge into.
+    fn num_threads(&self) -> usize;
+
+    /// Calls `f` on every task, potentially in parallel, and returns the results in order.
+    ///
+    /// If `f` panics, the panic is propagated once every task has finished.
+    ///
+    /// The merges don't rely on this for soundness: each task reports which part it was, so
+    /// results returned in another order are put back in place, and a merge whose executor drops a
+    /// task without running it panics.
+    fn map<T, R, F>(&self, tasks: Vec<T>, f: F) -> Vec<R>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(T) -> R + Send + Sync;
+}
+
+/// Runs tasks on the global rayon thread pool.
+#[cfg(feature = "rayon")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rayon;
+
+#[cfg(feature = "rayon")]
+impl Executor for Rayon {
+    fn num_threads(&self) -> usize {
+        rayon::current_num_threads()
+    }
+
+    fn map<T, R, F>(&self, tasks: Vec<T>, f: F) -> Vec<R>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(T) -> R + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        tasks.into_par_iter().map(f).collect()
+    }
+}
+
+/// Runs each task on its own thread, spawned with `std::thread::scope`. The first task runs on the
+/// calling thread.
+#[cfg(feature = "threads")]
+#[derive(Clone, Copy, Debug)]
+pub struct ScopedThreads {
+    threads: usize,
+}
+
+#[cfg(feature = "threads")]
+impl ScopedThreads {
+    /// Returns an executor that splits merges across `threads` threads, including the calling one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "an executor needs at least one thread");
+        ScopedThreads { threads }
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Default for ScopedThreads {
+    /// Uses as many threads as `std::thread::available_parallelism` reports.
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        ScopedThreads::new(threads)
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Executor for ScopedThreads {
+    fn num_threads(&self) -> usize {
+        self.threads
+    }
+
+    fn map<T, R, F>(&self, tasks: Vec<T>, f: F) -> Vec<R>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(T) -> R + Send + Sync,
+    {
+        let f = &f;
+        thread::scope(|scope| {
+            let mut tasks = tasks.into_iter();
+            let first = tasks.next();
+            let handles: Vec<_> = tasks.map(|task| scope.spawn(move || f(task))).collect();
+
+            let mut results = Vec::with_capacity(handles.len() + 1);
+            results.extend(first.map(f));
+            for handle in handles {
+                match handle.join() {
+                    Ok(res) => results.push(res),
+                    Err(payload) => panic::resume_unwind(payload),
+                }
+            }
+            results
+        })
+    }
+}
+
+#[cfg(feature = "rayon")]
+pub(crate) fn default_executor() -> Rayon {
+    Rayon
+}
+
+#[cfg(not(feature = "rayon"))]
+pub(crate) fn default_executor() -> ScopedThreads {
+    ScopedThreads::default()
+}
+
+/// Merges two sorted, deduplicated vectors using every thread of the default executor.
+///
+/// When an element appears in both, the one from `a` is kept, as in `crate::merge_uniq`. Small
+/// inputs are merged on the current thread with `crate::merge_uniq`.
+pub fn merge_uniq<T: Ord + Send>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    merge_uniq_with(&default_executor(), a, b)
+}
+
+/// Like `merge_uniq`, but runs on `exec`.
+pub fn merge_uniq_with<T: Ord + Send, E: Executor>(exec: &E, a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    let parts = exec.num_threads().min((a.len() + b.len()) / MIN_PART_LEN);
+    merge_uniq_in_parts(exec, a, b, parts)
+}
+
+pub(crate) fn merge_uniq_in_parts<T: Ord + Send, E: Executor>(
+    exec: &E,
+    a: Vec<T>,
+    b: Vec<T>,
+    parts: usize,
+) -> Vec<T> {
+    if parts <= 1 {
+        return crate::merge_uniq(a, b);
+    }
+
+    let splits = split_points(&a, &b, parts);
+    merge_uniq_split(exec, a, b, &splits)
+}
+
+/// Merges each part between consecutive `splits` of `a` and `b` as a task on `exec`, and joins
+/// the results. See `split_points` for what the splits must satisfy.
+#[cfg(not(feature = "force-safe"))]
+fn merge_uniq_split<T: Ord + Send, E: Executor>(
+    exec: &E,
+    mut a: Vec<T>,
+    mut b: Vec<T>,
+    splits: &[(usize, usize)],
+) -> Vec<T> {
+    if splits.len() <= 2 || std::mem::size_of::<T>() == 0 {
+        return crate::merge_uniq(a, b);
+    }
+
+    let mut out: Vec<T> = Vec::with_capacity(a.len() + b.len());
+
+    // The tasks take ownership of the input elements. The allocations of `a` and `b` are freed
+    // when they go out of scope, along with `out` if a task panics.
+    let tasks: Vec<_> = unsafe {
+        let (ap, bp, op) = (a.as_mut_ptr(), b.as_mut_ptr(), out.as_mut_ptr());
+        a.set_len(0);
+        b.set_len(0);
+
+        splits
+            .windows(2)
+            .enumerate()
+            .map(|(part, w)| {
+                let ((i0, j0), (i1, j1)) = (w[0], w[1]);
+                Task {
+                    part,
+                    a: ap.add(i0),
+                    alen: i1 - i0,
+                    b: bp.add(j0),
+                    blen: j1 - j0,
+                    out: op.add(i0 + j0),
+                }
+            })
+            .collect()
+    };
+
+    let results = in_order(splits.len() - 1, exec.map(tasks, Task::run));
+
+    unsafe {
+        // Each part is written at the position of its first input element, so there are gaps
+        // between them wherever duplicates were dropped. Close them up, or drop the output of
+        // every part that finished if another one panicked or never ran.
+        let mut len = 0;
+        let mut panicked = None;
+        for (w, res) in splits.windows(2).zip(results) {
+            let start = out.as_mut_ptr().add(w[0].0 + w[0].1);
+            let res = res.unwrap_or_else(|| Err(Box::new(LOST_TASK)));
+            match res {
+                Ok(n) if panicked.is_none() => {
+                    std::ptr::copy(start, out.as_mut_ptr().add(len), n);
+                    len += n;
+                }
+                Ok(n) => std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(start, n)),
+                Err(payload) => {
+                    std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(
+                        out.as_mut_ptr(),
+                        len,
+                    ));
+                    len = 0;
+                    panicked.get_or_insert(payload);
+                }
+            }
+        }
+
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+        out.set_len(len);
+    }
+
+    out
+}
+
+/// Safe stand-in for the unsafe `merge_uniq_split`, used when the `force-safe` feature forbids
+/// unsafe code. The inputs are split into owned chunks, which costs an extra copy of each element.
+#[cfg(feature = "force-safe")]
+fn merge_uniq_split<T: Ord + Send, E: Executor>(
+    exec: &E,
+    mut a: Vec<T>,
+    mut b: Vec<T>,
+    splits: &[(usize, usize)],
+) -> Vec<T> {
+    if splits.len() <= 2 {
+        return crate::merge_uniq(a, b);
+    }
+
+    let parts = splits.len() - 1;
+    let mut chunks: Vec<_> = splits[1..parts]
+        .iter()
+        .rev()
+        .map(|&(i, j)| (a.split_off(i), b.split_off(j)))
+        .collect();
+    chunks.push((a, b));
+    chunks.reverse();
+
+    let merged = exec.map(chunks.into_iter().enumerate().collect(), |(i, (a, b))| {
+        (i, crate::merge_uniq(a, b))
+    });
+    concat_parts(parts, merged)
+}
+
+/// Merges two sorted, deduplicated vectors of integers using every thread of the default
+/// executor, splitting them by the top bits of their keys with `radix_split_points`.
+///
+/// Splitting by value takes one binary search per input for each part, and parts of keys spread
+/// evenly over their range come out about equal. Keys that cluster leave some threads with more
+/// work than others, and `merge_uniq` suits them better. The output is the same either way.
+pub fn merge_uniq_radix<T: RadixKey + Send>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    merge_uniq_radix_with(&default_executor(), a, b)
+}
+
+/// Like `merge_uniq_radix`, but runs on `exec`.
+pub fn merge_uniq_radix_with<T: RadixKey + Send, E: Executor>(
+    exec: &E,
+    a: Vec<T>,
+    b: Vec<T>,
+) -> Vec<T> {
+    let parts = exec.num_threads().min((a.len() + b.len()) / MIN_PART_LEN);
+    merge_uniq_radix_in_parts(exec, a, b, parts)
+}
+
+/// Splits the merge into the least power of two parts that is at least `parts`.
+fn merge_uniq_radix_in_parts<T: RadixKey + Send, E: Executor>(
+    exec: &E,
+    a: Vec<T>,
+    b: Vec<T>,
+    parts: usize,
+) -> Vec<T> {
+    if parts <= 1 {
+        return crate::merge_uniq(a, b);
+    }
+
+    let bits = parts.next_power_of_two().trailing_zeros().min(16);
+    let splits = radix_split_points(&a, &b, bits);
+    merge_uniq_split(exec, a, b, &splits)
+}
+
+/// Merges any number of sorted, deduplicated vectors using every thread of the default executor.
+///
+/// When an element appears in several inputs, the one from the earliest input is kept. Small
+/// inputs are merged on the current thread with `kway::merge_uniq`.
+pub fn merge_k_uniq<T: Ord + Send>(runs: Vec<Vec<T>>) -> Vec<T> {
+    merge_k_uniq_with(&default_executor(), runs)
+}
+
+/// Like `merge_k_uniq`, but runs on `exec`.
+pub fn merge_k_uniq_with<T: Ord + Send, E: Executor>(exec: &E, runs: Vec<Vec<T>>) -> Vec<T> {
+    let len: usize = runs.iter().map(Vec::len).sum();
+    let parts = exec.num_threads().min(len / MIN_PART_LEN);
+    merge_k_uniq_in_parts(exec, runs, parts)
+}
+
+fn merge_k_uniq_in_parts<T: Ord + Send, E: Executor>(
+    exec: &E,
+    mut runs: Vec<Vec<T>>,
+    parts: usize,
+) -> Vec<T> {
+    if parts <= 1 {
+        return kway::merge_uniq(runs);
+    }
+
+    // Split every run into one owned chunk per part, back to front so that each `split_off` only
+    // copies a single chunk.
+    let splits = k_split_points(&runs, parts);
+    let mut chunks: Vec<Vec<Vec<T>>> = (0..parts).map(|_| Vec::with_capacity(runs.len())).collect();
+    for (i, run) in runs.iter_mut().enumerate() {
+        for p in (1..parts).rev() {
+            chunks[p].push(run.split_off(splits[p - 1][i]));
+        }
+        chunks[0].push(std::mem::take(run));
+    }
+
+    let merged = exec.map(chunks.into_iter().enumerate().collect(), |(i, chunk)| {
+        (i, kway::merge_uniq(chunk))
+    });
+    concat_parts(parts, merged)
+}
+
+/// Puts the results of `parts` tasks, each tagged with the index of its task, back in the order
+/// of the tasks. The result of a task that never ran is `None`.
+fn in_order<R>(parts: usize, results: Vec<(usize, R)>) -> Vec<Option<R>> {
+    let mut ordered: Vec<Option<R>> = (0..parts).map(|_| None).collect();
+    for (i, res) in results {
+        ordered[i] = Some(res);
+    }
+    ordered
+}
+
+/// Concatenates the merged parts, each tagged with its index, in the order of their indices.
+///
+/// # Panics
+///
+/// Panics if a part is missing.
+fn concat_parts<T>(parts: usize, merged: Vec<(usize, Vec<T>)>) -> Vec<T> {
+    let merged = in_order(parts, merged);
+    let mut out = Vec::with_capacity(merged.iter().flatten().map(Vec::len).sum());
+    for part in merged {
+        out.extend(part.expect(LOST_TASK));
+    }
+    out
+}
+
+/// Merges many independent pairs of sorted, deduplicated vectors using every thread of the default
+/// executor, returning the merges in the order of `pairs`.
+///
+/// Rather than running one task per pair, the pairs are packed into one batch per thread, largest
+/// first onto the batch with the least work so far, so each thread gets a single task and about
+/// the same number of elements. A pair with more than its share of the elements would hold up its
+/// batch, so it's split across every thread with `merge_uniq` instead.
+///
+/// A task per pair would cost far more than the merges themselves when the pairs are small.
+/// Batched, the overhead over merging the pairs in turn on the calling thread is small.
+pub fn merge_many<T: Ord + Send>(pairs: Vec<(Vec<T>, Vec<T>)>) -> Vec<Vec<T>> {
+    merge_many_with(&default_executor(), pairs)
+}
+
+/// Like `merge_many`, but runs on `exec`.
+pub fn merge_many_with<T: Ord + Send, E: Executor>(
+    exec: &E,
+    pairs: Vec<(Vec<T>, Vec<T>)>,
+) -> Vec<Vec<T>> {
+    let len: usize = pairs.iter().map(|(a, b)| a.len() + b.len()).sum();
+    let batches = exec.num_threads().min(len / MIN_PART_LEN);
+    merge_many_in_batches(exec, pairs, batches)
+}
+
+fn merge_many_in_batches<T: Ord + Send, E: Executor>(
+    exec: &E,
+    pairs: Vec<(Vec<T>, Vec<T>)>,
+    batches: usize,
+) -> Vec<Vec<T>> {
+    if batches <= 1 {
+        return pairs
+            .into_iter()
+            .map(|(a, b)| crate::merge_uniq(a, b))
+            .collect();
+    }
+
+    let len: usize = pairs.iter().map(|(a, b)| a.len() + b.len()).sum();
+    let mut merged: Vec<Vec<T>> = pairs.iter().map(|_| Vec::new()).collect();
+    let mut small = Vec::with_capacity(pairs.len());
+    for (i, (a, b)) in pairs.into_iter().enumerate() {
+        if a.len() + b.len() > len / batches {
+            merged[i] = merge_uniq_in_parts(exec, a, b, batches);
+        } else {
+            small.push((i, a, b));
+        }
+    }
+
+    // The batch with the least work so far is at the top of the heap.
+    small.sort_unstable_by_key(|(_, a, b)| Reverse(a.len() + b.len()));
+    let mut loads: BinaryHeap<_> = (0..batches).map(|batch| Reverse((0, batch))).collect();
+    let mut tasks: Vec<Vec<_>> = (0..batches).map(|_| Vec::new()).collect();
+    for pair in small {
+        let mut least = loads.peek_mut().unwrap();
+        let Reverse((load, batch)) = *least;
+        *least = Reverse((load + pair.1.len() + pair.2.len(), batch));
+        tasks[batch].push(pair);
+    }
+
+    let results = exec.map(tasks, |task| {
+        task.into_iter()
+            .map(|(i, a, b)| (i, crate::merge_uniq(a, b)))
+            .collect::<Vec<_>>()
+    });
+    for (i, out) in results.into_iter().flatten() {
+        merged[i] = out;
+    }
+    merged
+}
+
+/// Splits the merge of `runs` into `parts` ranges that can be merged independently.
+///
+/// Returns `parts - 1` split points, each holding the index at which to split every run. All
+/// copies of an element end up in the same range.
+fn k_split_points<T: Ord>(runs: &[Vec<T>], parts: usize) -> Vec<Vec<usize>> {
+    let slices: Vec<&[T]> = runs.iter().map(|run| &run[..]).collect();
+    let len: usize = runs.iter().map(Vec::len).sum();
+    (1..parts)
+        .map(|p| match select_nth(&slices, p * len / parts) {
+            Some(key) => runs
+                .iter()
+                .map(|run| run.partition_point(|x| x < key))
+                .collect(),
+            None => vec![0; runs.len()],
+        })
+        .collect()
+}
+
+/// One part of a parallel merge, which owns its input elements until it runs.
+#[cfg(not(feature = "force-safe"))]
+struct Task<T> {
+    /// The index of the task among the parts.
+    part: usize,
+    a: *mut T,
+    alen: usize,
+    b: *mut T,
+    blen: usize,
+    out: *mut T,
+}
+
+// A task moves its elements to whichever thread runs it.
+#[cfg(not(feature = "force-safe"))]
+unsafe impl<T: Send> Send for Task<T> {}
+
+#[cfg(not(feature = "force-safe"))]
+impl<T: Ord> Task<T> {
+    /// Merges the inputs of the task into its output region, returning the index of the task and
+    /// the number of elements written. If a comparison panics, the inputs are dropped and the
+    /// panic is returned.
+    fn run(self) -> (usize, thread::Result<usize>) {
+        let task = std::mem::ManuallyDrop::new(self);
+        let written = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+            merge2_uniq::merge_raw_parts(task.a, task.alen, task.b, task.blen, task.out)
+        }));
+        (task.part, written)
+    }
+}
+
+#[cfg(not(feature = "force-safe"))]
+impl<T> Drop for Task<T> {
+    /// Drops the inputs of a task that never ran.
+    fn drop(&mut self) {
+        unsafe {
+            std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(self.a, self.alen));
+            std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(self.b, self.blen));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kway::ByKey;
+    use crate::merge2_uniq::naive;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[quickcheck]
+    fn merge_uniq_in_parts_impl(a: Vec<usize>, b: Vec<usize>, parts: u8) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+        let parts = parts as usize % 8 + 1;
+
+        let expected = naive(a.clone(), b.clone());
+        let actual = merge_uniq_in_parts(&default_executor(), a, b, parts);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn merge_uniq_impl(a: Vec<usize>, b: Vec<usize>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let expected = naive(a.clone(), b.clone());
+        let actual = merge_uniq(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn merge_uniq_radix_in_parts_impl(a: Vec<u32>, b: Vec<u32>, parts: u8) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+        let parts = parts as usize % 8 + 1;
+
+        let expected = naive(a.clone(), b.clone());
+        let actual = merge_uniq_radix_in_parts(&Reversed, a, b, parts);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn merge_uniq_radix_signed(a: Vec<i64>, b: Vec<i64>, parts: u8) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+        let parts = parts as usize % 8 + 1;
+
+        let expected = naive(a.clone(), b.clone());
+        let actual = merge_uniq_radix_in_parts(&Reversed, a, b, parts);
+        expected == actual
+    }
+
+    #[test]
+    fn merge_uniq_radix_large() {
+        let a: Vec<u64> = (0..1 << 17).map(|x| x * 3).collect();
+        let b: Vec<u64> = (0..1 << 17).map(|x| x * 5).collect();
+        let expected = naive(a.clone(), b.clone());
+        assert_eq!(merge_uniq_radix(a.clone(), b.clone()), expected);
+        assert_eq!(merge_uniq_radix_with(&Reversed, a, b), expected);
+    }
+
+    #[quickcheck]
+    fn merge_k_uniq_in_parts_impl(runs: Vec<Vec<usize>>, parts: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let parts = parts as usize % 8 + 1;
+
+        let expected = sorted_unique(runs.concat());
+        let actual = merge_k_uniq_in_parts(&default_executor(), runs, parts);
+        expected == actual
+    }
+
+    /// Tags each element with the index of its input, so that the tests can tell which copy of a
+    /// duplicate was kept.
+    fn tagged(runs: &[Vec<usize>]) -> Vec<Vec<ByKey<usize, usize>>> {
+        runs.iter()
+            .enumerate()
+            .map(|(i, run)| run.iter().map(|&key| ByKey { key, item: i }).collect())
+            .collect()
+    }
+
+    fn untagged(v: Vec<ByKey<usize, usize>>) -> Vec<(usize, usize)> {
+        v.into_iter().map(|x| (x.key, x.item)).collect()
+    }
+
+    /// Checks that splitting a merge into any number of parts, each run on `exec(parts)`, keeps
+    /// the same copies as the sequential merges.
+    fn check_deterministic<E: Executor>(exec: impl Fn(usize) -> E, runs: Vec<Vec<usize>>) -> bool {
+        let mut runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        runs.resize(runs.len().max(2), Vec::new());
+        let pair = || {
+            let mut pair = tagged(&runs[..2]);
+            let b = pair.pop().unwrap();
+            (pair.pop().unwrap(), b)
+        };
+
+        let (a, b) = pair();
+        let expected = untagged(crate::merge_uniq(a, b));
+        let expected_k = untagged(kway::merge_uniq(tagged(&runs)));
+        (1..=8).all(|parts| {
+            let (a, b) = pair();
+            untagged(merge_uniq_in_parts(&exec(parts), a, b, parts)) == expected
+                && untagged(merge_k_uniq_in_parts(&exec(parts), tagged(&runs), parts)) == expected_k
+        })
+    }
+
+    #[quickcheck]
+    fn deterministic(runs: Vec<Vec<usize>>) -> bool {
+        check_deterministic(|_| default_executor(), runs)
+    }
+
+    /// Runs the tasks in turn and returns their results backwards.
+    struct Reversed;
+
+    impl Executor for Reversed {
+        fn num_threads(&self) -> usize {
+            4
+        }
+
+        fn map<T, R, F>(&self, tasks: Vec<T>, f: F) -> Vec<R>
+        where
+            T: Send,
+            R: Send,
+            F: Fn(T) -> R + Send + Sync,
+        {
+            let mut results: Vec<R> = tasks.into_iter().map(f).collect();
+            results.reverse();
+            results
+        }
+    }
+
+    /// Runs every task but the last, which it drops.
+    struct Lossy;
+
+    impl Executor for Lossy {
+        fn num_threads(&self) -> usize {
+            4
+        }
+
+        fn map<T, R, F>(&self, mut tasks: Vec<T>, f: F) -> Vec<R>
+        where
+            T: Send,
+            R: Send,
+            F: Fn(T) -> R + Send + Sync,
+        {
+            tasks.pop();
+            tasks.into_iter().map(f).collect()
+        }
+    }
+
+    #[quickcheck]
+    fn reordered_results(runs: Vec<Vec<usize>>) -> bool {
+        check_deterministic(|_| Reversed, runs)
+    }
+
+    #[test]
+    fn reordered_results_strings() {
+        let a: Vec<String> = (0..40000).step_by(2).map(|i| format!("{:06}", i)).collect();
+        let b: Vec<String> = (0..40000).step_by(3).map(|i| format!("{:06}", i)).collect();
+        let expected = naive(a.clone(), b.clone());
+        assert_eq!(merge_uniq_with(&Reversed, a, b), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "the executor didn't return the result of every task")]
+    fn lost_task() {
+        let a: Vec<String> = (0..1000).step_by(2).map(|i| i.to_string()).collect();
+        let b: Vec<String> = (0..1000).step_by(3).map(|i| i.to_string()).collect();
+        merge_uniq_in_parts(&Lossy, a, b, 4);
+    }
+
+    #[quickcheck]
+    fn k_split_points_balanced(runs: Vec<Vec<usize>>, parts: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let parts = parts as usize % 8 + 1;
+        let len: usize = runs.iter().map(Vec::len).sum();
+
+        // Each split lands within one copy per run of its target rank, since all copies of the
+        // split key go to the later range.
+        k_split_points(&runs, parts)
+            .iter()
+            .enumerate()
+            .all(|(p, split)| {
+                let before: usize = split.iter().sum();
+                let target = (p + 1) * len / parts;
+                before <= target && target < before + runs.len().max(1)
+            })
+    }
+
+    #[quickcheck]
+    fn merge_k_uniq_impl(runs: Vec<Vec<usize>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+
+        let expected = sorted_unique(runs.concat());
+        let actual = merge_k_uniq(runs);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn merge_many_in_batches_impl(pairs: Vec<(Vec<usize>, Vec<usize>)>, batches: u8) -> bool {
+        let pairs: Vec<_> = pairs
+            .into_iter()
+            .map(|(a, b)| (sorted_unique(a), sorted_unique(b)))
+            .collect();
+        let batches = batches as usize % 8 + 1;
+
+        let expected: Vec<_> = pairs
+            .iter()
+            .map(|(a, b)| naive(a.clone(), b.clone()))
+            .collect();
+        let actual = merge_many_in_batches(&default_executor(), pairs, batches);
+        expected == actual
+    }
+
+    #[test]
+    fn merge_many_large_pair() {
+        // One pair holds most of the elements, so it's split instead of batched.
+        let big = (
+            (0..100000).step_by(2).collect(),
+            (0..100000).step_by(3).collect(),
+        );
+        let pairs = vec![(vec![1, 3], vec![2]), big, (vec![], vec![5])];
+        let expected: Vec<_> = pairs
+            .iter()
+            .map(|(a, b)| naive(a.clone(), b.clone()))
+            .collect();
+        assert_eq!(merge_many(pairs), expected);
+    }
+
+    /// An element whose comparison panics if either side is poisoned, and which counts its drops
+    /// in a counter shared between threads.
+    mod counted {
+        use std::cmp::Ordering;
+        use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+        use std::sync::Arc;
+
+        #[derive(Debug)]
+        pub struct Counted {
+            pub value: usize,
+            pub poisoned: bool,
+            pub drops: Arc<AtomicUsize>,
+        }
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.drops.fetch_add(1, SeqCst);
+            }
+        }
+
+        impl Ord for Counted {
+            fn cmp(&self, other: &Self) -> Ordering {
+                assert!(
+                    !self.poisoned && !other.poisoned,
+                    "compared a poisoned element"
+                );
+                self.value.cmp(&other.value)
+            }
+        }
+
+        impl PartialOrd for Counted {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl PartialEq for Counted {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other) == Ordering::Equal
+            }
+        }
+
+        impl Eq for Counted {}
+    }
+
+    fn check_panic_safety<E: Executor>(
+        exec: &E,
+        a: Vec<usize>,
+        b: Vec<usize>,
+        parts: u8,
+        poison: usize,
+    ) -> bool {
+        use counted::Counted;
+        use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+        use std::sync::Arc;
+
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+        let parts = parts as usize % 8 + 1;
+        let n = a.len() + b.len();
+
+        // Poison one element of `b`. Splitting the inputs compares them too, so the panic may
+        // come from there instead of from a task.
+        let drops = Arc::new(AtomicUsize::new(0));
+        let make = |value, poisoned| Counted {
+            value,
+            poisoned,
+            drops: Arc::clone(&drops),
+        };
+        let a: Vec<_> = a.into_iter().map(|x| make(x, false)).collect();
+        let b: Vec<_> = b
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| make(x, i == poison % n.max(1)))
+            .collect();
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| merge_uniq_in_parts(exec, a, b, parts)));
+        drop(res);
+        drops.load(SeqCst) == n
+    }
+
+    #[quickcheck]
+    fn merge_uniq_in_parts_panic_safety(
+        a: Vec<usize>,
+        b: Vec<usize>,
+        parts: u8,
+        poison: usize,
+    ) -> bool {
+        check_panic_safety(&default_executor(), a, b, parts, poison)
+    }
+
+    #[cfg(feature = "threads")]
+    mod scoped_threads {
+        use super::*;
+
+        #[quickcheck]
+        fn merge_uniq_in_parts_impl(a: Vec<usize>, b: Vec<usize>, parts: u8) -> bool {
+            let (a, b) = (sorted_unique(a), sorted_unique(b));
+            let parts = parts as usize % 8 + 1;
+
+            let expected = naive(a.clone(), b.clone());
+            let actual = merge_uniq_in_parts(&ScopedThreads::new(parts), a, b, parts);
+            expected == actual
+        }
+
+        #[quickcheck]
+        fn merge_k_uniq_in_parts_impl(runs: Vec<Vec<usize>>, parts: u8) -> bool {
+            let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+            let parts = parts as usize % 8 + 1;
+
+            let expected = sorted_unique(runs.concat());
+            let actual = merge_k_uniq_in_parts(&ScopedThreads::new(parts), runs, parts);
+            expected == actual
+        }
+
+        #[quickcheck]
+        fn merge_uniq_in_parts_panic_safety(
+            a: Vec<usize>,
+            b: Vec<usize>,
+            parts: u8,
+            poison: usize,
+        ) -> bool {
+            let exec = ScopedThreads::new(parts as usize % 8 + 1);
+            check_panic_safety(&exec, a, b, parts, poison)
+        }
+
+        #[quickcheck]
+        fn deterministic(runs: Vec<Vec<usize>>) -> bool {
+            check_deterministic(ScopedThreads::new, runs)
+        }
+
+        #[test]
+        fn map_preserves_order() {
+            let exec = ScopedThreads::new(4);
+            let squares = exec.map((0..100).collect(), |x: u64| x * x);
+            assert_eq!(squares, (0..100).map(|x| x * x).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Splitting merges into independent parts.
+//!
+//! These are the primitives behind the `parallel` module, for callers that want to schedule the
+//! parts on their own thread pool, or somewhere else entirely.
+
+/// Splits the merge of two sorted, deduplicated slices into `parts` ranges that can be merged
+/// independently.
+///
+/// Returns `parts + 1` pairs of indices, starting with `(0, 0)` and ending with
+/// `(a.len(), b.len())`. Merging `a[i0..i1]` with `b[j0..j1]` for each pair of consecutive
+/// entries `(i0, j0)` and `(i1, j1)`, then concatenating the results, gives the same result as
+/// merging `a` with `b`.
+///
+/// Each range holds the same number of input elements, give or take two, so the work is evenly
+/// balanced. A range merges to at most `(i1 - i0) + (j1 - j0)` elements, so the ranges can be
+/// merged into disjoint regions of a single output buffer, starting at `i0 + j0`. Finding each
+/// split takes `O(log(a.len() + b.len()))` comparisons.
+///
+/// For integer keys, `radix_split_points` splits by the top bits of the keys instead.
+///
+/// # Panics
+///
+/// Panics if `parts` is zero.
+pub fn split_points<T: Ord>(a: &[T], b: &[T], parts: usize) -> Vec<(usize, usize)> {
+    assert!(parts > 0, "cannot split a merge into zero parts");
+
+    let len = a.len() + b.len();
+    let mut splits: Vec<_> = (0..parts)
+        .map(|k| split_diagonal(a, b, k * len / parts))
+        .collect();
+    splits.push((a.len(), b.len()));
+    splits
+}
+
+/// Returns how many elements of `a` and `b` make up the first `diag` elements of their merge,
+/// taking elements of `a` before equal ones in `b`.
+///
+/// If that would separate an element of `a` from its duplicate in `b`, the duplicate is moved
+/// before the split as well.
+fn split_diagonal<T: Ord>(a: &[T], b: &[T], diag: usize) -> (usize, usize) {
+    let mut lo = diag.saturating_sub(b.len());
+    let mut hi = diag.min(a.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if a[mid] <= b[diag - mid - 1] {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    let (i, j) = (lo, diag - lo);
+    if i > 0 && j < b.len() && a[i - 1] == b[j] {
+        (i, j + 1)
+    } else {
+        (i, j)
+    }
+}
+
+/// An integer key, for splitting merges by the top bits of their keys.
+pub trait RadixKey: Ord + Copy {
+    /// Maps the key to an unsigned integer with the same order.
+    fn to_radix(self) -> u64;
+}
+
+macro_rules! impl_radix_key {
+    ($($t:ty),*) => {$(
+        impl RadixKey for $t {
+            #[inline]
+            fn to_radix(self) -> u64 {
+                self as u64
+            }
+        }
+    )*};
+}
+
+impl_radix_key!(u8, u16, u32, u64, usize);
+
+macro_rules! impl_radix_key_signed {
+    ($($t:ty => $u:ty),*) => {$(
+        impl RadixKey for $t {
+            /// Flips the sign bit, so that negative keys come first.
+            #[inline]
+            fn to_radix(self) -> u64 {
+                ((self as $u) ^ (1 << (<$u>::BITS - 1))) as u64
+            }
+        }
+    )*};
+}
+
+impl_radix_key_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, isize => usize);
+
+/// Like `split_points`, but splits the merge of two sorted, deduplicated slices of integers by the
+/// top `bits` bits of each key's offset from the smallest key, into at most `2^bits` parts.
+///
+/// Each part holds the keys of one range of values, so finding a split takes one binary search
+/// per input, and all copies of a key land in the same part. When the keys are spread evenly over
+/// their range, the parts are about equal; when they aren't, some parts are larger than others,
+/// and `split_points` balances them better. There are fewer parts if the keys span fewer than
+/// `2^bits` values.
+///
+/// # Panics
+///
+/// Panics if `bits` is more than 16.
+pub fn radix_split_points<T: RadixKey>(a: &[T], b: &[T], bits: u32) -> Vec<(usize, usize)> {
+    assert!(bits <= 16, "cannot split a merge into more than 2^16 parts");
+
+    let ends = |run: &[T]| Some((run.first()?.to_radix(), run.last()?.to_radix()));
+    let (lo, hi) = match (ends(a), ends(b)) {
+        (Some((alo, ahi)), Some((blo, bhi))) => (alo.min(blo), ahi.max(bhi)),
+        (Some(ends), None) | (None, Some(ends)) => ends,
+        (None, None) => return vec![(0, 0)],
+    };
+
+    // The parts are the values of the top `bits` of the `width` bits that the offsets span.
+    let width = u64::BITS - (hi - lo).leading_zeros();
+    let bits = bits.min(width);
+    let shift = width - bits;
+    let mut splits: Vec<_> = (0..1u64 << bits)
+        .map(|p| {
+            let start = lo + (p << shift);
+            let split = |run: &[T]| run.partition_point(|x| x.to_radix() < start);
+            (split(a), split(b))
+        })
+        .collect();
+    splits.push((a.len(), b.len()));
+    splits
+}
+
+/// Returns the element at index `n` of the sorted concatenation of `runs`, or `None` if there are
+/// no more than `n` elements in all.
+///
+/// Copies of an element in several runs are counted separately, as in `split_points`. Nothing is
+/// merged: each round picks the weighted median of the middle elements of what's left of each
+/// run as a pivot, counts the elements on either side of it with a binary search per run, and
+/// discards roughly a quarter of the remaining elements or more. Selection takes `O(k log n)`
+/// comparisons per round, for `k` runs of up to `n` elements, and `O(log(k n))` rounds.
+pub fn select_nth<'a, T: Ord>(runs: &[&'a [T]], mut n: usize) -> Option<&'a T> {
+    // What's left of each run is `run[lo[i]..hi[i]]`, and `n` is relative to what's left.
+    let mut lo = vec![0; runs.len()];
+    let mut hi: Vec<_> = runs.iter().map(|run| run.len()).collect();
+    if n >= hi.iter().sum() {
+        return None;
+    }
+
+    let mut candidates = Vec::with_capacity(runs.len());
+    loop {
+        candidates.clear();
+        candidates.extend(
+            (0..runs.len())
+                .filter(|&i| lo[i] < hi[i])
+                .map(|i| (&runs[i][lo[i] + (hi[i] - lo[i]) / 2], hi[i] - lo[i])),
+        );
+        candidates.sort_unstable_by(|x, y| x.0.cmp(y.0));
+        let total: usize = candidates.iter().map(|&(_, len)| len).sum();
+        let mut seen = 0;
+        let pivot = candidates
+            .iter()
+            .find(|&&(_, len)| {
+                seen += len;
+                2 * seen >= total
+            })
+            .unwrap()
+            .0;
+
+        let (mut less, mut less_or_equal) = (0, 0);
+        let bounds: Vec<_> = (0..runs.len())
+            .map(|i| {
+                let rest = &runs[i][lo[i]..hi[i]];
+                let lt = rest.partition_point(|x| x < pivot);
+                let le = lt + rest[lt..].partition_point(|x| x <= pivot);
+                less += lt;
+                less_or_equal += le;
+                (lo[i] + lt, lo[i] + le)
+            })
+            .collect();
+
+        if n < less {
+            hi.iter_mut()
+                .zip(&bounds)
+                .for_each(|(hi, &(lt, _))| *hi = lt);
+        } else if n < less_or_equal {
+            return Some(pivot);
+        } else {
+            n -= less_or_equal;
+            lo.iter_mut()
+                .zip(&bounds)
+                .for_each(|(lo, &(_, le))| *lo = le);
+        }
+    }
+}
+
+/// Returns `k` elements evenly spaced by rank through the sorted concatenation of `runs`, without
+/// merging them: the first element of each of `k` equal parts of it, as splitters for
+/// range-partitioning a merge or keys for a sparse index.
+///
+/// Ranks are counted as in `select_nth`, so an element in several runs takes up several ranks.
+/// Repeated samples are dropped, so the result is sorted and deduplicated, and can hold fewer
+/// than `k` elements. It's empty if `runs` are. Each sample is one selection, so this is far
+/// cheaper than merging the runs and picking the samples from the result.
+///
+/// # Panics
+///
+/// Panics if `k` is zero.
+pub fn sample_merged<'a, T: Ord>(runs: &[&'a [T]], k: usize) -> Vec<&'a T> {
+    assert!(k > 0, "cannot take zero samples");
+
+    let len: usize = runs.iter().map(|run| run.len()).sum();
+    let mut samples: Vec<&T> = (0..k.min(len))
+        .map(|i| select_nth(runs, i * len / k).unwrap())
+        .collect();
+    samples.dedup();
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn split_points_impl(a: Vec<usize>, b: Vec<usize>, parts: u8) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+        let parts = parts as usize % 8 + 1;
+
+        let splits = split_points(&a, &b, parts);
+        let merged: Vec<_> = splits
+            .windows(2)
+            .flat_map(|w| {
+                let ((i0, j0), (i1, j1)) = (w[0], w[1]);
+                naive(a[i0..i1].to_vec(), b[j0..j1].to_vec())
+            })
+            .collect();
+
+        let monotone = splits
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1);
+        let balanced = splits.windows(2).all(|w| {
+            let len = (w[1].0 + w[1].1) - (w[0].0 + w[0].1);
+            len <= (a.len() + b.len()) / parts + 2
+        });
+        splits.len() == parts + 1 && monotone && balanced && merged == naive(a, b)
+    }
+
+    fn check_radix_splits<T: RadixKey + std::fmt::Debug>(a: Vec<T>, b: Vec<T>, bits: u32) -> bool {
+        let splits = radix_split_points(&a, &b, bits);
+        let merged: Vec<_> = splits
+            .windows(2)
+            .flat_map(|w| {
+                let ((i0, j0), (i1, j1)) = (w[0], w[1]);
+                naive(a[i0..i1].to_vec(), b[j0..j1].to_vec())
+            })
+            .collect();
+
+        let monotone = splits
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1);
+        // Each part holds one range of values, so no key is in two parts.
+        let disjoint = splits.iter().all(|&(i, j)| {
+            let before = a[..i].last().max(b[..j].last());
+            let after = match (a.get(i), b.get(j)) {
+                (Some(x), Some(y)) => Some(x.min(y)),
+                (x, y) => x.or(y),
+            };
+            match (before, after) {
+                (Some(before), Some(after)) => before < after,
+                _ => true,
+            }
+        });
+        splits.first() == Some(&(0, 0))
+            && splits.last() == Some(&(a.len(), b.len()))
+            && splits.len() <= (1 << bits) + 1
+            && monotone
+            && disjoint
+            && merged == naive(a, b)
+    }
+
+    #[quickcheck]
+    fn radix_split_points_impl(a: Vec<u32>, b: Vec<u32>, bits: u8) -> bool {
+        let sorted = |mut v: Vec<u32>| {
+            v.sort_unstable();
+            v.dedup();
+            v
+        };
+        check_radix_splits(sorted(a), sorted(b), u32::from(bits % 6))
+    }
+
+    #[quickcheck]
+    fn radix_split_points_signed(a: Vec<i64>, b: Vec<i64>, bits: u8) -> bool {
+        let sorted = |mut v: Vec<i64>| {
+            v.sort_unstable();
+            v.dedup();
+            v
+        };
+        check_radix_splits(sorted(a), sorted(b), u32::from(bits % 6))
+    }
+
+    #[test]
+    fn radix_split_points_uniform() {
+        let a: Vec<u64> = (0..1 << 16).map(|x| x * 2).collect();
+        let b: Vec<u64> = (0..1 << 16).map(|x| x * 4).collect();
+        let splits = radix_split_points(&a, &b, 3);
+        assert_eq!(splits.len(), 9);
+        assert!(splits
+            .windows(2)
+            .all(|w| w[1].0 - w[0].0 + w[1].1 - w[0].1 > 0));
+    }
+
+    #[quickcheck]
+    fn select_nth_impl(runs: Vec<Vec<usize>>, n: usize) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let mut all = runs.concat();
+        all.sort_unstable();
+        let n = n % (all.len() + 1);
+
+        let slices: Vec<&[usize]> = runs.iter().map(|run| &run[..]).collect();
+        select_nth(&slices, n) == all.get(n)
+    }
+
+    #[quickcheck]
+    fn sample_merged_impl(runs: Vec<Vec<usize>>, k: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let mut all = runs.concat();
+        all.sort_unstable();
+        let k = k as usize % 16 + 1;
+
+        let mut expected: Vec<_> = (0..k.min(all.len()))
+            .map(|i| &all[i * all.len() / k])
+            .collect();
+        expected.dedup();
+        let slices: Vec<&[usize]> = runs.iter().map(|run| &run[..]).collect();
+        sample_merged(&slices, k) == expected
+    }
+}
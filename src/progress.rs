@@ -0,0 +1,241 @@
+//! Observing and cancelling long-running merges.
+//!
+//! `kway::merge_uniq_observed` and `external::Merger::merge_uniq_observed` report to an `Observer`
+//! as they go, so a service can show how far a compaction has come and estimate how long it has
+//! left without wrapping the inputs to count what is read from them.
+//!
+//! `kway::merge_uniq_cancellable`, and the `external::Merger` and `external::Sorter` given a
+//! `CancelToken`, stop soon after the token is cancelled, so a service can shut down without
+//! waiting for a compaction to finish. The parallel merges don't take a token: their parts are
+//! merged by kernels that don't stop until they're done.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The number of items a merge writes between two calls to `Observer::on_progress`, and between
+/// two checks of its `CancelToken`.
+///
+/// Reports are coarse so that observing a merge costs next to nothing, even when the observer
+/// takes a lock or logs.
+pub const PROGRESS_INTERVAL: u64 = 1 << 16;
+
+/// Receives progress reports from a merge. Both methods do nothing by default.
+pub trait Observer {
+    /// Called every `PROGRESS_INTERVAL` items written, and once more when the merge is done, with
+    /// the number of items written so far and, if the merge knows it, the number of items left in
+    /// the inputs. Duplicates that were dropped are no longer left in the inputs, so the two
+    /// numbers don't always add up to the size of the inputs.
+    fn on_progress(&mut self, items_out: u64, items_in_remaining: Option<u64>) {
+        let _ = (items_out, items_in_remaining);
+    }
+
+    /// Called once for each run when its last item has been read, or at the start of the merge
+    /// if it is empty.
+    fn on_run_exhausted(&mut self, run: usize) {
+        let _ = run;
+    }
+}
+
+/// Ignores all reports.
+impl Observer for () {}
+
+/// A shared flag that asks the merges holding it to stop.
+///
+/// Clones share the flag, so one clone can cancel a merge that holds another. A token can also
+/// watch a condition of its own, such as a deadline, which is checked along with the flag.
+#[derive(Clone)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    condition: Option<Arc<dyn Fn() -> bool + Send + Sync>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        CancelToken::new()
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::from_flag(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Creates a token that is cancelled once `flag` is set, such as by a signal handler.
+    pub fn from_flag(flag: Arc<AtomicBool>) -> Self {
+        CancelToken {
+            flag,
+            condition: None,
+        }
+    }
+
+    /// Creates a token that is cancelled once `condition` returns `true`.
+    pub fn from_fn<F>(condition: F) -> Self
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        CancelToken {
+            condition: Some(Arc::new(condition)),
+            ..CancelToken::new()
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed) || self.condition.as_ref().is_some_and(|f| f())
+    }
+
+    /// Returns `Err(Cancelled)` if the token has been cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("flag", &self.flag)
+            .field("condition", &self.condition.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// The error returned by a merge whose `CancelToken` was cancelled.
+///
+/// The merges that return `io::Error`s wrap it in one of kind `Other`, which can be recognized
+/// with `error.get_ref().is_some_and(|e| e.is::<Cancelled>())`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the merge was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        io::Error::other(cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external::{FixedSize, Merger};
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Log {
+        progress: Vec<(u64, Option<u64>)>,
+        exhausted: Vec<usize>,
+    }
+
+    impl Observer for Log {
+        fn on_progress(&mut self, items_out: u64, items_in_remaining: Option<u64>) {
+            self.progress.push((items_out, items_in_remaining));
+        }
+
+        fn on_run_exhausted(&mut self, run: usize) {
+            self.exhausted.push(run);
+        }
+    }
+
+    /// Returns the order in which the runs run out, by the position of their last element in the
+    /// merged order, with empty runs first.
+    fn exhaustion_order(runs: &[Vec<u16>]) -> Vec<usize> {
+        let mut order: Vec<_> = (0..runs.len()).collect();
+        order.sort_by_key(|&i| runs[i].last().map(|&x| (x, i)));
+        order
+    }
+
+    #[quickcheck]
+    fn kway_observed(runs: Vec<Vec<u16>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let mut log = Log::default();
+        let out = crate::kway::merge_uniq_observed(runs.clone(), &mut log);
+        let expected = Log {
+            progress: vec![(out.len() as u64, Some(0))],
+            exhausted: exhaustion_order(&runs),
+        };
+        out == crate::kway::merge_uniq(runs) && log == expected
+    }
+
+    #[quickcheck]
+    fn external_observed(runs: Vec<Vec<u16>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let encoded: Vec<Vec<u8>> = runs
+            .iter()
+            .map(|run| run.iter().flat_map(|x| x.to_be_bytes()).collect())
+            .collect();
+
+        let mut log = Log::default();
+        let mut out = Vec::new();
+        let written = Merger::new(FixedSize::new(2))
+            .merge_uniq_observed(
+                encoded.iter().map(Vec::as_slice).collect(),
+                &mut out,
+                &mut log,
+            )
+            .unwrap();
+        let expected = Log {
+            progress: vec![(written, None)],
+            exhausted: exhaustion_order(&runs),
+        };
+        log == expected
+    }
+
+    #[test]
+    fn reports_at_intervals() {
+        let evens: Vec<u64> = (0..100_000).map(|x| 2 * x).collect();
+        let odds: Vec<u64> = (0..100_000).map(|x| 2 * x + 1).collect();
+        let mut log = Log::default();
+        crate::kway::merge_uniq_observed(vec![evens, odds], &mut log);
+
+        let n = PROGRESS_INTERVAL;
+        let expected: Vec<_> = (1..=3)
+            .map(|i| (i * n, Some(200_000 - i * n)))
+            .chain([(200_000, Some(0))])
+            .collect();
+        assert_eq!(log.progress, expected);
+        assert_eq!(log.exhausted, [0, 1]);
+    }
+
+    #[test]
+    fn kway_cancelled() {
+        let runs = vec![
+            (0..100_000).collect(),
+            (50_000..200_000).collect::<Vec<u32>>(),
+        ];
+        let cancel = CancelToken::new();
+        assert_eq!(
+            crate::kway::merge_uniq_cancellable(runs.clone(), &cancel),
+            Ok(crate::kway::merge_uniq(runs.clone()))
+        );
+        cancel.cancel();
+        assert_eq!(
+            crate::kway::merge_uniq_cancellable(runs.clone(), &cancel.clone()),
+            Err(Cancelled)
+        );
+
+        // Cancelled partway through, at the second check after the start.
+        let checks = AtomicUsize::new(0);
+        let cancel = CancelToken::from_fn(move || checks.fetch_add(1, Ordering::Relaxed) == 2);
+        assert_eq!(
+            crate::kway::merge_uniq_cancellable(runs, &cancel),
+            Err(Cancelled)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Merging datafrog `Relation`s.
+//!
+//! A `Relation`'s elements are always sorted and deduplicated, so they can be handed to this
+//! crate's merge kernels as they are, and the results wrapped back up without sorting again.
+
+use datafrog::Relation;
+
+use crate::kway;
+use crate::SortedUniqueVec;
+
+/// Merges two relations. Of two equal elements, the one from `a` is kept.
+///
+/// This is a drop-in replacement for `Relation::merge`.
+pub fn merge_relations<T: Ord>(a: Relation<T>, b: Relation<T>) -> Relation<T> {
+    Relation {
+        elements: crate::merge_uniq(a.elements, b.elements),
+    }
+}
+
+/// Merges any number of relations. Of several equal elements, the one from the earliest relation
+/// is kept.
+pub fn merge_k_relations<T: Ord>(relations: Vec<Relation<T>>) -> Relation<T> {
+    let runs = relations.into_iter().map(|r| r.elements).collect();
+    Relation {
+        elements: kway::merge_uniq(runs),
+    }
+}
+
+impl<T: Ord> From<SortedUniqueVec<T>> for Relation<T> {
+    fn from(v: SortedUniqueVec<T>) -> Self {
+        Relation {
+            elements: v.into_vec(),
+        }
+    }
+}
+
+impl<T: Ord> From<Relation<T>> for SortedUniqueVec<T> {
+    fn from(r: Relation<T>) -> Self {
+        SortedUniqueVec::from_vec_unchecked(r.elements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn merge_relations_impl(a: Vec<u16>, b: Vec<u16>) -> bool {
+        let expected = Relation::from_vec(a.clone()).merge(Relation::from_vec(b.clone()));
+        let actual = merge_relations(Relation::from_vec(a), Relation::from_vec(b));
+        actual.elements == expected.elements
+    }
+
+    #[quickcheck]
+    fn merge_k_relations_impl(runs: Vec<Vec<u16>>) -> bool {
+        let expected = Relation::from_vec(runs.concat());
+        let actual = merge_k_relations(runs.into_iter().map(Relation::from_vec).collect());
+        actual.elements == expected.elements
+    }
+
+    #[quickcheck]
+    fn round_trip(v: Vec<u16>) -> bool {
+        let relation = Relation::from_vec(v);
+        let expected = relation.elements.clone();
+        let sorted = SortedUniqueVec::from(relation);
+        Relation::from(sorted).elements == expected
+    }
+}
@@ -0,0 +1,137 @@
+//! Merges that run a bounded amount of work at a time, and can be checkpointed between runs.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::kway::LoserTree;
+
+/// The progress of a k-way merge of sorted, deduplicated runs, as a position in each run.
+///
+/// The state doesn't own the runs: each call to `run_for` is passed them again, so a merge can be
+/// paused, its state saved (with the `serde` feature, or through `positions`), and resumed after a
+/// restart against the same runs. When an element appears in several runs, the copy from the
+/// earliest run is output, and every run is advanced past it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MergeState {
+    positions: Vec<usize>,
+}
+
+impl MergeState {
+    /// Returns the state of a merge of `runs` runs that hasn't started.
+    pub fn new(runs: usize) -> Self {
+        MergeState {
+            positions: vec![0; runs],
+        }
+    }
+
+    /// Returns the state of a merge that has consumed the first `positions[i]` elements of run
+    /// `i`.
+    ///
+    /// The positions must have come from `positions` for the same runs.
+    pub fn from_positions(positions: Vec<usize>) -> Self {
+        MergeState { positions }
+    }
+
+    /// Returns the number of elements consumed from each run so far.
+    pub fn positions(&self) -> &[usize] {
+        &self.positions
+    }
+
+    /// Returns `true` if every element of `runs` has been consumed.
+    pub fn is_done<T>(&self, runs: &[&[T]]) -> bool {
+        runs.iter()
+            .zip(&self.positions)
+            .all(|(run, &pos)| pos >= run.len())
+    }
+
+    /// Merges up to `n` more elements of `runs` onto the end of `out`, and returns the number
+    /// appended. This is less than `n` only once the merge is done.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `runs` holds a different number of runs than the state, or a run is shorter than
+    /// its position.
+    pub fn run_for<T: Ord + Clone>(&mut self, runs: &[&[T]], n: usize, out: &mut Vec<T>) -> usize {
+        assert_eq!(
+            runs.len(),
+            self.positions.len(),
+            "merge state is for a different number of runs"
+        );
+        if n == 0 {
+            return 0;
+        }
+
+        let sources = runs
+            .iter()
+            .zip(&self.positions)
+            .map(|(run, &pos)| run[pos..].iter())
+            .collect();
+        let mut tree = LoserTree::new(sources);
+
+        let mut emitted = 0;
+        while emitted < n {
+            let (i, x) = match tree.pop() {
+                Some(head) => head,
+                None => break,
+            };
+            self.positions[i] += 1;
+
+            // Copies from later runs come right after the first one, since ties go to earlier runs.
+            while tree.peek() == Some(&x) {
+                let (j, _) = tree.pop().unwrap();
+                self.positions[j] += 1;
+            }
+
+            out.push(x.clone());
+            emitted += 1;
+        }
+        emitted
+    }
+}
+
+/// Serializes the positions as a sequence.
+#[cfg(feature = "serde")]
+impl Serialize for MergeState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.positions.serialize(serializer)
+    }
+}
+
+/// Deserializes a sequence of positions.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for MergeState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(MergeState::from_positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn run_for_impl(runs: Vec<Vec<u8>>, steps: Vec<u8>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let slices: Vec<&[u8]> = runs.iter().map(|run| &run[..]).collect();
+        let expected = sorted_unique(runs.concat());
+
+        let mut state = MergeState::new(slices.len());
+        let mut out = Vec::new();
+        for step in steps
+            .into_iter()
+            .map(|s| usize::from(s % 16))
+            .chain(Some(usize::MAX))
+        {
+            let emitted = state.run_for(&slices, step, &mut out);
+            if emitted < step && !state.is_done(&slices) {
+                return false;
+            }
+
+            // Resume from a copy of the positions, as after a restart.
+            state = MergeState::from_positions(state.positions().to_vec());
+        }
+        state.is_done(&slices) && out == expected
+    }
+}
@@ -0,0 +1,188 @@
+use crate::merge2_uniq::append_merged;
+
+/// A reusable output buffer for performing many merges in a row.
+///
+/// Each merge writes into the buffer held by the `MergeScratch` and hands it to the caller. The
+/// allocations of the (now empty) inputs are kept in its place, so a steady stream of merges
+/// recycles the same few allocations instead of asking the allocator for a new one every time.
+#[derive(Debug)]
+pub struct MergeScratch<T> {
+    buf: Vec<T>,
+}
+
+impl<T> MergeScratch<T> {
+    pub fn new() -> Self {
+        MergeScratch { buf: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        MergeScratch {
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the capacity of the buffer that the next merge will write into.
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Keeps the allocation of `v` for a later merge if it is larger than the current one.
+    fn recycle(&mut self, mut v: Vec<T>) {
+        if v.capacity() > self.buf.capacity() {
+            v.clear();
+            self.buf = v;
+        }
+    }
+}
+
+impl<T: Ord> MergeScratch<T> {
+    /// Merges two sorted, deduplicated vectors into the scratch buffer and returns it.
+    pub fn merge_uniq(&mut self, mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+        // If one of the lists is zero-length, we don't need to do any work.
+        if a.is_empty() {
+            self.recycle(a);
+            return b;
+        }
+        if b.is_empty() {
+            self.recycle(b);
+            return a;
+        }
+
+        let mut out = std::mem::take(&mut self.buf);
+        append_merged(&mut out, &mut a, &mut b);
+
+        self.buf = if a.capacity() >= b.capacity() { a } else { b };
+        out
+    }
+}
+
+impl<T> Default for MergeScratch<T> {
+    fn default() -> Self {
+        MergeScratch::new()
+    }
+}
+
+/// A pair of buffers that take turns holding a growing set and receiving its merge with the next
+/// batch, as in the rounds of a semi-naive fixpoint loop.
+///
+/// Each round merges the current set and the batch into the spare buffer and swaps the two, so the
+/// old set's allocation is the next round's output. Once both buffers have room for the set plus a
+/// batch, rounds no longer allocate.
+#[derive(Clone, Debug)]
+pub struct PingPongBuffers<T> {
+    current: Vec<T>,
+    spare: Vec<T>,
+}
+
+impl<T> PingPongBuffers<T> {
+    pub fn new() -> Self {
+        PingPongBuffers {
+            current: Vec::new(),
+            spare: Vec::new(),
+        }
+    }
+
+    /// Returns the set built so far.
+    pub fn current(&self) -> &[T] {
+        &self.current
+    }
+
+    /// Returns the combined capacity of both buffers.
+    pub fn capacity(&self) -> usize {
+        self.current.capacity() + self.spare.capacity()
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.current
+    }
+}
+
+impl<T: Ord> PingPongBuffers<T> {
+    /// Merges a sorted, deduplicated batch into the set and returns the number of elements that
+    /// weren't already in it.
+    ///
+    /// `batch` is left empty with its allocation intact, so the caller can refill it for the next
+    /// round.
+    pub fn merge_round(&mut self, batch: &mut Vec<T>) -> usize {
+        let before = self.current.len();
+        self.spare.clear();
+        crate::merge_uniq_drain_into(&mut self.spare, &mut self.current, batch);
+        std::mem::swap(&mut self.current, &mut self.spare);
+        self.current.len() - before
+    }
+}
+
+impl<T> Default for PingPongBuffers<T> {
+    fn default() -> Self {
+        PingPongBuffers::new()
+    }
+}
+
+impl<T> From<Vec<T>> for PingPongBuffers<T> {
+    /// Starts from a sorted, deduplicated set.
+    fn from(current: Vec<T>) -> Self {
+        PingPongBuffers {
+            current,
+            spare: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn merge_uniq_impl(pairs: Vec<(Vec<usize>, Vec<usize>)>) -> bool {
+        let mut scratch = MergeScratch::new();
+
+        pairs.into_iter().all(|(mut a, mut b)| {
+            a.sort_unstable();
+            a.dedup();
+            b.sort_unstable();
+            b.dedup();
+
+            let expected: Vec<_> = naive(a.clone(), b.clone());
+            let actual: Vec<_> = scratch.merge_uniq(a, b);
+            expected == actual
+        })
+    }
+
+    #[quickcheck]
+    fn merge_round_impl(batches: Vec<Vec<u8>>) -> bool {
+        let mut buffers = PingPongBuffers::new();
+        let mut expected = std::collections::BTreeSet::new();
+
+        batches.into_iter().all(|mut batch| {
+            batch.sort_unstable();
+            batch.dedup();
+            let added = batch.iter().filter(|&&x| expected.insert(x)).count();
+
+            buffers.merge_round(&mut batch) == added
+                && batch.is_empty()
+                && buffers.current().iter().eq(expected.iter())
+        })
+    }
+
+    #[test]
+    fn merge_round_reuses_buffers() {
+        let mut buffers = PingPongBuffers::new();
+        let mut batch = Vec::new();
+        for round in 0..6 {
+            batch.extend((0..100).map(|i| i * 4 + round % 4));
+            buffers.merge_round(&mut batch);
+        }
+
+        // The last two warm-up rounds sized both buffers for the whole set plus a batch. From here on
+        // every element is already present, so neither buffer should grow.
+        let capacity = buffers.capacity();
+        for round in 0..10 {
+            batch.extend((0..100).map(|i| i * 4 + round % 4));
+            assert_eq!(buffers.merge_round(&mut batch), 0);
+            assert_eq!(buffers.capacity(), capacity);
+        }
+        assert_eq!(buffers.into_vec(), (0..400).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,204 @@
+//! Vectors stored as a list of fixed-size segments, for merges too large for one allocation.
+//!
+//! A merge of contiguous vectors needs an allocation the size of its output, while its inputs are
+//! still allocated too. On a fragmented heap, a multi-gigabyte allocation can fail even when there
+//! is plenty of free memory. Merging `SegmentedVec`s only ever allocates one segment at a time, and
+//! frees each segment of the inputs as soon as the merge has moved all of its elements out, so the
+//! merge needs little more memory than its inputs did.
+//!
+//! With segments of a few thousand elements or more, the merge is only a little slower than
+//! `crate::merge_uniq` on the same elements in contiguous vectors.
+
+use crate::iter::MergeUniq;
+use crate::kway::KMergeUniq;
+
+/// A vector stored as a list of segments, each of which holds `segment_len` elements, except for
+/// the last one, which may hold fewer.
+#[derive(Clone, Debug)]
+pub struct SegmentedVec<T> {
+    segments: Vec<Vec<T>>,
+    segment_len: usize,
+    len: usize,
+}
+
+impl<T> SegmentedVec<T> {
+    /// Creates an empty vector whose segments hold `segment_len` elements each.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `segment_len` is zero.
+    pub fn new(segment_len: usize) -> Self {
+        assert!(segment_len > 0, "segments must hold at least one element");
+        SegmentedVec {
+            segments: Vec::new(),
+            segment_len,
+            len: 0,
+        }
+    }
+
+    pub fn segment_len(&self) -> usize {
+        self.segment_len
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the segments, all of which are full except possibly the last.
+    pub fn segments(&self) -> &[Vec<T>] {
+        &self.segments
+    }
+
+    /// Appends `x`, allocating a new segment if the last one is full.
+    pub fn push(&mut self, x: T) {
+        match self.segments.last_mut() {
+            Some(segment) if segment.len() < self.segment_len => segment.push(x),
+            _ => {
+                let mut segment = Vec::with_capacity(self.segment_len);
+                segment.push(x);
+                self.segments.push(segment);
+            }
+        }
+        self.len += 1;
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.segments
+            .get(index / self.segment_len)?
+            .get(index % self.segment_len)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.segments.iter().flatten()
+    }
+}
+
+impl<T> Extend<T> for SegmentedVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter().peekable();
+        while iter.peek().is_some() {
+            let segment = match self.segments.last_mut() {
+                Some(segment) if segment.len() < self.segment_len => segment,
+                _ => {
+                    self.segments.push(Vec::with_capacity(self.segment_len));
+                    self.segments.last_mut().unwrap()
+                }
+            };
+            let before = segment.len();
+            segment.extend(iter.by_ref().take(self.segment_len - before));
+            self.len += segment.len() - before;
+        }
+    }
+}
+
+impl<T> IntoIterator for SegmentedVec<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter {
+            segments: self.segments.into_iter().flatten(),
+            len: self.len,
+        }
+    }
+}
+
+/// An iterator that moves the elements out of a `SegmentedVec`, freeing each segment once it is
+/// empty.
+pub struct IntoIter<T> {
+    segments: std::iter::Flatten<std::vec::IntoIter<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let x = self.segments.next()?;
+        self.len -= 1;
+        Some(x)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+/// Merges two sorted, deduplicated segmented vectors into one with the segment length of `a`.
+/// When an element appears in both, the one from `a` is kept.
+pub fn merge_uniq<T: Ord>(a: SegmentedVec<T>, b: SegmentedVec<T>) -> SegmentedVec<T> {
+    let mut out = SegmentedVec::new(a.segment_len);
+    out.extend(MergeUniq::new(a.into_iter(), b.into_iter()));
+    out
+}
+
+/// Merges any number of sorted, deduplicated segmented vectors into one whose segments hold
+/// `segment_len` elements. Of several equal elements, the one from the earliest run is kept.
+pub fn merge_k_uniq<T: Ord>(runs: Vec<SegmentedVec<T>>, segment_len: usize) -> SegmentedVec<T> {
+    let mut out = SegmentedVec::new(segment_len);
+    out.extend(KMergeUniq::new(
+        runs.into_iter().map(SegmentedVec::into_iter).collect(),
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kway::ByKey;
+    use quickcheck_macros::quickcheck;
+
+    fn segmented<T>(v: Vec<T>, segment_len: u8) -> SegmentedVec<T> {
+        let mut out = SegmentedVec::new(usize::from(segment_len % 8) + 1);
+        out.extend(v);
+        out
+    }
+
+    #[quickcheck]
+    fn segmented_vec_impl(v: Vec<u8>, segment_len: u8, index: usize) -> bool {
+        let s = segmented(v.clone(), segment_len);
+        let mut full = s.segments().iter().rev().skip(1);
+        s.len() == v.len()
+            && full.all(|seg| seg.len() == s.segment_len())
+            && s.iter().eq(&v)
+            && s.get(index % (v.len() + 1)) == v.get(index % (v.len() + 1))
+            && s.into_iter().eq(v)
+    }
+
+    #[quickcheck]
+    fn merge_k_uniq_impl(runs: Vec<Vec<u8>>, segment_len: u8) -> bool {
+        // Elements are tagged with their run, to check that ties go to the earliest one.
+        let runs: Vec<Vec<_>> = runs
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut run)| {
+                run.sort_unstable();
+                run.dedup();
+                run.into_iter().map(|key| (key, i)).collect()
+            })
+            .collect();
+        let mut expected: Vec<_> = runs.iter().flatten().copied().collect();
+        expected.sort_by_key(|&(key, _)| key);
+        expected.dedup_by_key(|&mut (key, _)| key);
+
+        let tagged = |run: &Vec<(u8, usize)>, segment_len| {
+            let run = run.iter().map(|&(key, item)| ByKey { key, item });
+            segmented(run.collect(), segment_len)
+        };
+        let untag = |s: SegmentedVec<ByKey<u8, usize>>| -> Vec<_> {
+            s.into_iter().map(|x| (x.key, x.item)).collect()
+        };
+        let two = match &runs[..] {
+            [a, b] => untag(merge_uniq(tagged(a, segment_len), tagged(b, 2))) == expected,
+            _ => true,
+        };
+        let runs = runs.iter().map(|run| tagged(run, segment_len)).collect();
+        two && untag(merge_k_uniq(runs, 5)) == expected
+    }
+}
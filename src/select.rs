@@ -0,0 +1,161 @@
+//! Order statistics of sorted inputs, computed without merging them.
+//!
+//! `partition::select_nth` finds the element at any index of the merge of `k` runs.
+
+use std::ops::{Bound, RangeBounds};
+
+use crate::kway::KMergeUniq;
+
+/// Returns the element at index `n` of the merge of `a` and `b`, counting elements that appear in
+/// both twice, or `None` if there are no more than `n` elements in all.
+///
+/// This binary searches for the number of elements `a` contributes to the first `n + 1`, taking
+/// `O(log(min(a.len(), b.len())))` comparisons.
+fn select_nth2<'a, T: Ord>(a: &'a [T], b: &'a [T], n: usize) -> Option<&'a T> {
+    if n >= a.len() + b.len() {
+        return None;
+    }
+
+    let take = n + 1;
+    let mut lo = take.saturating_sub(b.len());
+    let mut hi = take.min(a.len());
+    while lo < hi {
+        // `i < a.len()` and `j >= 1`, so both indices are in bounds.
+        let i = lo + (hi - lo) / 2;
+        let j = take - i;
+        if b[j - 1] <= a[i] {
+            hi = i;
+        } else {
+            lo = i + 1;
+        }
+    }
+
+    let (i, j) = (lo, take - lo);
+    let x = i.checked_sub(1).map(|i| &a[i]);
+    let y = j.checked_sub(1).map(|j| &b[j]);
+    x.max(y)
+}
+
+/// Returns the `q`-quantile of the merge of the sorted slices `a` and `b`, or `None` if both are
+/// empty.
+///
+/// Elements that appear in both count twice. Since `T` needn't be numeric, nothing is
+/// interpolated: the result is the element at index `floor(q * (len - 1))` of the merge.
+///
+/// # Panics
+///
+/// Panics if `q` is not between `0.0` and `1.0`.
+pub fn quantile2<'a, T: Ord>(a: &'a [T], b: &'a [T], q: f64) -> Option<&'a T> {
+    assert!((0.0..=1.0).contains(&q), "quantile must be between 0 and 1");
+
+    let len = (a.len() + b.len()).checked_sub(1)?;
+    select_nth2(a, b, (q * len as f64) as usize)
+}
+
+/// Returns the median of the merge of the sorted slices `a` and `b`, or `None` if both are empty.
+///
+/// For an even number of elements, this is the lower of the two middle ones.
+pub fn median2<'a, T: Ord>(a: &'a [T], b: &'a [T]) -> Option<&'a T> {
+    let len = (a.len() + b.len()).checked_sub(1)?;
+    select_nth2(a, b, len / 2)
+}
+
+/// Returns the number of distinct elements less than `x` across the sorted, deduplicated `runs`.
+///
+/// An element that appears in several runs is counted once.
+pub fn rank<T: Ord>(runs: &[&[T]], x: &T) -> usize {
+    count_range(runs, (Bound::Unbounded, Bound::Excluded(x)))
+}
+
+/// Returns the number of distinct elements within `range` across the sorted, deduplicated `runs`.
+///
+/// Each run is narrowed to `range` with a binary search. If only one run has elements in range,
+/// that's its count; otherwise the narrowed runs are merged to count elements they share, which
+/// takes time linear in the number of elements in range.
+pub fn count_range<'a, T, R>(runs: &[&'a [T]], range: R) -> usize
+where
+    T: Ord + 'a,
+    R: RangeBounds<&'a T>,
+{
+    let in_range: Vec<&[T]> = runs
+        .iter()
+        .map(|run| {
+            let start = match range.start_bound() {
+                Bound::Included(lo) => run.partition_point(|x| x < *lo),
+                Bound::Excluded(lo) => run.partition_point(|x| x <= *lo),
+                Bound::Unbounded => 0,
+            };
+            let end = match range.end_bound() {
+                Bound::Included(hi) => run.partition_point(|x| x <= *hi),
+                Bound::Excluded(hi) => run.partition_point(|x| x < *hi),
+                Bound::Unbounded => run.len(),
+            };
+            &run[start..end.max(start)]
+        })
+        .filter(|run| !run.is_empty())
+        .collect();
+
+    match in_range[..] {
+        [] => 0,
+        [run] => run.len(),
+        _ => KMergeUniq::new(in_range.into_iter().map(|run| run.iter()).collect()).count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn merged(a: &[u8], b: &[u8]) -> Vec<u8> {
+        let mut all = [a, b].concat();
+        all.sort_unstable();
+        all
+    }
+
+    #[quickcheck]
+    fn select_nth2_impl(mut a: Vec<u8>, mut b: Vec<u8>, n: usize) -> bool {
+        a.sort_unstable();
+        b.sort_unstable();
+        let all = merged(&a, &b);
+        let n = n % (all.len() + 1);
+        select_nth2(&a, &b, n) == all.get(n)
+    }
+
+    #[quickcheck]
+    fn quantile2_impl(mut a: Vec<u8>, mut b: Vec<u8>, q: u8) -> bool {
+        a.sort_unstable();
+        b.sort_unstable();
+        let all = merged(&a, &b);
+        let q = f64::from(q) / 255.0;
+
+        let expected = match all.len() {
+            0 => None,
+            len => Some(&all[(q * (len - 1) as f64) as usize]),
+        };
+        let median = all.len().checked_sub(1).map(|last| &all[last / 2]);
+        quantile2(&a, &b, q) == expected && median2(&a, &b) == median
+    }
+
+    #[quickcheck]
+    fn count_range_impl(runs: Vec<Vec<u8>>, lo: u8, hi: u8, x: u8) -> bool {
+        let runs: Vec<Vec<u8>> = runs
+            .into_iter()
+            .map(|mut run| {
+                run.sort_unstable();
+                run.dedup();
+                run
+            })
+            .collect();
+        let slices: Vec<&[u8]> = runs.iter().map(|run| &run[..]).collect();
+        let mut all = runs.concat();
+        all.sort_unstable();
+        all.dedup();
+
+        let count = |f: &dyn Fn(u8) -> bool| all.iter().filter(|&&y| f(y)).count();
+        rank(&slices, &x) == count(&|y| y < x)
+            && count_range(&slices, &lo..&hi) == count(&|y| lo <= y && y < hi)
+            && count_range(&slices, &lo..=&hi) == count(&|y| lo <= y && y <= hi)
+            && count_range(&slices, &lo..) == count(&|y| lo <= y)
+    }
+}
@@ -0,0 +1,475 @@
+use std::cmp::Ordering;
+use std::collections::binary_heap::{BinaryHeap, PeekMut};
+
+use crate::gallop::gallop;
+use crate::kway::LoserTree;
+
+/// Intersects `k` sorted, deduplicated runs.
+///
+/// This is a leapfrog-style intersection: each cursor gallops forward to the largest element
+/// seen so far, so runs that share few elements are skipped over in large strides instead of
+/// being compared element-by-element. The shortest run drives the search, and its allocation is
+/// reused for the output.
+pub fn intersect_k<T: Ord>(mut runs: Vec<Vec<T>>) -> Vec<T> {
+    let shortest = match (0..runs.len()).min_by_key(|&i| runs[i].len()) {
+        Some(i) => i,
+        None => return Vec::new(),
+    };
+
+    let mut out = runs.swap_remove(shortest);
+    let mut others: Vec<&[T]> = runs.iter().map(|run| &run[..]).collect();
+
+    // Elements in `out[..kept]` are in the intersection. Everything in `out[kept..i]` has been
+    // rejected and will be dropped by the final `truncate`.
+    let mut kept = 0;
+    let mut i = 0;
+    'search: while i < out.len() {
+        let mut found = true;
+        for other in &mut others {
+            let candidate = &out[i];
+            *other = &other[gallop(other, |x| x < candidate)..];
+
+            match other.first() {
+                None => break 'search,
+                Some(x) if x == candidate => {}
+
+                // `other` has no element equal to `candidate`. Leapfrog the driving run forward
+                // to the new maximum and start over.
+                Some(x) => {
+                    i += gallop(&out[i..], |y| y < x);
+                    found = false;
+                    break;
+                }
+            }
+        }
+
+        if found {
+            out.swap(kept, i);
+            kept += 1;
+            i += 1;
+        }
+    }
+
+    out.truncate(kept);
+    out
+}
+
+/// Returns the elements that appear in at least `m` of the sorted, deduplicated `runs`, in order.
+///
+/// The runs are merged with a loser tree, and each element is kept if enough copies of it come out
+/// in a row. Of the copies, the one from the earliest run is kept. With `m` at most 1 this is a
+/// union, and with `m` equal to the number of runs it is an intersection, for which `intersect_k`
+/// is faster since it can skip over elements without comparing them.
+pub fn t_overlap<T: Ord>(runs: Vec<Vec<T>>, m: usize) -> Vec<T> {
+    if m > runs.len() {
+        return Vec::new();
+    }
+
+    let mut tree = LoserTree::new(runs.into_iter().map(Vec::into_iter).collect());
+    let mut out = Vec::new();
+    while let Some(x) = tree.next() {
+        let mut count = 1;
+        while tree.peek() == Some(&x) {
+            tree.next();
+            count += 1;
+        }
+        if count >= m {
+            out.push(x);
+        }
+    }
+    out
+}
+
+/// Keeps the elements of `a` that are also in `b`, where both are sorted and deduplicated.
+///
+/// Like `difference`, this gallops through `b` and reuses `a`'s allocation. Use `intersect_k` to
+/// intersect more than two runs at once.
+pub fn intersection<T: Ord>(mut a: Vec<T>, mut b: &[T]) -> Vec<T> {
+    a.retain(|x| {
+        b = &b[gallop(b, |y| y < x)..];
+        b.first() == Some(x)
+    });
+    a
+}
+
+/// Removes the elements of `b` from `a`, where both are sorted and deduplicated.
+///
+/// The cursor into `b` gallops forward to each element of `a`, so a short `b` is cheap to
+/// subtract from a long `a` and vice versa. `a`'s allocation is reused for the output.
+pub fn difference<T: Ord>(mut a: Vec<T>, mut b: &[T]) -> Vec<T> {
+    a.retain(|x| {
+        b = &b[gallop(b, |y| y < x)..];
+        b.first() != Some(x)
+    });
+    a
+}
+
+/// Keeps the elements of `a` that are also in `b`, counting copies, where both are sorted and may
+/// hold duplicates.
+///
+/// An element that `a` holds `m` times and `b` holds `n` times is kept `min(m, n)` times, so the
+/// result is what both inputs have in stock, where `intersection` would keep every copy in `a` of
+/// anything in `b`. Like `intersection`, this gallops through `b` and reuses `a`'s allocation.
+pub fn intersect_multiset<T: Ord>(mut a: Vec<T>, mut b: &[T]) -> Vec<T> {
+    a.retain(|x| {
+        b = &b[gallop(b, |y| y < x)..];
+        let found = b.first() == Some(x);
+        if found {
+            b = &b[1..];
+        }
+        found
+    });
+    a
+}
+
+/// Removes one copy of an element from `a` for each copy of it in `b`, where both are sorted and
+/// may hold duplicates.
+///
+/// An element that `a` holds `m` times and `b` holds `n` times is kept `m - n` times, or not at
+/// all if `n >= m`, where `difference` would remove every copy. Like `difference`, this gallops
+/// through `b` and reuses `a`'s allocation.
+pub fn subtract_multiset<T: Ord>(mut a: Vec<T>, mut b: &[T]) -> Vec<T> {
+    a.retain(|x| {
+        b = &b[gallop(b, |y| y < x)..];
+        let found = b.first() == Some(x);
+        if found {
+            b = &b[1..];
+        }
+        !found
+    });
+    a
+}
+
+/// Removes the elements of any of `exclusions` from `base`, where all are sorted and deduplicated.
+///
+/// Each exclusion list has its own galloping cursor, and the cursors are kept in a heap by their
+/// current element, so only the lists that an element of `base` has passed are touched, at
+/// `O(log k)` each for `k` lists. A list that is dense next to `base` would be touched for most
+/// of its elements, so it is subtracted with `difference` up front instead. `base`'s allocation
+/// is reused for the output. This is much faster than chaining `difference` over many short
+/// lists, and about as fast over a few long ones.
+pub fn difference_k<T: Ord>(mut base: Vec<T>, exclusions: &[&[T]]) -> Vec<T> {
+    let mut heads = BinaryHeap::new();
+    for &e in exclusions {
+        if e.len() * DENSE_EXCLUSION_RATIO >= base.len() {
+            base = difference(base, e);
+        } else if !e.is_empty() {
+            heads.push(Head(e));
+        }
+    }
+
+    base.retain(|x| {
+        while let Some(mut head) = heads.peek_mut() {
+            match head.0[0].cmp(x) {
+                Ordering::Less => {
+                    let rest = &head.0[gallop(head.0, |y| y < x)..];
+                    if rest.is_empty() {
+                        PeekMut::pop(head);
+                    } else {
+                        head.0 = rest;
+                    }
+                }
+                Ordering::Equal => return false,
+                Ordering::Greater => return true,
+            }
+        }
+        true
+    });
+    base
+}
+
+/// `difference_k` subtracts an exclusion list on its own, with `difference`, if it has at least
+/// `1 / DENSE_EXCLUSION_RATIO` as many elements as the base.
+const DENSE_EXCLUSION_RATIO: usize = 16;
+
+/// What's left of a non-empty exclusion list in `difference_k`, ordered so that the list with the
+/// smallest first element is at the top of a max-heap.
+struct Head<'a, T>(&'a [T]);
+
+impl<T: Ord> PartialEq for Head<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0[0] == other.0[0]
+    }
+}
+
+impl<T: Ord> Eq for Head<'_, T> {}
+
+impl<T: Ord> PartialOrd for Head<'_, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> Ord for Head<'_, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0[0].cmp(&self.0[0])
+    }
+}
+
+/// Answers whether each of `queries` is in any of the sorted, deduplicated `runs`.
+///
+/// `queries` is sorted in place, and the answers are in its new order, so duplicate queries get
+/// the same answer. Each run is then checked in one pass that gallops from one query to the
+/// next, which takes `O(q log(n / q))` comparisons for `q` queries against a run of `n` elements
+/// instead of `O(q log n)` for a binary search per query. Runs are no longer searched once every
+/// query has been found.
+pub fn probe<T: Ord>(runs: &[&[T]], queries: &mut [T]) -> Vec<bool> {
+    queries.sort();
+    let mut found = vec![false; queries.len()];
+    let mut missing = queries.len();
+    for &run in runs {
+        if missing == 0 {
+            break;
+        }
+        let mut rest = run;
+        for (q, found) in queries.iter().zip(&mut found) {
+            rest = &rest[gallop(rest, |x| x < q)..];
+            match rest.first() {
+                None => break,
+                Some(x) if !*found && x == q => {
+                    *found = true;
+                    missing -= 1;
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    found
+}
+
+/// Returns the elements that are in exactly one of `a` and `b`, where both are sorted and
+/// deduplicated.
+pub fn symmetric_difference<T: Ord>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    loop {
+        let ord = match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return out,
+        };
+
+        match ord {
+            Ordering::Less => out.extend(a.next()),
+            Ordering::Greater => out.extend(b.next()),
+            Ordering::Equal => {
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// The changes between two sorted, deduplicated snapshots, as computed by `diff`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Diff<T> {
+    /// The elements only in the new snapshot, in ascending order.
+    pub added: Vec<T>,
+    /// The elements only in the old snapshot, in ascending order.
+    pub removed: Vec<T>,
+}
+
+impl<T> Diff<T> {
+    /// Returns `true` if the snapshots were equal.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Compares two sorted, deduplicated snapshots in a single pass, returning what was added and
+/// removed between `old` and `new`.
+pub fn diff<T: Ord>(old: Vec<T>, new: Vec<T>) -> Diff<T> {
+    let mut diff = Diff {
+        added: Vec::new(),
+        removed: Vec::new(),
+    };
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    loop {
+        let ord = match (old.peek(), new.peek()) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => {
+                diff.removed.extend(old);
+                return diff;
+            }
+            (None, Some(_)) => {
+                diff.added.extend(new);
+                return diff;
+            }
+            (None, None) => return diff,
+        };
+
+        match ord {
+            Ordering::Less => diff.removed.extend(old.next()),
+            Ordering::Greater => diff.added.extend(new.next()),
+            Ordering::Equal => {
+                old.next();
+                new.next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeMap;
+
+    #[quickcheck]
+    fn intersect_k_impl(runs: Vec<Vec<u8>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+
+        let expected: Vec<u8> = match runs.first() {
+            Some(first) => first
+                .iter()
+                .filter(|x| runs.iter().all(|run| run.contains(x)))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        let actual = intersect_k(runs);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn t_overlap_impl(runs: Vec<Vec<u8>>, m: u8) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let m = usize::from(m) % (runs.len() + 2);
+
+        let expected: Vec<u8> = (0..=255)
+            .filter(|x| runs.iter().filter(|run| run.contains(x)).count() >= m.max(1))
+            .collect();
+        t_overlap(runs, m) == expected
+    }
+
+    #[quickcheck]
+    fn probe_impl(runs: Vec<Vec<u8>>, queries: Vec<u8>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let refs: Vec<&[u8]> = runs.iter().map(Vec::as_slice).collect();
+        let mut sorted = queries.clone();
+        sorted.sort_unstable();
+
+        let mut queries = queries;
+        let found = probe(&refs, &mut queries);
+        let expected: Vec<_> = sorted
+            .iter()
+            .map(|q| runs.iter().any(|run| run.contains(q)))
+            .collect();
+        queries == sorted && found == expected
+    }
+
+    #[quickcheck]
+    fn intersection_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let expected: Vec<u8> = a.iter().filter(|x| b.contains(x)).cloned().collect();
+        let actual = intersection(a, &b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn difference_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let expected: Vec<u8> = a.iter().filter(|x| !b.contains(x)).cloned().collect();
+        let actual = difference(a, &b);
+        expected == actual
+    }
+
+    /// Counts the copies of each element.
+    fn counts(v: &[u8]) -> BTreeMap<u8, usize> {
+        let mut counts = BTreeMap::new();
+        for &x in v {
+            *counts.entry(x).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[quickcheck]
+    fn multiset_impl(mut a: Vec<u8>, mut b: Vec<u8>) -> bool {
+        // Few distinct values, so that elements have several copies.
+        a.iter_mut().chain(&mut b).for_each(|x| *x %= 8);
+        a.sort_unstable();
+        b.sort_unstable();
+        let (ca, cb) = (counts(&a), counts(&b));
+
+        let intersected = intersect_multiset(a.clone(), &b);
+        let subtracted = subtract_multiset(a, &b);
+        let sorted = |v: &[u8]| v.windows(2).all(|w| w[0] <= w[1]);
+        let (i, s) = (counts(&intersected), counts(&subtracted));
+        let counted = ca.iter().all(|(x, &m)| {
+            let n = cb.get(x).copied().unwrap_or(0);
+            i.get(x).copied().unwrap_or(0) == m.min(n)
+                && s.get(x).copied().unwrap_or(0) == m.saturating_sub(n)
+        });
+        sorted(&intersected) && sorted(&subtracted) && counted
+    }
+
+    #[quickcheck]
+    fn difference_k_impl(base: Vec<u8>, exclusions: Vec<Vec<u8>>) -> bool {
+        let base = sorted_unique(base);
+        let exclusions: Vec<_> = exclusions.into_iter().map(sorted_unique).collect();
+
+        let expected: Vec<u8> = base
+            .iter()
+            .filter(|x| !exclusions.iter().any(|e| e.contains(x)))
+            .cloned()
+            .collect();
+        let slices: Vec<&[u8]> = exclusions.iter().map(|e| &e[..]).collect();
+        difference_k(base, &slices) == expected
+    }
+
+    #[quickcheck]
+    fn difference_k_sparse(exclusions: Vec<Vec<u16>>) -> bool {
+        // Short lists against a long base, so that they go through the heap.
+        let base: Vec<u16> = (0..1024).collect();
+        let exclusions: Vec<Vec<u16>> = exclusions
+            .into_iter()
+            .map(|e| {
+                let mut e: Vec<_> = e.into_iter().take(8).map(|x| x % 1024).collect();
+                e.sort_unstable();
+                e.dedup();
+                e
+            })
+            .collect();
+
+        let expected: Vec<u16> = base
+            .iter()
+            .filter(|x| !exclusions.iter().any(|e| e.contains(x)))
+            .cloned()
+            .collect();
+        let slices: Vec<&[u16]> = exclusions.iter().map(|e| &e[..]).collect();
+        difference_k(base, &slices) == expected
+    }
+
+    #[quickcheck]
+    fn symmetric_difference_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let mut expected: Vec<u8> = a
+            .iter()
+            .filter(|x| !b.contains(x))
+            .chain(b.iter().filter(|x| !a.contains(x)))
+            .cloned()
+            .collect();
+        expected.sort_unstable();
+        let actual = symmetric_difference(a, b);
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn diff_impl(old: Vec<u8>, new: Vec<u8>) -> bool {
+        let (old, new) = (sorted_unique(old), sorted_unique(new));
+
+        let expected = Diff {
+            added: new.iter().filter(|x| !old.contains(x)).cloned().collect(),
+            removed: old.iter().filter(|x| !new.contains(x)).cloned().collect(),
+        };
+        let actual = diff(old.clone(), new.clone());
+        expected == actual && actual.is_empty() == (old == new)
+    }
+}
@@ -0,0 +1,896 @@
+//! Vectorized merging for integer keys.
+//!
+//! The vector kernels merge blocks of lanes from both inputs with a bitonic merge network,
+//! emitting the lower half of each merged block and keeping the upper half for the next round.
+//! Since the output of the network is sorted, duplicates end up adjacent and are removed with a
+//! branchless store. The kernel is selected at runtime based on the features of the CPU (AVX-512 or
+//! AVX2 on x86-64, NEON on AArch64), falling back to the scalar path if none are available.
+//! WebAssembly has no runtime feature detection, so its SIMD128 kernels are only available when
+//! the crate is built with `-C target-feature=+simd128`.
+
+use crate::merge2_uniq;
+
+/// An integer type with vectorized merge kernels.
+///
+/// This trait is sealed. It is implemented for `u32`, `u64` and `i64`.
+pub trait SimdElement: Copy + Ord + private::Sealed {
+    #[doc(hidden)]
+    fn merge_uniq(backend: Backend, a: &[Self], b: &[Self]) -> Vec<Self>;
+}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+    impl Sealed for i64 {}
+}
+
+/// A key type that merges like an integer it wraps, such as a `#[repr(transparent)]` newtype over
+/// a `u32` ID.
+///
+/// `merge_uniq_keys` converts the inputs to their primitives, merges those with the vector kernels
+/// and converts the result back. When the conversions are no-ops, as they are for a newtype, the
+/// vectors are converted in place and the merge costs as much as merging the integers directly.
+///
+/// Implementing the trait is safe, but the merge is only correct if the conversions preserve
+/// order: `a < b` exactly when `a.to_primitive() < b.to_primitive()`.
+pub trait PrimitiveKey: Copy + Ord {
+    type Primitive: SimdElement;
+
+    fn to_primitive(self) -> Self::Primitive;
+
+    /// Converts back a primitive returned by `to_primitive`.
+    fn from_primitive(p: Self::Primitive) -> Self;
+}
+
+/// A family of merge kernels, each targeting a different instruction set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The portable scalar kernel, `merge2_uniq::from_slices_copied`.
+    Scalar,
+    /// 256-bit kernels for x86-64.
+    Avx2,
+    /// 512-bit kernels for x86-64, with twice as many lanes as `Avx2`.
+    Avx512,
+    /// 128-bit kernels for AArch64.
+    Neon,
+    /// 128-bit kernels for WebAssembly, supported when built with the `simd128` target feature.
+    Simd128,
+}
+
+impl Backend {
+    /// Returns the fastest backend supported by the CPU.
+    pub fn detect() -> Self {
+        [
+            Backend::Avx512,
+            Backend::Avx2,
+            Backend::Neon,
+            Backend::Simd128,
+        ]
+        .iter()
+        .copied()
+        .find(|backend| backend.is_supported())
+        .unwrap_or(Backend::Scalar)
+    }
+
+    /// Returns `true` if this backend can run on the CPU.
+    ///
+    /// `Scalar` is always supported. The vector backends are never supported with the
+    /// `force-safe` feature enabled.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Backend::Scalar => true,
+            #[cfg(all(target_arch = "x86_64", not(feature = "force-safe")))]
+            Backend::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(all(target_arch = "x86_64", not(feature = "force-safe")))]
+            Backend::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(all(target_arch = "aarch64", not(feature = "force-safe")))]
+            Backend::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            #[cfg(all(
+                target_arch = "wasm32",
+                target_feature = "simd128",
+                not(feature = "force-safe")
+            ))]
+            Backend::Simd128 => true,
+            _ => false,
+        }
+    }
+}
+
+/// Merges two sorted, deduplicated vectors of integers, using the fastest backend supported by
+/// the CPU.
+pub fn merge_uniq<T: SimdElement>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    merge_uniq_with(Backend::detect(), a, b)
+}
+
+/// Like `merge_uniq`, but always uses `backend`. This is mostly useful for benchmarking.
+///
+/// # Panics
+///
+/// Panics if `backend` is not supported by the CPU.
+pub fn merge_uniq_with<T: SimdElement>(backend: Backend, a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    assert!(
+        backend.is_supported(),
+        "{:?} is not supported by this CPU",
+        backend
+    );
+
+    if a.is_empty() {
+        return b;
+    }
+    if b.is_empty() {
+        return a;
+    }
+
+    T::merge_uniq(backend, &a, &b)
+}
+
+/// Merges two sorted, deduplicated vectors of keys through the vector kernels for their
+/// primitives, using the fastest backend supported by the CPU.
+pub fn merge_uniq_keys<K: PrimitiveKey>(a: Vec<K>, b: Vec<K>) -> Vec<K> {
+    merge_uniq_keys_with(Backend::detect(), a, b)
+}
+
+/// Like `merge_uniq_keys`, but always uses `backend`.
+///
+/// # Panics
+///
+/// Panics if `backend` is not supported by the CPU.
+pub fn merge_uniq_keys_with<K: PrimitiveKey>(backend: Backend, a: Vec<K>, b: Vec<K>) -> Vec<K> {
+    // These reuse the allocations of their inputs, since the element types have the same layout.
+    let to_primitives = |v: Vec<K>| v.into_iter().map(K::to_primitive).collect();
+    let merged = merge_uniq_with(backend, to_primitives(a), to_primitives(b));
+    merged.into_iter().map(K::from_primitive).collect()
+}
+
+macro_rules! impl_simd_element {
+    ($($t:ident => $kernel:ident),* $(,)?) => {$(
+        impl PrimitiveKey for $t {
+            type Primitive = $t;
+
+            fn to_primitive(self) -> Self {
+                self
+            }
+
+            fn from_primitive(p: Self) -> Self {
+                p
+            }
+        }
+
+        impl SimdElement for $t {
+            fn merge_uniq(backend: Backend, a: &[Self], b: &[Self]) -> Vec<Self> {
+                // `merge_uniq_with` has already checked that the backend is supported.
+                match backend {
+                    #[cfg(all(target_arch = "x86_64", not(feature = "force-safe")))]
+                    Backend::Avx2 => unsafe { avx2::$kernel(a, b) },
+                    #[cfg(all(target_arch = "x86_64", not(feature = "force-safe")))]
+                    Backend::Avx512 => unsafe { avx512::$kernel(a, b) },
+                    #[cfg(all(target_arch = "aarch64", not(feature = "force-safe")))]
+                    Backend::Neon => unsafe { neon::$kernel(a, b) },
+                    #[cfg(all(
+                        target_arch = "wasm32",
+                        target_feature = "simd128",
+                        not(feature = "force-safe")
+                    ))]
+                    Backend::Simd128 => unsafe { simd128::$kernel(a, b) },
+                    _ => merge2_uniq::from_slices_copied(a, b),
+                }
+            }
+        }
+    )*};
+}
+
+impl_simd_element! {
+    u32 => merge_uniq_u32,
+    u64 => merge_uniq_u64,
+    i64 => merge_uniq_i64,
+}
+
+/// Writes a sorted stream of elements to an output buffer, skipping each one that is equal to
+/// its predecessor.
+///
+/// Every element is stored unconditionally, but the output only advances past it if it differs
+/// from the previous one, so duplicates don't cost a branch misprediction.
+#[cfg(all(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        all(target_arch = "wasm32", target_feature = "simd128")
+    ),
+    not(feature = "force-safe")
+))]
+struct Emitter<T> {
+    out: *mut T,
+    len: usize,
+    prev: T,
+}
+
+#[cfg(all(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        all(target_arch = "wasm32", target_feature = "simd128")
+    ),
+    not(feature = "force-safe")
+))]
+impl<T: Copy + Ord> Emitter<T> {
+    /// Starts the output with `first`, which must be the smallest element of the stream.
+    ///
+    /// # Safety
+    ///
+    /// `out` must be valid for writes of as many elements as will be emitted, including `first`.
+    unsafe fn new(out: *mut T, first: T) -> Self {
+        out.write(first);
+        Emitter {
+            out,
+            len: 1,
+            prev: first,
+        }
+    }
+
+    unsafe fn emit(&mut self, x: T) {
+        self.out.add(self.len).write(x);
+        self.len += (x != self.prev) as usize;
+        self.prev = x;
+    }
+
+    /// Emits the merged contents of `a` and `b`.
+    unsafe fn emit_merged(&mut self, mut a: &[T], mut b: &[T]) {
+        while let (Some(&x), Some(&y)) = (a.first(), b.first()) {
+            if x <= y {
+                self.emit(x);
+                a = &a[1..];
+            } else {
+                self.emit(y);
+                b = &b[1..];
+            }
+        }
+
+        for &x in a.iter().chain(b) {
+            self.emit(x);
+        }
+    }
+}
+
+/// Merges `a` and `b` into the front of `out`, keeping duplicates, and returns the number of
+/// elements written.
+#[cfg(all(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        all(target_arch = "wasm32", target_feature = "simd128")
+    ),
+    not(feature = "force-safe")
+))]
+fn merge_small<T: Copy + Ord>(mut a: &[T], mut b: &[T], out: &mut [T]) -> usize {
+    let mut len = 0;
+    while let (Some(&x), Some(&y)) = (a.first(), b.first()) {
+        if x <= y {
+            out[len] = x;
+            a = &a[1..];
+        } else {
+            out[len] = y;
+            b = &b[1..];
+        }
+        len += 1;
+    }
+
+    for &x in a.iter().chain(b) {
+        out[len] = x;
+        len += 1;
+    }
+    len
+}
+
+/// Generates the driver loop of a vector kernel.
+///
+/// `$load` and `$store` move one vector to or from a pointer to its first element. `$merge` takes
+/// two sorted vectors and returns the lower and upper halves of their merged contents, each
+/// sorted.
+#[cfg(all(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        all(target_arch = "wasm32", target_feature = "simd128")
+    ),
+    not(feature = "force-safe")
+))]
+macro_rules! merge_kernel {
+    (
+        $(#[$attr:meta])*
+        unsafe fn $name:ident($t:ty; $lanes:expr) {
+            vector: $v:ty,
+            load: $load:path,
+            store: $store:path,
+            merge: $merge:path,
+        }
+    ) => {
+        $(#[$attr])*
+        pub(super) unsafe fn $name(a: &[$t], b: &[$t]) -> Vec<$t> {
+            const LANES: usize = $lanes;
+
+            let mut out: Vec<$t> = Vec::with_capacity(a.len() + b.len());
+            let mut e = Emitter::new(out.as_mut_ptr(), a[0].min(b[0]));
+
+            if a.len() < LANES || b.len() < LANES {
+                e.emit_merged(a, b);
+                out.set_len(e.len);
+                return out;
+            }
+
+            let mut lo: $v = $load(a.as_ptr());
+            let mut hi: $v = $load(b.as_ptr());
+            let mut ia = LANES;
+            let mut ib = LANES;
+            let mut buf: [$t; LANES] = [0; LANES];
+
+            // Each round merges the next block with the upper half of the previous round. The
+            // next block comes from whichever input has the smaller head, so every element of the
+            // lower half is at most every element that hasn't been emitted yet.
+            loop {
+                let (l, h) = $merge(lo, hi);
+                $store(buf.as_mut_ptr(), l);
+                for &x in &buf {
+                    e.emit(x);
+                }
+                hi = h;
+
+                if ia + LANES > a.len() || ib + LANES > b.len() {
+                    break;
+                }
+
+                if a[ia] <= b[ib] {
+                    lo = $load(a.as_ptr().add(ia));
+                    ia += LANES;
+                } else {
+                    lo = $load(b.as_ptr().add(ib));
+                    ib += LANES;
+                }
+            }
+
+            // At least one input has less than a full block left. Merge it with the upper half of
+            // the last round on the stack, then merge the result with the other input.
+            $store(buf.as_mut_ptr(), hi);
+            let (short, long) = if a.len() - ia < LANES {
+                (&a[ia..], &b[ib..])
+            } else {
+                (&b[ib..], &a[ia..])
+            };
+            let mut rest: [$t; 2 * LANES] = [0; 2 * LANES];
+            let n = merge_small(&buf, short, &mut rest);
+            e.emit_merged(&rest[..n], long);
+
+            out.set_len(e.len);
+            out
+        }
+    };
+}
+
+#[cfg(all(target_arch = "x86_64", not(feature = "force-safe")))]
+mod avx2 {
+    use super::{merge_small, Emitter};
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn load<T>(p: *const T) -> __m256i {
+        _mm256_loadu_si256(p as *const __m256i)
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn store<T>(p: *mut T, v: __m256i) {
+        _mm256_storeu_si256(p as *mut __m256i, v)
+    }
+
+    /// Sorts a bitonic vector of eight `u32`s.
+    #[target_feature(enable = "avx2")]
+    unsafe fn sort_bitonic_u32(v: __m256i) -> __m256i {
+        let p = _mm256_permute2x128_si256(v, v, 0b0000_0001);
+        let v = _mm256_blend_epi32(_mm256_min_epu32(v, p), _mm256_max_epu32(v, p), 0b1111_0000);
+        let p = _mm256_shuffle_epi32(v, 0b01_00_11_10);
+        let v = _mm256_blend_epi32(_mm256_min_epu32(v, p), _mm256_max_epu32(v, p), 0b1100_1100);
+        let p = _mm256_shuffle_epi32(v, 0b10_11_00_01);
+        _mm256_blend_epi32(_mm256_min_epu32(v, p), _mm256_max_epu32(v, p), 0b1010_1010)
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn merge_u32(a: __m256i, b: __m256i) -> (__m256i, __m256i) {
+        // `a` followed by `b` reversed is a bitonic sequence.
+        let b = _mm256_permutevar8x32_epi32(b, _mm256_setr_epi32(7, 6, 5, 4, 3, 2, 1, 0));
+        let lo = _mm256_min_epu32(a, b);
+        let hi = _mm256_max_epu32(a, b);
+        (sort_bitonic_u32(lo), sort_bitonic_u32(hi))
+    }
+
+    /// AVX2 has no 64-bit `min` or `max`, so these are built from a signed comparison. Unsigned
+    /// inputs are biased into signed range first.
+    macro_rules! merge_64 {
+        ($minmax:ident, $sort:ident, $merge:ident, |$a:ident, $b:ident| $gt:expr) => {
+            #[target_feature(enable = "avx2")]
+            unsafe fn $minmax($a: __m256i, $b: __m256i) -> (__m256i, __m256i) {
+                let gt = $gt;
+                (
+                    _mm256_blendv_epi8($a, $b, gt),
+                    _mm256_blendv_epi8($b, $a, gt),
+                )
+            }
+
+            /// Sorts a bitonic vector of four 64-bit integers.
+            #[target_feature(enable = "avx2")]
+            unsafe fn $sort(v: __m256i) -> __m256i {
+                let p = _mm256_permute4x64_epi64(v, 0b01_00_11_10);
+                let (min, max) = $minmax(v, p);
+                let v = _mm256_blend_epi32(min, max, 0b1111_0000);
+                let p = _mm256_permute4x64_epi64(v, 0b10_11_00_01);
+                let (min, max) = $minmax(v, p);
+                _mm256_blend_epi32(min, max, 0b1100_1100)
+            }
+
+            #[target_feature(enable = "avx2")]
+            unsafe fn $merge(a: __m256i, b: __m256i) -> (__m256i, __m256i) {
+                let b = _mm256_permute4x64_epi64(b, 0b00_01_10_11);
+                let (lo, hi) = $minmax(a, b);
+                ($sort(lo), $sort(hi))
+            }
+        };
+    }
+
+    merge_64!(minmax_i64, sort_bitonic_i64, merge_i64, |a, b| {
+        _mm256_cmpgt_epi64(a, b)
+    });
+
+    merge_64!(minmax_u64, sort_bitonic_u64, merge_u64, |a, b| {
+        let bias = _mm256_set1_epi64x(i64::MIN);
+        _mm256_cmpgt_epi64(_mm256_xor_si256(a, bias), _mm256_xor_si256(b, bias))
+    });
+
+    merge_kernel! {
+        #[target_feature(enable = "avx2")]
+        unsafe fn merge_uniq_u32(u32; 8) {
+            vector: __m256i,
+            load: load,
+            store: store,
+            merge: merge_u32,
+        }
+    }
+
+    merge_kernel! {
+        #[target_feature(enable = "avx2")]
+        unsafe fn merge_uniq_u64(u64; 4) {
+            vector: __m256i,
+            load: load,
+            store: store,
+            merge: merge_u64,
+        }
+    }
+
+    merge_kernel! {
+        #[target_feature(enable = "avx2")]
+        unsafe fn merge_uniq_i64(i64; 4) {
+            vector: __m256i,
+            load: load,
+            store: store,
+            merge: merge_i64,
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", not(feature = "force-safe")))]
+mod avx512 {
+    use super::{merge_small, Emitter};
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn load<T>(p: *const T) -> __m512i {
+        _mm512_loadu_si512(p as *const _)
+    }
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn store<T>(p: *mut T, v: __m512i) {
+        _mm512_storeu_si512(p as *mut _, v)
+    }
+
+    /// Generates a merge network. Each stage of the bitonic sort compares every lane `i` with lane
+    /// `i ^ d`, keeping the minimum in the lanes where bit `d` of `i` is clear.
+    macro_rules! merge_512 {
+        (
+            $sort:ident,
+            $merge:ident,
+            iota: $iota:expr,
+            last: $last:expr,
+            set1: $set1:ident,
+            permute: $permute:ident,
+            blend: $blend:ident,
+            min: $min:ident,
+            max: $max:ident,
+            stages: [$(($d:expr, $mask:expr)),*]
+        ) => {
+            /// Sorts a bitonic vector.
+            #[target_feature(enable = "avx512f")]
+            unsafe fn $sort(mut v: __m512i, iota: __m512i) -> __m512i {
+                $(
+                    let p = $permute(_mm512_xor_si512(iota, $set1($d)), v);
+                    v = $blend($mask, $min(v, p), $max(v, p));
+                )*
+                v
+            }
+
+            #[target_feature(enable = "avx512f")]
+            unsafe fn $merge(a: __m512i, b: __m512i) -> (__m512i, __m512i) {
+                let iota = $iota;
+
+                // `a` followed by `b` reversed is a bitonic sequence. XOR-ing every lane index
+                // with the last one reverses them.
+                let b = $permute(_mm512_xor_si512(iota, $set1($last)), b);
+                let lo = $min(a, b);
+                let hi = $max(a, b);
+                ($sort(lo, iota), $sort(hi, iota))
+            }
+        };
+    }
+
+    merge_512!(
+        sort_bitonic_u32,
+        merge_u32,
+        iota: _mm512_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15),
+        last: 15,
+        set1: _mm512_set1_epi32,
+        permute: _mm512_permutexvar_epi32,
+        blend: _mm512_mask_blend_epi32,
+        min: _mm512_min_epu32,
+        max: _mm512_max_epu32,
+        stages: [(8, 0xff00), (4, 0xf0f0), (2, 0xcccc), (1, 0xaaaa)]
+    );
+
+    merge_512!(
+        sort_bitonic_u64,
+        merge_u64,
+        iota: _mm512_setr_epi64(0, 1, 2, 3, 4, 5, 6, 7),
+        last: 7,
+        set1: _mm512_set1_epi64,
+        permute: _mm512_permutexvar_epi64,
+        blend: _mm512_mask_blend_epi64,
+        min: _mm512_min_epu64,
+        max: _mm512_max_epu64,
+        stages: [(4, 0xf0), (2, 0xcc), (1, 0xaa)]
+    );
+
+    merge_512!(
+        sort_bitonic_i64,
+        merge_i64,
+        iota: _mm512_setr_epi64(0, 1, 2, 3, 4, 5, 6, 7),
+        last: 7,
+        set1: _mm512_set1_epi64,
+        permute: _mm512_permutexvar_epi64,
+        blend: _mm512_mask_blend_epi64,
+        min: _mm512_min_epi64,
+        max: _mm512_max_epi64,
+        stages: [(4, 0xf0), (2, 0xcc), (1, 0xaa)]
+    );
+
+    merge_kernel! {
+        #[target_feature(enable = "avx512f")]
+        unsafe fn merge_uniq_u32(u32; 16) {
+            vector: __m512i,
+            load: load,
+            store: store,
+            merge: merge_u32,
+        }
+    }
+
+    merge_kernel! {
+        #[target_feature(enable = "avx512f")]
+        unsafe fn merge_uniq_u64(u64; 8) {
+            vector: __m512i,
+            load: load,
+            store: store,
+            merge: merge_u64,
+        }
+    }
+
+    merge_kernel! {
+        #[target_feature(enable = "avx512f")]
+        unsafe fn merge_uniq_i64(i64; 8) {
+            vector: __m512i,
+            load: load,
+            store: store,
+            merge: merge_i64,
+        }
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", not(feature = "force-safe")))]
+mod neon {
+    use super::{merge_small, Emitter};
+    use std::arch::aarch64::*;
+
+    /// Sorts a bitonic vector of four `u32`s.
+    #[target_feature(enable = "neon")]
+    unsafe fn sort_bitonic_u32(v: uint32x4_t) -> uint32x4_t {
+        let p = vextq_u32(v, v, 2);
+        let (min, max) = (vminq_u32(v, p), vmaxq_u32(v, p));
+        let v = vcombine_u32(vget_low_u32(min), vget_high_u32(max));
+        let p = vrev64q_u32(v);
+        let (min, max) = (vminq_u32(v, p), vmaxq_u32(v, p));
+        // Lanes 0 and 1 of `min` are equal, as are lanes 0 and 1 of `max`, and likewise for lanes
+        // 2 and 3.
+        vtrn1q_u32(min, max)
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn merge_u32(a: uint32x4_t, b: uint32x4_t) -> (uint32x4_t, uint32x4_t) {
+        // `a` followed by `b` reversed is a bitonic sequence.
+        let b = vrev64q_u32(b);
+        let b = vextq_u32(b, b, 2);
+        let lo = vminq_u32(a, b);
+        let hi = vmaxq_u32(a, b);
+        (sort_bitonic_u32(lo), sort_bitonic_u32(hi))
+    }
+
+    /// NEON has no 64-bit `min` or `max`, so these are built from a comparison and a bitwise
+    /// select.
+    macro_rules! merge_64 {
+        (
+            $v:ty,
+            $minmax:ident,
+            $sort:ident,
+            $merge:ident,
+            $cgt:ident,
+            $bsl:ident,
+            $ext:ident,
+            $zip1:ident
+        ) => {
+            #[target_feature(enable = "neon")]
+            unsafe fn $minmax(a: $v, b: $v) -> ($v, $v) {
+                let gt = $cgt(a, b);
+                ($bsl(gt, b, a), $bsl(gt, a, b))
+            }
+
+            /// Sorts a bitonic vector of two 64-bit integers.
+            #[target_feature(enable = "neon")]
+            unsafe fn $sort(v: $v) -> $v {
+                let (min, max) = $minmax(v, $ext(v, v, 1));
+                $zip1(min, max)
+            }
+
+            #[target_feature(enable = "neon")]
+            unsafe fn $merge(a: $v, b: $v) -> ($v, $v) {
+                let b = $ext(b, b, 1);
+                let (lo, hi) = $minmax(a, b);
+                ($sort(lo), $sort(hi))
+            }
+        };
+    }
+
+    merge_64!(
+        uint64x2_t,
+        minmax_u64,
+        sort_bitonic_u64,
+        merge_u64,
+        vcgtq_u64,
+        vbslq_u64,
+        vextq_u64,
+        vzip1q_u64
+    );
+    merge_64!(
+        int64x2_t,
+        minmax_i64,
+        sort_bitonic_i64,
+        merge_i64,
+        vcgtq_s64,
+        vbslq_s64,
+        vextq_s64,
+        vzip1q_s64
+    );
+
+    merge_kernel! {
+        #[target_feature(enable = "neon")]
+        unsafe fn merge_uniq_u32(u32; 4) {
+            vector: uint32x4_t,
+            load: vld1q_u32,
+            store: vst1q_u32,
+            merge: merge_u32,
+        }
+    }
+
+    merge_kernel! {
+        #[target_feature(enable = "neon")]
+        unsafe fn merge_uniq_u64(u64; 2) {
+            vector: uint64x2_t,
+            load: vld1q_u64,
+            store: vst1q_u64,
+            merge: merge_u64,
+        }
+    }
+
+    merge_kernel! {
+        #[target_feature(enable = "neon")]
+        unsafe fn merge_uniq_i64(i64; 2) {
+            vector: int64x2_t,
+            load: vld1q_s64,
+            store: vst1q_s64,
+            merge: merge_i64,
+        }
+    }
+}
+
+#[cfg(all(
+    target_arch = "wasm32",
+    target_feature = "simd128",
+    not(feature = "force-safe")
+))]
+mod simd128 {
+    use super::{merge_small, Emitter};
+    use std::arch::wasm32::*;
+
+    unsafe fn load<T>(p: *const T) -> v128 {
+        v128_load(p as *const v128)
+    }
+
+    unsafe fn store<T>(p: *mut T, v: v128) {
+        v128_store(p as *mut v128, v)
+    }
+
+    /// Sorts a bitonic vector of four `u32`s.
+    unsafe fn sort_bitonic_u32(v: v128) -> v128 {
+        let p = i32x4_shuffle::<2, 3, 0, 1>(v, v);
+        let v = i32x4_shuffle::<0, 1, 6, 7>(u32x4_min(v, p), u32x4_max(v, p));
+        let p = i32x4_shuffle::<1, 0, 3, 2>(v, v);
+        i32x4_shuffle::<0, 5, 2, 7>(u32x4_min(v, p), u32x4_max(v, p))
+    }
+
+    unsafe fn merge_u32(a: v128, b: v128) -> (v128, v128) {
+        // `a` followed by `b` reversed is a bitonic sequence.
+        let b = i32x4_shuffle::<3, 2, 1, 0>(b, b);
+        let lo = u32x4_min(a, b);
+        let hi = u32x4_max(a, b);
+        (sort_bitonic_u32(lo), sort_bitonic_u32(hi))
+    }
+
+    /// SIMD128 has no 64-bit `min` or `max`, so these are built from a signed comparison and a
+    /// bitwise select. Unsigned inputs are biased into signed range first.
+    macro_rules! merge_64 {
+        ($minmax:ident, $sort:ident, $merge:ident, |$a:ident, $b:ident| $gt:expr) => {
+            unsafe fn $minmax($a: v128, $b: v128) -> (v128, v128) {
+                let gt = $gt;
+                (v128_bitselect($b, $a, gt), v128_bitselect($a, $b, gt))
+            }
+
+            /// Sorts a bitonic vector of two 64-bit integers.
+            unsafe fn $sort(v: v128) -> v128 {
+                let (min, max) = $minmax(v, i64x2_shuffle::<1, 0>(v, v));
+                i64x2_shuffle::<0, 3>(min, max)
+            }
+
+            unsafe fn $merge(a: v128, b: v128) -> (v128, v128) {
+                let b = i64x2_shuffle::<1, 0>(b, b);
+                let (lo, hi) = $minmax(a, b);
+                ($sort(lo), $sort(hi))
+            }
+        };
+    }
+
+    merge_64!(minmax_i64, sort_bitonic_i64, merge_i64, |a, b| {
+        i64x2_gt(a, b)
+    });
+
+    merge_64!(minmax_u64, sort_bitonic_u64, merge_u64, |a, b| {
+        let bias = i64x2_splat(i64::MIN);
+        i64x2_gt(v128_xor(a, bias), v128_xor(b, bias))
+    });
+
+    merge_kernel! {
+        unsafe fn merge_uniq_u32(u32; 4) {
+            vector: v128,
+            load: load,
+            store: store,
+            merge: merge_u32,
+        }
+    }
+
+    merge_kernel! {
+        unsafe fn merge_uniq_u64(u64; 2) {
+            vector: v128,
+            load: load,
+            store: store,
+            merge: merge_u64,
+        }
+    }
+
+    merge_kernel! {
+        unsafe fn merge_uniq_i64(i64; 2) {
+            vector: v128,
+            load: load,
+            store: store,
+            merge: merge_i64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    const BACKENDS: [Backend; 5] = [
+        Backend::Scalar,
+        Backend::Avx2,
+        Backend::Avx512,
+        Backend::Neon,
+        Backend::Simd128,
+    ];
+
+    /// Checks every backend supported by the CPU against `naive`.
+    fn check<T: SimdElement>(mut a: Vec<T>, mut b: Vec<T>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected = naive(a.clone(), b.clone());
+        BACKENDS
+            .iter()
+            .filter(|backend| backend.is_supported())
+            .all(|&backend| expected == merge_uniq_with(backend, a.clone(), b.clone()))
+    }
+
+    #[quickcheck]
+    fn merge_uniq_u32(a: Vec<u32>, b: Vec<u32>) -> bool {
+        check(a, b)
+    }
+
+    #[quickcheck]
+    fn merge_uniq_u64(a: Vec<u64>, b: Vec<u64>) -> bool {
+        // Rotating moves the low bit to the top, so both halves of the range are exercised.
+        let a = a.into_iter().map(|x| x.rotate_right(1)).collect();
+        let b = b.into_iter().map(|x| x.rotate_right(1)).collect();
+        check(a, b)
+    }
+
+    #[quickcheck]
+    fn merge_uniq_i64(a: Vec<i64>, b: Vec<i64>) -> bool {
+        check(a, b)
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    #[repr(transparent)]
+    struct Id(u32);
+
+    impl PrimitiveKey for Id {
+        type Primitive = u32;
+
+        fn to_primitive(self) -> u32 {
+            self.0
+        }
+
+        fn from_primitive(p: u32) -> Self {
+            Id(p)
+        }
+    }
+
+    #[quickcheck]
+    fn merge_uniq_keys_impl(a: Vec<u32>, b: Vec<u32>) -> bool {
+        let ids = |v: Vec<u32>| {
+            let mut v: Vec<_> = v.into_iter().map(Id).collect();
+            v.sort_unstable();
+            v.dedup();
+            v
+        };
+        let (a, b) = (ids(a), ids(b));
+
+        let expected = naive(a.clone(), b.clone());
+        BACKENDS
+            .iter()
+            .filter(|backend| backend.is_supported())
+            .all(|&backend| expected == merge_uniq_keys_with(backend, a.clone(), b.clone()))
+    }
+
+    #[quickcheck]
+    fn merge_uniq_long(a: Vec<u32>, b: Vec<u32>, len: u32) -> bool {
+        // Long enough to run the vector loop many times, with both sparse and dense overlap.
+        let a = a.into_iter().chain(0..len * 8).map(|x| x * 3).collect();
+        let b = b.into_iter().chain(0..len * 8).map(|x| x * 2).collect();
+        check(a, b)
+    }
+}
@@ -0,0 +1,90 @@
+//! Merging `SmallVec`s without spilling to the heap when the result fits inline.
+
+use smallvec::{Array, SmallVec};
+
+/// Merges two sorted, deduplicated `SmallVec`s.
+///
+/// If the merged result fits in `A`, no heap allocation takes place.
+pub fn merge_uniq<A>(mut a: SmallVec<A>, b: SmallVec<A>) -> SmallVec<A>
+where
+    A: Array,
+    A::Item: Ord,
+{
+    // If one of the lists is zero-length, we don't need to do any work.
+    if a.is_empty() {
+        return b;
+    }
+    if b.is_empty() {
+        return a;
+    }
+
+    // If the inputs don't overlap, `a` can be extended in place.
+    if a.last() < b.first() {
+        a.extend(b);
+        return a;
+    }
+
+    merge(a, b)
+}
+
+#[cfg(not(feature = "force-safe"))]
+fn merge<A>(mut a: SmallVec<A>, mut b: SmallVec<A>) -> SmallVec<A>
+where
+    A: Array,
+    A::Item: Ord,
+{
+    let mut out = SmallVec::with_capacity(a.len() + b.len());
+
+    // SAFETY: `out` has room for both inputs, and ownership of their elements is given up before
+    // they are moved.
+    unsafe {
+        let alen = a.len();
+        let blen = b.len();
+        a.set_len(0);
+        b.set_len(0);
+
+        let len = crate::merge2_uniq::merge_raw_parts(
+            a.as_mut_ptr(),
+            alen,
+            b.as_mut_ptr(),
+            blen,
+            out.as_mut_ptr(),
+        );
+        out.set_len(len);
+    }
+
+    out
+}
+
+#[cfg(feature = "force-safe")]
+fn merge<A>(a: SmallVec<A>, b: SmallVec<A>) -> SmallVec<A>
+where
+    A: Array,
+    A::Item: Ord,
+{
+    let mut out = SmallVec::with_capacity(a.len() + b.len());
+    out.extend(crate::iter::MergeUniq::new(a.into_iter(), b.into_iter()));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn merge_uniq_impl(mut a: Vec<usize>, mut b: Vec<usize>) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let expected: Vec<_> = naive(a.clone(), b.clone());
+
+        let a: SmallVec<[usize; 8]> = a.into_iter().collect();
+        let b: SmallVec<[usize; 8]> = b.into_iter().collect();
+        let actual = merge_uniq(a, b);
+        expected[..] == actual[..]
+    }
+}
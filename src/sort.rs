@@ -0,0 +1,170 @@
+//! Sorting vectors that are mostly sorted already into sorted, deduplicated ones.
+//!
+//! `natural_sort_uniq` finds the ascending and descending runs already in its input and merges
+//! them with the same kernels as `merge_uniq`, so a concatenation of a few sorted batches is sorted
+//! in close to linear time, and duplicates are dropped as the runs meet.
+//!
+//! `merge_uniq` concatenates runs that don't overlap and gallops over long stretches that don't
+//! interleave, so this pays off most when each batch overlaps only the ones next to it, as in logs
+//! of events that arrive a little out of order. Batches of unrelated keys all overlap each other,
+//! and for those `slice::sort` followed by `Vec::dedup` does as well or better.
+
+use crate::merge_uniq;
+
+/// The shortest average run for which the runs are merged, rather than the whole input sorted.
+const MIN_RUN: usize = 32;
+
+/// Sorts and deduplicates `v` by merging the runs already in it. Of several equal elements, the
+/// first is kept.
+///
+/// The result is the same as that of `v.sort(); v.dedup()`. Runs are maximal ascending or strictly
+/// descending stretches of `v`; descending ones are reversed in place. If the runs are short on
+/// average, `v` is not mostly sorted, and it is sorted as a whole instead.
+pub fn natural_sort_uniq<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+    let mut starts = Vec::new();
+    let mut start = 0;
+    while start < v.len() {
+        starts.push(start);
+        if starts.len() * MIN_RUN > v.len() {
+            v.sort();
+            v.dedup();
+            return v;
+        }
+        start += find_run(&mut v[start..]);
+    }
+
+    // The runs are split off from the back, so that each element is moved once.
+    let mut runs = Vec::with_capacity(starts.len());
+    for &start in starts.iter().rev() {
+        let mut run = v.split_off(start);
+        run.dedup();
+        runs.push(run);
+    }
+    runs.reverse();
+    merge_runs(runs)
+}
+
+/// Returns the length of the run at the start of `v`, reversing it if it's descending.
+fn find_run<T: Ord>(v: &mut [T]) -> usize {
+    if v.len() < 2 {
+        return v.len();
+    }
+    let mut end = 2;
+    if v[1] < v[0] {
+        // Only strictly descending runs are reversed, so that equal elements keep their order.
+        while end < v.len() && v[end] < v[end - 1] {
+            end += 1;
+        }
+        v[..end].reverse();
+    } else {
+        while end < v.len() && v[end] >= v[end - 1] {
+            end += 1;
+        }
+    }
+    end
+}
+
+/// Merges sorted, deduplicated runs in passes over adjacent pairs. Of several equal elements, the
+/// one from the earliest run is kept.
+fn merge_runs<T: Ord>(mut runs: Vec<Vec<T>>) -> Vec<T> {
+    while runs.len() > 1 {
+        let mut merged = Vec::with_capacity(runs.len().div_ceil(2));
+        let mut runs_iter = runs.into_iter();
+        while let Some(a) = runs_iter.next() {
+            merged.push(match runs_iter.next() {
+                Some(b) => merge_uniq(a, b),
+                None => a,
+            });
+        }
+        runs = merged;
+    }
+    runs.pop().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sorted_unique;
+    use quickcheck_macros::quickcheck;
+    use std::cmp::Ordering;
+
+    /// Concatenates the batches, each sorted and, if its first element is odd, reversed.
+    fn presorted(batches: Vec<Vec<u8>>) -> Vec<u8> {
+        batches
+            .into_iter()
+            .flat_map(|mut batch| {
+                batch.sort_unstable();
+                if batch.first().is_some_and(|x| x % 2 == 1) {
+                    batch.reverse();
+                }
+                batch
+            })
+            .collect()
+    }
+
+    /// An element ordered by its key alone, tagged with its position in the input.
+    #[derive(Debug)]
+    struct Tagged {
+        key: u8,
+        pos: usize,
+    }
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Self) -> bool {
+            self.key == other.key
+        }
+    }
+
+    impl Eq for Tagged {}
+
+    impl PartialOrd for Tagged {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Tagged {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.key.cmp(&other.key)
+        }
+    }
+
+    /// Tags each element with its position, to check which of several equal elements is kept.
+    fn tagged(v: &[u8]) -> Vec<Tagged> {
+        v.iter()
+            .enumerate()
+            .map(|(pos, &key)| Tagged { key, pos })
+            .collect()
+    }
+
+    fn untagged(v: Vec<Tagged>) -> Vec<(u8, usize)> {
+        v.into_iter().map(|x| (x.key, x.pos)).collect()
+    }
+
+    #[quickcheck]
+    fn natural_sort_uniq_impl(v: Vec<u32>) -> bool {
+        natural_sort_uniq(v.clone()) == sorted_unique(v)
+    }
+
+    #[quickcheck]
+    fn natural_sort_uniq_presorted(batches: Vec<Vec<u8>>) -> bool {
+        let v = presorted(batches);
+        untagged(natural_sort_uniq(tagged(&v))) == untagged(sorted_unique(tagged(&v)))
+    }
+
+    #[test]
+    fn natural_sort_uniq_batches() {
+        let batches: Vec<Vec<u8>> = (0..8u8)
+            .map(|i| (0..200).map(|x: u8| x.wrapping_mul(i * 2 + 1)).collect())
+            .collect();
+        let v = presorted(batches);
+        assert_eq!(
+            untagged(natural_sort_uniq(tagged(&v))),
+            untagged(sorted_unique(tagged(&v)))
+        );
+        assert_eq!(
+            natural_sort_uniq(vec![3, 2, 2, 1, 5, 4, 4]),
+            vec![1, 2, 3, 4, 5]
+        );
+    }
+}
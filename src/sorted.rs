@@ -0,0 +1,597 @@
+//! A vector that is known to be sorted and deduplicated.
+
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "indexmap")]
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{
+    BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Deref, Sub, SubAssign,
+};
+
+#[cfg(feature = "serde")]
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::checked::is_sorted_unique;
+use crate::kway;
+use crate::merge2_uniq;
+use crate::scratch::PingPongBuffers;
+use crate::set_ops::{difference, intersect_k, symmetric_difference};
+
+/// A `Vec` whose elements are sorted in ascending order, without duplicates.
+///
+/// The invariant is established when the vector is built and kept by every method, so set
+/// operations can go straight to the merge kernels without having to trust their callers.
+/// Mutable access to the elements is not offered, since it could break the invariant.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortedUniqueVec<T> {
+    inner: Vec<T>,
+}
+
+impl<T> SortedUniqueVec<T> {
+    pub fn new() -> Self {
+        SortedUniqueVec { inner: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        SortedUniqueVec {
+            inner: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.inner
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.inner
+    }
+}
+
+impl<T: Ord> SortedUniqueVec<T> {
+    /// Sorts and deduplicates `v`. Of several equal elements, the first is kept.
+    ///
+    /// When `v` is a concatenation of sorted batches that each overlap only their neighbours,
+    /// `sort::natural_sort_uniq` is faster.
+    pub fn from_vec(mut v: Vec<T>) -> Self {
+        v.sort();
+        v.dedup();
+        SortedUniqueVec { inner: v }
+    }
+
+    /// Wraps `v` if it's already sorted and deduplicated, or returns it unchanged if not.
+    pub fn try_from_vec(v: Vec<T>) -> Result<Self, Vec<T>> {
+        if is_sorted_unique(&v) {
+            Ok(SortedUniqueVec { inner: v })
+        } else {
+            Err(v)
+        }
+    }
+
+    /// Wraps `v` without checking that it's sorted and deduplicated.
+    ///
+    /// Passing a vector that isn't is a logic error: it won't cause undefined behavior, but the
+    /// results of later operations are unspecified. This is checked in debug builds.
+    pub fn from_vec_unchecked(v: Vec<T>) -> Self {
+        debug_assert!(is_sorted_unique(&v), "input is not sorted and deduplicated");
+        SortedUniqueVec { inner: v }
+    }
+
+    /// Returns `true` if `x` is an element, using binary search.
+    pub fn contains(&self, x: &T) -> bool {
+        self.inner.binary_search(x).is_ok()
+    }
+
+    /// Inserts `x`, returning `false` if it was already present.
+    ///
+    /// This shifts every larger element over, so use `insert_sorted_batch` or `extend` to add many
+    /// elements at once.
+    pub fn insert(&mut self, x: T) -> bool {
+        match self.inner.binary_search(&x) {
+            Ok(_) => false,
+            Err(i) => {
+                self.inner.insert(i, x);
+                true
+            }
+        }
+    }
+
+    /// Sorts `batch` and merges it in with a single pass over the existing elements.
+    ///
+    /// Elements that are already present are kept in place of the equal ones from `batch`. The
+    /// merge happens in place if there is enough spare capacity for `batch`.
+    pub fn insert_sorted_batch(&mut self, mut batch: Vec<T>) {
+        batch.sort();
+        batch.dedup();
+        merge2_uniq::in_place(&mut self.inner, batch);
+    }
+
+    /// Removes `x`, returning `false` if it wasn't present.
+    pub fn remove(&mut self, x: &T) -> bool {
+        match self.inner.binary_search(x) {
+            Ok(i) => {
+                self.inner.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the elements in either `self` or `other`. Equal elements are taken from `self`.
+    pub fn union(self, other: Self) -> Self {
+        SortedUniqueVec {
+            inner: crate::merge_uniq(self.inner, other.inner),
+        }
+    }
+
+    /// Returns the elements in both `self` and `other`.
+    pub fn intersect(self, other: Self) -> Self {
+        SortedUniqueVec {
+            inner: intersect_k(vec![self.inner, other.inner]),
+        }
+    }
+
+    /// Returns the elements in `self` but not in `other`.
+    pub fn difference(self, other: &Self) -> Self {
+        SortedUniqueVec {
+            inner: difference(self.inner, &other.inner),
+        }
+    }
+
+    /// Returns the elements in exactly one of `self` and `other`.
+    pub fn symmetric_difference(self, other: Self) -> Self {
+        SortedUniqueVec {
+            inner: symmetric_difference(self.inner, other.inner),
+        }
+    }
+}
+
+/// Implements a binary operator and its assigning form with `$f`, one of the set operations.
+macro_rules! set_operator {
+    ($Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident, $f:expr) => {
+        impl<T: Ord> $Op for SortedUniqueVec<T> {
+            type Output = Self;
+
+            fn $op(self, rhs: Self) -> Self {
+                ($f)(self, rhs)
+            }
+        }
+
+        impl<T: Ord> $OpAssign for SortedUniqueVec<T> {
+            fn $op_assign(&mut self, rhs: Self) {
+                *self = ($f)(mem::take(self), rhs);
+            }
+        }
+    };
+}
+
+set_operator!(BitOr, bitor, BitOrAssign, bitor_assign, Self::union);
+set_operator!(BitAnd, bitand, BitAndAssign, bitand_assign, Self::intersect);
+set_operator!(Sub, sub, SubAssign, sub_assign, |a: Self, b: Self| {
+    a.difference(&b)
+});
+set_operator!(
+    BitXor,
+    bitxor,
+    BitXorAssign,
+    bitxor_assign,
+    Self::symmetric_difference
+);
+
+impl<T> Default for SortedUniqueVec<T> {
+    fn default() -> Self {
+        SortedUniqueVec::new()
+    }
+}
+
+impl<T> Deref for SortedUniqueVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.inner
+    }
+}
+
+impl<T> AsRef<[T]> for SortedUniqueVec<T> {
+    fn as_ref(&self) -> &[T] {
+        &self.inner
+    }
+}
+
+impl<T: Ord> From<Vec<T>> for SortedUniqueVec<T> {
+    fn from(v: Vec<T>) -> Self {
+        SortedUniqueVec::from_vec(v)
+    }
+}
+
+/// Takes the elements of `set` in order, since they are already sorted and deduplicated.
+impl<T: Ord> From<BTreeSet<T>> for SortedUniqueVec<T> {
+    fn from(set: BTreeSet<T>) -> Self {
+        SortedUniqueVec {
+            inner: set.into_iter().collect(),
+        }
+    }
+}
+
+/// Builds the set in bulk. `BTreeSet`'s `FromIterator` sorts its input before building the tree
+/// from it, and sorting elements that are already in order takes a single pass.
+impl<T: Ord> From<SortedUniqueVec<T>> for BTreeSet<T> {
+    fn from(v: SortedUniqueVec<T>) -> Self {
+        v.inner.into_iter().collect()
+    }
+}
+
+/// Takes the entries of `map` in order, since they are already sorted by key and so also as pairs.
+impl<K: Ord, V: Ord> From<BTreeMap<K, V>> for SortedUniqueVec<(K, V)> {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        SortedUniqueVec {
+            inner: map.into_iter().collect(),
+        }
+    }
+}
+
+/// Builds the map in bulk, like the conversion to `BTreeSet`. The pairs are only unique as pairs,
+/// so when a key appears with several values, the last (and greatest) one is kept.
+impl<K: Ord, V: Ord> From<SortedUniqueVec<(K, V)>> for BTreeMap<K, V> {
+    fn from(v: SortedUniqueVec<(K, V)>) -> Self {
+        v.inner.into_iter().collect()
+    }
+}
+
+/// Sorts the elements of `set`. They are already distinct, so they don't need to be deduplicated.
+#[cfg(feature = "indexmap")]
+impl<T: Ord, S> From<indexmap::IndexSet<T, S>> for SortedUniqueVec<T> {
+    fn from(set: indexmap::IndexSet<T, S>) -> Self {
+        let mut inner: Vec<_> = set.into_iter().collect();
+        inner.sort_unstable();
+        SortedUniqueVec { inner }
+    }
+}
+
+/// Inserts the elements in order, so the set iterates in sorted order until it is modified.
+#[cfg(feature = "indexmap")]
+impl<T, S> From<SortedUniqueVec<T>> for indexmap::IndexSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn from(v: SortedUniqueVec<T>) -> Self {
+        let mut set = indexmap::IndexSet::with_capacity_and_hasher(v.inner.len(), S::default());
+        set.extend(v.inner);
+        set
+    }
+}
+
+impl<T> From<SortedUniqueVec<T>> for Vec<T> {
+    fn from(v: SortedUniqueVec<T>) -> Self {
+        v.inner
+    }
+}
+
+/// Sorts and deduplicates the collected elements.
+impl<T: Ord> FromIterator<T> for SortedUniqueVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        SortedUniqueVec::from_vec(iter.into_iter().collect())
+    }
+}
+
+/// Merges sorted, deduplicated runs with `kway::merge_uniq`. Of several equal elements, the one
+/// from the earliest run is kept.
+impl<T: Ord> FromIterator<Vec<T>> for SortedUniqueVec<T> {
+    fn from_iter<I: IntoIterator<Item = Vec<T>>>(iter: I) -> Self {
+        SortedUniqueVec::from_vec_unchecked(kway::merge_uniq(iter.into_iter().collect()))
+    }
+}
+
+/// Collects the new elements into a batch and merges them in with `insert_sorted_batch`.
+impl<T: Ord> Extend<T> for SortedUniqueVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.insert_sorted_batch(iter.into_iter().collect());
+    }
+}
+
+impl<'a, T: Ord + Copy + 'a> Extend<&'a T> for SortedUniqueVec<T> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<T> IntoIterator for SortedUniqueVec<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a SortedUniqueVec<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+/// The shortest buffer a `Builder` fills before sorting it, unless told otherwise.
+const BUILDER_BUFFER_LEN: usize = 1024;
+
+/// Builds a `SortedUniqueVec` from elements pushed in any order.
+///
+/// Pushed elements are buffered, and once the buffer is as long as the elements sorted so far (and
+/// at least as long as the buffer length), it is sorted and merged into them. Each merge at least
+/// doubles the sorted elements, so every element takes part in `O(log n)` merges, and the merges
+/// reuse two buffers rather than allocating a new one each time. Of equal elements, the first one
+/// pushed is kept.
+///
+/// This is a little slower than `SortedUniqueVec::from_vec` or collecting a `BTreeSet`. Unlike
+/// `from_vec`, the builder drops duplicates as it goes, so it never holds more than the distinct
+/// elements and one buffer, and the elements sorted so far can be read before it is finished.
+#[derive(Clone, Debug)]
+pub struct Builder<T> {
+    sorted: PingPongBuffers<T>,
+    pending: Vec<T>,
+    buffer_len: usize,
+}
+
+impl<T> Default for Builder<T> {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl<T> Builder<T> {
+    pub fn new() -> Self {
+        Builder::with_buffer_len(BUILDER_BUFFER_LEN)
+    }
+
+    /// Creates a builder that sorts its buffer once it holds `buffer_len` elements, or as many as
+    /// have been sorted already, whichever is more.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer_len` is zero.
+    pub fn with_buffer_len(buffer_len: usize) -> Self {
+        assert!(buffer_len > 0, "the buffer must hold at least one element");
+        Builder {
+            sorted: PingPongBuffers::new(),
+            pending: Vec::new(),
+            buffer_len,
+        }
+    }
+
+    /// Returns the number of elements pushed since the buffer was last sorted.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl<T: Ord> Builder<T> {
+    pub fn push(&mut self, x: T) {
+        self.pending.push(x);
+        if self.pending.len() >= self.buffer_len.max(self.sorted.current().len()) {
+            self.flush();
+        }
+    }
+
+    /// Sorts the buffer and merges it into the elements sorted so far.
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        self.pending.sort();
+        self.pending.dedup();
+        self.sorted.merge_round(&mut self.pending);
+    }
+
+    /// Returns the elements sorted so far, which don't include those still in the buffer.
+    pub fn sorted(&self) -> &[T] {
+        self.sorted.current()
+    }
+
+    pub fn finish(mut self) -> SortedUniqueVec<T> {
+        self.flush();
+        SortedUniqueVec {
+            inner: self.sorted.into_vec(),
+        }
+    }
+}
+
+impl<T: Ord> Extend<T> for Builder<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for x in iter {
+            self.push(x);
+        }
+    }
+}
+
+/// Serializes the elements as a sequence, like a `Vec`.
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for SortedUniqueVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.inner.serialize(serializer)
+    }
+}
+
+/// Deserializes a sequence, failing if it isn't sorted and deduplicated.
+///
+/// Use `deserialize_repairing` to accept any sequence instead.
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de> + Ord> Deserialize<'de> for SortedUniqueVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let v = Vec::deserialize(deserializer)?;
+        SortedUniqueVec::try_from_vec(v)
+            .map_err(|_| D::Error::custom("sequence is not sorted and deduplicated"))
+    }
+}
+
+/// Deserializes any sequence, sorting and deduplicating it.
+///
+/// For use with `#[serde(deserialize_with = "kmerge::sorted::deserialize_repairing")]`.
+#[cfg(feature = "serde")]
+pub fn deserialize_repairing<'de, D, T>(deserializer: D) -> Result<SortedUniqueVec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Ord,
+{
+    Vec::deserialize(deserializer).map(SortedUniqueVec::from_vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeSet;
+
+    fn set(v: &[u8]) -> BTreeSet<u8> {
+        v.iter().cloned().collect()
+    }
+
+    #[quickcheck]
+    fn from_vec_impl(v: Vec<u8>) -> bool {
+        let expected: Vec<_> = set(&v).into_iter().collect();
+        let sorted = SortedUniqueVec::from_vec(v);
+        SortedUniqueVec::try_from_vec(expected.clone()).as_ref() == Ok(&sorted)
+            && sorted.into_vec() == expected
+    }
+
+    #[quickcheck]
+    fn from_btree_set(v: Vec<u8>) -> bool {
+        SortedUniqueVec::from(set(&v)) == SortedUniqueVec::from_vec(v)
+    }
+
+    #[quickcheck]
+    fn btree_round_trip(v: Vec<u8>, pairs: Vec<(u8, u8)>) -> bool {
+        let sorted = SortedUniqueVec::from_vec(v.clone());
+        let map: BTreeMap<_, _> = pairs.iter().copied().collect();
+        let entries = SortedUniqueVec::from(map.clone());
+
+        // Of the values paired with a key, the greatest is kept.
+        let mut greatest = BTreeMap::new();
+        for &(k, v) in &pairs {
+            let max = greatest.entry(k).or_insert(v);
+            *max = v.max(*max);
+        }
+
+        BTreeSet::from(sorted) == set(&v)
+            && entries.iter().copied().eq(map.clone())
+            && BTreeMap::from(entries) == map
+            && BTreeMap::from(SortedUniqueVec::from_vec(pairs)) == greatest
+    }
+
+    #[cfg(feature = "indexmap")]
+    #[quickcheck]
+    fn index_set_round_trip(v: Vec<u8>) -> bool {
+        let index: indexmap::IndexSet<u8> = v.iter().copied().collect();
+        let sorted = SortedUniqueVec::from(index);
+        let back: indexmap::IndexSet<u8> = sorted.clone().into();
+        sorted == SortedUniqueVec::from_vec(v) && back.iter().eq(sorted.iter())
+    }
+
+    #[quickcheck]
+    fn builder_impl(v: Vec<(u8, u8)>, buffer_len: u8) -> bool {
+        let mut builder = Builder::with_buffer_len(usize::from(buffer_len % 16) + 1);
+        for &(k, i) in &v {
+            builder.push(crate::kway::ByKey { key: k, item: i });
+            if builder.pending() == 0 && !builder.sorted().windows(2).all(|w| w[0] < w[1]) {
+                return false;
+            }
+        }
+
+        // Of equal keys, the first pushed is kept.
+        let mut expected = std::collections::BTreeMap::new();
+        for &(k, i) in &v {
+            expected.entry(k).or_insert(i);
+        }
+        let built = builder.finish();
+        built.iter().map(|x| (x.key, x.item)).eq(expected)
+    }
+
+    #[quickcheck]
+    fn try_from_vec_rejects(v: Vec<u8>) -> bool {
+        let valid = v.windows(2).all(|w| w[0] < w[1]);
+        SortedUniqueVec::try_from_vec(v).is_ok() == valid
+    }
+
+    #[quickcheck]
+    fn union_intersect_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (sa, sb) = (set(&a), set(&b));
+        let (a, b) = (SortedUniqueVec::from_vec(a), SortedUniqueVec::from_vec(b));
+
+        let union: Vec<_> = sa.union(&sb).cloned().collect();
+        let intersection: Vec<_> = sa.intersection(&sb).cloned().collect();
+        a.clone().union(b.clone()).as_slice() == &union[..]
+            && a.intersect(b).as_slice() == &intersection[..]
+    }
+
+    #[quickcheck]
+    fn operators_impl(a: Vec<u8>, b: Vec<u8>) -> bool {
+        let (sa, sb) = (set(&a), set(&b));
+        let (a, b) = (SortedUniqueVec::from_vec(a), SortedUniqueVec::from_vec(b));
+        let check =
+            |actual: SortedUniqueVec<u8>, expected: BTreeSet<u8>| actual.iter().eq(expected.iter());
+
+        let mut assigned = a.clone();
+        assigned ^= b.clone();
+        assigned -= a.clone();
+        check(assigned, &sb - &sa)
+            && check(a.clone() | b.clone(), &sa | &sb)
+            && check(a.clone() & b.clone(), &sa & &sb)
+            && check(a.clone() - b.clone(), &sa - &sb)
+            && check(a ^ b, &sa ^ &sb)
+    }
+
+    #[quickcheck]
+    fn extend_impl(batches: Vec<Vec<u8>>, reserve: u8) -> bool {
+        let mut expected = BTreeSet::new();
+        let mut actual: SortedUniqueVec<u8> = SortedUniqueVec::with_capacity(reserve as usize);
+        for batch in batches {
+            expected.extend(&batch);
+            actual.extend(&batch);
+        }
+        actual.iter().eq(expected.iter())
+    }
+
+    #[quickcheck]
+    fn from_iter_impl(runs: Vec<Vec<u8>>) -> bool {
+        let expected = set(&runs.concat());
+        let collected: SortedUniqueVec<u8> = runs.iter().flatten().copied().collect();
+
+        let runs = runs
+            .into_iter()
+            .map(|run| set(&run).into_iter().collect::<Vec<_>>());
+        let merged: SortedUniqueVec<u8> = runs.collect();
+        collected.iter().eq(expected.iter()) && merged == collected
+    }
+
+    #[cfg(feature = "serde")]
+    #[quickcheck]
+    fn deserialize_impl(v: Vec<u8>) -> bool {
+        use serde::de::value::{Error, SeqDeserializer};
+
+        let de = || SeqDeserializer::<_, Error>::new(v.clone().into_iter());
+        let valid = SortedUniqueVec::try_from_vec(v.clone()).ok();
+        let validated = SortedUniqueVec::<u8>::deserialize(de()).ok();
+        let repaired = deserialize_repairing(de()).unwrap();
+        validated == valid && repaired == SortedUniqueVec::from_vec(v)
+    }
+
+    #[quickcheck]
+    fn insert_remove_contains(inserts: Vec<u8>, removes: Vec<u8>) -> bool {
+        let mut expected = BTreeSet::new();
+        let mut actual = SortedUniqueVec::new();
+        for x in inserts {
+            if expected.insert(x) != actual.insert(x) {
+                return false;
+            }
+        }
+        for x in removes {
+            if expected.remove(&x) != actual.remove(&x) {
+                return false;
+            }
+        }
+        (0..=u8::MAX).all(|x| expected.contains(&x) == actual.contains(&x))
+            && actual.iter().eq(expected.iter())
+    }
+}
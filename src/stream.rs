@@ -0,0 +1,450 @@
+//! Merging sorted asynchronous streams.
+//!
+//! The combinators only poll a stream when its head is needed to decide what comes next, so a
+//! slow stream never gets polled ahead of the data it's holding up. Streams must be `Unpin`; pin
+//! others with `Box::pin` first.
+//!
+//! Two guarantees make them safe to use over network streams:
+//!
+//! - They buffer at most one item per input, the head that is waiting to be compared, so an input
+//!   is never read further than the merge has got. Backpressure reaches each input as it would
+//!   without the merge.
+//! - They are cancellation safe. The heads live in the combinator rather than in the future that
+//!   polls it, so dropping a `next()` future between polls, as `select!` does with the branches
+//!   that lose, loses no items: the heads received so far are yielded by later polls.
+
+use std::cmp::Ordering;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::stream::{FusedStream, Stream};
+
+/// The head of one input stream.
+enum Head<T> {
+    /// The next item hasn't been received yet.
+    Empty,
+    Ready(T),
+    Done,
+}
+
+impl<T> Head<T> {
+    /// Polls `stream` for its next item if it isn't already known. Returns `true` if the head is
+    /// still unknown, in which case `stream` will wake the task when it's ready.
+    fn fill<S>(&mut self, stream: &mut S, cx: &mut Context<'_>) -> bool
+    where
+        S: Stream<Item = T> + Unpin,
+    {
+        if let Head::Empty = self {
+            match Pin::new(stream).poll_next(cx) {
+                Poll::Ready(Some(x)) => *self = Head::Ready(x),
+                Poll::Ready(None) => *self = Head::Done,
+                Poll::Pending => return true,
+            }
+        }
+        false
+    }
+
+    /// Like `fill`, for streams of `Result`s. An error ends the stream.
+    fn try_fill<S, E>(&mut self, stream: &mut S, cx: &mut Context<'_>) -> Result<bool, E>
+    where
+        S: Stream<Item = Result<T, E>> + Unpin,
+    {
+        if let Head::Empty = self {
+            match Pin::new(stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(x))) => *self = Head::Ready(x),
+                Poll::Ready(Some(Err(e))) => {
+                    *self = Head::Done;
+                    return Err(e);
+                }
+                Poll::Ready(None) => *self = Head::Done,
+                Poll::Pending => return Ok(true),
+            }
+        }
+        Ok(false)
+    }
+
+    fn take(&mut self) -> Option<T> {
+        match std::mem::replace(self, Head::Empty) {
+            Head::Ready(x) => Some(x),
+            head => {
+                *self = head;
+                None
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&T> {
+        match self {
+            Head::Ready(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+/// A stream of the merged, deduplicated contents of two sorted, deduplicated streams.
+///
+/// When an item appears in both inputs, the one from `a` is yielded and the one from `b` is
+/// dropped.
+pub struct MergeUniqStream<S: Stream, T: Stream = S> {
+    a: S,
+    b: T,
+    a_head: Head<S::Item>,
+    b_head: Head<T::Item>,
+}
+
+impl<S: Stream, T: Stream> MergeUniqStream<S, T> {
+    pub fn new(a: S, b: T) -> Self {
+        MergeUniqStream {
+            a,
+            b,
+            a_head: Head::Empty,
+            b_head: Head::Empty,
+        }
+    }
+}
+
+// The heads are never pinned, so only the streams need to be `Unpin`.
+impl<S: Stream + Unpin, T: Stream + Unpin> Unpin for MergeUniqStream<S, T> {}
+
+impl<S, T> Stream for MergeUniqStream<S, T>
+where
+    S: Stream + Unpin,
+    T: Stream<Item = S::Item> + Unpin,
+    S::Item: Ord,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let a_pending = this.a_head.fill(&mut this.a, cx);
+        let b_pending = this.b_head.fill(&mut this.b, cx);
+        if a_pending || b_pending {
+            return Poll::Pending;
+        }
+
+        let ord = match (this.a_head.peek(), this.b_head.peek()) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => Ordering::Less,
+            (None, _) => Ordering::Greater,
+        };
+
+        Poll::Ready(match ord {
+            Ordering::Less => this.a_head.take(),
+            Ordering::Greater => this.b_head.take(),
+            Ordering::Equal => {
+                this.b_head.take();
+                this.a_head.take()
+            }
+        })
+    }
+}
+
+impl<S, T> FusedStream for MergeUniqStream<S, T>
+where
+    S: Stream + Unpin,
+    T: Stream<Item = S::Item> + Unpin,
+    S::Item: Ord,
+{
+    fn is_terminated(&self) -> bool {
+        matches!((&self.a_head, &self.b_head), (Head::Done, Head::Done))
+    }
+}
+
+/// Takes the smallest of `heads`, which must all be known, and drops any copies of it.
+fn take_min<T: Ord>(heads: &mut [Head<T>]) -> Option<T> {
+    // `min_by` returns the first of several equal items, which is the earliest stream's.
+    let min = heads
+        .iter()
+        .enumerate()
+        .filter_map(|(i, head)| head.peek().map(|x| (i, x)))
+        .min_by(|(_, x), (_, y)| x.cmp(y))
+        .map(|(i, _)| i)?;
+    let item = heads[min].take().unwrap();
+    for head in &mut heads[min + 1..] {
+        if head.peek() == Some(&item) {
+            head.take();
+        }
+    }
+    Some(item)
+}
+
+/// A stream of the merged, deduplicated contents of any number of sorted, deduplicated streams.
+///
+/// When an item appears in several inputs, the one from the earliest input is yielded. Finding
+/// the smallest head scans all of them, which is cheap for the handful of streams this is meant
+/// for, such as the results of a query fanned out to several shards.
+pub struct KMergeUniqStream<S: Stream> {
+    streams: Vec<S>,
+    heads: Vec<Head<S::Item>>,
+}
+
+impl<S: Stream> KMergeUniqStream<S> {
+    pub fn new(streams: Vec<S>) -> Self {
+        let heads = streams.iter().map(|_| Head::Empty).collect();
+        KMergeUniqStream { streams, heads }
+    }
+}
+
+impl<S: Stream + Unpin> Unpin for KMergeUniqStream<S> {}
+
+impl<S> Stream for KMergeUniqStream<S>
+where
+    S: Stream + Unpin,
+    S::Item: Ord,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut pending = false;
+        for (head, stream) in this.heads.iter_mut().zip(&mut this.streams) {
+            pending |= head.fill(stream, cx);
+        }
+        if pending {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(take_min(&mut this.heads))
+    }
+}
+
+impl<S> FusedStream for KMergeUniqStream<S>
+where
+    S: Stream + Unpin,
+    S::Item: Ord,
+{
+    fn is_terminated(&self) -> bool {
+        self.heads.iter().all(|head| matches!(head, Head::Done))
+    }
+}
+
+/// Like `KMergeUniqStream`, but for streams that can fail.
+///
+/// Yields `Ok` items in merged, deduplicated order until any input yields an `Err`. That error is
+/// yielded next, and the stream ends after it.
+pub struct TryKMergeUniqStream<S, T, E>
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    streams: Vec<S>,
+    heads: Vec<Head<T>>,
+    failed: bool,
+}
+
+impl<S, T, E> TryKMergeUniqStream<S, T, E>
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    pub fn new(streams: Vec<S>) -> Self {
+        let heads = streams.iter().map(|_| Head::Empty).collect();
+        TryKMergeUniqStream {
+            streams,
+            heads,
+            failed: false,
+        }
+    }
+}
+
+impl<S, T, E> Unpin for TryKMergeUniqStream<S, T, E> where S: Stream<Item = Result<T, E>> + Unpin {}
+
+impl<S, T, E> Stream for TryKMergeUniqStream<S, T, E>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+    T: Ord,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.failed {
+            return Poll::Ready(None);
+        }
+
+        let mut pending = false;
+        for (head, stream) in this.heads.iter_mut().zip(&mut this.streams) {
+            match head.try_fill(stream, cx) {
+                Ok(p) => pending |= p,
+                Err(e) => {
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+        if pending {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(take_min(&mut this.heads).map(Ok))
+    }
+}
+
+impl<S, T, E> FusedStream for TryKMergeUniqStream<S, T, E>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+    T: Ord,
+{
+    fn is_terminated(&self) -> bool {
+        self.failed || self.heads.iter().all(|head| matches!(head, Head::Done))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use crate::test_util::sorted_unique;
+    use futures::executor::block_on;
+    use futures::stream::{self, StreamExt};
+    use futures::task::noop_waker_ref;
+    use futures::FutureExt;
+    use quickcheck_macros::quickcheck;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A stream that is pending on every other poll, to exercise the combinators' wakeups.
+    struct Stutter<S> {
+        inner: S,
+        ready: bool,
+    }
+
+    impl<S: Stream + Unpin> Stream for Stutter<S> {
+        type Item = S::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Pin::new(&mut self.inner).poll_next(cx)
+        }
+    }
+
+    fn stutter(v: Vec<usize>) -> Stutter<stream::Iter<std::vec::IntoIter<usize>>> {
+        Stutter {
+            inner: stream::iter(v),
+            ready: false,
+        }
+    }
+
+    /// A stream that counts the items taken from it.
+    struct Counted<S> {
+        inner: S,
+        taken: Rc<Cell<usize>>,
+    }
+
+    impl<S: Stream + Unpin> Stream for Counted<S> {
+        type Item = S::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+            let next = Pin::new(&mut self.inner).poll_next(cx);
+            if let Poll::Ready(Some(_)) = next {
+                self.taken.set(self.taken.get() + 1);
+            }
+            next
+        }
+    }
+
+    #[quickcheck]
+    fn merge_uniq_stream_impl(a: Vec<usize>, b: Vec<usize>) -> bool {
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let expected = naive(a.clone(), b.clone());
+        let actual: Vec<_> = block_on(MergeUniqStream::new(stutter(a), stutter(b)).collect());
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn kmerge_uniq_stream_impl(runs: Vec<Vec<usize>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+
+        let expected = sorted_unique(runs.concat());
+        let streams = runs.into_iter().map(stutter).collect();
+        let actual: Vec<_> = block_on(KMergeUniqStream::new(streams).collect());
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn cancellation_safe(a: Vec<usize>, b: Vec<usize>, runs: Vec<Vec<usize>>) -> bool {
+        /// Collects `merge` by polling each `next()` future once and dropping it if it's pending.
+        fn collect_dropping_futures<S: Stream + Unpin>(mut merge: S) -> Vec<S::Item> {
+            let mut cx = Context::from_waker(noop_waker_ref());
+            let mut out = Vec::new();
+            loop {
+                match merge.next().poll_unpin(&mut cx) {
+                    Poll::Ready(Some(x)) => out.push(x),
+                    Poll::Ready(None) => return out,
+                    Poll::Pending => {}
+                }
+            }
+        }
+
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let two = naive(a.clone(), b.clone());
+        let k = sorted_unique(runs.concat());
+        let streams = runs.into_iter().map(stutter).collect();
+        collect_dropping_futures(MergeUniqStream::new(stutter(a), stutter(b))) == two
+            && collect_dropping_futures(KMergeUniqStream::new(streams)) == k
+    }
+
+    #[quickcheck]
+    fn buffers_one_item_per_input(runs: Vec<Vec<usize>>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let taken: Vec<_> = runs.iter().map(|_| Rc::new(Cell::new(0))).collect();
+        let streams = runs
+            .iter()
+            .zip(&taken)
+            .map(|(run, taken)| Counted {
+                inner: stutter(run.clone()),
+                taken: taken.clone(),
+            })
+            .collect();
+
+        // After yielding `x`, the merge holds at most the one item after `x` from each input.
+        let mut merge = KMergeUniqStream::new(streams);
+        block_on(async {
+            while let Some(x) = merge.next().await {
+                for (run, taken) in runs.iter().zip(&taken) {
+                    if taken.get() > run.partition_point(|&y| y <= x) + 1 {
+                        return false;
+                    }
+                }
+            }
+            true
+        })
+    }
+
+    #[quickcheck]
+    fn try_kmerge_uniq_stream_impl(runs: Vec<Vec<usize>>, fail: Option<(usize, usize)>) -> bool {
+        let runs: Vec<_> = runs.into_iter().map(sorted_unique).collect();
+        let expected = sorted_unique(runs.concat());
+
+        // Optionally replace one element of one run with an error.
+        let fail = fail.filter(|&(r, i)| r < runs.len() && i < runs[r].len());
+        let streams = runs
+            .into_iter()
+            .enumerate()
+            .map(|(r, run)| {
+                let run: Vec<Result<usize, usize>> = run
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, x)| if fail == Some((r, i)) { Err(r) } else { Ok(x) })
+                    .collect();
+                Stutter {
+                    inner: stream::iter(run),
+                    ready: false,
+                }
+            })
+            .collect();
+
+        let mut merge = TryKMergeUniqStream::new(streams);
+        let actual: Vec<_> = block_on((&mut merge).collect());
+        let actual: Result<Vec<_>, _> = actual.into_iter().collect();
+        let fused = merge.is_terminated();
+        match fail {
+            Some((r, _)) => fused && actual == Err(r),
+            None => fused && actual == Ok(expected),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Elements that count how often they are dropped, for testing that merges neither leak nor
+//! double-drop, including when a comparison panics partway through, and checks that compare a
+//! merge against `merge2_uniq::naive` on a fixed corpus of inputs.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::sync::{Arc, Mutex};
+
+use crate::generate::{Distribution, Generator};
+use crate::merge2_uniq::naive;
+
+struct State {
+    /// The number of times each element has been dropped, indexed by its id.
+    drops: Mutex<Vec<usize>>,
+    /// The number of comparisons left before the next one panics.
+    fuel: AtomicUsize,
+}
+
+/// Creates `DropCounter`s and keeps count of how often each has been dropped.
+///
+/// Elements can be moved to and dropped on other threads, so parallel merges can be checked too.
+pub struct DropTracker {
+    state: Arc<State>,
+}
+
+impl Default for DropTracker {
+    fn default() -> Self {
+        DropTracker::new()
+    }
+}
+
+impl DropTracker {
+    pub fn new() -> Self {
+        DropTracker {
+            state: Arc::new(State {
+                drops: Mutex::new(Vec::new()),
+                fuel: AtomicUsize::new(usize::MAX),
+            }),
+        }
+    }
+
+    /// Wraps `value` in a new element with the next id.
+    pub fn track<T>(&self, value: T) -> DropCounter<T> {
+        DropCounter {
+            value,
+            id: self.state.new_id(),
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    /// Makes comparisons between this tracker's elements panic after `comparisons` more of them.
+    /// Pass `usize::MAX` to never panic.
+    pub fn set_fuel(&self, comparisons: usize) {
+        self.state.fuel.store(comparisons, SeqCst);
+    }
+
+    /// Returns the number of times each element has been dropped so far, indexed by its id.
+    pub fn drops(&self) -> Vec<usize> {
+        self.state.drops.lock().unwrap().clone()
+    }
+}
+
+impl State {
+    fn new_id(&self) -> usize {
+        let mut drops = self.drops.lock().unwrap();
+        drops.push(0);
+        drops.len() - 1
+    }
+}
+
+/// An element that records when it is dropped, and compares by its value alone.
+///
+/// A clone is a new element, with its own id.
+pub struct DropCounter<T> {
+    value: T,
+    id: usize,
+    state: Arc<State>,
+}
+
+impl<T> DropCounter<T> {
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl<T> Drop for DropCounter<T> {
+    fn drop(&mut self) {
+        self.state.drops.lock().unwrap()[self.id] += 1;
+    }
+}
+
+impl<T: Clone> Clone for DropCounter<T> {
+    fn clone(&self) -> Self {
+        DropCounter {
+            value: self.value.clone(),
+            id: self.state.new_id(),
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DropCounter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropCounter")
+            .field("value", &self.value)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl<T: Ord> Ord for DropCounter<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let fuel = self
+            .state
+            .fuel
+            .fetch_update(SeqCst, SeqCst, |n| n.checked_sub(1));
+        if fuel.is_err() {
+            panic!("comparison fuel exhausted");
+        }
+        self.value.cmp(&other.value)
+    }
+}
+
+impl<T: Ord> PartialOrd for DropCounter<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> PartialEq for DropCounter<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for DropCounter<T> {}
+
+/// Runs `merge` on tracked copies of `a` and `b`, with comparisons that panic after `fuel` of
+/// them, and checks that every element either ended up in the output or was dropped exactly once.
+///
+/// Returns the values of the output, or `None` if `merge` panicked. Elements that `merge` clones
+/// are checked like the inputs.
+///
+/// # Panics
+///
+/// Panics if an element was leaked, dropped more than once, or is in the output after being
+/// dropped or more than once.
+pub fn check_merge2<T, F>(a: Vec<T>, b: Vec<T>, fuel: usize, merge: F) -> Option<Vec<T>>
+where
+    T: Clone,
+    F: FnOnce(Vec<DropCounter<T>>, Vec<DropCounter<T>>) -> Vec<DropCounter<T>>,
+{
+    let tracker = DropTracker::new();
+    let a = a.into_iter().map(|x| tracker.track(x)).collect();
+    let b = b.into_iter().map(|x| tracker.track(x)).collect();
+
+    tracker.set_fuel(fuel);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| merge(a, b)));
+    tracker.set_fuel(usize::MAX);
+
+    let drops = tracker.drops();
+    let mut in_output = vec![false; drops.len()];
+    for x in res.iter().flatten() {
+        assert!(!in_output[x.id], "element {} is in the output twice", x.id);
+        in_output[x.id] = true;
+    }
+    for (id, (&n, &kept)) in drops.iter().zip(&in_output).enumerate() {
+        match (n, kept) {
+            (0, true) | (1, false) => {}
+            (_, true) => panic!("element {} is in the output after being dropped", id),
+            (0, false) => panic!("element {} was leaked", id),
+            (n, false) => panic!("element {} was dropped {} times", id, n),
+        }
+    }
+
+    let out = res.ok()?;
+    let values = out.iter().map(|x| x.value.clone()).collect();
+    drop(out);
+    assert!(
+        tracker.drops().iter().all(|&n| n == 1),
+        "an element was dropped more than once"
+    );
+    Some(values)
+}
+
+/// Returns pairs of sorted, deduplicated inputs for every pair of lengths up to `max_len`, each
+/// with no, half and full overlap, and with elements that alternate between the inputs, come in
+/// short runs, or come in runs as long as the inputs. The pairs are the same on every call.
+pub fn corpus(max_len: usize) -> Vec<(Vec<u64>, Vec<u64>)> {
+    let distributions = [
+        Distribution::Uniform,
+        Distribution::Clustered { mean_run: 4 },
+        Distribution::Clustered {
+            mean_run: max_len.max(1),
+        },
+    ];
+    let mut pairs = Vec::new();
+    for len_a in 0..=max_len {
+        for len_b in 0..=max_len {
+            for &overlap in &[0.0, 0.5, 1.0] {
+                for &distribution in &distributions {
+                    let seed = (len_a * (max_len + 1) + len_b) as u64;
+                    let gen = Generator::new(len_a, len_b)
+                        .overlap(overlap)
+                        .distribution(distribution)
+                        .seed(seed);
+                    pairs.push(gen.pair());
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Checks that `merge` gives the same result as `merge2_uniq::naive` on every pair.
+///
+/// # Panics
+///
+/// Panics with the offending inputs if the results differ.
+pub fn check_against_naive<T, F>(pairs: &[(Vec<T>, Vec<T>)], mut merge: F)
+where
+    T: Ord + Clone + fmt::Debug,
+    F: FnMut(Vec<T>, Vec<T>) -> Vec<T>,
+{
+    for (a, b) in pairs {
+        let expected = naive(a.clone(), b.clone());
+        let actual = merge(a.clone(), b.clone());
+        assert_eq!(actual, expected, "merging {:?} with {:?}", a, b);
+    }
+}
+
+/// Like `check_against_naive`, but merges `DropCounter`s with `check_merge2`, once without
+/// panicking and then with a comparison panicking at the start, middle and end of each merge.
+///
+/// # Panics
+///
+/// Panics with the offending inputs if the results differ, or if an element was leaked or
+/// dropped twice.
+pub fn check_against_naive_tracked<T, F>(pairs: &[(Vec<T>, Vec<T>)], mut merge: F)
+where
+    T: Ord + Clone + fmt::Debug,
+    F: FnMut(Vec<DropCounter<T>>, Vec<DropCounter<T>>) -> Vec<DropCounter<T>>,
+{
+    for (a, b) in pairs {
+        let expected = naive(a.clone(), b.clone());
+        let len = a.len() + b.len();
+        for &fuel in &[usize::MAX, 0, len / 2, len] {
+            let out = check_merge2(a.clone(), b.clone(), fuel, &mut merge);
+            assert!(
+                out.as_ref()
+                    .map_or(fuel != usize::MAX, |out| *out == expected),
+                "merging {:?} with {:?} and fuel {} gave {:?}",
+                a,
+                b,
+                fuel,
+                out
+            );
+        }
+    }
+}
+
+/// Sorts and deduplicates `v`, keeping the first of several equal elements, to turn arbitrary
+/// inputs into the sorted, deduplicated runs the merges expect.
+#[cfg(test)]
+pub(crate) fn sorted_unique<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+    v.sort();
+    v.dedup();
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_drops_and_clones() {
+        let tracker = DropTracker::new();
+        let x = tracker.track(1);
+        let y = x.clone();
+        assert_eq!(y.id(), 1);
+        drop(x);
+        assert_eq!(tracker.drops(), [1, 0]);
+        drop(y);
+        assert_eq!(tracker.drops(), [1, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "comparison fuel exhausted")]
+    fn fuel_runs_out() {
+        let tracker = DropTracker::new();
+        let (x, y) = (tracker.track(1), tracker.track(2));
+        tracker.set_fuel(1);
+        assert!(x < y);
+        let _ = x < y;
+    }
+
+    #[test]
+    #[should_panic(expected = "element 1 was leaked")]
+    fn detects_leaks() {
+        check_merge2(vec![1], vec![2], usize::MAX, |a, mut b| {
+            std::mem::forget(b.pop());
+            a
+        });
+    }
+
+    #[test]
+    fn corpus_is_sorted_and_deterministic() {
+        let corpus = super::corpus(3);
+        assert_eq!(corpus.len(), 4 * 4 * 9);
+        assert!(corpus.iter().all(|(a, b)| {
+            crate::checked::is_sorted_unique(a) && crate::checked::is_sorted_unique(b)
+        }));
+        assert_eq!(corpus, super::corpus(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "merging [1] with [2]")]
+    fn reports_differences() {
+        check_against_naive(&[(vec![1], vec![2])], |a, _| a);
+    }
+
+    #[test]
+    fn accepts_dropped_duplicates() {
+        let out = check_merge2(vec![1, 2], vec![2, 3], usize::MAX, crate::merge_uniq);
+        assert_eq!(out, Some(vec![1, 2, 3]));
+        assert_eq!(
+            check_merge2(vec![1, 2], vec![2, 3], 1, crate::merge_uniq),
+            None
+        );
+    }
+}
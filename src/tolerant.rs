@@ -0,0 +1,284 @@
+//! Merging inputs that are only mostly sorted, such as feeds whose events arrive a little out of
+//! order.
+//!
+//! The other merges trust each input to be sorted, and one misplaced element quietly leaves the
+//! output out of order. `TolerantMerge` accepts inputs in which no element is more than
+//! `max_displacement` positions away from where it belongs, and puts each back in its place by
+//! holding a window of `max_displacement + 1` elements per input, reordered as a heap.
+//!
+//! An element that arrives early just waits in the window until its turn, however early it is, so
+//! it is reordered without complaint. An element that arrives too late to be put back, because a
+//! larger one has already left the window, fails the merge with a `DisplacedElement` naming its
+//! input and its index there, before anything out of order is yielded.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt;
+use std::iter::FusedIterator;
+
+use crate::kway::{ByKey, LoserTree};
+
+/// The error returned for an element that arrives too late for a `TolerantMerge` to put it back in
+/// its place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplacedElement {
+    /// The position of the offending input among the inputs.
+    pub input: usize,
+    /// The index in its input of the element that is less than one yielded before it.
+    pub index: usize,
+    /// The displacement the merge allowed.
+    pub max_displacement: usize,
+}
+
+impl fmt::Display for DisplacedElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "element {} of input {} is displaced by more than {} positions",
+            self.index, self.input, self.max_displacement
+        )
+    }
+}
+
+impl Error for DisplacedElement {}
+
+/// Sorts a mostly sorted input through a window of elements, yielding each with its index in the
+/// input.
+struct Resync<I: Iterator> {
+    input: I,
+    /// Ties are broken by index, so equal elements keep their order.
+    window: BinaryHeap<Reverse<(I::Item, usize)>>,
+    /// The number of elements read so far.
+    read: usize,
+    max_displacement: usize,
+}
+
+impl<I> Iterator for Resync<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = ByKey<I::Item, usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.window.len() <= self.max_displacement {
+            match self.input.next() {
+                Some(x) => {
+                    self.window.push(Reverse((x, self.read)));
+                    self.read += 1;
+                }
+                None => break,
+            }
+        }
+        let Reverse((key, item)) = self.window.pop()?;
+        Some(ByKey { key, item })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = self.input.size_hint();
+        let len = self.window.len();
+        (
+            lo.saturating_add(len),
+            hi.and_then(|hi| hi.checked_add(len)),
+        )
+    }
+}
+
+/// An iterator that merges inputs in which each element is at most `max_displacement` positions
+/// from its place. See the module docs.
+///
+/// Equal elements are yielded in the order of their inputs, and within an input in the order they
+/// were read, and none are dropped. The iterator yields `Err` once, at the first element that
+/// arrives too late, and then ends.
+pub struct TolerantMerge<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    tree: LoserTree<Resync<I>>,
+    max_displacement: usize,
+    error: Option<DisplacedElement>,
+    done: bool,
+}
+
+impl<I> TolerantMerge<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    pub fn new<J>(sources: Vec<J>, max_displacement: usize) -> Self
+    where
+        J: IntoIterator<IntoIter = I>,
+    {
+        let sources = sources
+            .into_iter()
+            .map(|source| Resync {
+                input: source.into_iter(),
+                window: BinaryHeap::with_capacity(max_displacement.saturating_add(1)),
+                read: 0,
+                max_displacement,
+            })
+            .collect();
+        TolerantMerge {
+            tree: LoserTree::new(sources),
+            max_displacement,
+            error: None,
+            done: false,
+        }
+    }
+}
+
+impl<I> Iterator for TolerantMerge<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = Result<I::Item, DisplacedElement>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(e) = self.error.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        // The heads of the other sources are all at least `x`, so `x` can be yielded even if the
+        // head that replaces it is out of place.
+        match self.tree.pop() {
+            Some((i, ByKey { key, .. })) => {
+                if let Some(head) = self.tree.head(i).filter(|head| head.key < key) {
+                    self.error = Some(DisplacedElement {
+                        input: i,
+                        index: head.item,
+                        max_displacement: self.max_displacement,
+                    });
+                }
+                Some(Ok(key))
+            }
+            None => {
+                self.done = true;
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            return (0, Some(0));
+        }
+        // An error can end the merge at any point.
+        let (_, hi) = self.tree.size_hint();
+        let error = usize::from(self.error.is_some());
+        (error, hi.and_then(|hi| hi.checked_add(1)))
+    }
+}
+
+impl<I> FusedIterator for TolerantMerge<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+}
+
+/// Merges inputs in which each element is at most `max_displacement` positions from its place
+/// into one sorted vector, keeping every element. See `TolerantMerge`.
+pub fn merge_tolerant<I>(
+    runs: Vec<I>,
+    max_displacement: usize,
+) -> Result<Vec<I::Item>, DisplacedElement>
+where
+    I: IntoIterator,
+    I::Item: Ord,
+{
+    TolerantMerge::new(runs, max_displacement).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    /// Moves elements of a sorted run by at most `d` positions, by reversing blocks of `d + 1`.
+    fn scramble(mut run: Vec<u8>, d: usize) -> Vec<u8> {
+        run.sort_unstable();
+        for block in run.chunks_mut(d + 1) {
+            block.reverse();
+        }
+        run
+    }
+
+    #[quickcheck]
+    fn merge_tolerant_impl(runs: Vec<Vec<u8>>, d: u8) -> bool {
+        let d = usize::from(d % 8);
+        let mut expected = runs.concat();
+        expected.sort_unstable();
+
+        let runs: Vec<_> = runs.into_iter().map(|run| scramble(run, d)).collect();
+        merge_tolerant(runs.clone(), d) == Ok(expected)
+            && (d == 0 || merge_tolerant(runs, d * 2).is_ok())
+    }
+
+    /// Returns how far the element of `run` furthest from its place is from it.
+    fn displacement(run: &[u8]) -> usize {
+        let mut order: Vec<usize> = (0..run.len()).collect();
+        order.sort_by_key(|&i| run[i]);
+        order
+            .iter()
+            .enumerate()
+            .map(|(pos, &i)| pos.abs_diff(i))
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[quickcheck]
+    fn sorted_prefix_before_error(runs: Vec<Vec<u8>>, d: u8) -> bool {
+        let d = usize::from(d % 4);
+        let mut yielded = Vec::new();
+        let mut error = None;
+        for x in TolerantMerge::new(runs.clone(), d) {
+            match x {
+                Ok(x) => yielded.push(x),
+                Err(e) => error = Some(e),
+            }
+        }
+
+        let sorted = yielded.windows(2).all(|w| w[0] <= w[1]);
+        match error {
+            // Everything was yielded, in order.
+            None => sorted && yielded.len() == runs.iter().map(Vec::len).sum::<usize>(),
+            // The element named is less than one before it, in an input that is displaced too
+            // far.
+            Some(e) => {
+                let run = &runs[e.input];
+                sorted
+                    && e.max_displacement == d
+                    && run[..e.index].iter().any(|&x| x > run[e.index])
+                    && displacement(run) > d
+            }
+        }
+    }
+
+    #[test]
+    fn reports_displaced_element() {
+        let runs = vec![vec![1, 3, 2, 4, 6, 5], vec![0, 7, 8, 9, 1, 10]];
+        assert_eq!(
+            merge_tolerant(runs.clone(), 1),
+            Err(DisplacedElement {
+                input: 1,
+                index: 4,
+                max_displacement: 1,
+            })
+        );
+        assert_eq!(
+            merge_tolerant(runs, 3),
+            Ok(vec![0, 1, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10])
+        );
+
+        let mut merge = TolerantMerge::new(vec![vec![2, 1]], 0);
+        assert_eq!(merge.next(), Some(Ok(2)));
+        assert!(matches!(merge.next(), Some(Err(e)) if e.index == 1));
+        assert_eq!(merge.next(), None);
+    }
+}
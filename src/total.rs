@@ -0,0 +1,133 @@
+//! Merging floating-point numbers and other types that are only `PartialOrd`.
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+
+/// Orders floats by their IEEE 754 total order, so that they can be merged like any `Ord` type.
+///
+/// The total order puts negative NaNs first and positive NaNs last, and `-0.0` before `0.0`.
+/// Unlike with `==`, every NaN equals itself and `-0.0` doesn't equal `0.0`, so deduplicating
+/// merges keep both zeros and a single copy of each distinct NaN.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(transparent)]
+pub struct Total<T>(pub T);
+
+macro_rules! total_order {
+    ($($t:ty),*) => {$(
+        impl PartialEq for Total<$t> {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other) == Ordering::Equal
+            }
+        }
+
+        impl Eq for Total<$t> {}
+
+        impl PartialOrd for Total<$t> {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Total<$t> {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.total_cmp(&other.0)
+            }
+        }
+    )*};
+}
+
+total_order!(f32, f64);
+
+/// Merges two vectors of floats that are sorted and deduplicated by their total order (see
+/// `Total`), with `crate::merge_uniq`.
+///
+/// `v.sort_by(f64::total_cmp)` followed by `v.dedup_by(|x, y| x.total_cmp(y).is_eq())` puts a
+/// vector in this form.
+pub fn merge2_uniq_total<T>(a: Vec<T>, b: Vec<T>) -> Vec<T>
+where
+    Total<T>: Ord,
+{
+    // The element types have the same layout, so these conversions reuse their allocations.
+    let a: Vec<Total<T>> = a.into_iter().map(Total).collect();
+    let b: Vec<Total<T>> = b.into_iter().map(Total).collect();
+    crate::merge_uniq(a, b).into_iter().map(|x| x.0).collect()
+}
+
+/// The error returned by `merge2_uniq_partial` when two elements have no order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Incomparable;
+
+impl fmt::Display for Incomparable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("merged elements are incomparable")
+    }
+}
+
+impl Error for Incomparable {}
+
+/// Merges two sorted, deduplicated vectors of a type that is only `PartialOrd`.
+///
+/// If the merge compares two elements for which `partial_cmp` returns `None`, such as a float
+/// and NaN, it stops and returns `Incomparable`. Only elements that have to be compared are
+/// checked: once one input runs out, the rest of the other is moved to the output as is. Use
+/// `merge2_uniq_total` to merge floats that may include NaNs.
+pub fn merge2_uniq_partial<T: PartialOrd>(a: Vec<T>, b: Vec<T>) -> Result<Vec<T>, Incomparable> {
+    crate::try_merge2_uniq_by(a, b, |x, y| x.partial_cmp(y).ok_or(Incomparable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn sorted_unique_total(mut v: Vec<f64>) -> Vec<f64> {
+        v.sort_by(f64::total_cmp);
+        v.dedup_by(|x, y| x.total_cmp(y).is_eq());
+        v
+    }
+
+    fn bits(v: &[f64]) -> Vec<u64> {
+        v.iter().map(|x| x.to_bits()).collect()
+    }
+
+    #[quickcheck]
+    fn merge2_uniq_total_impl(a: Vec<f64>, b: Vec<f64>) -> bool {
+        // quickcheck rarely generates these on its own.
+        let specials = [f64::NAN, -f64::NAN, 0.0, -0.0, f64::INFINITY];
+        let a = sorted_unique_total(
+            a.into_iter()
+                .chain(specials.iter().copied().step_by(2))
+                .collect(),
+        );
+        let b = sorted_unique_total(
+            b.into_iter()
+                .chain(specials.iter().copied().skip(1))
+                .collect(),
+        );
+
+        let expected = sorted_unique_total([&a[..], &b].concat());
+        let actual = merge2_uniq_total(a, b);
+        bits(&expected) == bits(&actual)
+    }
+
+    #[quickcheck]
+    fn merge2_uniq_partial_impl(a: Vec<u32>, b: Vec<u32>) -> bool {
+        let sorted_unique = |v: Vec<u32>| -> Vec<f64> {
+            let mut v: Vec<_> = v.into_iter().map(f64::from).collect();
+            v.sort_by(f64::total_cmp);
+            v.dedup();
+            v
+        };
+        let (a, b) = (sorted_unique(a), sorted_unique(b));
+
+        let expected = sorted_unique_total([&a[..], &b].concat());
+        merge2_uniq_partial(a, b) == Ok(expected)
+    }
+
+    #[test]
+    fn merge2_uniq_partial_nan() {
+        let res = merge2_uniq_partial(vec![1.0, f64::NAN], vec![2.0, 3.0]);
+        assert_eq!(res, Err(Incomparable));
+    }
+}
@@ -0,0 +1,333 @@
+//! Choosing the dispatch thresholds of the merges by timing them on the current machine.
+//!
+//! `crate::merge_uniq` and friends pick a kernel with fixed thresholds, which were measured on one
+//! machine and can be far off on another: the ratio of input lengths past which galloping pays
+//! off, for example, depends on the cost of a branch misprediction relative to a cache miss.
+//! `MergeConfig::measure` times the candidates on generated inputs and records the winners, and
+//! the methods of `MergeConfig` dispatch with them. Measuring takes a few tens of milliseconds, so
+//! `config` does it once per process, and a config can be saved with its `Display` impl and loaded
+//! back with `FromStr` and `set_config` to skip it altogether.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::generate::Generator;
+use crate::merge2_uniq;
+use crate::simd::{self, Backend, SimdElement};
+
+/// The number of elements in each input of the balanced probes.
+const PROBE_LEN: usize = 1 << 15;
+
+/// How many times each probe runs. The fastest run counts, which filters out most of the noise of
+/// a busy machine.
+const PROBE_REPEATS: usize = 3;
+
+/// The length ratios tried by `measure`, in the order they're tried.
+const GALLOP_RATIOS: [usize; 8] = [2, 4, 8, 16, 32, 64, 128, 256];
+
+/// The part lengths tried by `measure`, in the order they're tried.
+#[cfg(any(feature = "rayon", feature = "threads"))]
+const PART_LENS: [usize; 4] = [1 << 10, 1 << 12, 1 << 14, 1 << 16];
+
+const BACKENDS: [Backend; 5] = [
+    Backend::Scalar,
+    Backend::Avx2,
+    Backend::Avx512,
+    Backend::Neon,
+    Backend::Simd128,
+];
+
+/// The thresholds a merge uses to choose between its kernels.
+///
+/// `Default` gives the thresholds `crate::merge_uniq`, `simd::merge_uniq` and `parallel` use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MergeConfig {
+    /// Gallop through the longer input once it is at least this many times longer than the other.
+    pub gallop_ratio: usize,
+    /// Merge inputs of similar lengths with `merge2_uniq::branchless` rather than
+    /// `merge2_uniq::adaptive`.
+    pub branchless: bool,
+    /// The backend for merges of integers.
+    pub simd: Backend,
+    /// Don't split a parallel merge into parts shorter than this.
+    pub min_part_len: usize,
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        MergeConfig {
+            gallop_ratio: merge2_uniq::GALLOP_RATIO,
+            branchless: false,
+            simd: Backend::detect(),
+            min_part_len: 1 << 14,
+        }
+    }
+}
+
+impl MergeConfig {
+    /// Times the kernels on generated inputs and returns the thresholds that suit this machine.
+    pub fn measure() -> Self {
+        let mut config = MergeConfig::default();
+        let (a, b) = Generator::new(PROBE_LEN, PROBE_LEN).overlap(0.1).pair();
+
+        config.branchless =
+            time_merge(&a, &b, merge2_uniq::branchless) < time_merge(&a, &b, merge2_uniq::adaptive);
+        let balanced: fn(Vec<u64>, Vec<u64>) -> Vec<u64> = if config.branchless {
+            merge2_uniq::branchless
+        } else {
+            merge2_uniq::adaptive
+        };
+
+        config.simd = BACKENDS
+            .iter()
+            .copied()
+            .filter(|backend| backend.is_supported())
+            .min_by_key(|&backend| time_merge(&a, &b, |a, b| simd::merge_uniq_with(backend, a, b)))
+            .unwrap_or(Backend::Scalar);
+
+        // The smallest ratio at which galloping beats the balanced kernel.
+        config.gallop_ratio = GALLOP_RATIOS
+            .iter()
+            .copied()
+            .find(|&ratio| {
+                let (a, b) = Generator::new(PROBE_LEN, PROBE_LEN / ratio)
+                    .overlap(0.1)
+                    .pair();
+                time_merge(&a, &b, merge2_uniq::galloping) < time_merge(&a, &b, balanced)
+            })
+            .unwrap_or(GALLOP_RATIOS[GALLOP_RATIOS.len() - 1] * 2);
+
+        #[cfg(any(feature = "rayon", feature = "threads"))]
+        if let Some(len) = measure_min_part_len() {
+            config.min_part_len = len;
+        }
+
+        config
+    }
+
+    /// Merges two sorted, deduplicated vectors like `crate::merge_uniq`, but with the thresholds
+    /// of this config.
+    pub fn merge_uniq<T: Ord>(&self, a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        // `crate::merge_uniq` concatenates inputs that don't overlap, including empty ones.
+        if a.last() < b.first() || b.last() < a.first() {
+            return crate::merge_uniq(a, b);
+        }
+
+        crate::checked::debug_validate(&[&a, &b]);
+        let ratio = self.gallop_ratio.max(1);
+        if a.len() / ratio >= b.len() || b.len() / ratio >= a.len() {
+            merge2_uniq::galloping(a, b)
+        } else if self.branchless {
+            merge2_uniq::branchless(a, b)
+        } else {
+            merge2_uniq::adaptive(a, b)
+        }
+    }
+
+    /// Merges two sorted, deduplicated vectors of integers with the backend of this config.
+    ///
+    /// Falls back to `Backend::detect` if the backend isn't supported by this CPU, as when the
+    /// config was measured on another machine.
+    pub fn simd_merge_uniq<T: SimdElement>(&self, a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        let backend = if self.simd.is_supported() {
+            self.simd
+        } else {
+            Backend::detect()
+        };
+        simd::merge_uniq_with(backend, a, b)
+    }
+
+    /// Merges two sorted, deduplicated vectors like `parallel::merge_uniq`, but splits them into
+    /// parts of at least `min_part_len` elements.
+    #[cfg(any(feature = "rayon", feature = "threads"))]
+    pub fn par_merge_uniq<T: Ord + Send>(&self, a: Vec<T>, b: Vec<T>) -> Vec<T> {
+        use crate::parallel::Executor;
+
+        let exec = crate::parallel::default_executor();
+        let parts = exec
+            .num_threads()
+            .min((a.len() + b.len()) / self.min_part_len.max(1));
+        crate::parallel::merge_uniq_in_parts(&exec, a, b, parts)
+    }
+}
+
+/// Returns the shortest part for which splitting a merge across the default executor beats
+/// merging on one thread, or `None` if there is only one thread.
+#[cfg(any(feature = "rayon", feature = "threads"))]
+fn measure_min_part_len() -> Option<usize> {
+    use crate::parallel::Executor;
+
+    let exec = crate::parallel::default_executor();
+    let threads = exec.num_threads();
+    if threads < 2 {
+        return None;
+    }
+
+    let len = PART_LENS.iter().copied().find(|&part_len| {
+        let half = part_len * threads / 2;
+        let (a, b) = Generator::new(half, half).overlap(0.1).pair();
+        let parallel = time_merge(&a, &b, |a, b| {
+            crate::parallel::merge_uniq_in_parts(&exec, a, b, threads)
+        });
+        parallel < time_merge(&a, &b, crate::merge_uniq)
+    });
+    Some(len.unwrap_or(PART_LENS[PART_LENS.len() - 1] * 4))
+}
+
+/// Returns the fastest of `PROBE_REPEATS` runs of `merge` on copies of `a` and `b`.
+fn time_merge(a: &[u64], b: &[u64], merge: impl Fn(Vec<u64>, Vec<u64>) -> Vec<u64>) -> Duration {
+    (0..PROBE_REPEATS)
+        .map(|_| {
+            let (a, b) = (a.to_vec(), b.to_vec());
+            let start = Instant::now();
+            let out = merge(a, b);
+            let elapsed = start.elapsed();
+            drop(std::hint::black_box(out));
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+static CONFIG: OnceLock<MergeConfig> = OnceLock::new();
+
+/// Returns the config of this process, measuring it with `MergeConfig::measure` on first use
+/// unless `set_config` was called first.
+pub fn config() -> &'static MergeConfig {
+    CONFIG.get_or_init(MergeConfig::measure)
+}
+
+/// Makes `config` return `c`, typically a config measured earlier and saved, without measuring.
+///
+/// Returns `c` back if the config of this process was already set or measured.
+pub fn set_config(c: MergeConfig) -> Result<(), MergeConfig> {
+    CONFIG.set(c)
+}
+
+fn backend_name(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Scalar => "scalar",
+        Backend::Avx2 => "avx2",
+        Backend::Avx512 => "avx512",
+        Backend::Neon => "neon",
+        Backend::Simd128 => "simd128",
+    }
+}
+
+/// Writes the config as space-separated `key=value` pairs, such as
+/// `gallop_ratio=32 branchless=false simd=avx2 min_part_len=16384`.
+impl fmt::Display for MergeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gallop_ratio={} branchless={} simd={} min_part_len={}",
+            self.gallop_ratio,
+            self.branchless,
+            backend_name(self.simd),
+            self.min_part_len
+        )
+    }
+}
+
+/// The error returned when parsing a `MergeConfig` fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseConfigError {
+    /// The `key=value` pair that couldn't be parsed.
+    pub pair: String,
+}
+
+impl fmt::Display for ParseConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid merge config entry `{}`", self.pair)
+    }
+}
+
+impl std::error::Error for ParseConfigError {}
+
+/// Parses the format written by `Display`. Keys that are left out keep their default values, so
+/// configs saved by older versions still load.
+impl FromStr for MergeConfig {
+    type Err = ParseConfigError;
+
+    fn from_str(s: &str) -> Result<Self, ParseConfigError> {
+        let mut config = MergeConfig::default();
+        for pair in s.split_whitespace() {
+            let err = || ParseConfigError {
+                pair: pair.to_owned(),
+            };
+            let (key, value) = pair.split_once('=').ok_or_else(err)?;
+            match key {
+                "gallop_ratio" => {
+                    config.gallop_ratio = value.parse().ok().filter(|&r| r > 0).ok_or_else(err)?
+                }
+                "branchless" => config.branchless = value.parse().map_err(|_| err())?,
+                "simd" => {
+                    config.simd = BACKENDS
+                        .iter()
+                        .copied()
+                        .find(|&backend| backend_name(backend) == value)
+                        .ok_or_else(err)?
+                }
+                "min_part_len" => {
+                    config.min_part_len = value.parse().ok().filter(|&n| n > 0).ok_or_else(err)?
+                }
+                _ => return Err(err()),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge2_uniq::naive;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn merge_uniq_impl(mut a: Vec<u32>, mut b: Vec<u32>, ratio: u8, branchless: bool) -> bool {
+        a.sort_unstable();
+        a.dedup();
+        b.sort_unstable();
+        b.dedup();
+
+        let config = MergeConfig {
+            gallop_ratio: usize::from(ratio % 8) + 1,
+            branchless,
+            ..MergeConfig::default()
+        };
+        let expected = naive(a.clone(), b.clone());
+        config.merge_uniq(a.clone(), b.clone()) == expected
+            && config.simd_merge_uniq(a, b) == expected
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let config = MergeConfig {
+            gallop_ratio: 8,
+            branchless: true,
+            simd: Backend::Scalar,
+            min_part_len: 4096,
+        };
+        assert_eq!(config.to_string().parse(), Ok(config));
+        assert_eq!("".parse(), Ok(MergeConfig::default()));
+        assert_eq!(
+            "gallop_ratio=0".parse::<MergeConfig>(),
+            Err(ParseConfigError {
+                pair: "gallop_ratio=0".to_owned()
+            })
+        );
+        assert!("simd=sse2".parse::<MergeConfig>().is_err());
+        assert!("branchless".parse::<MergeConfig>().is_err());
+    }
+
+    #[test]
+    fn measure_picks_candidates() {
+        let config = MergeConfig::measure();
+        assert!(config.simd.is_supported());
+        assert!(config.gallop_ratio >= GALLOP_RATIOS[0]);
+        assert!(config.min_part_len > 0);
+    }
+}
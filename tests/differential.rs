@@ -0,0 +1,259 @@
+//! Runs every public two-way merge against `merge2_uniq::naive` on a fixed corpus of inputs,
+//! tracking drops and panicking partway through comparisons.
+//!
+//! Run with `cargo test --features differential-tests`.
+
+use std::sync::{Arc, OnceLock};
+
+use kmerge::generate::{Distribution, Generator};
+use kmerge::test_util::{check_against_naive, check_against_naive_tracked, corpus};
+use kmerge::{bytes, kway, merge2_uniq};
+
+/// Every pair of lengths up to this one is in the corpus.
+const MAX_LEN: usize = 16;
+
+fn small() -> &'static [(Vec<u64>, Vec<u64>)] {
+    static SMALL: OnceLock<Vec<(Vec<u64>, Vec<u64>)>> = OnceLock::new();
+    SMALL.get_or_init(|| corpus(MAX_LEN))
+}
+
+/// A few pairs large enough to be split by the parallel merges and to run the vector loops of the
+/// SIMD merges many times.
+fn large() -> Vec<(Vec<u64>, Vec<u64>)> {
+    [0.0, 0.5, 1.0]
+        .iter()
+        .map(|&overlap| {
+            Generator::new(20000, 30000)
+                .overlap(overlap)
+                .distribution(Distribution::Clustered { mean_run: 16 })
+                .pair()
+        })
+        .collect()
+}
+
+/// Checks a merge that's generic over its elements on the small corpus with tracked elements,
+/// and on the large pairs with plain ones.
+macro_rules! differential {
+    ($name:ident, $merge:expr) => {
+        #[test]
+        fn $name() {
+            check_against_naive_tracked(small(), $merge);
+            check_against_naive(&large(), $merge);
+        }
+    };
+}
+
+differential!(merge_uniq, kmerge::merge_uniq);
+differential!(merge3_uniq, |a, b| kmerge::merge3_uniq(a, b, Vec::new()));
+differential!(merge_uniq_into, |a, b| {
+    let mut out = Vec::new();
+    kmerge::merge_uniq_into(&mut out, a, b);
+    out
+});
+differential!(merge_append, |mut a, b| {
+    kmerge::merge_append(&mut a, b);
+    a
+});
+differential!(merge_uniq_drain, |mut a, mut b| {
+    kmerge::merge_uniq_drain(&mut a, &mut b)
+});
+differential!(merge_uniq_boxed, |a: Vec<_>, b: Vec<_>| {
+    kmerge::merge_uniq_boxed(a.into_boxed_slice(), b.into_boxed_slice()).into_vec()
+});
+differential!(merge_uniq_take, |a, b| {
+    kmerge::merge_uniq_take(a, b, usize::MAX)
+});
+differential!(try_merge_uniq, |a, b| kmerge::try_merge_uniq(a, b).unwrap());
+differential!(merge2_uniq_by, |a, b| {
+    kmerge::merge2_uniq_by(a, b, Ord::cmp)
+});
+differential!(merge_uniq_filter_map, |a, b| {
+    kmerge::merge_uniq_filter_map(a, b, Some)
+});
+differential!(merge_uniq_cow, |a: Vec<_>, b: Vec<_>| {
+    kmerge::merge_uniq_cow(&a, &b).into_owned()
+});
+differential!(merge_uniq_shared, |a: Vec<_>, b: Vec<_>| {
+    let (a, b): (Arc<[_]>, Arc<[_]>) = (a.into(), b.into());
+    kmerge::merge_uniq_shared(&a, &b).to_vec()
+});
+differential!(merge2_uniq_by_cached_key, |a, b| {
+    kmerge::merge2_uniq_by_cached_key(a, b, Clone::clone)
+});
+differential!(try_merge2_uniq_by, |a, b| {
+    kmerge::try_merge2_uniq_by(a, b, |x, y| Ok::<_, ()>(Ord::cmp(x, y))).unwrap()
+});
+differential!(merge_uniq_by_eq, |a, b| {
+    kmerge::merge_uniq_by_eq(a, b, Ord::cmp, PartialEq::eq)
+});
+differential!(merge2_uniq_exact, merge2_uniq::exact);
+differential!(merge2_uniq_old_datafrog, merge2_uniq::old_datafrog);
+differential!(merge2_uniq_low_memory, merge2_uniq::low_memory);
+differential!(kway_merge_uniq, |a, b| kway::merge_uniq(vec![a, b]));
+differential!(kway_merge_stable, |a, b| {
+    let mut out = kway::merge_stable(vec![a, b]);
+    out.dedup();
+    out
+});
+differential!(kway_kmerge_to, |a: Vec<_>, b: Vec<_>| {
+    let mut out = Vec::new();
+    kway::kmerge_to(&[&a, &b], &mut out);
+    out
+});
+
+#[cfg(not(feature = "force-safe"))]
+mod kernels {
+    use super::*;
+
+    differential!(into_iter, merge2_uniq::into_iter);
+    differential!(into_iter_safer, merge2_uniq::into_iter_safer);
+    differential!(raw_ptr, merge2_uniq::raw_ptr);
+    differential!(branchless, merge2_uniq::branchless);
+    differential!(galloping, merge2_uniq::galloping);
+    differential!(adaptive, merge2_uniq::adaptive);
+    differential!(in_place, |mut a, b| {
+        merge2_uniq::in_place(&mut a, b);
+        a
+    });
+    differential!(binary_insert, |mut a, b| {
+        merge2_uniq::binary_insert(&mut a, b);
+        a
+    });
+    differential!(merge_adjacent_uniq, |mut a: Vec<_>, b| {
+        let mid = a.len();
+        a.extend(b);
+        merge2_uniq::merge_adjacent_uniq(&mut a, mid);
+        a
+    });
+    differential!(from_slices, |a: Vec<_>, b: Vec<_>| {
+        merge2_uniq::from_slices(&a, &b)
+    });
+}
+
+// Reading back the output requires unsafe code, and the safe implementation leaks the elements
+// it has written if a comparison panics.
+#[cfg(not(feature = "force-safe"))]
+differential!(merge_uniq_into_uninit, |a: Vec<_>, b: Vec<_>| {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let len = kmerge::merge_uniq_into_uninit(a, b, out.spare_capacity_mut());
+    unsafe { out.set_len(len) };
+    out
+});
+
+#[cfg(any(feature = "rayon", feature = "threads"))]
+mod parallel {
+    use super::*;
+
+    differential!(merge_uniq, kmerge::parallel::merge_uniq);
+    differential!(merge_k_uniq, |a, b| {
+        kmerge::parallel::merge_k_uniq(vec![a, b])
+    });
+    differential!(merge_many, |a, b| {
+        kmerge::parallel::merge_many(vec![(a, b)]).pop().unwrap()
+    });
+}
+
+/// The elements of the SIMD merges are `Copy`, so there's nothing to track.
+#[test]
+fn simd_merge_uniq() {
+    check_against_naive(small(), kmerge::simd::merge_uniq);
+    check_against_naive(&large(), kmerge::simd::merge_uniq);
+}
+
+#[cfg(not(feature = "force-safe"))]
+#[test]
+fn from_slices_copied() {
+    let merge = |a: Vec<_>, b: Vec<_>| merge2_uniq::from_slices_copied(&a, &b);
+    check_against_naive(small(), merge);
+    check_against_naive(&large(), merge);
+}
+
+/// Byte-string keys aren't tracked either, so the elements are merged as their big-endian
+/// encodings, which sort the same way.
+#[test]
+fn bytes_merge_uniq() {
+    let merge = |a: Vec<u64>, b: Vec<u64>| {
+        let encode = |v: Vec<u64>| v.into_iter().map(u64::to_be_bytes).collect();
+        let out = bytes::merge_uniq::<[u8; 8]>(encode(a), encode(b));
+        out.into_iter().map(u64::from_be_bytes).collect()
+    };
+    check_against_naive(small(), merge);
+    check_against_naive(&large(), merge);
+}
+
+/// The external merges read and write records framed in bytes, so these run them on in-memory
+/// cursors over the big-endian encodings of the elements.
+mod external {
+    use std::convert::TryInto;
+    use std::io::Cursor;
+
+    use kmerge::external::{BoundedMerger, FixedSize, Merger, Sorter};
+
+    use super::*;
+
+    fn encode(v: &[u64]) -> Vec<u8> {
+        v.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+
+    fn records(v: Vec<u64>) -> impl Iterator<Item = Vec<u8>> {
+        v.into_iter().map(|x| x.to_be_bytes().to_vec())
+    }
+
+    /// Spills the large pairs in several runs, which are then merged a few at a time.
+    fn sorter() -> Sorter<FixedSize> {
+        Sorter::new(FixedSize::new(8))
+            .memory_budget(64 * 1024)
+            .max_open_runs(4)
+    }
+
+    /// Checks `merge`, which writes the merged encodings to the vector it's given.
+    fn check(mut merge: impl FnMut(Vec<u64>, Vec<u64>, &mut Vec<u8>)) {
+        let mut merge = |a, b| {
+            let mut out = Vec::new();
+            merge(a, b, &mut out);
+            out.chunks_exact(8)
+                .map(|x| u64::from_be_bytes(x.try_into().unwrap()))
+                .collect()
+        };
+        check_against_naive(small(), &mut merge);
+        check_against_naive(&large(), merge);
+    }
+
+    #[test]
+    fn merger_merge_uniq() {
+        check(|a, b, out| {
+            let runs = vec![Cursor::new(encode(&a)), Cursor::new(encode(&b))];
+            Merger::new(FixedSize::new(8))
+                .merge_uniq(runs, out)
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn merger_merge_uniq_pipelined() {
+        check(|a, b, out| {
+            let runs = vec![Cursor::new(encode(&a)), Cursor::new(encode(&b))];
+            Merger::new(FixedSize::new(8))
+                .merge_uniq_pipelined(runs, out)
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn sorter_sort_uniq() {
+        check(|a, b, out| {
+            let input = Cursor::new(encode(&[a, b].concat()));
+            sorter().sort_uniq(input, out).unwrap();
+        });
+    }
+
+    #[test]
+    fn bounded_merger() {
+        check(|a, b, out| {
+            let mut merger = BoundedMerger::from_sorter(sorter());
+            merger.push(records(a)).unwrap();
+            merger.push(records(b)).unwrap();
+            merger.finish(out).unwrap();
+        });
+    }
+}